@@ -0,0 +1,1768 @@
+//! Routes meant for the people operating the lockers, rather than for customers or the lockers
+//! themselves. Everything here is mounted under `/admin`.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::FromRequestParts;
+use axum::extract::MatchedPath;
+use axum::extract::OriginalUri;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::RawPathParams;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::request::Parts;
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::routing::delete;
+use axum::routing::get;
+use axum::routing::patch;
+use axum::routing::post;
+use axum::routing::put;
+use axum::Router;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use futures_util::Stream;
+use futures_util::StreamExt;
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+
+use crate::api::ApiResponse;
+use crate::authorization_signatures;
+use crate::AdminCall;
+use crate::AdminLocker;
+use crate::authorize;
+use crate::cancel_pending_payment;
+use crate::ClockDrift;
+use crate::config::AdminRole;
+use crate::consistency;
+use crate::csv;
+use crate::db;
+use crate::device;
+use crate::enrollment;
+use crate::error;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::OperatorAccount;
+use crate::Owned;
+use crate::secret;
+use crate::params;
+use crate::receipt;
+use crate::Granularity;
+use crate::Locker;
+use crate::LockerAuth;
+use crate::LockerEvent;
+use crate::LockerMetadata;
+use crate::LockerRevenue;
+use crate::NewLocker;
+use crate::PaymentFilter;
+use crate::ProvisioningCode;
+use crate::Revocation;
+use crate::signing;
+use crate::Server;
+use crate::Site;
+use crate::SiteMetadata;
+use crate::StoreError;
+use crate::Webhook;
+use crate::webhook;
+use crate::WebhookDelivery;
+
+/// Builds the router for everything under `/admin`, all of it behind [require_admin].
+pub fn router<Ln: LnBackend>(state: Arc<Server<Ln>>) -> Router<Arc<Server<Ln>>> {
+    Router::new()
+        .route("/lockers", get(get_lockers).post(create_locker))
+        .route(
+            "/lockers/{locker_id}",
+            patch(update_locker).delete(delete_locker),
+        )
+        .route("/lockers/{locker_id}/events", get(get_locker_events))
+        .route("/lockers/{locker_id}/history", get(get_locker_history))
+        .route("/lockers/{locker_id}/decommission", post(decommission_locker))
+        .route("/lockers/{locker_id}/reactivate", post(reactivate_locker))
+        .route(
+            "/lockers/{locker_id}/maintenance",
+            post(start_maintenance).delete(end_maintenance),
+        )
+        .route("/lockers/{locker_id}/force_open", post(force_open))
+        .route("/lockers/{locker_id}/force_release", post(force_release))
+        .route("/lockers/{locker_id}/approve", post(approve_locker))
+        .route("/lockers/{locker_id}/price", put(set_locker_price))
+        .route("/lockers/{locker_id}/rotate_key", post(rotate_locker_key))
+        .route(
+            "/lockers/{locker_id}/device_token",
+            post(issue_device_token).delete(revoke_device_token),
+        )
+        .route(
+            "/provisioning_codes",
+            get(get_provisioning_codes).post(create_provisioning_code),
+        )
+        .route(
+            "/provisioning_codes/{code_id}",
+            delete(revoke_provisioning_code),
+        )
+        .route("/sites", get(get_sites).post(create_site))
+        .route(
+            "/sites/{site_id}",
+            get(get_site).patch(update_site).delete(delete_site),
+        )
+        .route("/payments", get(get_payments))
+        .route("/payments/pending", get(get_pending_payments))
+        .route("/payments/{payment_hash}/cancel", post(cancel_payment))
+        .route("/receipts/{payment_hash}/revoke", post(revoke_receipt))
+        .route("/metrics", get(get_metrics))
+        .route("/backup", get(get_backup))
+        .route("/export/payments.csv", get(export_payments))
+        .route("/export/sessions.csv", get(export_sessions))
+        .route("/consistency_check", post(check_consistency))
+        .route("/clock_drift", get(get_clock_drift))
+        .route("/stats/revenue", get(get_revenue_stats))
+        .route("/stats/occupancy", get(get_occupancy_stats))
+        .route("/audit", get(get_audit_log))
+        .route("/webhooks", get(get_webhooks).post(create_webhook))
+        .route("/webhooks/{webhook_id}", delete(delete_webhook))
+        .route("/webhooks/{webhook_id}/deliveries", get(get_webhook_deliveries))
+        .route("/operators", get(get_operators).post(create_operator))
+        // layers run from the last one added, so the scope is known by the time it's enforced
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            enforce_scope::<Ln>,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state,
+            require_admin::<Ln>,
+        ))
+}
+
+/// Rejects any request that doesn't carry one of the admin tokens as a bearer token, and records
+/// who made each call in the audit log: every call that changes something, whatever became of it,
+/// and the reads that succeeded.
+async fn require_admin<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, error::Error> {
+    let token = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(error::Error::Unauthorized)?;
+
+    // check every token, rather than stopping at the first match, so the time it takes doesn't
+    // tell which one matched
+    let hash = sha256::Hash::hash(token.as_bytes()).to_byte_array();
+    let admin_token = state
+        .config
+        .admin_tokens
+        .iter()
+        .fold(None, |found, admin_token| {
+            let matches = signing::constant_time_eq(&hash, &admin_token.sha256);
+            found.or(matches.then_some(admin_token))
+        })
+        .ok_or(error::Error::Unauthorized)?
+        .clone();
+
+    let method = request.method().to_string();
+    let is_mutation = !matches!(*request.method(), Method::GET | Method::HEAD);
+    // nesting strips the `/admin` prefix from the uri we see
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri(), |uri| &uri.0)
+        .to_string();
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|route| route.as_str().to_string());
+
+    // the body was already read whole by [crate::limit_body], so this can't fail
+    let (mut parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| error::Error::BadRequest)?;
+    let summary = summarize_body(&body);
+    parts.extensions.insert(admin_token.role);
+    parts.extensions.insert(Scope(admin_token.operator_id));
+    let request = Request::from_parts(parts, Body::from(body));
+
+    let response = next.run(request).await;
+    if is_mutation || response.status().is_success() {
+        state
+            .record_admin_call(
+                &admin_token,
+                &method,
+                &path,
+                route,
+                summary,
+                response.status().as_u16(),
+            )
+            .await;
+    }
+
+    Ok(response)
+}
+
+/// The least [AdminRole] a [RequireRole] asks for.
+pub trait MinimumRole {
+    const ROLE: AdminRole;
+}
+
+/// Asks for [AdminRole::Operator].
+pub struct Operator;
+
+impl MinimumRole for Operator {
+    const ROLE: AdminRole = AdminRole::Operator;
+}
+
+/// Asks for [AdminRole::Owner].
+pub struct Owner;
+
+impl MinimumRole for Owner {
+    const ROLE: AdminRole = AdminRole::Owner;
+}
+
+/// Rejects admin calls made with a token whose role is below `R`'s with a `403`. Routes without
+/// one are open to every admin token, [AdminRole::Viewer] included.
+pub struct RequireRole<R>(PhantomData<R>);
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    R: MinimumRole,
+{
+    type Rejection = error::Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // set by [require_admin], which every admin route is behind
+        let role = parts
+            .extensions
+            .get::<AdminRole>()
+            .ok_or(error::Error::Unauthorized)?;
+        if *role < R::ROLE {
+            return Err(error::Error::Forbidden);
+        }
+
+        Ok(Self(PhantomData))
+    }
+}
+
+/// The operator whose lockers an admin call is limited to, that of the token it was made with, or
+/// `None` for super-admins, who see every operator's.
+///
+/// Routes about one thing, like `/lockers/{locker_id}`, are checked by [enforce_scope], while
+/// those listing things pass the operator on to the query so nothing of other operators comes
+/// back.
+#[derive(Debug, Clone, Copy)]
+pub struct Scope(pub Option<i64>);
+
+impl<S: Send + Sync> FromRequestParts<S> for Scope {
+    type Rejection = error::Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // set by [require_admin], which every admin route is behind
+        parts
+            .extensions
+            .get::<Scope>()
+            .copied()
+            .ok_or(error::Error::Unauthorized)
+    }
+}
+
+impl Scope {
+    /// The operator something added with this token belongs to: the token's own, or the one a
+    /// super-admin asked for, if any. Scoped tokens asking for another operator get a `403`.
+    async fn owner<Ln: LnBackend>(
+        self,
+        state: &Server<Ln>,
+        requested: Option<i64>,
+    ) -> Result<Option<i64>, error::Error> {
+        match (self.0, requested) {
+            (Some(own), Some(requested)) if own != requested => Err(error::Error::Forbidden),
+            (Some(own), _) => Ok(Some(own)),
+            (None, Some(requested)) => {
+                let operators = state.list_operators().await?;
+                if !operators.iter().any(|operator| operator.id == requested) {
+                    return Err(error::Error::InvalidParam(format!(
+                        "operator_id: unknown operator {requested}"
+                    )));
+                }
+
+                Ok(Some(requested))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Rejects admin calls made with a token scoped to an operator with a `403`, for routes about
+/// the whole server rather than any operator's lockers.
+pub struct SuperAdmin;
+
+impl<S: Send + Sync> FromRequestParts<S> for SuperAdmin {
+    type Rejection = error::Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Scope::from_request_parts(parts, state).await? {
+            Scope(None) => Ok(Self),
+            Scope(Some(_)) => Err(error::Error::Forbidden),
+        }
+    }
+}
+
+/// Answers calls about something that isn't the [Scope]'s operator's with a `404`, as if it
+/// didn't exist, going by the route's path parameters.
+async fn enforce_scope<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, error::Error> {
+    let (mut parts, body) = request.into_parts();
+    let Scope(Some(operator_id)) = Scope::from_request_parts(&mut parts, &state).await? else {
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    };
+
+    let path_params = RawPathParams::from_request_parts(&mut parts, &state)
+        .await
+        .map_err(|_| error::Error::BadRequest)?;
+    for (name, value) in &path_params {
+        // parameters that don't parse are left for the route to reject
+        let owned = match name {
+            "locker_id" => value.parse().ok().map(Owned::Locker),
+            "site_id" => value.parse().ok().map(Owned::Site),
+            "payment_hash" => value
+                .parse::<params::PaymentHash>()
+                .ok()
+                .map(|payment_hash| Owned::Payment(payment_hash.into())),
+            "webhook_id" => value.parse().ok().map(Owned::Webhook),
+            "code_id" => value.parse().ok().map(Owned::ProvisioningCode),
+            _ => None,
+        };
+        let Some(owned) = owned else {
+            continue;
+        };
+
+        match state.operator_of(owned).await {
+            Ok(owner) if owner == Some(operator_id) => {}
+            Ok(_) | Err(StoreError::NotFound) => return Err(error::Error::NotFound),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// What we keep in the audit log of an admin call's body: the JSON, with the fields that may hold
+/// secrets redacted and invoices cut down to a prefix, or only the size of anything else. Long
+/// summaries are truncated to [MAX_SUMMARY_CHARS].
+pub fn summarize_body(body: &[u8]) -> Option<String> {
+    if body.is_empty() {
+        return None;
+    }
+
+    let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return Some(format!("{} bytes", body.len()));
+    };
+    redact(&mut value);
+
+    let summary = value.to_string();
+    match summary.char_indices().nth(MAX_SUMMARY_CHARS) {
+        Some((end, _)) => Some(format!("{}…", &summary[..end])),
+        None => Some(summary),
+    }
+}
+
+/// How many characters of an admin call's body we keep, see [summarize_body].
+const MAX_SUMMARY_CHARS: usize = 512;
+
+/// How many characters of a BOLT11 invoice we keep, enough to tell its network and amount.
+const BOLT11_PREFIX_CHARS: usize = 16;
+
+/// Fields whose names contain any of these are never written to the audit log.
+const SECRET_FIELDS: [&str; 5] = ["secret", "password", "token", "preimage", "private"];
+
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                let name = name.to_lowercase();
+                if SECRET_FIELDS.iter().any(|secret| name.contains(secret)) {
+                    *value = serde_json::Value::from(secret::REDACTED);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        serde_json::Value::String(string) if is_bolt11(string) => {
+            let prefix: String = string.chars().take(BOLT11_PREFIX_CHARS).collect();
+            *string = format!("{prefix}…");
+        }
+        _ => {}
+    }
+}
+
+/// Whether `string` looks like a BOLT11 invoice, on any network.
+fn is_bolt11(string: &str) -> bool {
+    let string = string.to_lowercase();
+    let string = string.strip_prefix("lightning:").unwrap_or(&string);
+    string.len() > BOLT11_PREFIX_CHARS
+        && ["lnbc", "lntb", "lnsb"]
+            .iter()
+            .any(|prefix| string.starts_with(prefix))
+}
+
+/// Updates a locker's name, location, size, description or site, returning the updated locker.
+async fn update_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    metadata: axum::Json<LockerMetadata>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    state.update_locker_metadata(locker_id, &metadata).await?;
+    state
+        .record_event(
+            locker_id,
+            "metadata_updated",
+            "admin",
+            serde_json::json!({
+                "name": metadata.name,
+                "location": metadata.location,
+                "size": metadata.size,
+                "description": metadata.description,
+                "site_id": metadata.site_id,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct LockerPrice {
+    /// The locker's own price, or `null` to go back to its site's price or the configured one.
+    price_per_minute_msat: Option<u64>,
+}
+
+/// Sets or clears the price of a single locker, returning the updated locker.
+async fn set_locker_price<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    price: axum::Json<LockerPrice>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    state
+        .set_locker_price(locker_id, price.price_per_minute_msat)
+        .await?;
+    state
+        .record_event(
+            locker_id,
+            "price_updated",
+            "admin",
+            serde_json::json!({ "price_per_minute_msat": price.price_per_minute_msat }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// Takes a locker out of service: customers won't see it or be able to use it anymore, but its
+/// history is kept.
+async fn decommission_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    update_locker_active(locker_id, &state, false).await
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteLockerQuery {
+    /// Take the locker out of service even if someone is using it, for hardware that's broken.
+    #[serde(default)]
+    force: bool,
+}
+
+/// Takes a locker out of service like [decommission_locker], but only once nobody is using it and
+/// its payments are settled, otherwise it's a `409` saying what's in the way. With `?force=true`
+/// a locker in use is taken out anyway: its session is closed without charging anything and
+/// flagged for staff to follow up on.
+async fn delete_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    Query(query): Query<DeleteLockerQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    let session_id = state
+        .retire_locker(locker_id, query.force, now())
+        .await?;
+    state
+        .record_event(
+            locker_id,
+            "decommissioned",
+            "admin",
+            serde_json::json!({
+                "forced": query.force,
+                "closed_session_id": session_id,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// Puts a decommissioned locker back into service.
+async fn reactivate_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    update_locker_active(locker_id, &state, true).await
+}
+
+/// Puts an available locker under maintenance: it's still listed, but customers can't claim it.
+/// A locker someone is using gets a `409` saying its state, staff have to wait for them or
+/// release it first.
+async fn start_maintenance<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    update_locker_maintenance(locker_id, &state, true).await
+}
+
+/// Makes a locker under maintenance available again.
+async fn end_maintenance<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    update_locker_maintenance(locker_id, &state, false).await
+}
+
+async fn update_locker_maintenance<Ln: LnBackend>(
+    locker_id: i64,
+    state: &Server<Ln>,
+    maintenance: bool,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    state.set_locker_maintenance(locker_id, maintenance).await?;
+
+    let event = if maintenance {
+        "maintenance_started"
+    } else {
+        "maintenance_ended"
+    };
+    state
+        .record_event(locker_id, event, "admin", serde_json::json!({}))
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct ForceOpen {
+    /// Why staff had to open the locker, kept in the event log.
+    reason: String,
+    /// What the locker is opened for, defaults to `retrieve`.
+    action: Option<signing::Action>,
+}
+
+/// Authorizes opening a locker without anyone paying for it, for staff helping a customer who's
+/// stuck. The receipt says `actor: admin`, so it can't be mistaken for one a customer bought, and
+/// the locker's state is left alone.
+async fn force_open<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<ForceOpen>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let reason = require_reason(&body.reason)?;
+    let action = body.action.unwrap_or(signing::Action::Retrieve);
+
+    let now = now();
+    let auth = state.get_locker_auth(locker_id).await?;
+    let key = state.keys.active();
+    let expires_at = now + state.config.token_ttl_seconds;
+    let expiring = authorize(key, &auth, locker_id, action, now, expires_at);
+    let (signature, expiring_signature) =
+        authorization_signatures(&state, key, &auth, locker_id, now, expiring);
+    let signed_receipt = receipt::sign(
+        receipt::Receipt {
+            action,
+            actor: Some("admin".to_string()),
+            expires_at,
+            issued_at: now,
+            kid: key.id.clone(),
+            locker_id,
+            payment_hash: None,
+        },
+        key,
+    );
+    state
+        .record_event(
+            locker_id,
+            "force_opened",
+            "admin",
+            serde_json::json!({ "reason": reason, "action": action }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "locker_id": locker_id,
+        "action": action,
+        "start_time": now,
+        "expires_at": expires_at,
+        "signature": signature,
+        "expiring_signature": expiring_signature,
+        "kid": key.id,
+        "signed_receipt": signed_receipt,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ForceRelease {
+    /// Why staff had to release the locker, kept in the event log.
+    reason: String,
+    /// Also cancel the invoice the session is waiting on, if it isn't paid yet.
+    #[serde(default)]
+    cancel_invoice: bool,
+}
+
+/// Makes a locker in use available again, closing its session as it is. Its invoice stays payable
+/// unless `cancel_invoice` is set. Returns the updated locker, or a `409` if it wasn't in use.
+async fn force_release<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<ForceRelease>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    let reason = require_reason(&body.reason)?;
+
+    let now = now();
+    state.get_locker(locker_id).await?;
+    let session = match state.get_active_session(locker_id).await {
+        Ok(session) => Some(session),
+        Err(StoreError::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if !state
+        .release_locker(locker_id, session.as_ref().map(|session| session.id), now)
+        .await?
+    {
+        return Err(error::Error::Conflict("Nobody is using the locker".to_string()));
+    }
+
+    let payment_hash = session.as_ref().and_then(|session| session.payment_hash.as_deref());
+    let invoice_cancelled = match payment_hash {
+        Some(payment_hash) if body.cancel_invoice => {
+            cancel_pending_payment(&state, locker_id, payment_hash, "admin", now)
+                .await?
+                .is_some()
+        }
+        _ => false,
+    };
+    state
+        .record_event(
+            locker_id,
+            "force_released",
+            "admin",
+            serde_json::json!({
+                "reason": reason,
+                "session_id": session.as_ref().map(|session| session.id),
+                "invoice_cancelled": invoice_cancelled,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// The reason staff gave for forcing a locker, which has to say something.
+fn require_reason(reason: &str) -> Result<&str, error::Error> {
+    match reason.trim() {
+        "" => Err(error::Error::InvalidParam(
+            "reason: expected why the locker is forced".to_string(),
+        )),
+        reason => Ok(reason),
+    }
+}
+
+/// Returns every locker, unlike `/lockers` including those waiting to be approved and the
+/// decommissioned ones, along with the key each authenticates with and how.
+async fn get_lockers<Ln: LnBackend>(
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<AdminLocker>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_admin_lockers(scope.0).await?))
+}
+
+/// Commissions a locker with the key it will sign its reports with, ready to be used, returning
+/// it with its id.
+async fn create_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<NewLocker>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    let operator_id = scope.owner(&state, body.operator_id).await?;
+    let locker_id = state.insert_locker(&body, operator_id).await?;
+    state.publish_locker_state(locker_id).await;
+    state
+        .record_event(
+            locker_id,
+            "added",
+            "admin",
+            serde_json::json!({ "pk": body.pk.0.to_string() }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// Lets a locker that registered itself through `/provision/register` be used.
+async fn approve_locker<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    state.approve_locker(locker_id).await?;
+    state
+        .record_event(locker_id, "approved", "admin", serde_json::json!({}))
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// Issues a locker a new device token, revoking the ones it had, see [device]. The token is only
+/// returned this once.
+async fn issue_device_token<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let (token, digest) = device::generate();
+    let revoked = state.issue_device_token(locker_id, &digest, now()).await?;
+    state
+        .record_event(
+            locker_id,
+            "device_token_issued",
+            "admin",
+            serde_json::json!({ "revoked": revoked }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({ "locker_id": locker_id, "device_token": token })))
+}
+
+/// Revokes a locker's device tokens, locking the device out of the routes meant for it until it's
+/// issued another.
+async fn revoke_device_token<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let revoked = state.revoke_device_tokens(locker_id, now()).await?;
+    state
+        .record_event(
+            locker_id,
+            "device_token_revoked",
+            "admin",
+            serde_json::json!({ "revoked": revoked }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({ "locker_id": locker_id, "revoked": revoked })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CreateProvisioningCode {
+    /// What the code is for, like where it's being installed.
+    label: Option<String>,
+    /// How long the code stays valid, defaults to
+    /// [crate::config::Config::provisioning_code_ttl_seconds].
+    ttl_seconds: Option<u64>,
+    /// The operator lockers registering with the code belong to, see [Scope::owner].
+    operator_id: Option<i64>,
+}
+
+/// Makes a one-time code a new locker can register with, see [enrollment]. The code is only
+/// returned this once.
+async fn create_provisioning_code<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+    body: Option<axum::Json<CreateProvisioningCode>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let body = body.map(|body| body.0).unwrap_or_default();
+    let ttl_seconds = body
+        .ttl_seconds
+        .unwrap_or(state.config.provisioning_code_ttl_seconds);
+    if ttl_seconds == 0 {
+        return Err(error::Error::InvalidParam(
+            "ttl_seconds: expected a positive number".to_string(),
+        ));
+    }
+
+    let operator_id = scope.owner(&state, body.operator_id).await?;
+    let (code, digest) = enrollment::generate();
+    let created_at = now();
+    let expires_at = created_at.saturating_add(ttl_seconds);
+    let id = state
+        .insert_provisioning_code(&digest, body.label.clone(), created_at, expires_at, operator_id)
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "id": id,
+        "code": code,
+        "label": body.label,
+        "created_at": created_at,
+        "expires_at": expires_at,
+        "operator_id": operator_id,
+    })))
+}
+
+/// Lists the provisioning codes that can still register a locker, without the codes themselves.
+async fn get_provisioning_codes<Ln: LnBackend>(
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<ProvisioningCode>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_provisioning_codes(now(), scope.0).await?))
+}
+
+/// Revokes a provisioning code that wasn't used yet.
+async fn revoke_provisioning_code<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    Path(code_id): Path<i64>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<()>, error::Error> {
+    state.revoke_provisioning_code(code_id, now()).await?;
+
+    Ok(ApiResponse::ok(()))
+}
+
+#[derive(Debug, Deserialize)]
+struct RotateKey {
+    /// The x-only public key of the locker's new controller.
+    pk: params::XOnlyPubkey,
+    /// The signature of the locker's old key over [signing::rotate_key_digest], if its old
+    /// controller can still sign. If given, it must be valid.
+    signature: Option<params::SchnorrSignature>,
+}
+
+/// Gives a locker whose controller was replaced the public key of the new one, invalidating
+/// everything we authorized for the old one, see [Server::rotate_locker_key]. Returns the updated
+/// locker.
+async fn rotate_locker_key<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<RotateKey>,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    let pk = body.pk.0.serialize();
+    if let Some(signature) = &body.signature {
+        let LockerAuth::Schnorr(old_pk) = state.get_locker_auth(locker_id).await? else {
+            return Err(error::Error::BadRequest);
+        };
+        let old_pk = old_pk
+            .parse::<params::XOnlyPubkey>()
+            .map_err(|_| error::Error::BadRequest)?;
+
+        secp256k1::Secp256k1::verification_only()
+            .verify_schnorr(
+                &signature.0,
+                &signing::rotate_key_digest(locker_id, &pk),
+                &old_pk.0,
+            )
+            .map_err(|_| error::Error::BadRequest)?;
+    }
+
+    let (old_pk, revoked) = state
+        .rotate_locker_key(locker_id, &body.pk.0.to_string(), now())
+        .await?;
+    state
+        .record_event(
+            locker_id,
+            "key_rotated",
+            "admin",
+            serde_json::json!({
+                "old_pk": old_pk,
+                "pk": body.pk.0.to_string(),
+                "signed_by_old_key": body.signature.is_some(),
+                "revoked_receipts": revoked,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+async fn update_locker_active<Ln: LnBackend>(
+    locker_id: i64,
+    state: &Server<Ln>,
+    active: bool,
+) -> Result<ApiResponse<Locker>, error::Error> {
+    state.set_locker_active(locker_id, active).await?;
+
+    let event = if active { "reactivated" } else { "decommissioned" };
+    state
+        .record_event(locker_id, event, "admin", serde_json::json!({}))
+        .await;
+
+    Ok(ApiResponse::ok(state.get_locker(locker_id).await?))
+}
+
+/// Returns every site.
+async fn get_sites<Ln: LnBackend>(
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<Site>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_sites(scope.0).await?))
+}
+
+async fn get_site<Ln: LnBackend>(
+    Path(site_id): Path<i64>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Site>, error::Error> {
+    Ok(ApiResponse::ok(state.get_site(site_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewSite {
+    #[serde(flatten)]
+    metadata: SiteMetadata,
+    /// The operator the site belongs to, see [Scope::owner].
+    operator_id: Option<i64>,
+}
+
+/// Adds a site, which needs at least a name, returning it.
+async fn create_site<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<NewSite>,
+) -> Result<ApiResponse<Site>, error::Error> {
+    let metadata = &body.metadata;
+    let Some(name) = metadata.name.as_deref().filter(|name| !name.is_empty()) else {
+        return Err(error::Error::BadRequest);
+    };
+
+    if !metadata.timezone.as_deref().is_none_or(is_timezone) {
+        return Err(error::Error::BadRequest);
+    }
+
+    let operator_id = scope.owner(&state, body.operator_id).await?;
+    let site_id = state.insert_site(name, metadata, operator_id).await?;
+    Ok(ApiResponse::ok(state.get_site(site_id).await?))
+}
+
+/// Updates a site's name, address, timezone or price, returning the updated site.
+async fn update_site<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    Path(site_id): Path<i64>,
+    state: State<Arc<Server<Ln>>>,
+    metadata: axum::Json<SiteMetadata>,
+) -> Result<ApiResponse<Site>, error::Error> {
+    let empty_name = metadata.name.as_deref() == Some("");
+    if empty_name || !metadata.timezone.as_deref().is_none_or(is_timezone) {
+        return Err(error::Error::BadRequest);
+    }
+
+    state.update_site(site_id, &metadata).await?;
+    Ok(ApiResponse::ok(state.get_site(site_id).await?))
+}
+
+/// Deletes a site, as long as none of our lockers are there anymore.
+async fn delete_site<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    Path(site_id): Path<i64>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<()>, error::Error> {
+    state.delete_site(site_id).await?;
+
+    Ok(ApiResponse::ok(()))
+}
+
+async fn get_webhooks<Ln: LnBackend>(
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<Webhook>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_webhooks(scope.0).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateWebhook {
+    /// Where to `POST` the webhooks, over http or https.
+    url: String,
+    /// Which of [webhook::EVENTS] to send, every one if left out.
+    events: Option<Vec<String>>,
+    /// Only send events about the lockers of this operator, see [Scope::owner].
+    operator_id: Option<i64>,
+}
+
+/// Subscribes `url` to the webhooks, or only to some `events`, returning the subscription along
+/// with the secret its deliveries are signed with. This is the only time the secret is handed out.
+async fn create_webhook<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<CreateWebhook>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let is_http = ["http://", "https://"]
+        .iter()
+        .any(|scheme| body.url.len() > scheme.len() && body.url.starts_with(scheme));
+    if !is_http {
+        return Err(error::Error::InvalidParam(
+            "url: expected an http or https URL".to_string(),
+        ));
+    }
+
+    if let Some(events) = &body.events {
+        if events.is_empty() {
+            return Err(error::Error::InvalidParam(
+                "events: expected at least one event, or leave it out for every one".to_string(),
+            ));
+        }
+        if let Some(event) = events.iter().find(|e| !webhook::EVENTS.contains(&e.as_str())) {
+            return Err(error::Error::InvalidParam(format!(
+                "events: unknown event {event}, expected one of {}",
+                webhook::EVENTS.join(", ")
+            )));
+        }
+    }
+
+    let operator_id = scope.owner(&state, body.operator_id).await?;
+    let secret = secret::Secret::new(rand::random::<[u8; 32]>().to_vec());
+    let webhook = state
+        .insert_webhook(&body.url, body.events.clone(), secret.expose(), operator_id)
+        .await?;
+    Ok(ApiResponse::ok(serde_json::json!({
+        "id": webhook.id,
+        "url": webhook.url,
+        "events": webhook.events,
+        "created_at": webhook.created_at,
+        "secret": secret.expose().to_lower_hex_string(),
+    })))
+}
+
+/// Stops sending webhooks to a subscription.
+async fn delete_webhook<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    Path(webhook_id): Path<i64>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<()>, error::Error> {
+    state.delete_webhook(webhook_id).await?;
+
+    Ok(ApiResponse::ok(()))
+}
+
+/// The most webhook deliveries we return at once.
+const MAX_DELIVERIES_PER_PAGE: u64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct DeliveriesQuery {
+    /// One of `pending`, `delivered` or `dead`.
+    status: Option<String>,
+    /// How many deliveries to return, defaults to 50.
+    limit: Option<u64>,
+}
+
+/// Returns the last deliveries queued for a subscription, newest first, with how many times each
+/// was tried and what the receiver last answered, to tell why it missed an event.
+async fn get_webhook_deliveries<Ln: LnBackend>(
+    Path(webhook_id): Path<i64>,
+    Query(query): Query<DeliveriesQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<WebhookDelivery>>, error::Error> {
+    if let Some(status) = &query.status {
+        if !["pending", "delivered", "dead"].contains(&status.as_str()) {
+            return Err(error::Error::InvalidParam(format!(
+                "status: unknown status {status}, expected pending, delivered or dead"
+            )));
+        }
+    }
+
+    let limit = query.limit.unwrap_or(50).min(MAX_DELIVERIES_PER_PAGE);
+    let deliveries = state
+        .list_webhook_deliveries(webhook_id, query.status, limit)
+        .await?;
+
+    Ok(ApiResponse::ok(deliveries))
+}
+
+/// Returns every operator we host.
+async fn get_operators<Ln: LnBackend>(
+    _: SuperAdmin,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<OperatorAccount>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_operators().await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateOperator {
+    name: String,
+}
+
+/// Adds an operator, returning it with the id its admin tokens are scoped to, see
+/// [crate::config::AdminToken::operator_id]. Names are unique.
+async fn create_operator<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    _: SuperAdmin,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<CreateOperator>,
+) -> Result<ApiResponse<OperatorAccount>, error::Error> {
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(error::Error::InvalidParam(
+            "name: expected the operator's name".to_string(),
+        ));
+    }
+
+    Ok(ApiResponse::ok(state.insert_operator(name).await?))
+}
+
+/// Whether `timezone` looks like an IANA timezone name, e.g. "UTC" or "America/Los_Angeles". We
+/// don't ship the timezone database, so we can't tell whether it actually exists.
+fn is_timezone(timezone: &str) -> bool {
+    !timezone.is_empty()
+        && timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'))
+}
+
+/// The most events we return in a single page.
+const MAX_EVENTS_PER_PAGE: u64 = 500;
+
+/// The most payments we return in a single page.
+const MAX_PAYMENTS_PER_PAGE: u64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// How many events to return, defaults to 50.
+    limit: Option<u64>,
+    /// Only return events with an id lower than this one, used to fetch the next page.
+    before: Option<i64>,
+}
+
+/// Returns the successful admin calls, newest first, along with the id of the token each one was
+/// made with. Tokens scoped to an operator only see the calls made with that operator's tokens.
+async fn get_audit_log<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<EventsQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<AdminCall>>, error::Error> {
+    let limit = query.limit.unwrap_or(50).min(MAX_EVENTS_PER_PAGE);
+    let calls = state
+        .list_admin_calls(limit, query.before, scope.0)
+        .await?;
+
+    Ok(ApiResponse::ok(calls))
+}
+
+/// Returns the events recorded for a locker, newest first.
+async fn get_locker_events<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    Query(query): Query<EventsQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<LockerEvent>>, error::Error> {
+    let limit = query.limit.unwrap_or(50).min(MAX_EVENTS_PER_PAGE);
+    let events = state
+        .list_locker_events(locker_id, limit, query.before)
+        .await?;
+
+    Ok(ApiResponse::ok(events))
+}
+
+/// The most sessions we return in a single page of a locker's history.
+const MAX_SESSIONS_PER_PAGE: u64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// Only sessions started at or after this unix timestamp.
+    from: Option<u64>,
+    /// Only sessions started before this unix timestamp.
+    to: Option<u64>,
+    /// How many sessions to return, defaults to 50.
+    limit: Option<u64>,
+    /// How many sessions to skip, used to fetch the next pages.
+    offset: Option<u64>,
+}
+
+/// Returns the sessions of a locker, newest first, with how long each lasted, what was paid for it
+/// and how it ended, along with how many match in total. This is what support looks at when a
+/// customer calls about a locker they used.
+async fn get_locker_history<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    Query(query): Query<HistoryQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    // unknown lockers are a 404 rather than an empty history
+    state.get_locker(locker_id).await?;
+
+    let limit = query.limit.unwrap_or(50).min(MAX_SESSIONS_PER_PAGE);
+    let (sessions, total) = state
+        .list_locker_history(
+            locker_id,
+            query.from,
+            query.to,
+            limit,
+            query.offset.unwrap_or(0),
+            now(),
+        )
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "sessions": sessions,
+        "total": total,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct PaymentsQuery {
+    status: Option<String>,
+    locker_id: Option<i64>,
+    /// Only payments created at or after this unix timestamp.
+    from: Option<u64>,
+    /// Only payments created before this unix timestamp.
+    to: Option<u64>,
+    /// How many payments to return, defaults to 50.
+    limit: Option<u64>,
+    /// How many payments to skip, used to fetch the next pages.
+    offset: Option<u64>,
+}
+
+/// Returns the payments matching the query, newest first, along with how many match in total.
+async fn get_payments<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<PaymentsQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let filter = PaymentFilter {
+        status: query.status,
+        locker_id: query.locker_id,
+        from: query.from,
+        to: query.to,
+        operator_id: scope.0,
+        ..Default::default()
+    };
+
+    let limit = query.limit.unwrap_or(50).min(MAX_PAYMENTS_PER_PAGE);
+    let (payments, total) = state
+        .list_payments(&filter, limit, query.offset.unwrap_or(0))
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "payments": payments,
+        "total": total,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct PendingPaymentsQuery {
+    /// Only payments whose invoice was created at least this many minutes ago.
+    stale_minutes: Option<u64>,
+}
+
+/// Returns the payments we're still waiting on, oldest first, along with the locker and session
+/// each one is for. Payments made for several lockers at once are listed once for each.
+async fn get_pending_payments<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<PendingPaymentsQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<serde_json::Value>>, error::Error> {
+    let now = now();
+    let created_before = match query.stale_minutes {
+        Some(minutes) => now.saturating_sub(minutes.saturating_mul(60)),
+        None => u64::MAX,
+    };
+
+    let payments = state
+        .list_pending_payments(created_before, scope.0)
+        .await?
+        .into_iter()
+        .map(|payment| {
+            serde_json::json!({
+                "payment_hash": payment.payment_hash,
+                "amount": payment.amount,
+                "created_at": payment.created_at,
+                "age_seconds": payment.created_at.map(|created_at| now.saturating_sub(created_at)),
+                "locker_id": payment.locker_id,
+                "locker_name": payment.locker_name,
+                "locker_state": payment.locker_state,
+                "session_id": payment.session_id,
+                "session_started_at": payment.session_started_at,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(ApiResponse::ok(payments))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeReceipt {
+    /// Why the receipt is being revoked, kept for staff and never shown to lockers.
+    reason: Option<String>,
+    /// Which locker's receipt to revoke, needed only for payments made for several lockers.
+    locker_id: Option<i64>,
+}
+
+/// Revokes the receipt bought with a payment, for when the payment turns out to be fraudulent:
+/// we stop handing it out, `/verify_token` and `/verify_receipt` refuse it, and it's listed under
+/// `/revocations` for lockers to refuse it too. Payments made for several lockers bought one
+/// receipt for each, so the body must say which. Returns the revocation.
+async fn revoke_receipt<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+    body: Option<axum::Json<RevokeReceipt>>,
+) -> Result<ApiResponse<Revocation>, error::Error> {
+    let payment_hash = String::from(payment_hash);
+    let (reason, locker_id) = match body {
+        Some(axum::Json(body)) => (body.reason, body.locker_id),
+        None => (None, None),
+    };
+    let revocation = state
+        .revoke_receipt(&payment_hash, locker_id, reason, now())
+        .await?;
+
+    state
+        .record_event(
+            revocation.locker_id,
+            "receipt_revoked",
+            "admin",
+            serde_json::json!({
+                "payment_hash": revocation.payment_hash,
+                "reason": revocation.reason,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(revocation))
+}
+
+/// Cancels a payment that wasn't settled yet, along with its invoice if the lightning backend
+/// supports it, for when staff resolved the situation some other way. Returns the cancelled
+/// payment.
+async fn cancel_payment<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let payment_hash = String::from(payment_hash);
+    let payment = state.get_payment(payment_hash.clone()).await?;
+    let Some(invoice_cancelled) =
+        cancel_pending_payment(&state, payment.locker_id, &payment_hash, "admin", now()).await?
+    else {
+        return Err(error::Error::Conflict("Payment isn't pending".to_string()));
+    };
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "payment": state.get_payment(payment_hash).await?,
+        "invoice_cancelled": invoice_cancelled,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevenueQuery {
+    /// How to group payments together, defaults to `day`.
+    #[serde(default)]
+    granularity: Granularity,
+    /// Only payments settled at or after this unix timestamp.
+    from: Option<u64>,
+    /// Only payments settled before this unix timestamp.
+    to: Option<u64>,
+}
+
+/// Returns how much we made in each period, overall and for each locker.
+async fn get_revenue_stats<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<RevenueQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<serde_json::Value>>, error::Error> {
+    let rows = state
+        .revenue_by_period(
+            query.granularity,
+            query.from.unwrap_or(0),
+            query.to.unwrap_or(u64::MAX),
+            scope.0,
+        )
+        .await?;
+
+    // rows come ordered by period, so each period's lockers are next to each other
+    let mut periods: Vec<(String, Vec<LockerRevenue>)> = Vec::new();
+    for (period, locker) in rows {
+        match periods.last_mut() {
+            Some((last, lockers)) if *last == period => lockers.push(locker),
+            _ => periods.push((period, vec![locker])),
+        }
+    }
+
+    let periods = periods
+        .into_iter()
+        .map(|(period, lockers)| {
+            let sessions = lockers.iter().map(|locker| locker.sessions).sum::<u64>();
+            let session_seconds = lockers
+                .iter()
+                .filter_map(|locker| Some(locker.average_session_seconds? * locker.sessions as f64))
+                .sum::<f64>();
+
+            serde_json::json!({
+                "period": period,
+                "total_sat": lockers.iter().map(|locker| locker.total_sat).sum::<u64>(),
+                "payments": lockers.iter().map(|locker| locker.payments).sum::<u64>(),
+                "sessions": sessions,
+                "average_session_seconds": (sessions > 0).then(|| session_seconds / sessions as f64),
+                "lockers": lockers,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(ApiResponse::ok(periods))
+}
+
+#[derive(Debug, Deserialize)]
+struct OccupancyQuery {
+    /// Where the period starts, as a unix timestamp, defaults to a day before `to`.
+    from: Option<u64>,
+    /// Where the period ends, as a unix timestamp, defaults to now. Anything later is cut off at
+    /// now, since we can't know whether lockers will be in use in the future.
+    to: Option<u64>,
+}
+
+/// Returns how much of the period each locker spent in use, and how much all of them did.
+async fn get_occupancy_stats<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<OccupancyQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let now = now();
+    let to = query.to.unwrap_or(now).min(now);
+    let from = query.from.unwrap_or(to.saturating_sub(24 * 60 * 60));
+    if from >= to {
+        return Err(error::Error::UnprocessableEntity("from must be before to".to_string()));
+    }
+
+    let period = to - from;
+    let percentage = |seconds: u64, lockers: u64| seconds as f64 * 100.0 / (period * lockers) as f64;
+
+    let lockers = state.occupancy(from, to, now, scope.0).await?;
+    let occupied_seconds = lockers.iter().map(|locker| locker.occupied_seconds).sum::<u64>();
+    Ok(ApiResponse::ok(serde_json::json!({
+        "from": from,
+        "to": to,
+        "occupied_seconds": occupied_seconds,
+        "occupancy_percentage": percentage(occupied_seconds, lockers.len().max(1) as u64),
+        "sessions": lockers.iter().map(|locker| locker.sessions).sum::<u64>(),
+        "lockers": lockers
+            .iter()
+            .map(|locker| {
+                serde_json::json!({
+                    "locker_id": locker.locker_id,
+                    "occupied_seconds": locker.occupied_seconds,
+                    "occupancy_percentage": percentage(locker.occupied_seconds, 1),
+                    "sessions": locker.sessions,
+                })
+            })
+            .collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsistencyQuery {
+    /// Whether to repair what we find, rather than only reporting it.
+    #[serde(default)]
+    fix: bool,
+}
+
+/// Looks for lockers, sessions and payments that disagree with each other, like the check we run
+/// at startup, and returns what was found and repaired.
+async fn check_consistency<Ln: LnBackend>(
+    _: RequireRole<Operator>,
+    _: SuperAdmin,
+    Query(query): Query<ConsistencyQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<consistency::Finding>>, error::Error> {
+    Ok(ApiResponse::ok(consistency::check(&state, query.fix).await?))
+}
+
+/// Returns the server's counters, see [crate::metrics::Metrics].
+/// Returns how far off each locker's clock was when it last reported being opened, the furthest
+/// off first, to spot lockers whose clock is failing.
+async fn get_clock_drift<Ln: LnBackend>(
+    scope: Scope,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<ClockDrift>>, error::Error> {
+    Ok(ApiResponse::ok(state.list_clock_drift(scope.0).await?))
+}
+
+async fn get_metrics<Ln: LnBackend>(
+    _: SuperAdmin,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    Ok(ApiResponse::ok(state.metrics.snapshot()))
+}
+
+/// Returns a consistent snapshot of the whole database, as an sqlite file.
+async fn get_backup<Ln: LnBackend>(
+    _: RequireRole<Owner>,
+    _: SuperAdmin,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let path = std::env::temp_dir().join(format!(
+        "locker-backup-{}-{}.sqlite",
+        std::process::id(),
+        rand::random::<u64>()
+    ));
+
+    let result = db::backup(&state.database, &path).await;
+    let file = match result {
+        Ok(()) => tokio::fs::File::open(&path).await,
+        Err(e) => {
+            eprintln!("[get_backup] backup failed: {e}");
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(error::Error::DbError);
+        }
+    };
+
+    // the open file outlives its path, so we can stream it without leaving it behind
+    let _ = tokio::fs::remove_file(&path).await;
+    let file = file.map_err(|_| error::Error::Server)?;
+
+    Ok(axum::http::Response::builder()
+        .header(axum::http::header::CONTENT_TYPE, "application/vnd.sqlite3")
+        .header(
+            axum::http::header::CONTENT_DISPOSITION,
+            "attachment; filename=\"backup.sqlite\"",
+        )
+        .body(Body::from_stream(tokio_util::io::ReaderStream::new(file)))
+        .unwrap())
+}
+
+/// How many rows we read from the database at a time while exporting.
+const EXPORT_BATCH_SIZE: u64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    /// Only payments created at or after this unix timestamp.
+    from: Option<u64>,
+    /// Only payments created before this unix timestamp.
+    to: Option<u64>,
+}
+
+/// Returns the payments created between `from` and `to` as CSV, oldest first. Payments made for
+/// several lockers at once get a row for each locker, with its share as the amount.
+async fn export_payments<Ln: LnBackend>(
+    scope: Scope,
+    Query(query): Query<ExportQuery>,
+    State(state): State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let filter = PaymentFilter {
+        from: query.from,
+        to: query.to,
+        operator_id: scope.0,
+        ..Default::default()
+    };
+
+    let header = csv::row(
+        [
+            "payment_hash",
+            "locker_id",
+            "locker_name",
+            "amount",
+            "status",
+            "created_at",
+            "paid_at",
+            "expired_at",
+            "redeemed_at",
+            "operator_id",
+        ]
+        .map(String::from),
+    );
+
+    let names = locker_names(&state).await?;
+    let rows = futures_util::stream::try_unfold(
+        (state, filter, Some(0)),
+        move |(state, filter, after)| {
+            let names = names.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok(None);
+                };
+
+                let payments = state
+                    .list_payments_after(&filter, after, EXPORT_BATCH_SIZE)
+                    .await?;
+                let Some((last, _)) = payments.last() else {
+                    return Ok(None);
+                };
+
+                let next = (payments.len() as u64 == EXPORT_BATCH_SIZE).then_some(*last);
+                let mut shares: HashMap<i64, Vec<(i64, u64)>> = HashMap::new();
+                for (id, locker_id, amount) in state.list_payment_shares(after, *last).await? {
+                    shares.entry(id).or_default().push((locker_id, amount));
+                }
+
+                let names = &names;
+                let rows = payments
+                    .into_iter()
+                    .flat_map(|(id, payment)| {
+                        let shares = shares
+                            .remove(&id)
+                            .unwrap_or_else(|| vec![(payment.locker_id, payment.amount)]);
+                        shares.into_iter().map(move |(locker_id, amount)| {
+                            csv::row([
+                                payment.payment_hash.clone(),
+                                locker_id.to_string(),
+                                csv::optional(names.get(&locker_id).cloned().flatten()),
+                                amount.to_string(),
+                                payment.status.clone(),
+                                csv::optional(payment.created_at.map(csv::timestamp)),
+                                csv::optional(payment.paid_at.map(csv::timestamp)),
+                                csv::optional(payment.expired_at.map(csv::timestamp)),
+                                csv::optional(payment.redeemed_at.map(csv::timestamp)),
+                                csv::optional(payment.operator_id),
+                            ])
+                        })
+                    })
+                    .collect::<String>();
+
+                Ok(Some((rows, (state, filter, next))))
+            }
+        },
+    );
+
+    Ok(csv_response("payments.csv", header, rows))
+}
+
+/// Returns every usage session as CSV, oldest first.
+async fn export_sessions<Ln: LnBackend>(
+    scope: Scope,
+    State(state): State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let header = csv::row(
+        [
+            "session_id",
+            "locker_id",
+            "locker_name",
+            "started_at",
+            "ended_at",
+            "amount_sat",
+            "payment_hash",
+            "state",
+            "nostr_pubkey",
+        ]
+        .map(String::from),
+    );
+
+    let names = locker_names(&state).await?;
+    let rows = futures_util::stream::try_unfold((state, Some(0)), move |(state, after)| {
+        let names = names.clone();
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+
+            let sessions = state
+                .list_sessions_after(after, EXPORT_BATCH_SIZE, scope.0)
+                .await?;
+            let Some(last) = sessions.last() else {
+                return Ok(None);
+            };
+
+            let next = (sessions.len() as u64 == EXPORT_BATCH_SIZE).then_some(last.id);
+            let rows = sessions
+                .into_iter()
+                .map(|session| {
+                    csv::row([
+                        session.id.to_string(),
+                        session.locker_id.to_string(),
+                        csv::optional(names.get(&session.locker_id).cloned().flatten()),
+                        csv::timestamp(session.started_at),
+                        csv::optional(session.ended_at.map(csv::timestamp)),
+                        csv::optional(session.amount_sat),
+                        csv::optional(session.payment_hash),
+                        session.state,
+                        csv::optional(session.nostr_pubkey),
+                    ])
+                })
+                .collect::<String>();
+
+            Ok(Some((rows, (state, next))))
+        }
+    });
+
+    Ok(csv_response("sessions.csv", header, rows))
+}
+
+/// The name of every locker, by id, so exports don't have to look them up for each row.
+async fn locker_names<Ln: LnBackend>(
+    state: &Server<Ln>,
+) -> Result<Arc<HashMap<i64, Option<String>>>, error::Error> {
+    let lockers = state.list_lockers(true, None).await?;
+    Ok(Arc::new(
+        lockers
+            .into_iter()
+            .map(|locker| (locker.id, locker.name))
+            .collect(),
+    ))
+}
+
+/// Streams a CSV file made of `header` followed by `rows` as a download named `filename`.
+///
+/// By the time a batch of rows fails to load the response has already started, so all we can do
+/// is cut it short.
+fn csv_response(
+    filename: &str,
+    header: String,
+    rows: impl Stream<Item = Result<String, error::Error>> + Send + 'static,
+) -> Response {
+    let body = futures_util::stream::once(async { Ok(header) })
+        .chain(rows)
+        .map_err(|e| {
+            eprintln!("[csv_response] export failed: {e:?}");
+            std::io::Error::other("export failed")
+        });
+
+    axum::http::Response::builder()
+        .header(axum::http::header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            axum::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn summaries_leave_out_secrets() {
+        let invoice = format!("lnbc10u1p{}", "q".repeat(200));
+        let body = serde_json::json!({
+            "reason": "refunded",
+            "hmac_secret": "42".repeat(32),
+            "payments": [{"bolt11": invoice, "preimage": "ab".repeat(32)}],
+        });
+
+        let summary = super::summarize_body(body.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            summary,
+            r#"{"hmac_secret":"[redacted]","payments":[{"bolt11":"lnbc10u1pqqqqqqq…","preimage":"[redacted]"}],"reason":"refunded"}"#
+        );
+
+        assert_eq!(super::summarize_body(b""), None);
+        assert_eq!(
+            super::summarize_body(b"not json").as_deref(),
+            Some("8 bytes")
+        );
+
+        let long = serde_json::json!({ "reason": "a".repeat(1000) }).to_string();
+        let summary = super::summarize_body(long.as_bytes()).unwrap();
+        assert_eq!(summary.chars().count(), super::MAX_SUMMARY_CHARS + 1);
+        assert!(summary.ends_with('…'));
+    }
+}
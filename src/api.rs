@@ -0,0 +1,345 @@
+//! The envelope every JSON response is wrapped in: `{"data": ..., "error": null}` when the request
+//! succeeded, and `{"data": null, "error": {"code": "...", "message": "..."}}` when it didn't, see
+//! [crate::error::Error]. A few errors say more under `data`, like the state of a locker that's
+//! busy. The errors axum answers on its own are wrapped too, see [wrap_rejections].
+//!
+//! The only responses that aren't wrapped are read by something expecting another shape:
+//! `/.well-known/locker-server.json`, `/openapi.json`, the LNURL callback wallets read and the
+//! files staff download.
+//!
+//! JSON goes out as [JSON_CONTENT_TYPE], and [negotiate] makes sure clients can take what we
+//! answer and that what they send us is JSON.
+
+use axum::extract::Request;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Serialize;
+
+use crate::error;
+use crate::request_id;
+
+/// The `Content-Type` of every JSON response.
+pub const JSON_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+/// A response in the envelope, see the module documentation.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiResponse<T> {
+    pub data: Option<T>,
+    /// How many entries match in total, for lists returned a page at a time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    pub error: Option<ApiError>,
+}
+
+/// Why a request failed, as told to the client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiError {
+    /// What went wrong, for programs, like `not_found`. Codes never change once they're out,
+    /// unlike messages.
+    pub code: &'static str,
+    /// What went wrong, for people.
+    pub message: String,
+    /// The id of the request, for matching what the client saw with what we logged, see
+    /// [crate::request_id].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        Self {
+            data: Some(data),
+            total: None,
+            error: None,
+        }
+    }
+
+    /// A failure, with what there's more to say about it, if anything, under `data`.
+    pub fn error(code: &'static str, message: impl Into<String>, data: Option<T>) -> Self {
+        Self {
+            data,
+            total: None,
+            error: Some(ApiError {
+                code,
+                message: message.into(),
+                request_id: request_id::current(),
+            }),
+        }
+    }
+
+    pub fn with_total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+}
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+/// Like [axum::Json], but saying the body is UTF-8, for the few responses outside the envelope.
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => ([(header::CONTENT_TYPE, JSON_CONTENT_TYPE)], body).into_response(),
+            Err(e) => {
+                eprintln!("[json] failed to serialize a response: {e:?}");
+                error::Error::Server.into_response()
+            }
+        }
+    }
+}
+
+/// Refuses requests with a body that isn't JSON with a `415`, and those whose `Accept` rules out
+/// what we'd answer with a `406`, and says JSON responses are UTF-8.
+///
+/// Every route but a few downloads answers JSON, so requests that may change something are
+/// refused upfront if their `Accept` rules it out. The rest are checked against the `Content-Type`
+/// of the response, so clients can ask for an image, a CSV file or an event stream alone.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let headers = request.headers();
+    if !safe && has_body(headers) && !is_json(headers.get(header::CONTENT_TYPE)) {
+        return error::Error::UnsupportedMediaType.into_response();
+    }
+    let accept = headers.get(header::ACCEPT).cloned();
+    if !safe && !accepts(accept.as_ref(), "application/json") {
+        return error::Error::NotAcceptable.into_response();
+    }
+
+    let mut response = next.run(request).await;
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next());
+    // errors go out as they are, since they say more than a 406 would
+    let acceptable = !response.status().is_success()
+        || content_type.is_none_or(|content_type| accepts(accept.as_ref(), content_type.trim()));
+    if !acceptable {
+        return error::Error::NotAcceptable.into_response();
+    }
+
+    // anything that still says it's JSON without a charset came from axum or a handler building
+    // its own body
+    if response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value == "application/json")
+    {
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(JSON_CONTENT_TYPE),
+        );
+    }
+    response
+}
+
+/// Whether the request says it has a body.
+fn has_body(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|length| length.trim() != "0")
+}
+
+/// Whether `content_type` is JSON, whatever its parameters.
+fn is_json(content_type: Option<&HeaderValue>) -> bool {
+    content_type
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Whether `accept` lets us answer with `content_type`, like `image/png`. The most specific media
+/// range matching it decides, and it's ruled out if that has `q=0`. Clients that don't send an
+/// `Accept`, or send one we can't read, take anything.
+fn accepts(accept: Option<&HeaderValue>, content_type: &str) -> bool {
+    let ranges = accept
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .filter(|range| !range.trim().is_empty())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if ranges.is_empty() {
+        return true;
+    }
+
+    let (kind, _) = content_type.split_once('/').unwrap_or((content_type, ""));
+    let mut best: Option<(u8, bool)> = None;
+    for range in ranges {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or_default().trim();
+        let specificity = if media.eq_ignore_ascii_case(content_type) {
+            2
+        } else if media
+            .strip_suffix("/*")
+            .is_some_and(|range_kind| range_kind.eq_ignore_ascii_case(kind))
+        {
+            1
+        } else if media == "*/*" {
+            0
+        } else {
+            continue;
+        };
+
+        let acceptable = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .is_none_or(|(_, q)| q.trim().parse::<f32>().map_or(true, |q| q > 0.0));
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, acceptable));
+        }
+    }
+
+    best.is_some_and(|(_, acceptable)| acceptable)
+}
+
+/// Wraps the errors axum answers on its own, like a body that isn't JSON or a route that doesn't
+/// exist, which are plain text, in the envelope, keeping their status and headers.
+pub async fn wrap_rejections(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !response.status().is_client_error() && !response.status().is_server_error() || is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // rejections are a line of text, anything longer isn't one
+    let message = match axum::body::to_bytes(body, 1024).await {
+        Ok(body) if !body.is_empty() => String::from_utf8_lossy(&body).into_owned(),
+        _ => parts
+            .status
+            .canonical_reason()
+            .unwrap_or("Error")
+            .to_string(),
+    };
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let code = rejection_code(parts.status);
+    (parts, ApiResponse::<()>::error(code, message, None)).into_response()
+}
+
+/// The code of an error axum answered on its own, which only its status tells.
+fn rejection_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_body",
+        status if status.is_client_error() => "bad_request",
+        _ => "server_error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    use super::ApiResponse;
+
+    async fn render(response: impl IntoResponse) -> (Option<String>, serde_json::Value) {
+        let response = response.into_response();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn responses_are_wrapped() {
+        let (content_type, body) = render(ApiResponse::ok(vec![1, 2])).await;
+        assert_eq!(content_type.as_deref(), Some(super::JSON_CONTENT_TYPE));
+        assert_eq!(body, serde_json::json!({ "data": [1, 2], "error": null }));
+
+        let (_, body) = render(ApiResponse::ok(()).with_total(0)).await;
+        assert_eq!(body, serde_json::json!({ "data": null, "total": 0, "error": null }));
+
+        let (_, body) = render(ApiResponse::<()>::error("conflict", "Conflict", None)).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "data": null,
+                "error": { "code": "conflict", "message": "Conflict" },
+            })
+        );
+    }
+
+    #[test]
+    fn accept_picks_the_most_specific_range() {
+        let accepts = |accept: &'static str, content_type| {
+            super::accepts(Some(&HeaderValue::from_static(accept)), content_type)
+        };
+
+        assert!(super::accepts(None, "application/json"));
+        assert!(accepts("", "application/json"));
+        assert!(accepts("*/*", "application/json"));
+        assert!(accepts("application/*", "application/json"));
+        assert!(accepts("text/html, application/json;q=0.5", "application/json"));
+        assert!(accepts("Application/JSON", "application/json"));
+
+        assert!(!accepts("text/html", "application/json"));
+        assert!(!accepts("application/json;q=0, */*", "application/json"));
+        assert!(!accepts("image/*", "application/json"));
+        assert!(accepts("image/*", "image/png"));
+        assert!(accepts("image/*;q=0, image/png", "image/png"));
+        assert!(!accepts("image/*;q=0, image/png", "image/svg+xml"));
+    }
+
+    #[test]
+    fn bodies_must_be_json() {
+        for content_type in [
+            "application/json",
+            "application/json; charset=utf-8",
+            "APPLICATION/JSON",
+        ] {
+            assert!(super::is_json(Some(&HeaderValue::from_static(content_type))));
+        }
+        for content_type in [
+            "text/plain",
+            "application/x-www-form-urlencoded",
+            "application/jsonx",
+        ] {
+            assert!(!super::is_json(Some(&HeaderValue::from_static(content_type))));
+        }
+        assert!(!super::is_json(None));
+    }
+
+    #[test]
+    fn rejections_have_codes() {
+        for (status, code) in [
+            (StatusCode::NOT_FOUND, "not_found"),
+            (StatusCode::UNPROCESSABLE_ENTITY, "invalid_body"),
+            (StatusCode::IM_A_TEAPOT, "bad_request"),
+            (StatusCode::BAD_GATEWAY, "server_error"),
+        ] {
+            assert_eq!(super::rejection_code(status), code);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Paying for several lockers with a single invoice, for customers who rent a few at once.
+//!
+//! `POST /pay_for_usage_batch` takes the ids of lockers the caller is using, bills each of their
+//! sessions as `/pay_for_usage` would, and invoices the sum. The payment is linked to every
+//! session it covers, and once it settles `/payments/{payment_hash}/receipts` hands out one
+//! receipt for each locker, see [crate::issue_receipts].
+//!
+//! Only customers who claimed the lockers while logged in, see [crate::customer], can pay for
+//! them together: that's how we know the sessions are all theirs. The lockers must also belong to
+//! the same operator, since each payment is settled with a single one, see
+//! [crate::OperatorAccount].
+
+use std::sync::Arc;
+
+use axum::extract::OriginalUri;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Method;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::api::ApiResponse;
+use crate::billable_usage;
+use crate::customer;
+use crate::error;
+use crate::ln;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::Owned;
+use crate::Server;
+use crate::UsageSession;
+
+/// How many lockers can be paid for with a single invoice.
+pub const MAX_LOCKERS: usize = 10;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PayForUsageBatch {
+    locker_ids: Vec<i64>,
+}
+
+/// What a locker costs in a batch payment.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchItem {
+    pub locker_id: i64,
+    pub session_id: i64,
+    /// How long the locker was used for, in seconds.
+    pub lease_time: u64,
+    /// In satoshis.
+    pub amount: u64,
+}
+
+/// What `/pay_for_usage_batch` answers: one invoice for the sum of what every locker costs.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchInvoiceResponse {
+    pub lockers: Vec<BatchItem>,
+    /// In satoshis.
+    pub amount: u64,
+    pub invoice: ln::Invoice,
+}
+
+/// Invoices the usage of several lockers at once, see the module documentation.
+pub async fn pay_for_usage_batch<Ln: LnBackend>(
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<PayForUsageBatch>,
+) -> Result<ApiResponse<BatchInvoiceResponse>, error::Error> {
+    let locker_ids = &body.locker_ids;
+    if locker_ids.is_empty() || locker_ids.len() > MAX_LOCKERS {
+        return Err(error::Error::InvalidParam(format!(
+            "locker_ids: expected between 1 and {MAX_LOCKERS} lockers"
+        )));
+    }
+    if let Some((_, locker_id)) = locker_ids
+        .iter()
+        .enumerate()
+        .find(|(i, locker_id)| locker_ids[..*i].contains(locker_id))
+    {
+        return Err(error::Error::InvalidParam(format!(
+            "locker_ids: locker {locker_id} is listed twice"
+        )));
+    }
+
+    let linking_key = customer::customer(&state, &headers).await?;
+    let nostr_pubkey = customer::nostr_user(&state, &method, &uri, &headers)?;
+    if linking_key.is_none() && nostr_pubkey.is_none() {
+        return Err(error::Error::Unauthorized);
+    }
+    let is_callers = |session: &UsageSession| {
+        (linking_key.is_some() && session.linking_key == linking_key)
+            || (nostr_pubkey.is_some() && session.nostr_pubkey == nostr_pubkey)
+    };
+
+    let now = now();
+    let mut items = Vec::new();
+    let mut operator_id = None;
+    for &locker_id in locker_ids {
+        let locker = state.get_locker(locker_id).await?;
+        if locker.state != "in_use" && locker.state != "awaiting_payment" {
+            return Err(error::Error::Conflict(format!("Nobody is using locker {locker_id}")));
+        }
+
+        let operator = state.operator_of(Owned::Locker(locker_id)).await?;
+        if *operator_id.get_or_insert(operator) != operator {
+            return Err(error::Error::Conflict(
+                "Lockers of different operators can't be paid for together".to_string(),
+            ));
+        }
+
+        let session = state.get_active_session(locker_id).await?;
+        if !is_callers(&session) {
+            return Err(error::Error::Conflict(format!(
+                "Locker {locker_id} is used by someone else"
+            )));
+        }
+
+        let (lease_time, amount) = billable_usage(&state, &locker, &session, now);
+        items.push(BatchItem {
+            locker_id,
+            session_id: session.id,
+            lease_time,
+            amount,
+        });
+    }
+
+    let amount = items.iter().map(|item| item.amount).sum();
+    let invoice = state
+        .ln
+        .get_invoice(amount)
+        .map_err(|_| error::Error::LnBackend)?;
+
+    let sessions: Vec<_> = items
+        .iter()
+        .map(|item| (item.locker_id, item.session_id, item.amount))
+        .collect();
+    state
+        .insert_batch_payment(amount, &invoice.payment_hash, &invoice.bolt11, &sessions)
+        .await?;
+    for item in &items {
+        state
+            .record_event(
+                item.locker_id,
+                "invoiced",
+                "customer",
+                serde_json::json!({
+                    "session_id": item.session_id,
+                    "payment_hash": invoice.payment_hash,
+                    "amount": item.amount,
+                    "batch_amount": amount,
+                }),
+            )
+            .await;
+    }
+
+    Ok(ApiResponse::ok(BatchInvoiceResponse {
+        lockers: items,
+        amount,
+        invoice,
+    }))
+}
@@ -0,0 +1,756 @@
+//! Runtime configuration, read from environment variables and command line flags at startup.
+
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use axum::http::header;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::FromHex;
+use secp256k1::XOnlyPublicKey;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::db;
+use crate::secret::Secret;
+use crate::AuthMode;
+use crate::LockerSize;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The bearer tokens the `/admin` routes accept. If there are none, admin routes are
+    /// disabled.
+    ///
+    /// Read from `ADMIN_TOKEN`, a single [AdminRole::Owner] token with the id `default`, and
+    /// `ADMIN_TOKENS`, a comma separated list of `id:sha256:role:operator_id` entries, `sha256`
+    /// being the hex SHA-256 of a token, `role` defaulting to [AdminRole::Owner] and
+    /// `operator_id`, if given, the operator the token is scoped to, see
+    /// [AdminToken::operator_id].
+    pub admin_tokens: Vec<AdminToken>,
+
+    /// How often, in seconds, we look for pending payments whose invoice expired.
+    ///
+    /// Read from `CLEANUP_INTERVAL_SECONDS`, defaults to five minutes.
+    pub cleanup_interval_seconds: u64,
+
+    /// Expired payments older than this many days are deleted. If unset, we keep them forever.
+    ///
+    /// Read from `EXPIRED_PAYMENT_RETENTION_DAYS`.
+    pub expired_payment_retention_days: Option<u64>,
+
+    /// Where to periodically write database backups. If unset, we don't make any.
+    ///
+    /// Read from `BACKUP_DIR` or the `--backup-dir` flag, the flag taking precedence.
+    pub backup_dir: Option<PathBuf>,
+
+    /// How often, in seconds, we write a backup to [Config::backup_dir].
+    ///
+    /// Read from `BACKUP_INTERVAL_SECONDS`, defaults to once a day.
+    pub backup_interval_seconds: u64,
+
+    /// How often, in seconds, we checkpoint and optimize the database.
+    ///
+    /// Read from `MAINTENANCE_INTERVAL_SECONDS`, defaults to once a day.
+    pub maintenance_interval_seconds: u64,
+
+    /// The hour of the day, in UTC, at which the first maintenance run happens. The following ones
+    /// happen every [Config::maintenance_interval_seconds] after it, or at this hour again after
+    /// a run that had to be put off.
+    ///
+    /// Read from `MAINTENANCE_HOUR`, defaults to 4.
+    pub maintenance_hour: u64,
+
+    /// How often, in seconds, we check the lightning backend is reachable. `/health` reports the
+    /// last answer rather than asking the backend on every probe.
+    ///
+    /// Read from `LN_CHECK_INTERVAL_SECONDS`, defaults to a minute.
+    pub ln_check_interval_seconds: u64,
+
+    /// Whether `/health` fails while the lightning backend is unreachable. If not, the server is
+    /// reported as `degraded`, since it can still serve everything that doesn't need a payment.
+    ///
+    /// Read from `HEALTH_REQUIRES_LN`, defaults to true.
+    pub health_requires_ln: bool,
+
+    /// How long, in seconds, the signatures and tokens we hand out for opening a locker stay
+    /// valid.
+    ///
+    /// Read from `TOKEN_TTL_SECONDS`, defaults to 15 minutes.
+    pub token_ttl_seconds: u64,
+
+    /// Until when, as a unix timestamp, to keep signing receipts' authorizations the way firmware
+    /// from before they expired expects, and to accept open reports that don't say when their
+    /// authorization expires, see [Config::legacy_signatures]. Meant to be set while the lockers
+    /// are being updated: those signatures don't commit to anything, so any of them opens any
+    /// locker running old firmware, and such reports can be replayed.
+    ///
+    /// Turned on with `LEGACY_SIGNATURES`, which defaults to false, and then read from
+    /// `LEGACY_SIGNATURES_UNTIL`, which must be set.
+    pub legacy_signatures_until: Option<u64>,
+
+    /// Whether `/use_locker` and `/pay_for_usage` still answer GET requests, marked deprecated,
+    /// for kiosks that weren't updated to POST yet. Otherwise they get a `405`.
+    ///
+    /// Read from `LEGACY_GET_MUTATIONS`, defaults to false.
+    pub legacy_get_mutations: bool,
+
+    /// Whether `/payment_receipt/{payment_hash}` still answers, marked deprecated, for clients
+    /// that weren't moved to `/payments/{payment_hash}/receipt` yet. Otherwise it's a `404`.
+    ///
+    /// Read from `LEGACY_RECEIPT_PATH`, defaults to true.
+    pub legacy_receipt_path: bool,
+
+    /// Whether the API still answers without a version prefix, as it did before `/v1`, marked
+    /// deprecated, for clients that weren't moved to the prefixed paths yet. Otherwise it's a
+    /// `404`.
+    ///
+    /// Read from `LEGACY_UNPREFIXED_PATHS`, defaults to true.
+    pub legacy_unprefixed_paths: bool,
+
+    /// How many seconds a locker's clock may be off from ours, either way, before we reject its
+    /// reports of being opened, since we can't tell whether they're fresh.
+    ///
+    /// Read from `OPEN_REPORT_MAX_DRIFT_SECONDS`, defaults to 5 minutes.
+    pub open_report_max_drift_seconds: u64,
+
+    /// How many times a minute each locker may ask us to verify a token.
+    ///
+    /// Read from `VERIFY_TOKEN_RATE_LIMIT`, defaults to 60.
+    pub verify_token_rate_limit: u32,
+
+    /// How many times a minute anyone may ask for the receipt of each payment, however many
+    /// clients they're spread over, since kiosks share one address between many customers.
+    ///
+    /// Read from `RECEIPT_POLL_RATE_LIMIT`, defaults to 30.
+    pub receipt_poll_rate_limit: u32,
+
+    /// How many seconds we remember that a payment wasn't settled yet, answering polls for its
+    /// receipt without asking the lightning backend again. Zero asks every time.
+    ///
+    /// Read from `RECEIPT_POLL_CACHE_SECONDS`, defaults to 2.
+    pub receipt_poll_cache_seconds: u64,
+
+    /// How many times a minute each client may call the cheap public routes, like `/lockers`.
+    ///
+    /// Read from `READ_RATE_LIMIT`, defaults to 120.
+    pub read_rate_limit: u32,
+
+    /// How many times a minute each client may call the routes that claim lockers or reach the
+    /// lightning backend, like `/pay_for_usage` and `/payments/{payment_hash}/receipt`.
+    ///
+    /// Read from `EXPENSIVE_RATE_LIMIT`, defaults to 20.
+    pub expensive_rate_limit: u32,
+
+    /// Whether we're behind a proxy we trust to tell us who the client is in `X-Forwarded-For`,
+    /// rather than limiting everyone by the proxy's address. Must not be set otherwise, as clients
+    /// could then pick any address they like.
+    ///
+    /// Read from `TRUST_FORWARDED_FOR`, defaults to false.
+    pub trust_forwarded_for: bool,
+
+    /// The certificate we serve HTTPS with, see [crate::tls]. If unset, we serve plain HTTP,
+    /// leaving TLS to a reverse proxy if there's one.
+    ///
+    /// Read from `TLS_CERT_FILE`, `TLS_KEY_FILE` and `HTTP_REDIRECT_ADDRESS`.
+    pub tls: Option<TlsConfig>,
+
+    /// Which web pages, like the ones running on kiosks, may call us from a browser.
+    ///
+    /// Read from `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` and
+    /// `CORS_ALLOW_PRIVATE_NETWORK`.
+    pub cors: CorsConfig,
+
+    /// The largest request body we accept, in bytes. Everything we're sent is a small JSON
+    /// object, so anything much larger is someone trying to tie up our memory.
+    ///
+    /// Read from `MAX_BODY_BYTES`, defaults to 4 KiB.
+    pub max_body_bytes: usize,
+
+    /// What using a locker costs, in millisatoshis per minute, for lockers whose site doesn't set
+    /// its own price.
+    ///
+    /// Read from `PRICE_PER_MINUTE_MSAT`, defaults to 60000, one satoshi per second.
+    pub price_per_minute_msat: u64,
+
+    /// What customers pay, in satoshis, to back out of a session after the locker was already
+    /// opened for it, see `/cancel_usage`.
+    ///
+    /// Read from `CANCEL_FEE_SAT`, defaults to 10.
+    pub cancel_fee_sat: u64,
+
+    /// Whether the consistency check we run at startup repairs what it finds, rather than only
+    /// logging it. See [crate::consistency].
+    ///
+    /// Read from `REPAIR_INCONSISTENCIES`, defaults to false.
+    pub repair_inconsistencies: bool,
+
+    /// Sessions older than this many hours that nobody paid for are considered abandoned, and
+    /// their locker released by the consistency check. If unset, sessions are never abandoned.
+    ///
+    /// Read from `STALE_SESSION_HOURS`.
+    pub stale_session_hours: Option<u64>,
+
+    /// The secret key the server signs with, as hex or WIF. If unset, it's read from
+    /// [Config::server_key_file] instead. See [crate::keys].
+    ///
+    /// Read from `SERVER_KEY`.
+    pub server_key: Option<Secret<String>>,
+
+    /// Where the secret key the server signs with is kept, generated on first run if it doesn't
+    /// exist. Only used if [Config::server_key] is unset.
+    ///
+    /// Read from `SERVER_KEY_FILE`, defaults to `server.key`.
+    pub server_key_file: PathBuf,
+
+    /// Every key the server has signed with, for rotating keys without breaking the lockers that
+    /// still trust the previous one. If set, [Config::server_key] and [Config::server_key_file]
+    /// are ignored.
+    ///
+    /// Read from the file at `SERVER_KEYRING`, see `keyring.example.toml`.
+    pub server_keyring: Option<KeyringConfig>,
+
+    /// Whether to sign every response, rather than only those to requests asking for it with
+    /// `X-Sign-Response`, see [crate::sign_response].
+    ///
+    /// Read from `SIGN_RESPONSES`, defaults to false.
+    pub sign_responses: bool,
+
+    /// Whether every locker must send its device token on the routes meant for lockers, even the
+    /// ones that were never issued one, see [crate::device]. Meant to be set once every locker has
+    /// a token.
+    ///
+    /// Read from `REQUIRE_DEVICE_TOKENS`, defaults to false.
+    pub require_device_tokens: bool,
+
+    /// The key installers sign new lockers' public keys with, letting them register through
+    /// `/provision/register`. If unset, lockers can only register with a one-time code from staff,
+    /// see [crate::enrollment], or be added through [Config::lockers].
+    ///
+    /// Read from `PROVISIONING_PUBKEY`, an x-only public key in hex.
+    pub provisioning_pubkey: Option<XOnlyPublicKey>,
+
+    /// How long, in seconds, the one-time codes staff hand out for registering a locker stay
+    /// valid, unless they ask for another expiry, see [crate::enrollment].
+    ///
+    /// Read from `PROVISIONING_CODE_TTL_SECONDS`, defaults to a day.
+    pub provisioning_code_ttl_seconds: u64,
+
+    /// The URL customers and their wallets reach us at, like `https://lockers.example.com`, which
+    /// we need to tell wallets where to log in. If unset, customers can't log in and only use the
+    /// lockers anonymously.
+    ///
+    /// Read from `PUBLIC_URL`.
+    pub public_url: Option<String>,
+
+    /// How long, in seconds, customers stay logged in after their wallet signs in.
+    ///
+    /// Read from `CUSTOMER_SESSION_SECONDS`, defaults to 30 days.
+    pub customer_session_seconds: u64,
+
+    /// How long, in seconds, a locker stays reserved for whoever reserved it, see
+    /// [crate::reservation].
+    ///
+    /// Read from `RESERVATION_SECONDS`, defaults to ten minutes.
+    pub reservation_seconds: u64,
+
+    /// How long, in seconds, we keep the response to a request made with an `Idempotency-Key`, to
+    /// give it again to retries, see [crate::idempotency].
+    ///
+    /// Read from `IDEMPOTENCY_TTL_SECONDS`, defaults to a day.
+    pub idempotency_ttl_seconds: u64,
+
+    /// How many times we try delivering a webhook before giving up on it, see [crate::webhook].
+    ///
+    /// Read from `WEBHOOK_MAX_ATTEMPTS`, defaults to 8.
+    pub webhook_max_attempts: u32,
+
+    /// How long, in seconds, we wait before trying a failed webhook delivery again the first time,
+    /// doubling after every failure.
+    ///
+    /// Read from `WEBHOOK_RETRY_SECONDS`, defaults to 10.
+    pub webhook_retry_seconds: u64,
+
+    /// Where the sqlite database lives.
+    ///
+    /// Read from `DATABASE_PATH`, defaults to an in-memory database that is lost on restart.
+    pub database_path: String,
+
+    /// How we set up each database connection.
+    ///
+    /// Read from `SQLITE_JOURNAL_MODE`, `SQLITE_SYNCHRONOUS`, `SQLITE_BUSY_TIMEOUT_MS` and
+    /// `SQLITE_READ_CONNECTIONS`.
+    pub database: db::Options,
+
+    /// The lockers we control, synced into the database at startup.
+    ///
+    /// Read from the `[[lockers]]` entries of the file at `LOCKERS_CONFIG`, defaults to
+    /// `lockers.toml`. If the file doesn't exist, we only know the lockers already in the
+    /// database.
+    pub lockers: Vec<LockerConfig>,
+}
+
+/// A locker, as described in the lockers configuration file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LockerConfig {
+    /// The x-only public key the locker signs its reports with, in hex. Lockers using
+    /// [AuthMode::Hmac] don't have one, for them it's any name that tells them apart, like the
+    /// serial number of their controller.
+    pub pk: String,
+    pub name: Option<String>,
+    pub location: Option<String>,
+    pub size: Option<LockerSize>,
+    pub description: Option<String>,
+    /// How the locker authenticates, defaults to [AuthMode::Schnorr].
+    pub auth_mode: Option<AuthMode>,
+    /// The secret shared with a locker using [AuthMode::Hmac], in hex.
+    pub hmac_secret: Option<Secret<String>>,
+}
+
+/// What we let pages served from other origins do, see
+/// <https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS>.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// The origins allowed to call us, like `https://kiosk.example.com`, or `*` for any. If empty,
+    /// browsers only let pages we serve ourselves call us.
+    pub allowed_origins: Vec<String>,
+    /// Defaults to `GET` and `POST`.
+    pub allowed_methods: Vec<Method>,
+    /// The request headers pages may set, defaults to `Content-Type` and `Authorization`.
+    pub allowed_headers: Vec<HeaderName>,
+    /// Whether pages on the internet may call us when we're on a private network, defaults to
+    /// false.
+    pub allow_private_network: bool,
+}
+
+/// Where to find the certificate we serve HTTPS with, see [Config::tls].
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// The certificate chain, in PEM, starting with our own certificate.
+    pub cert_file: PathBuf,
+    /// The certificate's private key, in PEM.
+    pub key_file: PathBuf,
+    /// Where to also answer plain HTTP, like `0.0.0.0:80`, redirecting every request to HTTPS. If
+    /// unset, we only answer HTTPS.
+    pub redirect_address: Option<String>,
+}
+
+/// A token accepted by the `/admin` routes. We only keep its hash, so the configuration doesn't
+/// need to hold the token itself.
+#[derive(Debug, Clone)]
+pub struct AdminToken {
+    /// Identifies whoever holds the token in the audit log.
+    pub id: String,
+    pub sha256: [u8; 32],
+    pub role: AdminRole,
+    /// The operator whose lockers, and everything about them, the token is limited to, see
+    /// [crate::admin::Scope]. Tokens without one are super-admins, who see every operator's.
+    pub operator_id: Option<i64>,
+}
+
+/// What an admin token lets its holder do, each role allowing everything the ones before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminRole {
+    /// Can only look, at the lockers, payments, stats and audit log.
+    Viewer,
+    /// Can also run the lockers day to day: edit and price them, take them out of service, cancel
+    /// payments and revoke receipts.
+    Operator,
+    /// Can also manage credentials: rotate lockers' keys, issue device tokens and provisioning
+    /// codes, set up webhooks and download backups.
+    Owner,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::Viewer => "viewer",
+            AdminRole::Operator => "operator",
+            AdminRole::Owner => "owner",
+        }
+    }
+}
+
+impl FromStr for AdminRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(AdminRole::Viewer),
+            "operator" => Ok(AdminRole::Operator),
+            "owner" => Ok(AdminRole::Owner),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The server keys, as described in the keyring file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyringConfig {
+    /// The id of the key we sign with.
+    pub active: String,
+    pub keys: Vec<KeyConfig>,
+}
+
+/// A server key, as described in the keyring file. Its secret is either given inline or kept in
+/// a file of its own, which is generated if it doesn't exist.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyConfig {
+    /// What we call the key in the `kid` of receipts and tokens.
+    pub id: String,
+    pub secret: Option<Secret<String>>,
+    pub secret_file: Option<PathBuf>,
+    /// When lockers should start trusting the key, as a unix timestamp.
+    pub not_before: Option<u64>,
+    /// When lockers should stop trusting the key, as a unix timestamp.
+    pub not_after: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockersFile {
+    #[serde(default)]
+    lockers: Vec<LockerConfig>,
+}
+
+impl Config {
+    /// Reads the configuration from the environment and command line, panicking if anything is
+    /// set to something we can't parse.
+    pub fn load() -> Self {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let defaults = db::Options::default();
+
+        Self {
+            admin_tokens: admin_tokens(),
+            cleanup_interval_seconds: parse_var("CLEANUP_INTERVAL_SECONDS").unwrap_or(300),
+            expired_payment_retention_days: parse_var("EXPIRED_PAYMENT_RETENTION_DAYS"),
+            backup_dir: flag(&args, "--backup-dir")
+                .or_else(|| env::var("BACKUP_DIR").ok())
+                .map(PathBuf::from),
+            backup_interval_seconds: parse_var("BACKUP_INTERVAL_SECONDS").unwrap_or(24 * 60 * 60),
+            maintenance_interval_seconds: parse_var("MAINTENANCE_INTERVAL_SECONDS")
+                .unwrap_or(24 * 60 * 60),
+            maintenance_hour: match parse_var("MAINTENANCE_HOUR") {
+                Some(hour) if hour > 23 => panic!("invalid value for MAINTENANCE_HOUR: {hour}"),
+                hour => hour.unwrap_or(4),
+            },
+            ln_check_interval_seconds: parse_var("LN_CHECK_INTERVAL_SECONDS").unwrap_or(60),
+            health_requires_ln: parse_var("HEALTH_REQUIRES_LN").unwrap_or(true),
+            token_ttl_seconds: parse_var("TOKEN_TTL_SECONDS").unwrap_or(15 * 60),
+            legacy_signatures_until: legacy_signatures_until(),
+            legacy_get_mutations: parse_var("LEGACY_GET_MUTATIONS").unwrap_or(false),
+            legacy_receipt_path: parse_var("LEGACY_RECEIPT_PATH").unwrap_or(true),
+            legacy_unprefixed_paths: parse_var("LEGACY_UNPREFIXED_PATHS").unwrap_or(true),
+            open_report_max_drift_seconds: parse_var("OPEN_REPORT_MAX_DRIFT_SECONDS")
+                .unwrap_or(5 * 60),
+            verify_token_rate_limit: parse_var("VERIFY_TOKEN_RATE_LIMIT").unwrap_or(60),
+            receipt_poll_rate_limit: parse_var("RECEIPT_POLL_RATE_LIMIT").unwrap_or(30),
+            receipt_poll_cache_seconds: parse_var("RECEIPT_POLL_CACHE_SECONDS").unwrap_or(2),
+            read_rate_limit: parse_var("READ_RATE_LIMIT").unwrap_or(120),
+            expensive_rate_limit: parse_var("EXPENSIVE_RATE_LIMIT").unwrap_or(20),
+            trust_forwarded_for: parse_var("TRUST_FORWARDED_FOR").unwrap_or(false),
+            tls: tls(),
+            cors: cors(),
+            max_body_bytes: parse_var("MAX_BODY_BYTES").unwrap_or(4 * 1024),
+            price_per_minute_msat: parse_var("PRICE_PER_MINUTE_MSAT").unwrap_or(60_000),
+            cancel_fee_sat: parse_var("CANCEL_FEE_SAT").unwrap_or(10),
+            repair_inconsistencies: parse_var("REPAIR_INCONSISTENCIES").unwrap_or(false),
+            stale_session_hours: parse_var("STALE_SESSION_HOURS"),
+            server_key: env::var("SERVER_KEY").ok().map(Secret::new),
+            server_key_file: env::var("SERVER_KEY_FILE")
+                .unwrap_or("server.key".to_string())
+                .into(),
+            server_keyring: env::var("SERVER_KEYRING").ok().map(|path| read_keyring(&path)),
+            sign_responses: parse_var("SIGN_RESPONSES").unwrap_or(false),
+            require_device_tokens: parse_var("REQUIRE_DEVICE_TOKENS").unwrap_or(false),
+            provisioning_pubkey: parse_var("PROVISIONING_PUBKEY"),
+            provisioning_code_ttl_seconds: parse_var("PROVISIONING_CODE_TTL_SECONDS")
+                .unwrap_or(24 * 60 * 60),
+            public_url: public_url(),
+            customer_session_seconds: parse_var("CUSTOMER_SESSION_SECONDS")
+                .unwrap_or(30 * 24 * 60 * 60),
+            reservation_seconds: parse_var("RESERVATION_SECONDS").unwrap_or(10 * 60),
+            idempotency_ttl_seconds: parse_var("IDEMPOTENCY_TTL_SECONDS")
+                .unwrap_or(24 * 60 * 60),
+            webhook_max_attempts: match parse_var("WEBHOOK_MAX_ATTEMPTS") {
+                Some(0) => panic!("invalid value for WEBHOOK_MAX_ATTEMPTS: 0"),
+                attempts => attempts.unwrap_or(8),
+            },
+            webhook_retry_seconds: parse_var("WEBHOOK_RETRY_SECONDS").unwrap_or(10),
+            database_path: env::var("DATABASE_PATH").unwrap_or(":memory:".to_string()),
+            database: db::Options {
+                journal_mode: pragma_var("SQLITE_JOURNAL_MODE").unwrap_or(defaults.journal_mode),
+                synchronous: pragma_var("SQLITE_SYNCHRONOUS").unwrap_or(defaults.synchronous),
+                busy_timeout_ms: parse_var("SQLITE_BUSY_TIMEOUT_MS")
+                    .unwrap_or(defaults.busy_timeout_ms),
+                read_connections: parse_var("SQLITE_READ_CONNECTIONS")
+                    .unwrap_or(defaults.read_connections),
+            },
+            lockers: read_lockers(
+                &env::var("LOCKERS_CONFIG").unwrap_or("lockers.toml".to_string()),
+            ),
+        }
+    }
+
+    /// Whether we still sign and accept what old firmware expects at `now`, see
+    /// [Config::legacy_signatures_until].
+    pub fn legacy_signatures(&self, now: u64) -> bool {
+        self.legacy_signatures_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Parses the environment variable `name`, returning `None` if it isn't set.
+fn parse_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => panic!("invalid value for {name}: {value}"),
+    }
+}
+
+/// The shortest HMAC secret we accept, in bytes.
+const MIN_HMAC_SECRET_BYTES: usize = 16;
+
+/// Reads the lockers out of the configuration file at `path`, panicking if any of them has an
+/// invalid or duplicated public key, or a missing or short HMAC secret.
+fn read_lockers(path: &str) -> Vec<LockerConfig> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => panic!("failed to read {path}: {e}"),
+    };
+
+    let file: LockersFile =
+        toml::from_str(&contents).unwrap_or_else(|e| panic!("invalid lockers file {path}: {e}"));
+
+    let mut seen = HashSet::new();
+    for locker in &file.lockers {
+        match (locker.auth_mode.unwrap_or_default(), &locker.hmac_secret) {
+            (AuthMode::Schnorr, None) => {
+                if XOnlyPublicKey::from_str(&locker.pk).is_err() {
+                    panic!("invalid lockers file {path}: {} isn't an x-only public key", locker.pk);
+                }
+            }
+            (AuthMode::Hmac, Some(secret)) => {
+                let secret = Secret::new(Vec::<u8>::from_hex(secret.expose()).unwrap_or_default());
+                if locker.pk.is_empty() || secret.expose().len() < MIN_HMAC_SECRET_BYTES {
+                    panic!(
+                        "invalid lockers file {path}: {} needs a pk and an hmac_secret of at least \
+                         {MIN_HMAC_SECRET_BYTES} bytes in hex",
+                        locker.pk
+                    );
+                }
+            }
+            (AuthMode::Schnorr, Some(_)) => {
+                panic!("invalid lockers file {path}: {} has an hmac_secret", locker.pk);
+            }
+            (AuthMode::Hmac, None) => {
+                panic!("invalid lockers file {path}: {} needs an hmac_secret", locker.pk);
+            }
+        }
+
+        if !seen.insert(locker.pk.to_lowercase()) {
+            panic!("invalid lockers file {path}: {} is listed twice", locker.pk);
+        }
+    }
+
+    file.lockers
+}
+
+/// Reads the admin tokens out of `ADMIN_TOKEN` and `ADMIN_TOKENS`, panicking if a hash or id is
+/// invalid or an id is used twice.
+fn admin_tokens() -> Vec<AdminToken> {
+    let mut tokens = Vec::new();
+    if let Ok(token) = env::var("ADMIN_TOKEN") {
+        tokens.push(AdminToken {
+            id: "default".to_string(),
+            sha256: sha256::Hash::hash(token.as_bytes()).to_byte_array(),
+            role: AdminRole::Owner,
+            operator_id: None,
+        });
+    }
+
+    for entry in env::var("ADMIN_TOKENS").unwrap_or_default().split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        let Some((id, hash)) = entry.split_once(':') else {
+            panic!("invalid value for ADMIN_TOKENS: {entry} should be id:sha256:role:operator_id");
+        };
+        let (hash, operator_id) = match hash.matches(':').count() {
+            2 => {
+                let (hash, operator_id) = hash.rsplit_once(':').expect("counted the colons");
+                match operator_id.parse::<i64>() {
+                    Ok(operator_id) if operator_id > 0 => (hash, Some(operator_id)),
+                    _ => panic!(
+                        "invalid value for ADMIN_TOKENS: {operator_id} isn't an operator id"
+                    ),
+                }
+            }
+            _ => (hash, None),
+        };
+        let (hash, role) = match hash.split_once(':') {
+            Some((hash, role)) => match role.parse() {
+                Ok(role) => (hash, role),
+                Err(_) => panic!(
+                    "invalid value for ADMIN_TOKENS: {role} isn't viewer, operator or owner"
+                ),
+            },
+            None => (hash, AdminRole::Owner),
+        };
+        let Ok(hash) = sha256::Hash::from_str(hash) else {
+            panic!("invalid value for ADMIN_TOKENS: {hash} isn't a hex SHA-256");
+        };
+
+        if id.is_empty() || tokens.iter().any(|token: &AdminToken| token.id == id) {
+            panic!("invalid value for ADMIN_TOKENS: {id:?} is empty or used twice");
+        }
+
+        tokens.push(AdminToken {
+            id: id.to_string(),
+            sha256: hash.to_byte_array(),
+            role,
+            operator_id,
+        });
+    }
+
+    tokens
+}
+
+/// Reads `PUBLIC_URL`, panicking if it isn't an http or https URL without a trailing slash.
+fn public_url() -> Option<String> {
+    let url = env::var("PUBLIC_URL").ok()?;
+    if !(url.starts_with("http://") || url.starts_with("https://")) || url.ends_with('/') {
+        panic!("invalid value for PUBLIC_URL: {url} should be like https://host");
+    }
+
+    Some(url)
+}
+
+/// Reads when to stop signing and accepting what old firmware expects, panicking if
+/// `LEGACY_SIGNATURES` is on without saying until when, so it can't be left on for good.
+fn legacy_signatures_until() -> Option<u64> {
+    if !parse_var("LEGACY_SIGNATURES").unwrap_or(false) {
+        return None;
+    }
+
+    match parse_var("LEGACY_SIGNATURES_UNTIL") {
+        Some(until) => Some(until),
+        None => panic!("LEGACY_SIGNATURES needs LEGACY_SIGNATURES_UNTIL, the unix time to stop at"),
+    }
+}
+
+/// Reads the TLS settings, panicking if only one of the certificate and its key is set, or if we're
+/// asked to redirect to HTTPS without serving it.
+fn tls() -> Option<TlsConfig> {
+    let redirect_address = env::var("HTTP_REDIRECT_ADDRESS").ok();
+    match (env::var("TLS_CERT_FILE").ok(), env::var("TLS_KEY_FILE").ok()) {
+        (Some(cert_file), Some(key_file)) => Some(TlsConfig {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+            redirect_address,
+        }),
+        (None, None) if redirect_address.is_some() => {
+            panic!("HTTP_REDIRECT_ADDRESS needs TLS_CERT_FILE and TLS_KEY_FILE")
+        }
+        (None, None) => None,
+        _ => panic!("TLS_CERT_FILE and TLS_KEY_FILE must be set together"),
+    }
+}
+
+/// Reads the CORS settings, panicking if an origin, method or header is invalid.
+fn cors() -> CorsConfig {
+    let allowed_origins = list_var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
+    for origin in &allowed_origins {
+        let is_origin = origin == "*"
+            || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/')
+                && HeaderValue::from_str(origin).is_ok());
+        if !is_origin {
+            panic!("invalid value for CORS_ALLOWED_ORIGINS: {origin} should be like https://host");
+        }
+    }
+
+    if allowed_origins.len() > 1 && allowed_origins.iter().any(|origin| origin == "*") {
+        panic!("invalid value for CORS_ALLOWED_ORIGINS: * can't be listed with other origins");
+    }
+
+    let allowed_methods = match list_var("CORS_ALLOWED_METHODS") {
+        Some(methods) => methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase())
+                    .unwrap_or_else(|_| panic!("invalid value for CORS_ALLOWED_METHODS: {method}"))
+            })
+            .collect(),
+        None => vec![Method::GET, Method::POST],
+    };
+
+    let allowed_headers = match list_var("CORS_ALLOWED_HEADERS") {
+        Some(headers) => headers
+            .iter()
+            .map(|header| {
+                HeaderName::from_str(header)
+                    .unwrap_or_else(|_| panic!("invalid value for CORS_ALLOWED_HEADERS: {header}"))
+            })
+            .collect(),
+        None => vec![header::CONTENT_TYPE, header::AUTHORIZATION],
+    };
+
+    CorsConfig {
+        allowed_origins,
+        allowed_methods,
+        allowed_headers,
+        allow_private_network: parse_var("CORS_ALLOW_PRIVATE_NETWORK").unwrap_or(false),
+    }
+}
+
+/// Reads the keyring file at `path`, panicking if it can't be read or parsed. Whether the keys in
+/// it make sense together is checked when loading them, see [crate::keys::load].
+fn read_keyring(path: &str) -> KeyringConfig {
+    let contents =
+        std::fs::read_to_string(path).unwrap_or_else(|e| panic!("failed to read {path}: {e}"));
+
+    toml::from_str(&contents).unwrap_or_else(|e| panic!("invalid keyring file {path}: {e}"))
+}
+
+/// Reads the comma separated list in the environment variable `name`, returning `None` if it isn't
+/// set.
+fn list_var(name: &str) -> Option<Vec<String>> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Reads a pragma value, which ends up in an sqlite statement and so must be a plain word.
+fn pragma_var(name: &str) -> Option<String> {
+    let value = env::var(name).ok()?;
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+        panic!("invalid value for {name}: {value}");
+    }
+
+    Some(value)
+}
+
+/// Returns the value of a `--name value` or `--name=value` command line flag.
+fn flag(args: &[String], name: &str) -> Option<String> {
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            return Some(args.get(i + 1).unwrap_or_else(|| panic!("{name} needs a value")).clone());
+        }
+
+        arg.strip_prefix(name)?.strip_prefix('=').map(str::to_string)
+    })
+}
@@ -0,0 +1,181 @@
+//! Finds lockers, sessions and payments that disagree with each other, which can happen if we
+//! crash halfway through a flow, and optionally puts them back in order.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::error::StoreError;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::Server;
+
+/// Something that doesn't add up between a locker and its sessions or payments.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// The locker is in use, but nobody has a session for it. Repaired by releasing the locker.
+    InUseWithoutSession { locker_id: i64 },
+    /// The locker was claimed longer ago than [crate::config::Config::stale_session_hours] and
+    /// nobody is paying for it, so whoever claimed it walked away. Repaired by closing the session
+    /// and releasing the locker.
+    StaleSession {
+        locker_id: i64,
+        session_id: i64,
+        started_at: u64,
+    },
+    /// The session is still open, but its locker is available again. Repaired by closing the
+    /// session.
+    SessionOnAvailableLocker { locker_id: i64, session_id: i64 },
+    /// We're still waiting on a payment for a locker that is available again. Repaired by
+    /// cancelling the payment, so it gets flagged for a refund if it's paid anyway.
+    PendingPaymentOnAvailableLocker {
+        locker_id: i64,
+        payment_hash: String,
+    },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::InUseWithoutSession { locker_id } => {
+                write!(f, "locker {locker_id} is in use without a session")
+            }
+            Anomaly::StaleSession {
+                locker_id,
+                session_id,
+                started_at,
+            } => write!(
+                f,
+                "locker {locker_id} has been in use since {started_at} by session {session_id}, which nobody paid for"
+            ),
+            Anomaly::SessionOnAvailableLocker {
+                locker_id,
+                session_id,
+            } => write!(
+                f,
+                "session {session_id} is still open, but locker {locker_id} is available"
+            ),
+            Anomaly::PendingPaymentOnAvailableLocker {
+                locker_id,
+                payment_hash,
+            } => write!(
+                f,
+                "payment {payment_hash} is still pending, but locker {locker_id} is available"
+            ),
+        }
+    }
+}
+
+/// An anomaly we found, and whether we repaired it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    #[serde(flatten)]
+    pub anomaly: Anomaly,
+    pub repaired: bool,
+}
+
+/// Looks for anomalies, logging each one, and repairs them if `fix` is set.
+///
+/// Repairs are made one at a time and each one only applies if the anomaly is still there, so
+/// this is safe to run while customers use the lockers.
+pub async fn check<Ln: LnBackend>(
+    state: &Server<Ln>,
+    fix: bool,
+) -> Result<Vec<Finding>, StoreError> {
+    let now = now();
+    let stale_before = state
+        .config
+        .stale_session_hours
+        .map(|hours| now.saturating_sub(hours.saturating_mul(60 * 60)));
+
+    let mut findings = Vec::new();
+    for anomaly in state.find_anomalies(stale_before).await? {
+        let repaired = fix && repair(state, &anomaly, now).await?;
+        println!(
+            "[consistency] {anomaly}{}",
+            if repaired { ", repaired" } else { "" }
+        );
+
+        findings.push(Finding { anomaly, repaired });
+    }
+
+    Ok(findings)
+}
+
+/// Repairs a single anomaly, returning whether it was still there to be repaired.
+async fn repair<Ln: LnBackend>(
+    state: &Server<Ln>,
+    anomaly: &Anomaly,
+    now: u64,
+) -> Result<bool, StoreError> {
+    let (locker_id, event, mut details) = match anomaly {
+        Anomaly::InUseWithoutSession { locker_id } => {
+            if !state.release_locker(*locker_id, None, now).await? {
+                return Ok(false);
+            }
+
+            (*locker_id, "released", serde_json::json!({}))
+        }
+        Anomaly::StaleSession {
+            locker_id,
+            session_id,
+            ..
+        } => {
+            if !state
+                .release_locker(*locker_id, Some(*session_id), now)
+                .await?
+            {
+                return Ok(false);
+            }
+
+            (
+                *locker_id,
+                "released",
+                serde_json::json!({ "session_id": session_id }),
+            )
+        }
+        Anomaly::SessionOnAvailableLocker {
+            locker_id,
+            session_id,
+        } => {
+            if !state.close_session(*session_id, now).await? {
+                return Ok(false);
+            }
+
+            (
+                *locker_id,
+                "session_closed",
+                serde_json::json!({ "session_id": session_id }),
+            )
+        }
+        Anomaly::PendingPaymentOnAvailableLocker {
+            locker_id,
+            payment_hash,
+        } => {
+            if !state.cancel_payment(payment_hash, now).await? {
+                return Ok(false);
+            }
+
+            let invoice_cancelled = state
+                .ln
+                .cancel_invoice(payment_hash.clone())
+                .unwrap_or(false);
+            (
+                *locker_id,
+                "payment_cancelled",
+                serde_json::json!({
+                    "payment_hash": payment_hash,
+                    "invoice_cancelled": invoice_cancelled,
+                }),
+            )
+        }
+    };
+
+    details["reason"] = serde_json::Value::String(anomaly.to_string());
+    state
+        .record_event(locker_id, event, "server", details)
+        .await;
+
+    Ok(true)
+}
@@ -0,0 +1,84 @@
+//! Just enough CSV for the exports under `/admin/export`, as described in RFC 4180.
+
+/// Builds a single CSV line out of `fields`, quoting the ones that need it.
+pub fn row<I: IntoIterator<Item = String>>(fields: I) -> String {
+    let mut row = fields
+        .into_iter()
+        .map(|field| escape(&field))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    row.push_str("\r\n");
+    row
+}
+
+/// Quotes a field if it contains anything that would otherwise break the row apart, doubling
+/// any quotes inside it.
+fn escape(field: &str) -> String {
+    if !field.contains([',', '"', '\r', '\n']) {
+        return field.to_string();
+    }
+
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Formats an optional value, leaving the field empty if it isn't set.
+pub fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Formats a unix timestamp as an RFC 3339 date in UTC, like `2025-04-12T18:30:00Z`.
+pub fn timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Howard Hinnant's civil_from_days, with days counted from 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn fields_are_escaped() {
+        let row = super::row([
+            "plain".to_string(),
+            "with, comma".to_string(),
+            "with \"quotes\"".to_string(),
+            "two\nlines".to_string(),
+            String::new(),
+        ]);
+
+        assert_eq!(
+            row,
+            "plain,\"with, comma\",\"with \"\"quotes\"\"\",\"two\nlines\",\r\n"
+        );
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339() {
+        assert_eq!(super::timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(super::timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(super::timestamp(1744482600), "2025-04-12T18:30:00Z");
+        assert_eq!(super::timestamp(4102444799), "2099-12-31T23:59:59Z");
+    }
+}
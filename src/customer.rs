@@ -0,0 +1,274 @@
+//! Routes for customers who log in with their lightning wallet, see [lnurl], to look back at the
+//! lockers they used. Logging in is optional: everything else works the same for customers who
+//! don't.
+//!
+//! `GET /auth/lnurl` hands out a challenge, as an LNURL for the wallet to scan, along with a bearer
+//! token. The token is good for nothing until the wallet signs the challenge, after which it logs
+//! in as the wallet's linking key for [crate::config::Config::customer_session_seconds]. Lockers
+//! claimed with the token are recorded as used by that linking key, which is what `/me/sessions`
+//! and `/me/payments` list.
+
+use std::sync::Arc;
+
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use bitcoin::hex::DisplayHex;
+use bitcoin::hex::FromHex;
+use serde::Deserialize;
+
+use crate::api;
+use crate::api::ApiResponse;
+use crate::error;
+use crate::error::StoreError;
+use crate::ln::LnBackend;
+use crate::lnurl;
+use crate::nostr;
+use crate::now;
+use crate::token;
+use crate::ApiVersion;
+use crate::PaymentFilter;
+use crate::Server;
+
+/// The most sessions or payments we return at once.
+const MAX_PER_PAGE: u64 = 100;
+
+/// Hands out a challenge for a wallet to sign, and the bearer token that logs in once it does.
+/// Customers can't log in unless we know our [crate::config::Config::public_url].
+pub async fn get_lnurl_auth<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let Some(public_url) = &state.config.public_url else {
+        return Err(error::Error::NotFound);
+    };
+
+    let now = now();
+    let k1 = rand::random::<[u8; 32]>().to_lower_hex_string();
+    let bearer = rand::random::<[u8; 32]>().to_lower_hex_string();
+    let expires_at = now + lnurl::CHALLENGE_SECONDS;
+    state
+        .insert_customer_login(&k1, &token::digest_hex(&bearer), now, expires_at)
+        .await?;
+
+    let api_url = format!("{public_url}{}", ApiVersion::LATEST.prefix());
+    let callback = lnurl::login_url(&api_url, &k1);
+    Ok(ApiResponse::ok(serde_json::json!({
+        "k1": k1,
+        "lnurl": lnurl::encode(&callback),
+        "callback": callback,
+        "token": bearer,
+        "expires_at": expires_at,
+    })))
+}
+
+/// What a wallet sends to log in, see LUD-04.
+#[derive(Debug, Deserialize)]
+pub struct LoginCallback {
+    tag: Option<String>,
+    k1: Option<String>,
+    /// A DER encoded ECDSA signature over `k1`, in hex.
+    sig: Option<String>,
+    /// The wallet's linking key, a compressed public key in hex.
+    key: Option<String>,
+}
+
+/// Where wallets send their signature of a challenge. Unlike the other routes, this answers the
+/// way LUD-04 says, since wallets are the ones reading it.
+pub async fn lnurl_auth_callback<Ln: LnBackend>(
+    Query(query): Query<LoginCallback>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let (Some(k1), Some(sig), Some(key)) = (&query.k1, &query.sig, &query.key) else {
+        return Ok(login_error("missing k1, sig or key"));
+    };
+
+    if query.tag.as_deref().is_some_and(|tag| tag != "login") {
+        return Ok(login_error("unsupported tag"));
+    }
+
+    let Ok(challenge) = <[u8; 32]>::from_hex(k1) else {
+        return Ok(login_error("malformed k1"));
+    };
+
+    let linking_key = match lnurl::verify_login(&challenge, sig, key) {
+        Ok(linking_key) => linking_key,
+        Err(e) => return Ok(login_error(&e.to_string())),
+    };
+
+    let now = now();
+    let linked = state
+        .link_customer_login(
+            &k1.to_lowercase(),
+            &linking_key.to_string(),
+            now,
+            now + state.config.customer_session_seconds,
+        )
+        .await?;
+    if !linked {
+        return Ok(login_error("unknown, expired or already used k1"));
+    }
+
+    Ok(login_response(
+        StatusCode::OK,
+        serde_json::json!({ "status": "OK" }),
+    ))
+}
+
+/// Tells who is logged in with the bearer token, and until when.
+pub async fn get_me<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let (linking_key, expires_at) = login(&state, &headers).await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "linking_key": linking_key,
+        "expires_at": expires_at,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    /// How many entries to return, defaults to 50.
+    limit: Option<u64>,
+    /// How many entries to skip, used to fetch the next pages.
+    offset: Option<u64>,
+}
+
+/// Returns the sessions of the customer logged in with the bearer token, newest first, along with
+/// how many they had in total.
+pub async fn get_my_sessions<Ln: LnBackend>(
+    Query(query): Query<PageQuery>,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let (linking_key, _) = login(&state, &headers).await?;
+
+    let limit = query.limit.unwrap_or(50).min(MAX_PER_PAGE);
+    let (sessions, total) = state
+        .list_customer_sessions(&linking_key, limit, query.offset.unwrap_or(0))
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "sessions": sessions,
+        "total": total,
+    })))
+}
+
+/// Returns the payments for the sessions of the customer logged in with the bearer token, newest
+/// first, along with how many they made in total.
+pub async fn get_my_payments<Ln: LnBackend>(
+    Query(query): Query<PageQuery>,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let (linking_key, _) = login(&state, &headers).await?;
+
+    let filter = PaymentFilter {
+        linking_key: Some(linking_key),
+        ..Default::default()
+    };
+    let limit = query.limit.unwrap_or(50).min(MAX_PER_PAGE);
+    let (payments, total) = state
+        .list_payments(&filter, limit, query.offset.unwrap_or(0))
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "payments": payments,
+        "total": total,
+    })))
+}
+
+/// Returns the linking key of the customer logged in with the bearer token in `headers`, or
+/// `None` if there isn't one. Tokens that don't log anyone in are rejected rather than treated as
+/// anonymous, so customers notice they need to log in again.
+pub async fn customer<Ln: LnBackend>(
+    state: &Server<Ln>,
+    headers: &HeaderMap,
+) -> Result<Option<String>, error::Error> {
+    let is_bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "));
+    if !is_bearer {
+        return Ok(None);
+    }
+
+    let (linking_key, _) = login(state, headers).await?;
+    Ok(Some(linking_key))
+}
+
+/// Returns the linking key logged in with the bearer token in `headers`, and when the login
+/// expires.
+async fn login<Ln: LnBackend>(
+    state: &Server<Ln>,
+    headers: &HeaderMap,
+) -> Result<(String, u64), error::Error> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(error::Error::Unauthorized)?;
+
+    match state
+        .get_customer_login(&token::digest_hex(bearer), now())
+        .await
+    {
+        Ok(login) => Ok(login),
+        Err(StoreError::NotFound) => Err(error::Error::Unauthorized),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the nostr public key of whoever a NIP-98 `Authorization` header in `headers` says the
+/// `method` request to `uri` is made for, see [nostr], or `None` if there isn't one. Headers that
+/// don't check out are rejected rather than treated as anonymous.
+///
+/// `uri` is the one the client sent, version prefix included, see
+/// [axum::extract::OriginalUri], since that's what it signed.
+pub fn nostr_user<Ln: LnBackend>(
+    state: &Server<Ln>,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Option<String>, error::Error> {
+    let Some(authorization) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.starts_with(nostr::SCHEME))
+    else {
+        return Ok(None);
+    };
+
+    let path_and_query = uri.path_and_query().map_or(uri.path(), |path| path.as_str());
+    match nostr::verify_auth(
+        authorization,
+        method.as_str(),
+        path_and_query,
+        state.config.public_url.as_deref(),
+        now(),
+    ) {
+        Ok(pubkey) => Ok(Some(pubkey.to_string())),
+        Err(e) => {
+            eprintln!("[nostr_user] rejected a NIP-98 header: {e}");
+            Err(error::Error::Unauthorized)
+        }
+    }
+}
+
+fn login_error(reason: &str) -> Response {
+    login_response(
+        StatusCode::BAD_REQUEST,
+        serde_json::json!({ "status": "ERROR", "reason": reason }),
+    )
+}
+
+fn login_response(status: StatusCode, body: serde_json::Value) -> Response {
+    (status, api::Json(body)).into_response()
+}
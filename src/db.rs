@@ -0,0 +1,1379 @@
+//! Database connections, schema, migrations and backups.
+//!
+//! The schema version is tracked with sqlite's `user_version` pragma, and every entry of
+//! [MIGRATIONS] bumps it by one. Once a migration is released it must never be edited, since
+//! existing databases already applied it: add a new migration instead, and update [SCHEMA] to
+//! match.
+
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio::sync::OwnedMutexGuard;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+use crate::config::LockerConfig;
+
+/// All migrations, in the order they should be applied.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE IF NOT EXISTS lockers (id INTEGER PRIMARY KEY AUTOINCREMENT, pk TEXT NOT NULL, state TEXT NOT NULL, start_time INTEGER NOT NULL);
+     CREATE TABLE IF NOT EXISTS pending_payments (id INTEGER PRIMARY KEY AUTOINCREMENT, amount INTEGER NOT NULL, payment_hash TEXT NOT NULL, status TEXT NOT NULL, locker_id TEXT NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id));",
+    // 2: timestamps for each payment status transition
+    "ALTER TABLE pending_payments ADD COLUMN paid_at INTEGER;
+     ALTER TABLE pending_payments ADD COLUMN expired_at INTEGER;
+     ALTER TABLE pending_payments ADD COLUMN redeemed_at INTEGER;",
+    // 3: payment hashes are unique, keep the oldest row for any hash we already duplicated and move
+    // the others to duplicate_pending_payments, so staff can still look them up
+    "CREATE TABLE duplicate_pending_payments AS SELECT * FROM pending_payments WHERE id NOT IN (SELECT MIN(id) FROM pending_payments GROUP BY payment_hash);
+     DELETE FROM pending_payments WHERE id IN (SELECT id FROM duplicate_pending_payments);
+     CREATE UNIQUE INDEX IF NOT EXISTS pending_payments_payment_hash ON pending_payments (payment_hash);",
+    // 4: pending_payments.locker_id was declared as TEXT, but it references an INTEGER key
+    "CREATE TABLE pending_payments_new (id INTEGER PRIMARY KEY AUTOINCREMENT, amount INTEGER NOT NULL, payment_hash TEXT NOT NULL, status TEXT NOT NULL, locker_id INTEGER NOT NULL, paid_at INTEGER, expired_at INTEGER, redeemed_at INTEGER, FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     INSERT INTO pending_payments_new SELECT id, amount, payment_hash, status, CAST(locker_id AS INTEGER), paid_at, expired_at, redeemed_at FROM pending_payments;
+     DROP TABLE pending_payments;
+     ALTER TABLE pending_payments_new RENAME TO pending_payments;
+     CREATE UNIQUE INDEX pending_payments_payment_hash ON pending_payments (payment_hash);",
+    // 5: usage sessions, lockers that are in use right now get a session for their current usage
+    "CREATE TABLE usage_sessions (id INTEGER PRIMARY KEY AUTOINCREMENT, locker_id INTEGER NOT NULL, started_at INTEGER NOT NULL, ended_at INTEGER, amount_sat INTEGER, payment_hash TEXT, state TEXT NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     INSERT INTO usage_sessions (locker_id, started_at, state) SELECT id, start_time, 'active' FROM lockers WHERE state = 'in_use';",
+    // 6: append-only log of everything that happens to a locker
+    "CREATE TABLE locker_events (id INTEGER PRIMARY KEY AUTOINCREMENT, locker_id INTEGER NOT NULL, event TEXT NOT NULL, actor TEXT NOT NULL, timestamp INTEGER NOT NULL, details TEXT NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     CREATE INDEX locker_events_locker_id ON locker_events (locker_id, id);",
+    // 7: when each payment was created, older rows won't have it
+    "ALTER TABLE pending_payments ADD COLUMN created_at INTEGER;",
+    // 8: descriptive metadata for lockers
+    "ALTER TABLE lockers ADD COLUMN name TEXT;
+     ALTER TABLE lockers ADD COLUMN location TEXT;
+     ALTER TABLE lockers ADD COLUMN size TEXT CHECK (size IN ('small', 'medium', 'large'));
+     ALTER TABLE lockers ADD COLUMN description TEXT;",
+    // 9: indexes for the hot queries, pending_payments.payment_hash is covered by its unique index
+    "CREATE INDEX pending_payments_status ON pending_payments (status);
+     CREATE INDEX lockers_state ON lockers (state);",
+    // 10: decommissioned lockers are kept around for their history, but can't be used
+    "ALTER TABLE lockers ADD COLUMN active INTEGER NOT NULL DEFAULT 1;",
+    // 11: receipts we handed out, so each payment only ever gets one
+    "CREATE TABLE receipts (payment_hash TEXT PRIMARY KEY, locker_id INTEGER NOT NULL, session_id INTEGER NOT NULL, issued_at INTEGER NOT NULL, signature TEXT NOT NULL, consumed_at INTEGER, FOREIGN KEY (locker_id) REFERENCES lockers(id), FOREIGN KEY (session_id) REFERENCES usage_sessions(id));
+     CREATE INDEX receipts_locker_id ON receipts (locker_id) WHERE consumed_at IS NULL;",
+    // 12: revenue statistics look payments up by when they were settled
+    "CREATE INDEX pending_payments_paid_at ON pending_payments (paid_at);",
+    // 13: the invoice itself, so clients that lose it can fetch it again, older rows won't have it
+    "ALTER TABLE pending_payments ADD COLUMN bolt11 TEXT;",
+    // 14: lockers are grouped into sites, each of which may have its own pricing, and receipts
+    // remember the site of the locker they open
+    "CREATE TABLE sites (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, address TEXT, timezone TEXT NOT NULL DEFAULT 'UTC', price_per_minute_msat INTEGER);
+     ALTER TABLE lockers ADD COLUMN site_id INTEGER REFERENCES sites(id);
+     CREATE INDEX lockers_site_id ON lockers (site_id);
+     ALTER TABLE receipts ADD COLUMN site_id INTEGER;",
+    // 15: lockers that cost more or less than the rest of their site
+    "ALTER TABLE lockers ADD COLUMN price_per_minute_msat INTEGER;",
+    // 16: payments staff cancelled, and whether one of them got paid anyway and must be refunded
+    "ALTER TABLE pending_payments ADD COLUMN cancelled_at INTEGER;
+     ALTER TABLE pending_payments ADD COLUMN needs_refund INTEGER NOT NULL DEFAULT 0;",
+    // 17: the JWT handed out along with each receipt, older receipts won't have one
+    "ALTER TABLE receipts ADD COLUMN token TEXT;",
+    // 18: tokens lockers already checked with us, kept until they expire so they can't be replayed
+    "CREATE TABLE used_tokens (digest TEXT PRIMARY KEY, locker_id INTEGER NOT NULL, used_at INTEGER NOT NULL, expires_at INTEGER NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     CREATE INDEX used_tokens_expires_at ON used_tokens (expires_at);",
+    // 19: when each receipt's authorization expires, older receipts don't say
+    "ALTER TABLE receipts ADD COLUMN expires_at INTEGER;",
+    // 20: the timestamp of the last open each locker reported, so reports can't be replayed
+    "ALTER TABLE lockers ADD COLUMN last_open_report_at INTEGER;",
+    // 21: how far off each locker's clock was when it last reported being opened
+    "ALTER TABLE lockers ADD COLUMN clock_drift_seconds INTEGER;
+     ALTER TABLE lockers ADD COLUMN clock_drift_at INTEGER;",
+    // 22: which server key signed each receipt
+    "ALTER TABLE receipts ADD COLUMN kid TEXT;",
+    // 23: who made each successful admin call
+    "CREATE TABLE admin_audit (id INTEGER PRIMARY KEY AUTOINCREMENT, token_id TEXT NOT NULL, method TEXT NOT NULL, path TEXT NOT NULL, status INTEGER NOT NULL, timestamp INTEGER NOT NULL);",
+    // 24: lockers that can't verify signatures share a secret with us instead
+    "ALTER TABLE lockers ADD COLUMN auth_mode TEXT NOT NULL DEFAULT 'schnorr';
+     ALTER TABLE lockers ADD COLUMN hmac_secret TEXT;",
+    // 25: when the locker was opened to store something during each session, and to retrieve it
+    "ALTER TABLE usage_sessions ADD COLUMN stored_at INTEGER;
+     ALTER TABLE usage_sessions ADD COLUMN retrieved_at INTEGER;",
+    // 26: receipts staff revoked, which lockers must refuse even though they're signed
+    "CREATE TABLE revoked_receipts (payment_hash TEXT PRIMARY KEY, locker_id INTEGER NOT NULL, reason TEXT, revoked_at INTEGER NOT NULL, expires_at INTEGER, FOREIGN KEY (payment_hash) REFERENCES receipts(payment_hash), FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     CREATE INDEX revoked_receipts_revoked_at ON revoked_receipts (revoked_at);",
+    // 27: which route each admin call went to, and what it was asked to do
+    "ALTER TABLE admin_audit ADD COLUMN route TEXT;
+     ALTER TABLE admin_audit ADD COLUMN summary TEXT;",
+    // 28: where to send webhooks, and the secret each subscription's deliveries are signed with
+    "CREATE TABLE webhooks (id INTEGER PRIMARY KEY AUTOINCREMENT, url TEXT NOT NULL, secret TEXT NOT NULL, created_at INTEGER NOT NULL);",
+    // 29: the id of each used token, which is what tells tokens apart now
+    "ALTER TABLE used_tokens ADD COLUMN jti TEXT;
+     CREATE UNIQUE INDEX used_tokens_jti ON used_tokens (jti);",
+    // 30: the keys lockers used before their controller was replaced, which must never come back
+    "ALTER TABLE lockers ADD COLUMN key_rotated_at INTEGER;
+     CREATE TABLE retired_locker_keys (pk TEXT PRIMARY KEY, locker_id INTEGER NOT NULL, retired_at INTEGER NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id));",
+    // 31: customers logged in with LNURL-auth, and the linking key of whoever used each session
+    "CREATE TABLE customer_logins (k1 TEXT PRIMARY KEY, token_digest TEXT NOT NULL UNIQUE, linking_key TEXT, created_at INTEGER NOT NULL, linked_at INTEGER, expires_at INTEGER NOT NULL);
+     CREATE INDEX customer_logins_expires_at ON customer_logins (expires_at);
+     ALTER TABLE usage_sessions ADD COLUMN linking_key TEXT;
+     CREATE INDEX usage_sessions_linking_key ON usage_sessions (linking_key) WHERE linking_key IS NOT NULL;",
+    // 32: the nostr public key of whoever used each session, for clients that send NIP-98 headers
+    "ALTER TABLE usage_sessions ADD COLUMN nostr_pubkey TEXT;",
+    // 33: the tokens locker devices present on the routes meant for them, of which we only keep a
+    // hash
+    "CREATE TABLE device_tokens (id INTEGER PRIMARY KEY AUTOINCREMENT, locker_id INTEGER NOT NULL, token_digest TEXT NOT NULL UNIQUE, created_at INTEGER NOT NULL, revoked_at INTEGER, FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     CREATE INDEX device_tokens_locker_id ON device_tokens (locker_id);",
+    // 34: the role of the admin token each call was made with
+    "ALTER TABLE admin_audit ADD COLUMN role TEXT;",
+    // 35: the one-time codes new lockers can register with, of which we only keep a hash
+    "CREATE TABLE provisioning_codes (id INTEGER PRIMARY KEY AUTOINCREMENT, code_digest TEXT NOT NULL UNIQUE, label TEXT, created_at INTEGER NOT NULL, expires_at INTEGER NOT NULL, used_at INTEGER, locker_id INTEGER, revoked_at INTEGER, FOREIGN KEY (locker_id) REFERENCES lockers(id));",
+    // 36: when the customer said they were done with the locker, freezing what they owe
+    "ALTER TABLE usage_sessions ADD COLUMN usage_ended_at INTEGER;",
+    // 37: sessions staff closed without charging, when forcing a broken locker out of service
+    "ALTER TABLE usage_sessions ADD COLUMN needs_follow_up INTEGER NOT NULL DEFAULT 0;",
+    // 38: the events each webhook subscription wants, comma separated, or every event if null, and
+    // every delivery we queued, so staff can tell why a receiver missed one
+    "ALTER TABLE webhooks ADD COLUMN events TEXT;
+     CREATE TABLE webhook_deliveries (id INTEGER PRIMARY KEY AUTOINCREMENT, webhook_id INTEGER NOT NULL, event_id TEXT NOT NULL, event TEXT NOT NULL, body TEXT NOT NULL, status TEXT NOT NULL, attempts INTEGER NOT NULL DEFAULT 0, last_status INTEGER, last_error TEXT, next_attempt_at INTEGER, created_at INTEGER NOT NULL, delivered_at INTEGER, FOREIGN KEY (webhook_id) REFERENCES webhooks(id));
+     CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id);
+     CREATE INDEX webhook_deliveries_due ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';",
+    // 39: lockers held for a customer on their way, of whose tokens we only keep a hash. A
+    // reservation is over once it's used or ended, and a locker has at most one that isn't
+    "CREATE TABLE reservations (id INTEGER PRIMARY KEY AUTOINCREMENT, locker_id INTEGER NOT NULL, token_digest TEXT NOT NULL UNIQUE, created_at INTEGER NOT NULL, expires_at INTEGER NOT NULL, used_at INTEGER, session_id INTEGER, ended_at INTEGER, FOREIGN KEY (locker_id) REFERENCES lockers(id), FOREIGN KEY (session_id) REFERENCES usage_sessions(id));
+     CREATE UNIQUE INDEX reservations_locker_id ON reservations (locker_id) WHERE used_at IS NULL AND ended_at IS NULL;
+     CREATE INDEX reservations_expires_at ON reservations (expires_at) WHERE used_at IS NULL AND ended_at IS NULL;",
+    // 40: the responses to requests made with an Idempotency-Key, of which we only keep a hash,
+    // with no status until the first request with it is answered
+    "CREATE TABLE idempotency_keys (key_digest TEXT PRIMARY KEY, fingerprint TEXT NOT NULL, status INTEGER, content_type TEXT, body BLOB, created_at INTEGER NOT NULL, expires_at INTEGER NOT NULL);
+     CREATE INDEX idempotency_keys_expires_at ON idempotency_keys (expires_at);",
+    // 41: a counter bumped whenever anything `/lockers` shows changes, see [locker_version]
+    "CREATE TABLE locker_version (id INTEGER PRIMARY KEY CHECK (id = 1), version INTEGER NOT NULL);
+     INSERT INTO locker_version (id, version) VALUES (1, 0);
+     CREATE TRIGGER lockers_inserted AFTER INSERT ON lockers BEGIN UPDATE locker_version SET version = version + 1; END;
+     CREATE TRIGGER lockers_deleted AFTER DELETE ON lockers BEGIN UPDATE locker_version SET version = version + 1; END;
+     CREATE TRIGGER lockers_updated AFTER UPDATE OF state, name, location, size, description, active, price_per_minute_msat, site_id, auth_mode ON lockers BEGIN UPDATE locker_version SET version = version + 1; END;
+     CREATE TRIGGER sites_updated AFTER UPDATE ON sites BEGIN UPDATE locker_version SET version = version + 1; END;
+     CREATE TRIGGER sites_deleted AFTER DELETE ON sites BEGIN UPDATE locker_version SET version = version + 1; END;",
+    // 42: the rate each session is billed at, fixed when it starts, older sessions don't have one
+    "ALTER TABLE usage_sessions ADD COLUMN price_per_minute_msat INTEGER;",
+    // 43: payments covering the sessions of several lockers at once, with what each came to, and
+    // the receipt they buy for each of those lockers, which are told apart by locker from now on,
+    // and so are their revocations
+    "CREATE TABLE payment_sessions (payment_hash TEXT NOT NULL, session_id INTEGER NOT NULL, locker_id INTEGER NOT NULL, amount_sat INTEGER NOT NULL, PRIMARY KEY (payment_hash, session_id), FOREIGN KEY (payment_hash) REFERENCES pending_payments(payment_hash), FOREIGN KEY (session_id) REFERENCES usage_sessions(id), FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     CREATE INDEX payment_sessions_session_id ON payment_sessions (session_id);
+     CREATE TABLE receipts_new (payment_hash TEXT NOT NULL, locker_id INTEGER NOT NULL, session_id INTEGER NOT NULL, issued_at INTEGER NOT NULL, signature TEXT NOT NULL, consumed_at INTEGER, site_id INTEGER, token TEXT, expires_at INTEGER, kid TEXT, PRIMARY KEY (payment_hash, locker_id), FOREIGN KEY (locker_id) REFERENCES lockers(id), FOREIGN KEY (session_id) REFERENCES usage_sessions(id));
+     INSERT INTO receipts_new SELECT payment_hash, locker_id, session_id, issued_at, signature, consumed_at, site_id, token, expires_at, kid FROM receipts;
+     CREATE TABLE revoked_receipts_new (payment_hash TEXT NOT NULL, locker_id INTEGER NOT NULL, reason TEXT, revoked_at INTEGER NOT NULL, expires_at INTEGER, PRIMARY KEY (payment_hash, locker_id), FOREIGN KEY (payment_hash, locker_id) REFERENCES receipts(payment_hash, locker_id), FOREIGN KEY (locker_id) REFERENCES lockers(id));
+     INSERT INTO revoked_receipts_new SELECT payment_hash, locker_id, reason, revoked_at, expires_at FROM revoked_receipts;
+     DROP TABLE revoked_receipts;
+     DROP TABLE receipts;
+     ALTER TABLE receipts_new RENAME TO receipts;
+     ALTER TABLE revoked_receipts_new RENAME TO revoked_receipts;
+     CREATE INDEX receipts_locker_id ON receipts (locker_id) WHERE consumed_at IS NULL;
+     CREATE INDEX revoked_receipts_revoked_at ON revoked_receipts (revoked_at);",
+    // 44: the operators hosting their lockers here, who own lockers, sites, provisioning codes and
+    // webhooks, and the operator each payment went to, for settling with them. What was there
+    // before belongs to no operator, which only super-admins see
+    "CREATE TABLE operators (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE, created_at INTEGER NOT NULL);
+     ALTER TABLE lockers ADD COLUMN operator_id INTEGER REFERENCES operators(id);
+     ALTER TABLE sites ADD COLUMN operator_id INTEGER REFERENCES operators(id);
+     ALTER TABLE pending_payments ADD COLUMN operator_id INTEGER REFERENCES operators(id);
+     ALTER TABLE provisioning_codes ADD COLUMN operator_id INTEGER REFERENCES operators(id);
+     ALTER TABLE webhooks ADD COLUMN operator_id INTEGER REFERENCES operators(id);
+     ALTER TABLE admin_audit ADD COLUMN operator_id INTEGER;
+     CREATE INDEX lockers_operator_id ON lockers (operator_id);
+     CREATE INDEX sites_operator_id ON sites (operator_id);
+     CREATE INDEX pending_payments_operator_id ON pending_payments (operator_id);",
+    // 45: our signature over the signed receipt handed out with each receipt, kept so fetching it
+    // again gives the same one
+    "ALTER TABLE receipts ADD COLUMN receipt_signature TEXT;",
+    // 46: the authorization in the format old firmware expects, for receipts issued while we still
+    // made those, kept for the same reason
+    "ALTER TABLE receipts ADD COLUMN legacy_signature TEXT;",
+];
+
+/// The columns each table should have once every migration is applied, checked at startup.
+const SCHEMA: &[(&str, &[&str])] = &[
+    (
+        "lockers",
+        &[
+            "id",
+            "pk",
+            "state",
+            "start_time",
+            "name",
+            "location",
+            "size",
+            "description",
+            "active",
+            "site_id",
+            "price_per_minute_msat",
+            "last_open_report_at",
+            "clock_drift_seconds",
+            "clock_drift_at",
+            "auth_mode",
+            "hmac_secret",
+            "key_rotated_at",
+            "operator_id",
+        ],
+    ),
+    (
+        "pending_payments",
+        &[
+            "id",
+            "amount",
+            "payment_hash",
+            "status",
+            "locker_id",
+            "paid_at",
+            "expired_at",
+            "redeemed_at",
+            "created_at",
+            "bolt11",
+            "cancelled_at",
+            "needs_refund",
+            "operator_id",
+        ],
+    ),
+    (
+        "usage_sessions",
+        &[
+            "id",
+            "locker_id",
+            "started_at",
+            "ended_at",
+            "amount_sat",
+            "payment_hash",
+            "state",
+            "stored_at",
+            "retrieved_at",
+            "linking_key",
+            "nostr_pubkey",
+            "usage_ended_at",
+            "needs_follow_up",
+            "price_per_minute_msat",
+        ],
+    ),
+    (
+        "locker_events",
+        &["id", "locker_id", "event", "actor", "timestamp", "details"],
+    ),
+    (
+        "receipts",
+        &[
+            "payment_hash",
+            "locker_id",
+            "session_id",
+            "issued_at",
+            "signature",
+            "consumed_at",
+            "site_id",
+            "token",
+            "expires_at",
+            "kid",
+            "receipt_signature",
+            "legacy_signature",
+        ],
+    ),
+    (
+        "revoked_receipts",
+        &[
+            "payment_hash",
+            "locker_id",
+            "reason",
+            "revoked_at",
+            "expires_at",
+        ],
+    ),
+    (
+        "used_tokens",
+        &["digest", "locker_id", "used_at", "expires_at", "jti"],
+    ),
+    (
+        "sites",
+        &[
+            "id",
+            "name",
+            "address",
+            "timezone",
+            "price_per_minute_msat",
+            "operator_id",
+        ],
+    ),
+    (
+        "admin_audit",
+        &[
+            "id",
+            "token_id",
+            "method",
+            "path",
+            "status",
+            "timestamp",
+            "route",
+            "summary",
+            "role",
+            "operator_id",
+        ],
+    ),
+    (
+        "webhooks",
+        &["id", "url", "secret", "created_at", "events", "operator_id"],
+    ),
+    ("locker_version", &["id", "version"]),
+    (
+        "idempotency_keys",
+        &[
+            "key_digest",
+            "fingerprint",
+            "status",
+            "content_type",
+            "body",
+            "created_at",
+            "expires_at",
+        ],
+    ),
+    (
+        "reservations",
+        &[
+            "id",
+            "locker_id",
+            "token_digest",
+            "created_at",
+            "expires_at",
+            "used_at",
+            "session_id",
+            "ended_at",
+        ],
+    ),
+    (
+        "webhook_deliveries",
+        &[
+            "id",
+            "webhook_id",
+            "event_id",
+            "event",
+            "body",
+            "status",
+            "attempts",
+            "last_status",
+            "last_error",
+            "next_attempt_at",
+            "created_at",
+            "delivered_at",
+        ],
+    ),
+    ("retired_locker_keys", &["pk", "locker_id", "retired_at"]),
+    (
+        "payment_sessions",
+        &["payment_hash", "session_id", "locker_id", "amount_sat"],
+    ),
+    (
+        "device_tokens",
+        &["id", "locker_id", "token_digest", "created_at", "revoked_at"],
+    ),
+    (
+        "provisioning_codes",
+        &[
+            "id",
+            "code_digest",
+            "label",
+            "created_at",
+            "expires_at",
+            "used_at",
+            "locker_id",
+            "revoked_at",
+            "operator_id",
+        ],
+    ),
+    ("operators", &["id", "name", "created_at"]),
+    (
+        "customer_logins",
+        &[
+            "k1",
+            "token_digest",
+            "linking_key",
+            "created_at",
+            "linked_at",
+            "expires_at",
+        ],
+    ),
+];
+
+/// Opens the database at `path`, bringing its schema up to date and checking it ended up the way
+/// we expect.
+///
+/// Foreign keys are only enforced after migrating, since sqlite can't toggle them inside a
+/// transaction and rebuilding a table may temporarily leave dangling references.
+pub fn open(path: &str, options: &Options) -> Result<sqlite::Connection, sqlite::Error> {
+    let mut database = sqlite::open(path)?;
+    database.set_busy_timeout(options.busy_timeout_ms)?;
+    database.execute(format!(
+        "PRAGMA journal_mode = {}; PRAGMA synchronous = {};",
+        options.journal_mode, options.synchronous
+    ))?;
+
+    migrate(&database)?;
+    check_schema(&database)?;
+    database.execute("PRAGMA foreign_keys = ON")?;
+
+    Ok(database)
+}
+
+/// Fails if any table is missing a column we expect it to have, listing all of them, so a
+/// database that was tampered with or migrated by something else is caught at startup rather than
+/// at the first request touching it.
+fn check_schema(database: &sqlite::Connection) -> Result<(), sqlite::Error> {
+    let mut missing = Vec::new();
+    for (table, columns) in SCHEMA {
+        let mut statement = database.prepare(format!("PRAGMA table_info({table})"))?;
+        let mut found = Vec::new();
+        while let sqlite::State::Row = statement.next()? {
+            found.push(statement.read::<String, _>("name")?);
+        }
+
+        if found.is_empty() {
+            missing.push(format!("table {table}"));
+            continue;
+        }
+
+        missing.extend(
+            columns
+                .iter()
+                .filter(|column| !found.iter().any(|found| found == *column))
+                .map(|column| format!("{table}.{column}")),
+        );
+    }
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    Err(sqlite::Error {
+        code: None,
+        message: Some(format!(
+            "the database schema is missing {}",
+            missing.join(", ")
+        )),
+    })
+}
+
+/// The connections to our database: a single one for writes, since sqlite only lets one
+/// connection write at a time anyway, and a few more for reads, which in WAL mode don't wait for
+/// writers or for each other.
+pub struct Pool {
+    writer: Arc<Mutex<sqlite::Connection>>,
+    readers: std::sync::Mutex<Vec<sqlite::Connection>>,
+    /// One permit per connection in `readers`.
+    available_readers: Arc<Semaphore>,
+    /// How many read connections we have, free or not.
+    reader_count: usize,
+    /// The [locker_version] as of the last write.
+    locker_version: AtomicU64,
+}
+
+impl Pool {
+    /// Opens the database at `path` and brings its schema up to date, see [open], then opens the
+    /// read connections.
+    ///
+    /// An in-memory database only exists within the connection that created it, so for those
+    /// every read goes through the write connection instead.
+    pub fn open(path: &str, options: &Options) -> Result<Self, sqlite::Error> {
+        let writer = open(path, options)?;
+        let version = locker_version(&writer)?;
+
+        let mut readers = Vec::new();
+        if path != ":memory:" && !path.is_empty() {
+            for _ in 0..options.read_connections {
+                let mut reader = sqlite::open(path)?;
+                reader.set_busy_timeout(options.busy_timeout_ms)?;
+                reader.execute("PRAGMA query_only = ON; PRAGMA foreign_keys = ON;")?;
+                readers.push(reader);
+            }
+        }
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            available_readers: Arc::new(Semaphore::new(readers.len())),
+            reader_count: readers.len(),
+            readers: std::sync::Mutex::new(readers),
+            locker_version: AtomicU64::new(version),
+        })
+    }
+
+    /// Runs `f` with the write connection, on the blocking thread pool so it doesn't hold up the
+    /// async runtime. Anything that may write must go through here, so [Pool::locker_version]
+    /// keeps up.
+    pub async fn write<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&sqlite::Connection) -> T + Send + 'static,
+    ) -> T {
+        let writer = self.writer().await;
+        let (value, version) = blocking(move || {
+            let value = f(&writer);
+            (value, locker_version(&writer))
+        })
+        .await;
+
+        match version {
+            Ok(version) => {
+                self.locker_version.fetch_max(version, Ordering::Relaxed);
+            }
+            Err(e) => eprintln!("[write] failed to read the locker version: {e:?}"),
+        }
+        value
+    }
+
+    /// The [locker_version] as of the last write, without asking the database.
+    pub fn locker_version(&self) -> u64 {
+        self.locker_version.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with a connection that can't be used to write, on the blocking thread pool so it
+    /// doesn't hold up the async runtime.
+    pub async fn read<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&sqlite::Connection) -> T + Send + 'static,
+    ) -> T {
+        let reader = self.reader().await;
+        blocking(move || f(&reader)).await
+    }
+
+    /// Waits for the write connection.
+    pub async fn writer(self: &Arc<Self>) -> OwnedMutexGuard<sqlite::Connection> {
+        self.writer.clone().lock_owned().await
+    }
+
+    /// Waits for a connection to read from.
+    pub async fn reader(self: &Arc<Self>) -> Reader {
+        if self.reader_count == 0 {
+            return Reader::Writer(self.writer().await);
+        }
+
+        let permit = self
+            .available_readers
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("we never close the semaphore");
+        let connection = self
+            .readers
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("we hold a permit, so there's a free reader");
+
+        Reader::Pooled {
+            pool: self.clone(),
+            connection: Some(connection),
+            _permit: permit,
+        }
+    }
+}
+
+/// Runs `f` on the blocking thread pool, resuming its panic if it panics.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// A connection borrowed from a [Pool] for reading, given back once dropped.
+pub enum Reader {
+    Pooled {
+        pool: Arc<Pool>,
+        connection: Option<sqlite::Connection>,
+        _permit: OwnedSemaphorePermit,
+    },
+    /// The pool has no read connections, so we read through the write one.
+    Writer(OwnedMutexGuard<sqlite::Connection>),
+}
+
+impl Deref for Reader {
+    type Target = sqlite::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Reader::Pooled { connection, .. } => connection.as_ref().unwrap(),
+            Reader::Writer(connection) => connection,
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        if let Reader::Pooled {
+            pool, connection, ..
+        } = self
+        {
+            // the connection must be back in the list before the permit is released
+            if let Some(connection) = connection.take() {
+                pool.readers.lock().unwrap().push(connection);
+            }
+        }
+    }
+}
+
+/// How each connection is set up, see the sqlite docs for what each pragma means.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// `PRAGMA journal_mode`, WAL lets readers proceed while someone is writing.
+    pub journal_mode: String,
+    /// `PRAGMA synchronous`, NORMAL is safe in WAL mode and much faster than FULL.
+    pub synchronous: String,
+    /// How long a statement waits for a lock held by another connection before giving up.
+    pub busy_timeout_ms: usize,
+    /// How many connections [Pool] opens for reads, on top of the one for writes.
+    pub read_connections: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            journal_mode: "WAL".to_string(),
+            synchronous: "NORMAL".to_string(),
+            busy_timeout_ms: 5000,
+            read_connections: 4,
+        }
+    }
+}
+
+/// How many times we try to start a transaction while another connection holds the write lock,
+/// on top of the busy timeout sqlite already waits for on each attempt.
+const BUSY_RETRIES: u32 = 3;
+
+/// Returns whether `error` means the database was locked by someone else, and it's worth trying
+/// again later.
+pub fn is_busy(error: &sqlite::Error) -> bool {
+    matches!(
+        error.code.map(|code| code as i32),
+        Some(sqlite::ffi::SQLITE_BUSY | sqlite::ffi::SQLITE_LOCKED)
+    )
+}
+
+/// Brings the lockers table in line with the configured lockers, matching them by public key.
+///
+/// Lockers we don't know yet are added as available, and the metadata of the ones we do is
+/// updated to match the configuration. We never touch the state of an existing locker, and
+/// lockers that aren't in the configuration are left alone. Keys a locker used before its
+/// controller was replaced are skipped, rather than coming back as a locker of their own. Returns
+/// how many lockers were added and updated.
+pub fn sync_lockers(
+    database: &sqlite::Connection,
+    lockers: &[LockerConfig],
+) -> Result<(usize, usize), sqlite::Error> {
+    transaction(database, || {
+        let (mut added, mut updated) = (0, 0);
+        for locker in lockers {
+            let mut statement = database
+                .prepare("SELECT EXISTS (SELECT 1 FROM retired_locker_keys WHERE pk = lower(?))")?;
+            statement.bind((1, locker.pk.as_str()))?;
+            statement.next()?;
+            if statement.read::<i64, _>(0)? != 0 {
+                continue;
+            }
+
+            let mut statement = database.prepare(
+                "UPDATE lockers SET name = COALESCE(?, name), location = COALESCE(?, location), size = COALESCE(?, size), description = COALESCE(?, description), auth_mode = ?, hmac_secret = ? WHERE pk = ?",
+            )?;
+            statement.bind((1, locker.name.as_deref()))?;
+            statement.bind((2, locker.location.as_deref()))?;
+            statement.bind((3, locker.size.map(|size| size.as_str())))?;
+            statement.bind((4, locker.description.as_deref()))?;
+            statement.bind((5, locker.auth_mode.unwrap_or_default().as_str()))?;
+            statement.bind((6, locker.hmac_secret.as_ref().map(|s| s.expose().as_str())))?;
+            statement.bind((7, locker.pk.as_str()))?;
+            statement.next()?;
+
+            if database.change_count() > 0 {
+                updated += 1;
+                continue;
+            }
+
+            let mut statement = database.prepare(
+                "INSERT INTO lockers (pk, state, start_time, name, location, size, description, auth_mode, hmac_secret) VALUES (?, 'available', 0, ?, ?, ?, ?, ?, ?)",
+            )?;
+            statement.bind((1, locker.pk.as_str()))?;
+            statement.bind((2, locker.name.as_deref()))?;
+            statement.bind((3, locker.location.as_deref()))?;
+            statement.bind((4, locker.size.map(|size| size.as_str())))?;
+            statement.bind((5, locker.description.as_deref()))?;
+            statement.bind((6, locker.auth_mode.unwrap_or_default().as_str()))?;
+            statement.bind((7, locker.hmac_secret.as_ref().map(|s| s.expose().as_str())))?;
+            statement.next()?;
+            added += 1;
+        }
+
+        Ok((added, updated))
+    })
+}
+
+/// How many pages we copy each time we take the database lock during a backup.
+const BACKUP_PAGES_PER_STEP: i32 = 256;
+
+/// An in-progress online backup, see [backup].
+struct Backup {
+    raw: *mut sqlite::ffi::sqlite3_backup,
+    // the backup must be finished before its destination is closed, which the drop order of a
+    // struct with a Drop impl guarantees
+    _destination: sqlite::Connection,
+}
+
+// the backup is only ever stepped while holding its source connection
+unsafe impl Send for Backup {}
+
+impl Drop for Backup {
+    fn drop(&mut self) {
+        unsafe { sqlite::ffi::sqlite3_backup_finish(self.raw) };
+    }
+}
+
+/// Copies the database behind `database` into a new file at `path` using sqlite's online backup
+/// API.
+///
+/// The database is copied from the write connection a few pages at a time, and the connection is
+/// released between each batch so a large backup doesn't stall every write. Writes made in the
+/// meantime are picked up by the backup, so the result is a consistent snapshot.
+pub async fn backup(database: &Arc<Pool>, path: &Path) -> Result<(), sqlite::Error> {
+    let destination = sqlite::open(path)?;
+
+    let mut backup = {
+        let source = database.writer().await;
+        let raw = unsafe {
+            sqlite::ffi::sqlite3_backup_init(
+                destination.as_raw(),
+                c"main".as_ptr(),
+                source.as_raw(),
+                c"main".as_ptr(),
+            )
+        };
+
+        if raw.is_null() {
+            return Err(sqlite::Error {
+                code: None,
+                message: Some("failed to start the backup".to_string()),
+            });
+        }
+
+        Backup {
+            raw,
+            _destination: destination,
+        }
+    };
+
+    loop {
+        let source = database.writer().await;
+        let code;
+        (backup, code) = blocking(move || {
+            let _source = source;
+            let code = unsafe { sqlite::ffi::sqlite3_backup_step(backup.raw, BACKUP_PAGES_PER_STEP) };
+            (backup, code)
+        })
+        .await;
+
+        match code {
+            sqlite::ffi::SQLITE_DONE => return Ok(()),
+            sqlite::ffi::SQLITE_OK | sqlite::ffi::SQLITE_BUSY | sqlite::ffi::SQLITE_LOCKED => {
+                tokio::task::yield_now().await
+            }
+            code => {
+                return Err(sqlite::Error {
+                    code: Some(code as isize),
+                    message: Some("backup step failed".to_string()),
+                })
+            }
+        }
+    }
+}
+
+/// What [maintain] did.
+#[derive(Debug)]
+pub struct Maintenance {
+    /// How many pages the WAL held before we truncated it.
+    pub wal_pages: i64,
+    /// Whether someone was still reading from the WAL, in which case it couldn't be truncated.
+    pub busy: bool,
+}
+
+impl Maintenance {
+    /// How many pages truncating the WAL gave back, none if it couldn't be truncated.
+    pub fn reclaimed_pages(&self) -> i64 {
+        if self.busy {
+            0
+        } else {
+            self.wal_pages
+        }
+    }
+}
+
+/// Moves everything in the WAL back into the database file and truncates it, then lets sqlite
+/// refresh whatever statistics its query planner needs.
+pub fn maintain(database: &sqlite::Connection) -> Result<Maintenance, sqlite::Error> {
+    // a truncating checkpoint reports an empty WAL once it's done, so we ask how big it was first
+    let mut statement = database.prepare("PRAGMA wal_checkpoint(PASSIVE)")?;
+    statement.next()?;
+    // -1 when the database isn't in WAL mode
+    let wal_pages = statement.read::<i64, _>(1)?.max(0);
+    drop(statement);
+
+    // optimizing may write statistics, which the checkpoint should take along
+    database.execute("PRAGMA optimize")?;
+
+    let mut statement = database.prepare("PRAGMA wal_checkpoint(TRUNCATE)")?;
+    statement.next()?;
+    let busy = statement.read::<i64, _>(0)? != 0;
+
+    Ok(Maintenance { wal_pages, busy })
+}
+
+/// Runs `f` inside a transaction, committing if it succeeds and rolling back otherwise.
+///
+/// The write lock is taken upfront, which sqlite can wait on, rather than when the first write
+/// happens, which fails straight away if someone else is writing.
+pub fn transaction<T, E: From<sqlite::Error>>(
+    database: &sqlite::Connection,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 0;
+    while let Err(e) = database.execute("BEGIN IMMEDIATE") {
+        attempt += 1;
+        if !is_busy(&e) || attempt > BUSY_RETRIES {
+            return Err(e.into());
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(10 << attempt));
+    }
+
+    match f() {
+        Ok(value) => {
+            database.execute("COMMIT")?;
+            Ok(value)
+        }
+        Err(e) => {
+            database.execute("ROLLBACK")?;
+            Err(e)
+        }
+    }
+}
+
+/// A counter the database bumps whenever a locker is added, changed in a way `/lockers` shows, or
+/// removed, or a site is changed or removed, which only ever grows. Clients polling `/lockers` are
+/// given it as an `ETag`, so they can tell nothing changed without us listing the lockers.
+///
+/// It's bumped by triggers rather than by each route changing lockers, so none can forget to.
+pub fn locker_version(database: &sqlite::Connection) -> Result<u64, sqlite::Error> {
+    let mut statement = database.prepare("SELECT version FROM locker_version WHERE id = 1")?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>(0)? as u64)
+}
+
+/// Brings the database schema up to date, applying every migration that wasn't applied yet.
+fn migrate(database: &sqlite::Connection) -> Result<(), sqlite::Error> {
+    let mut statement = database.prepare("PRAGMA user_version")?;
+    statement.next()?;
+    let version = statement.read::<i64, _>(0)? as usize;
+    drop(statement);
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let result = database.execute(format!(
+            "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+            i + 1
+        ));
+
+        if let Err(e) = result {
+            let _ = database.execute("ROLLBACK");
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    #[test]
+    fn payment_for_unknown_locker_is_rejected() {
+        let database = super::open(":memory:", &Options::default()).unwrap();
+        database
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .unwrap();
+
+        let insert = |locker_id: i64| {
+            database.execute(format!(
+                "INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES (1, 'hash{locker_id}', 'pending', {locker_id})"
+            ))
+        };
+
+        assert!(insert(1).is_ok());
+        assert!(insert(2).is_err());
+    }
+
+    #[test]
+    fn payments_buy_a_receipt_for_each_locker() {
+        let database = super::open(":memory:", &Options::default()).unwrap();
+        database
+            .execute(
+                "INSERT INTO lockers (pk, state, start_time) VALUES ('a', 'in_use', 0), ('b', 'in_use', 0);
+                 INSERT INTO usage_sessions (locker_id, started_at, state) VALUES (1, 0, 'active'), (2, 0, 'active');
+                 INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES (2, 'hash', 'paid', 1);
+                 INSERT INTO payment_sessions (payment_hash, session_id, locker_id, amount_sat) VALUES ('hash', 1, 1, 1), ('hash', 2, 2, 1);",
+            )
+            .unwrap();
+
+        let issue = |locker_id: i64| {
+            database.execute(format!(
+                "INSERT INTO receipts (payment_hash, locker_id, session_id, issued_at, signature) VALUES ('hash', {locker_id}, {locker_id}, 0, 'sig')"
+            ))
+        };
+        assert!(issue(1).is_ok());
+        assert!(issue(2).is_ok());
+        // but only one for each locker
+        assert!(issue(1).is_err());
+
+        let revoke = |locker_id: i64| {
+            database.execute(format!(
+                "INSERT INTO revoked_receipts (payment_hash, locker_id, revoked_at) VALUES ('hash', {locker_id}, 0)"
+            ))
+        };
+        assert!(revoke(1).is_ok());
+        // revocations are of receipts we issued
+        database
+            .execute("DELETE FROM receipts WHERE locker_id = 2")
+            .unwrap();
+        assert!(revoke(2).is_err());
+    }
+
+    #[test]
+    fn lockers_belong_to_operators_we_host() {
+        let database = super::open(":memory:", &Options::default()).unwrap();
+        database
+            .execute("INSERT INTO operators (name, created_at) VALUES ('acme', 0)")
+            .unwrap();
+
+        let insert = |operator_id: i64| {
+            database.execute(format!(
+                "INSERT INTO lockers (pk, state, start_time, operator_id) VALUES ('pk{operator_id}', 'available', 0, {operator_id})"
+            ))
+        };
+
+        assert!(insert(1).is_ok());
+        assert!(insert(2).is_err());
+        // operator names are what staff tell them apart by
+        assert!(database
+            .execute("INSERT INTO operators (name, created_at) VALUES ('acme', 1)")
+            .is_err());
+    }
+
+    #[test]
+    fn duplicated_payment_hashes_are_set_aside() {
+        // a database from before payment hashes were unique
+        let database = sqlite::open(":memory:").unwrap();
+        for migration in &super::MIGRATIONS[..2] {
+            database.execute(migration).unwrap();
+        }
+        database
+            .execute(
+                "PRAGMA user_version = 2;
+                 INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0);
+                 INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES (1, 'hash', 'redeemed', '1'), (2, 'hash', 'paid', '1'), (3, 'other', 'pending', '1');",
+            )
+            .unwrap();
+        super::migrate(&database).unwrap();
+
+        let amounts = |table: &str| {
+            let mut statement = database
+                .prepare(format!("SELECT amount FROM {table} ORDER BY id"))
+                .unwrap();
+            let mut amounts = Vec::new();
+            while let sqlite::State::Row = statement.next().unwrap() {
+                amounts.push(statement.read::<i64, _>(0).unwrap());
+            }
+            amounts
+        };
+        // the oldest row for each hash stays, the other one is kept aside rather than deleted
+        assert_eq!(amounts("pending_payments"), [1, 3]);
+        assert_eq!(amounts("duplicate_pending_payments"), [2]);
+    }
+
+    #[test]
+    fn missing_columns_are_reported() {
+        let path =
+            std::env::temp_dir().join(format!("locker-schema-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        drop(super::open(&path, &Options::default()).unwrap());
+
+        // something other than our migrations changed the schema behind our back
+        let database = sqlite::open(&path).unwrap();
+        database
+            .execute("ALTER TABLE lockers DROP COLUMN description; DROP TABLE receipts;")
+            .unwrap();
+        drop(database);
+
+        let Err(error) = super::open(&path, &Options::default()) else {
+            panic!("opened a database with missing columns");
+        };
+        assert_eq!(
+            error.message.as_deref(),
+            Some("the database schema is missing lockers.description, table receipts")
+        );
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let path = std::env::temp_dir().join(format!("locker-test-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let database = super::open(&path, &Options::default()).unwrap();
+        database
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .unwrap();
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let database = super::open(&path, &Options::default()).unwrap();
+                    for i in 0..50 {
+                        super::transaction(&database, || {
+                            database.execute(format!(
+                                "INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES (1, 'hash-{writer}-{i}', 'pending', 1)"
+                            ))
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut statement = database
+            .prepare("SELECT COUNT(*) FROM pending_payments")
+            .unwrap();
+        statement.next().unwrap();
+        assert_eq!(statement.read::<i64, _>(0).unwrap(), 400);
+
+        drop(statement);
+        drop(database);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    #[test]
+    fn maintenance_truncates_the_wal() {
+        let path = std::env::temp_dir().join(format!("locker-wal-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let database = super::open(&path, &Options::default()).unwrap();
+        database
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .unwrap();
+
+        let wal = format!("{path}-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        let maintenance = super::maintain(&database).unwrap();
+        assert!(!maintenance.busy);
+        assert!(maintenance.wal_pages > 0);
+        assert_eq!(maintenance.reclaimed_pages(), maintenance.wal_pages);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        drop(database);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    /// Inserts `count` payments for locker 1 into a fresh database.
+    fn database_with_payments(count: usize) -> sqlite::Connection {
+        let database = super::open(":memory:", &Options::default()).unwrap();
+        database
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .unwrap();
+
+        super::transaction(&database, || {
+            let mut statement = database.prepare(
+                "INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES (1, ?, ?, 1)",
+            )?;
+            for i in 0..count {
+                statement.reset()?;
+                statement.bind((1, format!("hash-{i}").as_str()))?;
+                statement.bind((2, if i % 100 == 0 { "pending" } else { "redeemed" }))?;
+                statement.next()?;
+            }
+
+            Ok::<_, sqlite::Error>(())
+        })
+        .unwrap();
+
+        database
+    }
+
+    /// How long it takes to look up 1000 payments by hash.
+    fn time_lookups(database: &sqlite::Connection, count: usize) -> std::time::Duration {
+        let start = std::time::Instant::now();
+        let mut statement = database
+            .prepare("SELECT amount FROM pending_payments WHERE payment_hash = ?")
+            .unwrap();
+        for i in 0..1000 {
+            statement.reset().unwrap();
+            statement
+                .bind((1, format!("hash-{}", i * 7919 % count).as_str()))
+                .unwrap();
+            assert_eq!(statement.next().unwrap(), sqlite::State::Row);
+        }
+
+        start.elapsed()
+    }
+
+    #[test]
+    fn retired_keys_are_not_synced_back() {
+        let database = super::open(":memory:", &Options::default()).unwrap();
+        let lockers: Vec<crate::config::LockerConfig> =
+            vec![toml::from_str(r#"pk = "AA""#).unwrap(), toml::from_str(r#"pk = "bb""#).unwrap()];
+        assert_eq!(super::sync_lockers(&database, &lockers).unwrap(), (2, 0));
+
+        // the first locker's controller was replaced, but the configuration wasn't updated
+        database
+            .execute(
+                "UPDATE lockers SET pk = 'cc' WHERE pk = 'AA';
+                 INSERT INTO retired_locker_keys (pk, locker_id, retired_at) VALUES ('aa', 1, 0);",
+            )
+            .unwrap();
+        assert_eq!(super::sync_lockers(&database, &lockers).unwrap(), (0, 1));
+    }
+
+    #[test]
+    fn hot_queries_use_indexes() {
+        let database = database_with_payments(50_000);
+
+        for query in [
+            "SELECT * FROM pending_payments WHERE payment_hash = 'hash-1'",
+            "SELECT * FROM pending_payments WHERE status = 'pending'",
+            "SELECT * FROM lockers WHERE state = 'available'",
+            "SELECT * FROM lockers WHERE site_id = 1",
+            "SELECT * FROM pending_payments WHERE paid_at >= 1 AND paid_at < 2",
+            "SELECT * FROM usage_sessions WHERE linking_key = 'key'",
+        ] {
+            let mut plan = String::new();
+            database
+                .iterate(format!("EXPLAIN QUERY PLAN {query}"), |row| {
+                    plan.extend(row.iter().filter_map(|(_, value)| *value));
+                    true
+                })
+                .unwrap();
+
+            assert!(plan.contains("USING INDEX"), "{query} doesn't use an index: {plan}");
+        }
+    }
+
+    /// Times lookups rather than checking plans, so it's only run on demand, on an idle machine,
+    /// with `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "benchmark, depends on how busy the machine is"]
+    fn lookups_dont_slow_down_with_more_payments() {
+        // with an index, ten times the rows shouldn't make lookups anywhere near ten times slower
+        let small = time_lookups(&database_with_payments(5_000), 5_000);
+        let large = time_lookups(&database_with_payments(50_000), 50_000);
+        assert!(large < small * 5, "lookups went from {small:?} to {large:?}");
+    }
+
+    /// Counts the lockers, as seen through `database`.
+    fn count_lockers(database: &sqlite::Connection) -> i64 {
+        let mut statement = database.prepare("SELECT COUNT(*) FROM lockers").unwrap();
+        statement.next().unwrap();
+        statement.read(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_dont_wait_for_writes() {
+        let path = std::env::temp_dir().join(format!("locker-pool-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let pool = std::sync::Arc::new(super::Pool::open(&path, &Options::default()).unwrap());
+
+        // hold the write connection in the middle of a transaction
+        let writer = pool.writer().await;
+        writer.execute("BEGIN IMMEDIATE").unwrap();
+        writer
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .unwrap();
+
+        // every reader is usable at once meanwhile, and sees what was last committed
+        let timeout = std::time::Duration::from_secs(1);
+        let mut readers = Vec::new();
+        for _ in 0..Options::default().read_connections {
+            let reader = tokio::time::timeout(timeout, pool.reader())
+                .await
+                .expect("reads shouldn't wait for the writer");
+            assert_eq!(count_lockers(&reader), 0);
+            readers.push(reader);
+        }
+
+        // readers can't be used to write
+        assert!(readers[0]
+            .execute("INSERT INTO lockers (pk, state, start_time) VALUES ('pk2', 'available', 0)")
+            .is_err());
+
+        writer.execute("COMMIT").unwrap();
+        drop(writer);
+
+        // and once given back, they see the new data
+        drop(readers);
+        assert_eq!(count_lockers(&*pool.reader().await), 1);
+
+        drop(pool);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    /// Times reads rather than checking what they see, so like
+    /// [lookups_dont_slow_down_with_more_payments] it's only run on demand, on an idle machine with
+    /// at least as many cores as read connections, with `cargo test -- --ignored`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark, depends on how busy the machine is"]
+    async fn parallel_readers_outpace_a_single_connection() {
+        let path =
+            std::env::temp_dir().join(format!("locker-throughput-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let pool = std::sync::Arc::new(super::Pool::open(&path, &Options::default()).unwrap());
+        pool.write(|database| {
+            database.execute(
+                "INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50000)
+                 INSERT INTO pending_payments (amount, payment_hash, status, locker_id) SELECT 1, 'hash-' || i, 'redeemed', 1 FROM n;",
+            )
+        })
+        .await
+        .unwrap();
+
+        // the same database, with every read going through the one connection as it used to
+        let options = Options {
+            read_connections: 0,
+            ..Options::default()
+        };
+        let single = std::sync::Arc::new(super::Pool::open(&path, &options).unwrap());
+
+        // as many clients as there are readers, each scanning the payments 50 times
+        let clients = Options::default().read_connections;
+        time_scans(&single, clients, 1).await;
+        time_scans(&pool, clients, 1).await;
+        let baseline = time_scans(&single, clients, 50).await;
+        let parallel = time_scans(&pool, clients, 50).await;
+
+        // they can't quite run side by side all the time, but they should come close
+        assert!(
+            parallel * 3 < baseline * 2,
+            "{} scans took {parallel:?} over {clients} readers, {baseline:?} over one connection",
+            clients * 50
+        );
+
+        drop((pool, single));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    /// How long it takes `clients` tasks reading through `pool` at once to each sum up the 50000
+    /// payments `scans` times.
+    async fn time_scans(
+        pool: &std::sync::Arc<super::Pool>,
+        clients: usize,
+        scans: usize,
+    ) -> std::time::Duration {
+        let start = std::time::Instant::now();
+        let mut tasks = Vec::new();
+        for _ in 0..clients {
+            let pool = pool.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..scans {
+                    let total = pool
+                        .read(|database| {
+                            let mut statement = database
+                                .prepare("SELECT SUM(amount) FROM pending_payments")
+                                .unwrap();
+                            statement.next().unwrap();
+                            statement.read::<i64, _>(0).unwrap()
+                        })
+                        .await;
+                    assert_eq!(total, 50_000);
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn in_memory_pool_reads_through_the_writer() {
+        let pool = std::sync::Arc::new(super::Pool::open(":memory:", &Options::default()).unwrap());
+        pool.write(|database| {
+            database.execute(
+                "INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)",
+            )
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(pool.read(count_lockers).await, 1);
+    }
+
+    #[tokio::test]
+    async fn writes_to_lockers_bump_their_version() {
+        let pool = std::sync::Arc::new(super::Pool::open(":memory:", &Options::default()).unwrap());
+        let start = pool.locker_version();
+
+        let write = |sql: &'static str| pool.write(move |database| database.execute(sql));
+        write("INSERT INTO lockers (pk, state, start_time) VALUES ('pk', 'available', 0)")
+            .await
+            .unwrap();
+        assert_eq!(pool.locker_version(), start + 1);
+
+        // columns nobody is shown don't change what clients see
+        write("UPDATE lockers SET start_time = 10").await.unwrap();
+        assert_eq!(pool.locker_version(), start + 1);
+
+        write("UPDATE lockers SET state = 'in_use'").await.unwrap();
+        assert_eq!(pool.locker_version(), start + 2);
+
+        write("INSERT INTO sites (name) VALUES ('site')").await.unwrap();
+        write("UPDATE lockers SET site_id = 1").await.unwrap();
+        write("UPDATE sites SET name = 'renamed'").await.unwrap();
+        assert_eq!(pool.locker_version(), start + 4);
+
+        // which is what the database says
+        assert_eq!(
+            pool.read(super::locker_version).await.unwrap(),
+            start + 4
+        );
+    }
+
+    #[tokio::test]
+    async fn queries_run_off_the_runtime() {
+        let pool = std::sync::Arc::new(super::Pool::open(":memory:", &Options::default()).unwrap());
+
+        // a query that only finishes once the test gets to run again, which it couldn't if the
+        // query was blocking the runtime's only thread
+        let (done, wait) = std::sync::mpsc::channel();
+        let query = tokio::spawn(async move {
+            pool.read(move |database| {
+                wait.recv_timeout(std::time::Duration::from_secs(1))
+                    .expect("the query blocked the runtime");
+                count_lockers(database)
+            })
+            .await
+        });
+
+        tokio::task::yield_now().await;
+        done.send(()).unwrap();
+        assert_eq!(query.await.unwrap(), 0);
+    }
+}
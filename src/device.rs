@@ -0,0 +1,44 @@
+//! Tokens each locker device presents on the routes meant for it, like `/update_locker_open`, so
+//! a device can only ever act as its own locker.
+//!
+//! A token is handed out once, when the locker registers through `/provision/register` or staff
+//! issue it a new one, and we only keep its SHA-256. Lockers that were ever issued a token must
+//! send their current one as `Authorization: Bearer`, and are locked out if it's revoked until
+//! they're issued another. Lockers that never were, like the ones from the lockers file, are let
+//! through without one unless [crate::config::Config::require_device_tokens] is set.
+
+use axum::http::header;
+use axum::http::HeaderMap;
+use bitcoin::hex::DisplayHex;
+
+use crate::error;
+use crate::ln::LnBackend;
+use crate::token;
+use crate::Server;
+
+/// Makes a new device token, returning it along with the digest we keep.
+pub fn generate() -> (String, String) {
+    let token = rand::random::<[u8; 32]>().to_lower_hex_string();
+    let digest = token::digest_hex(&token);
+    (token, digest)
+}
+
+/// Rejects requests acting as `locker_id` that don't carry its device token in `headers`, see the
+/// module documentation.
+pub async fn require<Ln: LnBackend>(
+    state: &Server<Ln>,
+    headers: &HeaderMap,
+    locker_id: i64,
+) -> Result<(), error::Error> {
+    let digest = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(token::digest_hex);
+
+    match state.check_device_token(locker_id, digest).await? {
+        Some(true) => Ok(()),
+        None if !state.config.require_device_tokens => Ok(()),
+        _ => Err(error::Error::Unauthorized),
+    }
+}
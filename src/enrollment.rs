@@ -0,0 +1,83 @@
+//! One-time codes staff hand installers, so a new locker can register through
+//! `/provision/register` without anyone carrying the provisioning key around.
+//!
+//! Codes are [CODE_CHARS] characters of Crockford's base32, shown split in two like
+//! `7KQ2M-XH9RT`, short enough to type on a keypad. We only keep their SHA-256, of the code as
+//! [normalize] spells it, so dashes, case and the letters easily mistaken for digits don't matter.
+//! Each code expires, and registers at most one locker.
+
+use std::fmt::Display;
+
+use crate::token;
+
+/// How many characters a code has, not counting the dash. At 5 bits each, guessing one of the
+/// few outstanding codes before they expire is hopeless.
+pub const CODE_CHARS: usize = 10;
+
+/// Crockford's base32 alphabet, which leaves out I, L, O and U.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Why a code can't register a locker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeError {
+    /// We never issued the code, or staff revoked it.
+    Unknown,
+    /// The code expired before it was used.
+    Expired,
+    /// The code already registered a locker.
+    Used,
+}
+
+impl Display for CodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeError::Unknown => write!(f, "code: unknown or revoked"),
+            CodeError::Expired => write!(f, "Provisioning code expired"),
+            CodeError::Used => write!(f, "Provisioning code already used"),
+        }
+    }
+}
+
+/// Makes a new code, returning it as shown to staff along with the digest we keep.
+pub fn generate() -> (String, String) {
+    let chars: String = rand::random::<[u8; CODE_CHARS]>()
+        .iter()
+        .map(|byte| ALPHABET[(byte & 31) as usize] as char)
+        .collect();
+
+    let code = format!("{}-{}", &chars[..CODE_CHARS / 2], &chars[CODE_CHARS / 2..]);
+    let digest = digest(&code);
+    (code, digest)
+}
+
+/// The digest we keep of `code`, however it was typed.
+pub fn digest(code: &str) -> String {
+    token::digest_hex(&normalize(code))
+}
+
+/// Spells `code` the way [generate] does, without the dash: upper case, with O read as 0, and I
+/// and L as 1, the way Crockford's base32 decodes them.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn codes_survive_being_retyped() {
+        let (code, digest) = super::generate();
+        assert_eq!(code.len(), super::CODE_CHARS + 1);
+        assert_eq!(super::digest(&code), digest);
+        assert_eq!(super::digest(&code.replace('-', "").to_lowercase()), digest);
+
+        assert_eq!(super::digest("1O0LI-abcde"), super::digest("10011-ABCDE"));
+        assert_ne!(super::digest("10011-ABCDE"), super::digest("10011-ABCDF"));
+    }
+}
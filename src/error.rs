@@ -1,10 +1,68 @@
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 
+use crate::api::ApiResponse;
+use crate::enrollment::CodeError;
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     NotFound,
     BadRequest,
+    /// A field of the request isn't what we expected, with a message saying which and why.
+    InvalidParam(String),
+    Unauthorized,
+    /// The route only takes POST requests, see [crate::reject_get].
+    MethodNotAllowed,
+    /// The admin token is valid, but its role doesn't allow what was asked.
+    Forbidden,
+    /// The request conflicts with the state of something, with a message saying what.
+    Conflict(String),
+    /// The request is well-formed but makes no sense, with a message saying why.
+    UnprocessableEntity(String),
+    Decommissioned,
+    /// The locker is being repaired or cleaned, so it can't be claimed until staff are done.
+    UnderMaintenance,
+    /// The locker is held for someone else, see [crate::reservation].
+    Reserved,
+    /// The locker can't be taken out of service or put under maintenance while it's in `state`,
+    /// or taken out of service while some of its payments aren't settled.
+    LockerBusy {
+        state: String,
+        unsettled_payments: Vec<String>,
+    },
+    /// The payment was cancelled by staff, so it can't buy a receipt anymore.
+    Cancelled,
+    /// Staff revoked the receipt, so it won't be handed out again.
+    Revoked,
+    /// The invoice was settled or expired, so there's no point showing it anymore.
+    InvoiceGone,
+    /// The locker was already opened with the receipt the payment bought, with the settlement
+    /// info of payments redeemed before we kept receipts, which we have nothing else to say about.
+    AlreadyRedeemed(Option<serde_json::Value>),
+    /// The payment wasn't settled yet, the caller should ask again in this many seconds.
+    PaymentRequired { retry_after: u64 },
+    /// The caller made too many requests, and should wait this many seconds before trying again.
+    RateLimited { retry_after: u64 },
+    /// The request body is larger than [crate::config::Config::max_body_bytes].
+    PayloadTooLarge,
+    /// The client's `Accept` rules out what the route answers, see [crate::api::negotiate].
+    NotAcceptable,
+    /// The request body isn't JSON, see [crate::api::negotiate].
+    UnsupportedMediaType,
+    /// The provisioning code a locker tried to register with can't be used.
+    ProvisioningCode(CodeError),
+    /// A locker's clock is too far off from ours, which is at `server_time`, for us to trust the
+    /// timestamp it sent.
+    ClockDrift { server_time: u64 },
+    /// The lightning backend failed or couldn't be reached, so we couldn't make an invoice or
+    /// tell whether one was paid.
+    LnBackend,
     DbError,
+    Busy,
     Hasher,
     Server,
 }
@@ -15,35 +73,387 @@ impl From<std::io::Error> for Error {
     }
 }
 
-impl From<sqlite::Error> for Error {
-    fn from(_: sqlite::Error) -> Self {
-        Error::DbError
+/// What can go wrong in the store, that is the database methods of [crate::Server].
+#[derive(Debug)]
+pub enum StoreError {
+    /// There's no row matching what we looked for.
+    NotFound,
+    /// Someone else held the database lock for longer than we were willing to wait.
+    Busy,
+    /// What we tried to write conflicts with what's already there.
+    Constraint(String),
+    /// The locker was taken out of service.
+    Decommissioned,
+    /// The locker is under maintenance.
+    UnderMaintenance,
+    /// The locker is reserved, and the reservation token, if any, isn't the one for it.
+    Reserved,
+    /// The locker is in use or has payments that aren't settled, see [Error::LockerBusy].
+    LockerBusy {
+        state: String,
+        unsettled_payments: Vec<String>,
+    },
+    /// The provisioning code can't be used, see [crate::enrollment].
+    ProvisioningCode(CodeError),
+    /// Anything else, with the underlying sqlite message.
+    Other(String),
+}
+
+impl From<sqlite::Error> for StoreError {
+    fn from(error: sqlite::Error) -> Self {
+        if crate::db::is_busy(&error) {
+            return StoreError::Busy;
+        }
+
+        let message = error.to_string();
+        match error.code.map(|code| code as i32) {
+            Some(sqlite::ffi::SQLITE_CONSTRAINT) => StoreError::Constraint(message),
+            _ => StoreError::Other(message),
+        }
+    }
+}
+
+impl From<crate::params::ParamError> for Error {
+    fn from(error: crate::params::ParamError) -> Self {
+        Error::InvalidParam(error.to_string())
+    }
+}
+
+impl From<StoreError> for Error {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::NotFound => Error::NotFound,
+            StoreError::Busy => {
+                eprintln!("[store] the database is busy");
+                Error::Busy
+            }
+            StoreError::Constraint(message) => {
+                eprintln!("[store] {message}");
+                Error::Conflict("Conflicts with what's already there".to_string())
+            }
+            StoreError::Decommissioned => Error::Decommissioned,
+            StoreError::UnderMaintenance => Error::UnderMaintenance,
+            StoreError::Reserved => Error::Reserved,
+            StoreError::LockerBusy {
+                state,
+                unsettled_payments,
+            } => Error::LockerBusy {
+                state,
+                unsettled_payments,
+            },
+            StoreError::ProvisioningCode(error) => Error::ProvisioningCode(error),
+            StoreError::Other(message) => {
+                eprintln!("[store] {message}");
+                Error::DbError
+            }
+        }
     }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::http::Response<axum::body::Body> {
-        match self {
-            Error::NotFound => axum::http::Response::builder()
-                .status(404)
-                .body(axum::body::Body::from("Not Found"))
-                .unwrap(),
-            Error::BadRequest => axum::http::Response::builder()
-                .status(400)
-                .body(axum::body::Body::from("Bad Request"))
-                .unwrap(),
-            Error::DbError => axum::http::Response::builder()
-                .status(500)
-                .body(axum::body::Body::from("Database Error"))
-                .unwrap(),
-            Error::Hasher => axum::http::Response::builder()
-                .status(500)
-                .body(axum::body::Body::from("Hasher Error"))
-                .unwrap(),
-            Error::Server => axum::http::Response::builder()
-                .status(500)
-                .body(axum::body::Body::from("Server Error"))
-                .unwrap(),
+        let mut headers = HeaderMap::new();
+        let (status, code, message, data): (u16, &str, String, Option<serde_json::Value>) =
+            match self {
+                Error::NotFound => (404, "not_found", "Not Found".to_string(), None),
+                Error::BadRequest => (400, "bad_request", "Bad Request".to_string(), None),
+                Error::InvalidParam(message) => (400, "invalid_param", message, None),
+                Error::Unauthorized => {
+                    headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                    (401, "unauthorized", "Unauthorized".to_string(), None)
+                }
+                Error::MethodNotAllowed => {
+                    headers.insert(header::ALLOW, HeaderValue::from_static("POST"));
+                    (
+                        405,
+                        "method_not_allowed",
+                        "Method Not Allowed, use POST".to_string(),
+                        None,
+                    )
+                }
+                Error::Forbidden => (403, "forbidden", "Forbidden".to_string(), None),
+                Error::Conflict(message) => (409, "conflict", message, None),
+                Error::UnprocessableEntity(message) => (422, "unprocessable", message, None),
+                Error::Decommissioned => (
+                    409,
+                    "locker_decommissioned",
+                    "Locker is decommissioned".to_string(),
+                    None,
+                ),
+                Error::UnderMaintenance => (
+                    409,
+                    "locker_under_maintenance",
+                    "Locker is under maintenance".to_string(),
+                    None,
+                ),
+                Error::Reserved => (409, "locker_reserved", "Locker is reserved".to_string(), None),
+                Error::LockerBusy {
+                    state,
+                    unsettled_payments,
+                } => (
+                    409,
+                    "locker_busy",
+                    "Locker is in use or has unsettled payments".to_string(),
+                    Some(serde_json::json!({
+                        "state": state,
+                        "unsettled_payments": unsettled_payments,
+                    })),
+                ),
+                Error::Cancelled => (
+                    410,
+                    "payment_cancelled",
+                    "Payment was cancelled, if you paid it you will be refunded".to_string(),
+                    None,
+                ),
+                Error::Revoked => (410, "receipt_revoked", "Receipt was revoked".to_string(), None),
+                Error::InvoiceGone => (
+                    410,
+                    "invoice_gone",
+                    "Invoice can't be paid anymore".to_string(),
+                    None,
+                ),
+                Error::AlreadyRedeemed(settlement) => (
+                    410,
+                    "payment_redeemed",
+                    "Payment already redeemed".to_string(),
+                    settlement,
+                ),
+                Error::PaymentRequired { retry_after } => {
+                    headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+                    (402, "payment_unpaid", "Payment not received yet".to_string(), None)
+                }
+                Error::RateLimited { retry_after } => {
+                    headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+                    (429, "rate_limited", "Too Many Requests".to_string(), None)
+                }
+                Error::PayloadTooLarge => (
+                    413,
+                    "payload_too_large",
+                    "Payload Too Large".to_string(),
+                    None,
+                ),
+                Error::NotAcceptable => (
+                    406,
+                    "not_acceptable",
+                    "Not Acceptable, we answer in JSON".to_string(),
+                    None,
+                ),
+                Error::UnsupportedMediaType => (
+                    415,
+                    "unsupported_media_type",
+                    "Unsupported Media Type, send JSON".to_string(),
+                    None,
+                ),
+                Error::ProvisioningCode(error) => {
+                    let (status, code) = match error {
+                        CodeError::Unknown => (400, "provisioning_code_unknown"),
+                        CodeError::Expired => (410, "provisioning_code_expired"),
+                        CodeError::Used => (409, "provisioning_code_used"),
+                    };
+                    (status, code, error.to_string(), None)
+                }
+                Error::ClockDrift { server_time } => {
+                    headers.insert("X-Server-Time", HeaderValue::from(server_time));
+                    (
+                        422,
+                        "clock_drift",
+                        "Timestamp out of window, check the clock".to_string(),
+                        None,
+                    )
+                }
+                Error::LnBackend => (
+                    502,
+                    "ln_backend_error",
+                    "Lightning backend unavailable".to_string(),
+                    None,
+                ),
+                Error::DbError => (500, "database_error", "Database Error".to_string(), None),
+                Error::Busy => {
+                    headers.insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+                    (503, "database_busy", "Database Busy".to_string(), None)
+                }
+                Error::Hasher => (500, "server_error", "Hasher Error".to_string(), None),
+                Error::Server => (500, "server_error", "Server Error".to_string(), None),
+            };
+
+        let status = StatusCode::from_u16(status).expect("error statuses are valid");
+        (status, headers, ApiResponse::error(code, message, data)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::CodeError;
+    use super::Error;
+    use super::StoreError;
+
+    #[test]
+    fn store_errors_map_to_status_codes() {
+        let database = sqlite::open(":memory:").unwrap();
+        database
+            .execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        database.execute("INSERT INTO t VALUES (1)").unwrap();
+
+        let constraint =
+            StoreError::from(database.execute("INSERT INTO t VALUES (1)").unwrap_err());
+        assert!(
+            matches!(&constraint, StoreError::Constraint(message) if message.contains("UNIQUE"))
+        );
+
+        let other = StoreError::from(database.execute("SELECT nothing FROM t").unwrap_err());
+        assert!(matches!(&other, StoreError::Other(message) if message.contains("nothing")));
+
+        for (error, status) in [
+            (StoreError::NotFound, 404),
+            (StoreError::Busy, 503),
+            (constraint, 409),
+            (StoreError::Decommissioned, 409),
+            (StoreError::UnderMaintenance, 409),
+            (StoreError::Reserved, 409),
+            (
+                StoreError::LockerBusy {
+                    state: "in_use".to_string(),
+                    unsettled_payments: Vec::new(),
+                },
+                409,
+            ),
+            (StoreError::ProvisioningCode(CodeError::Unknown), 400),
+            (StoreError::ProvisioningCode(CodeError::Expired), 410),
+            (StoreError::ProvisioningCode(CodeError::Used), 409),
+            (other, 500),
+        ] {
+            assert_eq!(Error::from(error).into_response().status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_are_wrapped() {
+        let busy = Error::LockerBusy {
+            state: "in_use".to_string(),
+            unsettled_payments: vec!["hash".to_string()],
+        };
+        for (error, status, body, header) in [
+            (
+                Error::NotFound,
+                404,
+                serde_json::json!({
+                    "data": null,
+                    "error": { "code": "not_found", "message": "Not Found" },
+                }),
+                None,
+            ),
+            (
+                Error::InvalidParam("locker_id must be positive".to_string()),
+                400,
+                serde_json::json!({
+                    "data": null,
+                    "error": {
+                        "code": "invalid_param",
+                        "message": "locker_id must be positive",
+                    },
+                }),
+                None,
+            ),
+            (
+                Error::Unauthorized,
+                401,
+                serde_json::json!({
+                    "data": null,
+                    "error": { "code": "unauthorized", "message": "Unauthorized" },
+                }),
+                Some(("www-authenticate", "Bearer")),
+            ),
+            (
+                busy,
+                409,
+                serde_json::json!({
+                    "data": { "state": "in_use", "unsettled_payments": ["hash"] },
+                    "error": {
+                        "code": "locker_busy",
+                        "message": "Locker is in use or has unsettled payments",
+                    },
+                }),
+                None,
+            ),
+            (
+                Error::AlreadyRedeemed(Some(serde_json::json!({ "locker_id": 1 }))),
+                410,
+                serde_json::json!({
+                    "data": { "locker_id": 1 },
+                    "error": { "code": "payment_redeemed", "message": "Payment already redeemed" },
+                }),
+                None,
+            ),
+            (
+                Error::PaymentRequired { retry_after: 2 },
+                402,
+                serde_json::json!({
+                    "data": null,
+                    "error": { "code": "payment_unpaid", "message": "Payment not received yet" },
+                }),
+                Some(("retry-after", "2")),
+            ),
+            (
+                Error::Conflict("Usage was already ended".to_string()),
+                409,
+                serde_json::json!({
+                    "data": null,
+                    "error": { "code": "conflict", "message": "Usage was already ended" },
+                }),
+                None,
+            ),
+            (
+                Error::UnprocessableEntity("from must be before to".to_string()),
+                422,
+                serde_json::json!({
+                    "data": null,
+                    "error": { "code": "unprocessable", "message": "from must be before to" },
+                }),
+                None,
+            ),
+            (
+                Error::ClockDrift { server_time: 10 },
+                422,
+                serde_json::json!({
+                    "data": null,
+                    "error": {
+                        "code": "clock_drift",
+                        "message": "Timestamp out of window, check the clock",
+                    },
+                }),
+                Some(("x-server-time", "10")),
+            ),
+            (
+                Error::LnBackend,
+                502,
+                serde_json::json!({
+                    "data": null,
+                    "error": {
+                        "code": "ln_backend_error",
+                        "message": "Lightning backend unavailable",
+                    },
+                }),
+                None,
+            ),
+        ] {
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+            assert_eq!(
+                response.headers()["content-type"],
+                crate::api::JSON_CONTENT_TYPE,
+                "{body}"
+            );
+            if let Some((name, value)) = header {
+                assert_eq!(response.headers()[name], value);
+            }
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let rendered: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(rendered, body);
         }
     }
 }
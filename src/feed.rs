@@ -0,0 +1,234 @@
+//! `GET /ws/lockers`, a WebSocket pushing every change of a locker's state, so kiosks don't have
+//! to poll `/lockers` to notice one freeing up.
+//!
+//! Whatever changes a locker's state, a customer, the locker itself, staff or a background task,
+//! goes through one of the store methods of [crate::Server], which publish the locker's state to
+//! the [LockerFeed] once they're done. The feed remembers the last state it saw for each locker,
+//! and only tells subscribers when it changed. On connecting, subscribers first get a snapshot of
+//! every locker, then each change as a [Message::StateChanged].
+//!
+//! Publishing never waits for subscribers: the feed keeps the last [CAPACITY] changes, and a
+//! subscriber that falls further behind is disconnected, and should connect again for a new
+//! snapshot.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use axum::extract::ws::WebSocket;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::State;
+use axum::response::Response;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::ln::LnBackend;
+use crate::now;
+use crate::Locker;
+use crate::Server;
+
+/// How many changes we keep for subscribers that haven't read them yet.
+pub const CAPACITY: usize = 256;
+
+/// The state of lockers that were decommissioned, which kiosks should stop showing.
+pub const DECOMMISSIONED: &str = "decommissioned";
+
+/// A locker's state changing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateChange {
+    pub locker_id: i64,
+    /// `null` for lockers that were just added.
+    pub old_state: Option<String>,
+    pub new_state: String,
+    /// When we noticed the change, as a unix timestamp.
+    pub ts: u64,
+}
+
+/// A locker's state, as listed in a [Message::Snapshot].
+#[derive(Debug, Clone, Serialize)]
+pub struct LockerState {
+    pub locker_id: i64,
+    pub state: String,
+}
+
+/// What subscribers are sent, tagged with its `type`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// Every locker that's in service, sent once on connecting.
+    Snapshot { lockers: Vec<LockerState>, ts: u64 },
+    StateChanged(StateChange),
+}
+
+/// Where locker state changes are published, see the module documentation.
+pub struct LockerFeed {
+    sender: broadcast::Sender<StateChange>,
+    /// The last state we saw for each locker.
+    states: Mutex<HashMap<i64, String>>,
+}
+
+impl LockerFeed {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remembers the state lockers are in without telling anyone, for when the server starts.
+    pub fn seed(&self, states: impl IntoIterator<Item = (i64, String)>) {
+        self.states.lock().unwrap().extend(states);
+    }
+
+    /// Records that `locker_id` is now in `state`, telling subscribers if it wasn't already.
+    pub fn publish(&self, locker_id: i64, state: String, ts: u64) -> Option<StateChange> {
+        let old_state = {
+            let mut states = self.states.lock().unwrap();
+            if states.get(&locker_id) == Some(&state) {
+                return None;
+            }
+            states.insert(locker_id, state.clone())
+        };
+
+        let change = StateChange {
+            locker_id,
+            old_state,
+            new_state: state,
+            ts,
+        };
+        // there being no subscribers isn't an error
+        let _ = self.sender.send(change.clone());
+        Some(change)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+        self.sender.subscribe()
+    }
+}
+
+/// The state we publish for `locker`, [DECOMMISSIONED] once it's out of service.
+pub fn state_of(locker: &Locker) -> String {
+    match locker.active {
+        true => locker.state.clone(),
+        false => DECOMMISSIONED.to_string(),
+    }
+}
+
+/// Upgrades to a WebSocket streaming the state of every locker, see the module documentation.
+pub async fn get_ws_lockers<Ln: LnBackend>(
+    ws: WebSocketUpgrade,
+    state: State<Arc<Server<Ln>>>,
+) -> Response {
+    ws.on_upgrade(move |socket| stream(socket, state.0))
+}
+
+async fn stream<Ln: LnBackend>(mut socket: WebSocket, state: Arc<Server<Ln>>) {
+    // subscribing before taking the snapshot, a change in between is sent twice rather than lost
+    let mut changes = state.locker_feed.subscribe();
+    let lockers = match state.list_lockers(false, None).await {
+        Ok(lockers) => lockers,
+        Err(e) => {
+            eprintln!("[feed] failed to list the lockers: {e:?}");
+            return;
+        }
+    };
+    let snapshot = Message::Snapshot {
+        lockers: lockers
+            .into_iter()
+            .map(|locker| LockerState {
+                locker_id: locker.id,
+                state: locker.state,
+            })
+            .collect(),
+        ts: now(),
+    };
+    if send(&mut socket, &snapshot).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) => {
+                    if send(&mut socket, &Message::StateChanged(change)).await.is_err() {
+                        return;
+                    }
+                }
+                // too slow to keep up, it should connect again for a new snapshot
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let _ = socket.send(close("too slow, reconnect")).await;
+                    return;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // we don't expect anything from subscribers, but must notice them leaving
+            received = socket.recv() => match received {
+                Some(Ok(axum::extract::ws::Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &Message) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("messages serialize");
+    socket
+        .send(axum::extract::ws::Message::Text(text.into()))
+        .await
+}
+
+fn close(reason: &'static str) -> axum::extract::ws::Message {
+    axum::extract::ws::Message::Close(Some(axum::extract::ws::CloseFrame {
+        // "policy violation", what's left for a peer that broke the rules
+        code: 1008,
+        reason: reason.into(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockerFeed;
+    use super::StateChange;
+
+    #[test]
+    fn only_changes_are_published() {
+        let feed = LockerFeed::new();
+        feed.seed([(1, "available".to_string())]);
+        let mut changes = feed.subscribe();
+
+        assert_eq!(feed.publish(1, "available".to_string(), 10), None);
+        let change = StateChange {
+            locker_id: 1,
+            old_state: Some("available".to_string()),
+            new_state: "in_use".to_string(),
+            ts: 11,
+        };
+        assert_eq!(feed.publish(1, "in_use".to_string(), 11), Some(change.clone()));
+        assert_eq!(changes.try_recv().unwrap(), change);
+
+        // a locker we hadn't seen
+        assert_eq!(
+            feed.publish(2, "provisioning".to_string(), 12).unwrap().old_state,
+            None
+        );
+        assert_eq!(changes.try_recv().unwrap().locker_id, 2);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn slow_subscribers_lag_behind() {
+        let feed = LockerFeed::new();
+        let mut changes = feed.subscribe();
+
+        // publishing never waits for the subscriber
+        for ts in 0..=super::CAPACITY as u64 {
+            let state = if ts % 2 == 0 { "in_use" } else { "available" };
+            assert!(feed.publish(1, state.to_string(), ts).is_some());
+        }
+
+        assert!(matches!(
+            changes.try_recv(),
+            Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_))
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+//! `GET /health`, for load balancers and uptime monitors to probe.
+//!
+//! Each probe checks the database with a trivial query, but not the lightning backend, which
+//! [crate::tasks::check_ln_backend] asks in the background instead, so probes can't overwhelm it.
+//! The background tasks are alive as long as they ran within twice their interval.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::api::ApiResponse;
+use crate::error;
+use crate::error::StoreError;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::Server;
+
+/// How a background task is doing, according to when it last ran.
+fn task(
+    started_at: u64,
+    last_run: &AtomicU64,
+    interval_seconds: u64,
+    now: u64,
+) -> serde_json::Value {
+    let last_run = last_run.load(Ordering::Relaxed);
+    // tasks that haven't run yet are only late once they had the time to
+    let since = last_run.max(started_at);
+    serde_json::json!({
+        "ok": now.saturating_sub(since) <= 2 * interval_seconds,
+        "last_run": (last_run > 0).then_some(last_run),
+    })
+}
+
+/// Tells whoever is watching the server whether it's healthy, with the details of each of its
+/// dependencies. It's a `503` naming the components that are failing if it isn't. An unreachable
+/// lightning backend only makes the server `degraded`, still a `200`, unless
+/// [crate::config::Config::health_requires_ln] is set.
+pub async fn get_health<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let now = now();
+    let config = &state.config;
+    let metrics = &state.metrics;
+    let started_at = metrics.started_at.load(Ordering::Relaxed);
+
+    let database = state
+        .database
+        .read(|database| Ok::<_, StoreError>(database.execute("SELECT 1")?))
+        .await
+        .is_ok();
+
+    // the backend counts as reachable until we first heard back from it
+    let last_ln_check = metrics.last_ln_check.load(Ordering::Relaxed);
+    let last_ln_reachable = metrics.last_ln_reachable.load(Ordering::Relaxed);
+    let ln = last_ln_reachable >= last_ln_check;
+
+    let mut tasks = serde_json::json!({
+        "cleanup_payments": task(
+            started_at,
+            &metrics.last_payment_cleanup,
+            config.cleanup_interval_seconds,
+            now,
+        ),
+        "check_ln_backend": task(
+            started_at,
+            &metrics.last_ln_check,
+            config.ln_check_interval_seconds,
+            now,
+        ),
+    });
+    if config.backup_dir.is_some() {
+        tasks["backup_database"] = task(
+            started_at,
+            &metrics.last_backup,
+            config.backup_interval_seconds,
+            now,
+        );
+    }
+    // the first run waits for the quiet hour, and later ones for payments in flight, so it's only
+    // reported
+    let last_maintenance = metrics.last_maintenance.load(Ordering::Relaxed);
+    tasks["maintain_database"] = serde_json::json!({
+        "ok": true,
+        "last_run": (last_maintenance > 0).then_some(last_maintenance),
+    });
+
+    let mut failing = Vec::new();
+    if !database {
+        failing.push("database".to_string());
+    }
+    if !ln && config.health_requires_ln {
+        failing.push("ln".to_string());
+    }
+    for (name, task) in tasks.as_object().into_iter().flatten() {
+        if task["ok"] == false {
+            failing.push(format!("tasks.{name}"));
+        }
+    }
+
+    let status = match (failing.is_empty(), ln) {
+        (false, _) => "failing",
+        (true, false) => "degraded",
+        (true, true) => "ok",
+    };
+    let data = serde_json::json!({
+        "status": status,
+        "failing": failing,
+        "version": env!("CARGO_PKG_VERSION"),
+        "checks": {
+            "database": { "ok": database },
+            "ln": {
+                "ok": ln,
+                "required": config.health_requires_ln,
+                "last_check": (last_ln_check > 0).then_some(last_ln_check),
+                "last_reachable": (last_ln_reachable > 0).then_some(last_ln_reachable),
+            },
+            "tasks": tasks,
+        },
+        "last_maintenance": (last_maintenance > 0).then_some(last_maintenance),
+    });
+
+    if failing.is_empty() {
+        return Ok(ApiResponse::ok(data).into_response());
+    }
+    let message = format!("Unhealthy: {}", failing.join(", "));
+    Ok((
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiResponse::error("unhealthy", message, Some(data)),
+    )
+        .into_response())
+}
@@ -0,0 +1,239 @@
+//! Answering requests retried with the same `Idempotency-Key` the way we answered the first one,
+//! so a client on a flaky network retrying `/pay_for_usage` doesn't end up with several invoices.
+//!
+//! Routes opt in with the [idempotent] middleware. The first request with a key claims it for its
+//! method, path and body, and once it succeeds, the response is kept until
+//! [crate::config::Config::idempotency_ttl_seconds] after it was first made. Requests with the same
+//! key get that response again, marked with [REPLAYED_HEADER], without reaching the handler. Using
+//! a key for another route, locker or body, like a batch of other lockers, is a `409`, and so is
+//! retrying while the first request is still being handled. Failed requests don't keep their key,
+//! so they can be retried with it.
+//!
+//! Keys belong to whoever sent them, see [caller], so the response to a request, with the
+//! authorization and token `/use_locker` hands out in it, is only ever given again to the same
+//! client, and the same key sent by someone else is just another key. We only keep the SHA-256 of
+//! the key along with its caller.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::extract::OriginalUri;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::middleware::Next;
+use axum::response::Response;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+
+use crate::customer;
+use crate::error;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::ratelimit;
+use crate::token;
+use crate::Server;
+
+/// The header clients send their key in.
+pub const KEY_HEADER: &str = "Idempotency-Key";
+
+/// The header set on responses we had already given, with `true`.
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// How long keys can be.
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// How long, in seconds, a key stays claimed by a request that hasn't been answered yet, so a key
+/// isn't stuck if we stop while handling it.
+pub const IN_FLIGHT_SECONDS: u64 = 60;
+
+/// A response we kept, to give it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What happened when claiming a key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Claim {
+    /// Nobody used the key yet, it's ours now.
+    Claimed,
+    /// The key was used for this request, which was answered with this.
+    Answered(StoredResponse),
+    /// The key was used for this request, which is still being handled.
+    InFlight,
+    /// The key was used for another request.
+    Mismatch,
+}
+
+/// Answers requests with an [KEY_HEADER] the way we answered the first one with that key, see the
+/// module documentation. Requests without one are handled as usual.
+pub async fn idempotent<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Result<Response, error::Error> {
+    let Some(key) = request.headers().get(KEY_HEADER) else {
+        return Ok(next.run(request).await);
+    };
+    let key = valid_key(key)?;
+    let caller = caller(&state, peer, &uri, &request)?;
+    let digest = token::digest_hex(&format!("{caller} {key}"));
+
+    // the body is only as large as `limit_body` let through, we need it whole to tell whether the
+    // key is reused for the same request
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, state.config.max_body_bytes)
+        .await
+        .map_err(|_| error::Error::PayloadTooLarge)?;
+    let fingerprint = format!(
+        "{} {} {}",
+        parts.method,
+        parts.uri.path(),
+        sha256::Hash::hash(&body)
+    );
+    let request = Request::from_parts(parts, Body::from(body));
+
+    let now = now();
+    let expires_at = now + state.config.idempotency_ttl_seconds;
+    match state
+        .claim_idempotency_key(&digest, &fingerprint, now, now + IN_FLIGHT_SECONDS)
+        .await?
+    {
+        Claim::Claimed => {}
+        Claim::Answered(stored) => return Ok(replay(stored)),
+        Claim::InFlight => {
+            return Err(error::Error::Conflict(format!(
+                "A request with this {KEY_HEADER} is still being handled"
+            )))
+        }
+        Claim::Mismatch => {
+            return Err(error::Error::Conflict(format!(
+                "{KEY_HEADER} was used for another request"
+            )))
+        }
+    }
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        if let Err(e) = state.release_idempotency_key(&digest).await {
+            eprintln!("[idempotent] failed to release a key: {e:?}");
+        }
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("[idempotent] failed to read the response body: {e:?}");
+            if let Err(e) = state.release_idempotency_key(&digest).await {
+                eprintln!("[idempotent] failed to release a key: {e:?}");
+            }
+            return Err(error::Error::Server);
+        }
+    };
+
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        body: body.to_vec(),
+    };
+    // the request went through either way, so the client gets its answer even if we can't keep it
+    if let Err(e) = state.save_idempotent_response(&digest, stored, expires_at).await {
+        eprintln!("[idempotent] failed to keep a response: {e:?}");
+    }
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Who sent `request`, as far as its key is concerned: the customer logged in with a bearer token,
+/// the nostr user a NIP-98 header is for, or for anonymous requests, the address the rate limits
+/// tell clients apart by. Bearer tokens are only checked by the handler, so one that doesn't log
+/// anyone in fails there and doesn't keep the key.
+///
+/// NIP-98 headers are signed for each request, so they're told apart by the user who signed them,
+/// rather than by the header itself.
+fn caller<Ln: LnBackend>(
+    state: &Server<Ln>,
+    peer: SocketAddr,
+    uri: &Uri,
+    request: &Request,
+) -> Result<String, error::Error> {
+    let headers = request.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(bearer) = bearer {
+        return Ok(format!("bearer {}", token::digest_hex(bearer)));
+    }
+
+    if let Some(pubkey) = customer::nostr_user(state, request.method(), uri, headers)? {
+        return Ok(format!("nostr {pubkey}"));
+    }
+
+    let ip = ratelimit::client_ip(peer, headers, state.config.trust_forwarded_for);
+    Ok(format!("ip {ip}"))
+}
+
+/// The key in `value`, if it's made of 1 to [MAX_KEY_LENGTH] visible ASCII characters.
+fn valid_key(value: &HeaderValue) -> Result<&str, error::Error> {
+    value
+        .to_str()
+        .ok()
+        .filter(|key| (1..=MAX_KEY_LENGTH).contains(&key.len()))
+        .filter(|key| key.bytes().all(|byte| byte.is_ascii_graphic()))
+        .ok_or_else(|| {
+            error::Error::InvalidParam(format!(
+                "{KEY_HEADER}: expected 1 to {MAX_KEY_LENGTH} visible ASCII characters"
+            ))
+        })
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut response = Response::new(Body::from(stored.body));
+    *response.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+
+    let headers = response.headers_mut();
+    if let Some(content_type) = stored.content_type.and_then(|t| t.parse().ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::valid_key;
+
+    #[test]
+    fn keys_are_visible_ascii() {
+        let key = HeaderValue::from_static("3f0b8c1e-retry");
+        assert_eq!(valid_key(&key).unwrap(), "3f0b8c1e-retry");
+
+        for invalid in ["", "with space", "tab\there"] {
+            assert!(valid_key(&HeaderValue::from_str(invalid).unwrap()).is_err(), "{invalid:?}");
+        }
+        assert!(valid_key(&HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap()).is_err());
+
+        let long = "k".repeat(256);
+        assert!(valid_key(&HeaderValue::from_str(&long).unwrap()).is_err());
+        assert!(valid_key(&HeaderValue::from_str(&long[1..]).unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,326 @@
+//! The server keys, which sign every authorization and token the lockers trust.
+//!
+//! We sign with a single active key, but keep the ones lockers may still trust around so they can
+//! be rotated: everything we sign carries the id of its key as `kid`. The keys come from
+//! [Config::server_keyring] if set. Otherwise there's only one, whose secret comes from
+//! [Config::server_key] if set, or else from the file at [Config::server_key_file], which we
+//! create with a fresh random key on first run.
+
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use secp256k1::Keypair;
+use secp256k1::Secp256k1;
+use secp256k1::SecretKey;
+
+use crate::config::Config;
+use crate::config::KeyringConfig;
+use crate::secret::Secret;
+
+/// One of our keys, and when lockers should trust it.
+#[derive(Debug, Clone)]
+pub struct ServerKey {
+    /// What we call the key in the `kid` of receipts and tokens.
+    pub id: String,
+    pub keypair: Secret<Keypair>,
+    /// When lockers should start trusting the key, as a unix timestamp.
+    pub not_before: Option<u64>,
+    /// When lockers should stop trusting the key, as a unix timestamp.
+    pub not_after: Option<u64>,
+}
+
+impl ServerKey {
+    /// Whether lockers should trust the key at `now`.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.not_before.is_none_or(|not_before| not_before <= now)
+            && self.not_after.is_none_or(|not_after| now < not_after)
+    }
+}
+
+/// Every key we know of, one of which we sign with.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: Vec<ServerKey>,
+    active: usize,
+}
+
+impl Keyring {
+    /// A keyring holding only `keypair`, with an id derived from its public key.
+    pub fn single(keypair: Keypair) -> Self {
+        Self {
+            keys: vec![ServerKey {
+                id: keypair.x_only_public_key().0.to_string()[..16].to_string(),
+                keypair: Secret::new(keypair),
+                not_before: None,
+                not_after: None,
+            }],
+            active: 0,
+        }
+    }
+
+    /// A keyring signing with the key called `active`, which must be valid at `now`.
+    pub fn new(keys: Vec<ServerKey>, active: &str, now: u64) -> Result<Self, KeyError> {
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|other| other.id == key.id) {
+                return Err(KeyError::Keyring(format!("{} is listed twice", key.id)));
+            }
+        }
+
+        let Some(active) = keys.iter().position(|key| key.id == active) else {
+            return Err(KeyError::Keyring(format!("there's no key called {active}")));
+        };
+
+        if !keys[active].is_valid_at(now) {
+            return Err(KeyError::Keyring(format!(
+                "the active key {} isn't valid now",
+                keys[active].id
+            )));
+        }
+
+        Ok(Self { keys, active })
+    }
+
+    /// The key we sign with.
+    pub fn active(&self) -> &ServerKey {
+        &self.keys[self.active]
+    }
+
+    /// Finds a key by its id.
+    pub fn get(&self, id: &str) -> Option<&ServerKey> {
+        self.keys.iter().find(|key| key.id == id)
+    }
+
+    /// Every key, in the order they were configured.
+    pub fn keys(&self) -> &[ServerKey] {
+        &self.keys
+    }
+}
+
+/// Why we couldn't load the server key.
+#[derive(Debug)]
+pub enum KeyError {
+    /// The secret is neither 64 hex characters nor a WIF private key.
+    Invalid,
+    /// The secret is one anyone could guess, like 1, so it might as well be public.
+    Weak,
+    /// We couldn't read or write the key file.
+    Io(std::io::Error),
+    /// The keys in the keyring don't make sense together.
+    Keyring(String),
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::Invalid => write!(f, "expected 64 hex characters or a WIF private key"),
+            KeyError::Weak => write!(f, "refusing to use a well known key"),
+            KeyError::Io(e) => write!(f, "{e}"),
+            KeyError::Keyring(message) => write!(f, "invalid keyring: {message}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for KeyError {
+    fn from(error: std::io::Error) -> Self {
+        KeyError::Io(error)
+    }
+}
+
+/// Loads the server keys, generating and saving the secret of any key that doesn't have one yet.
+pub fn load(config: &Config, now: u64) -> Result<Keyring, KeyError> {
+    if let Some(keyring) = &config.server_keyring {
+        return load_keyring(keyring, now);
+    }
+
+    let secret = match &config.server_key {
+        Some(secret) => parse_secret(secret.expose())?,
+        None => read_secret(&config.server_key_file)?,
+    };
+
+    Ok(Keyring::single(keypair(&secret)))
+}
+
+/// Loads the keys described in a keyring file, generating the secret files that are missing.
+pub fn load_keyring(config: &KeyringConfig, now: u64) -> Result<Keyring, KeyError> {
+    let mut keys = Vec::new();
+    for key in &config.keys {
+        let secret = match (&key.secret, &key.secret_file) {
+            (Some(secret), None) => parse_secret(secret.expose())?,
+            (None, Some(path)) => read_secret(path)?,
+            _ => {
+                return Err(KeyError::Keyring(format!(
+                    "{} needs either a secret or a secret_file",
+                    key.id
+                )))
+            }
+        };
+
+        keys.push(ServerKey {
+            id: key.id.clone(),
+            keypair: Secret::new(keypair(&secret)),
+            not_before: key.not_before,
+            not_after: key.not_after,
+        });
+    }
+
+    Keyring::new(keys, &config.active, now)
+}
+
+/// Reads a secret out of the file at `path`, generating it if the file doesn't exist.
+fn read_secret(path: &Path) -> Result<SecretKey, KeyError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_secret(&contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let secret = generate();
+            save(path, &secret)?;
+            eprintln!("[+] Generated a new server key in {}", path.display());
+            Ok(secret)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn keypair(secret: &SecretKey) -> Keypair {
+    Keypair::from_secret_key(&Secp256k1::new(), secret)
+}
+
+/// Parses a secret key given as hex or WIF, rejecting the ones anyone could guess.
+pub fn parse_secret(secret: &str) -> Result<SecretKey, KeyError> {
+    let secret = secret.trim();
+    let secret = match SecretKey::from_str(secret) {
+        Ok(secret) => secret,
+        Err(_) => {
+            let wif = bitcoin::PrivateKey::from_wif(secret).map_err(|_| KeyError::Invalid)?;
+            SecretKey::from_byte_array(wif.inner.secret_bytes()).map_err(|_| KeyError::Invalid)?
+        }
+    };
+
+    if is_weak(&secret) {
+        return Err(KeyError::Weak);
+    }
+
+    Ok(secret)
+}
+
+/// Whether a key is small enough, or close enough to the group order, that it could be found by
+/// counting, like the key 1 we used to ship with.
+fn is_weak(secret: &SecretKey) -> bool {
+    let is_small = |bytes: [u8; 32]| bytes[..24].iter().all(|byte| *byte == 0);
+    is_small(secret.secret_bytes()) || is_small(secret.negate().secret_bytes())
+}
+
+fn generate() -> SecretKey {
+    loop {
+        if let Ok(secret) = SecretKey::from_byte_array(rand::random()) {
+            if !is_weak(&secret) {
+                return secret;
+            }
+        }
+    }
+}
+
+/// Writes a key to a new file only we can read, so we never overwrite an existing key.
+fn save(path: &Path, secret: &SecretKey) -> Result<(), KeyError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", secret.display_secret())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyError;
+    use super::Keyring;
+    use super::ServerKey;
+    use crate::secret::Secret;
+
+    #[test]
+    fn secrets_parse_from_hex_and_wif() {
+        let hex = "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35";
+        // the same key, as a compressed mainnet WIF
+        let wif = "L52XzL2cMkHxqxBXRyEpnPQZGUs3uKiL3R11XbAdHigRzDozKZeW";
+
+        let secret = super::parse_secret(hex).unwrap();
+        assert_eq!(super::parse_secret(wif).unwrap(), secret);
+        assert_eq!(super::parse_secret(&format!(" {hex}\n")).unwrap(), secret);
+
+        assert!(matches!(
+            super::parse_secret("not a key"),
+            Err(KeyError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn weak_secrets_are_rejected() {
+        for secret in [
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "00000000000000000000000000000000000000000000000000000000deadbeef",
+            // the group order minus one, that is -1
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140",
+        ] {
+            assert!(matches!(super::parse_secret(secret), Err(KeyError::Weak)));
+        }
+
+        // all zeros isn't a key at all
+        assert!(matches!(
+            super::parse_secret(&"0".repeat(64)),
+            Err(KeyError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn keyrings_sign_with_a_valid_key() {
+        let key = |id: &str, not_before: Option<u64>, not_after: Option<u64>| ServerKey {
+            id: id.to_string(),
+            keypair: Secret::new(super::keypair(&super::generate())),
+            not_before,
+            not_after,
+        };
+
+        let keyring = Keyring::new(
+            vec![key("old", None, Some(2000)), key("new", Some(1000), None)],
+            "new",
+            1500,
+        )
+        .unwrap();
+        assert_eq!(keyring.active().id, "new");
+        assert!(keyring.get("old").unwrap().is_valid_at(1999));
+        assert!(!keyring.get("old").unwrap().is_valid_at(2000));
+
+        for (keys, active) in [
+            // the new key isn't valid yet
+            (vec![key("old", None, None), key("new", Some(2000), None)], "new"),
+            // the old key was retired
+            (vec![key("old", None, Some(1000)), key("new", None, None)], "old"),
+            (vec![key("old", None, None)], "new"),
+            (vec![key("old", None, None), key("old", None, None)], "old"),
+        ] {
+            assert!(matches!(
+                Keyring::new(keys, active, 1500),
+                Err(KeyError::Keyring(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn generated_keys_are_saved_and_loaded_back() {
+        let path = std::env::temp_dir().join(format!("server-{}.key", rand::random::<u64>()));
+
+        let secret = super::generate();
+        super::save(&path, &secret).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(super::parse_secret(&contents).unwrap(), secret);
+
+        // an existing key is never overwritten
+        assert!(super::save(&path, &super::generate()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -5,47 +5,81 @@ use std::{
 use bitcoin::hashes::Hash;
 use serde::{Deserialize, Serialize};
 
+use crate::secret::Secret;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Invoice {
     amount: u64,
-    bolt11: String,
+    pub bolt11: String,
     pub payment_hash: String,
 }
 
+/// How long, in seconds, an invoice we create stays payable.
+pub const INVOICE_EXPIRY_SECONDS: u64 = 3600;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InvoiceStatus {
     Unpaid,
     Paid,
+    /// The invoice wasn't paid before its expiry, and can't be paid anymore.
+    Expired,
 }
 
-pub trait LnBackend {
+pub trait LnBackend: Send + Sync + 'static {
     type Error;
 
+    /// What the backend is called in `/version`.
+    const NAME: &'static str;
+
     fn get_invoice(&self, amount: u64) -> Result<Invoice, Self::Error>;
     fn get_invoice_status(&self, hash: String) -> Result<InvoiceStatus, Self::Error>;
+
+    /// Asks the backend about itself, to check it's reachable.
+    fn get_info(&self) -> Result<(), Self::Error>;
+
+    /// Makes an invoice unpayable, for backends that support it. Returns whether the invoice was
+    /// cancelled, which it isn't by default.
+    fn cancel_invoice(&self, _hash: String) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
 }
 
+/// A backend that considers every invoice paid, for running the tests without a lightning
+/// wallet. Only built with the `mock-ln` feature, so it can't be turned on in production.
+#[cfg(feature = "mock-ln")]
 pub struct MockLnBackend {
     invoices: Arc<Mutex<HashMap<String, (Invoice, InvoiceStatus)>>>,
+    /// Whether every invoice gets the same payment hash, to check we refuse to store a payment
+    /// hash twice.
+    reuse_hashes: bool,
 }
 
+#[cfg(feature = "mock-ln")]
 impl MockLnBackend {
-    pub fn new() -> Self {
+    pub fn new(reuse_hashes: bool) -> Self {
         Self {
             invoices: Arc::new(Mutex::new(HashMap::new())),
+            reuse_hashes,
         }
     }
 }
 
+#[cfg(feature = "mock-ln")]
 impl LnBackend for MockLnBackend {
     type Error = ();
 
+    const NAME: &'static str = "mock";
+
     fn get_invoice(&self, amount: u64) -> Result<Invoice, Self::Error> {
-        let payment_preimage = "mock_payment_preimage".to_string();
-        let payment_hash = bitcoin::hashes::sha256d::Hash::hash(payment_preimage.as_bytes());
+        // a fresh preimage each time, like a real wallet, so invoices don't share a payment hash
+        let payment_preimage = match self.reuse_hashes {
+            true => [0; 32],
+            false => rand::random::<[u8; 32]>(),
+        };
+        let payment_hash = bitcoin::hashes::sha256::Hash::hash(&payment_preimage);
         let invoice = Invoice {
             amount,
-            bolt11: "mock_bolt11".to_string(),
+            bolt11: format!("mock_bolt11_{payment_hash}"),
             payment_hash: payment_hash.to_string(),
         };
 
@@ -58,19 +92,28 @@ impl LnBackend for MockLnBackend {
         Ok(invoice)
     }
 
-    fn get_invoice_status(&self, hash: String) -> Result<InvoiceStatus, Self::Error> {
+    fn get_invoice_status(&self, _hash: String) -> Result<InvoiceStatus, Self::Error> {
         Ok(InvoiceStatus::Paid)
     }
+
+    fn get_info(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn cancel_invoice(&self, hash: String) -> Result<bool, Self::Error> {
+        Ok(self.invoices.lock().unwrap().remove(&hash).is_some())
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 /// A struct that holds all data needed to connect with a running phoenixd,
 /// the actual lightning wallet powering this application
 pub struct PhoenixdClient {
     /// The password we use to authenticate with phoenixd.
     ///
-    /// You can find this in $PHOENIXD_DATA_DIR/phoenixd.conf
-    pub password: String,
+    /// You can find this in $PHOENIXD_DATA_DIR/phoenixd.conf, we keep it base64 encoded, ready
+    /// to go in the `Authorization` header.
+    pub password: Secret<String>,
 
     /// The host where phoenixd is running
     pub host: String,
@@ -119,7 +162,15 @@ impl PhoenixdClient {
     ///
     /// You can find this in $PHOENIXD_DATA_DIR/phoenixd.conf
     pub fn new(host: String, password: String) -> Self {
-        Self { password, host }
+        Self {
+            password: Secret::new(password),
+            host,
+        }
+    }
+
+    /// The value of the `Authorization` header phoenixd expects. Never log it, it's the password.
+    fn authorization(&self) -> String {
+        format!("Basic {}", self.password.expose())
     }
 }
 
@@ -153,18 +204,19 @@ impl From<serde_json::Error> for PhoenixdError {
 impl LnBackend for PhoenixdClient {
     type Error = PhoenixdError;
 
+    const NAME: &'static str = "phoenixd";
+
     fn get_invoice(&self, amount: u64) -> Result<Invoice, Self::Error> {
         let url = format!("{}/createinvoice", self.host);
         let response = minreq::post(url).with_body(
             format!(
-                "\rdescription=Test invoice&amountSat={amount}&expirySeconds=3600",
+                "\rdescription=Test invoice&amountSat={amount}&expirySeconds={INVOICE_EXPIRY_SECONDS}",
             )
         )
         .with_header("Content-Type", "application/x-www-form-urlencoded")
-        .with_header("Authorization", format!("Basic {}", self.password.clone()))
+        .with_header("Authorization", self.authorization())
         .send()?;
 
-        println!("[get_invoice] response: {:?}", response.as_str().unwrap());
         let response: CreateInvoiceResponse = serde_json::from_str(response.as_str()?)?;
         Ok(Invoice {
             amount,
@@ -176,15 +228,34 @@ impl LnBackend for PhoenixdClient {
     fn get_invoice_status(&self, hash: String) -> Result<InvoiceStatus, Self::Error> {
         let url = format!("{}//payments/incoming/{}", self.host, hash);
         let response = minreq::get(url)
-            .with_header("Authorization", format!("Basic {}", self.password.clone()))
+            .with_header("Authorization", self.authorization())
             .send()?;
-        
-        println!("[get_invoice_status] response: {:?}", response.as_str().unwrap());
+
         let response: GetInvoiceResponse = serde_json::from_str(response.as_str()?)?;
+        // phoenixd reports timestamps in milliseconds
+        let expires_at = response.createdAt / 1000 + INVOICE_EXPIRY_SECONDS;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
         Ok(if response.isPaid {
             InvoiceStatus::Paid
+        } else if now > expires_at {
+            InvoiceStatus::Expired
         } else {
             InvoiceStatus::Unpaid
         })
     }
+
+    fn get_info(&self) -> Result<(), Self::Error> {
+        let url = format!("{}/getinfo", self.host);
+        let response = minreq::get(url)
+            .with_header("Authorization", self.authorization())
+            .send()?;
+
+        // phoenixd answers errors, like a wrong password, with a message rather than its info
+        serde_json::from_str::<serde_json::Value>(response.as_str()?)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,134 @@
+//! LNURL-auth, which lets customers log in with their lightning wallet rather than an account, as
+//! described in LUD-04 <https://github.com/lnurl/luds/blob/luds/04.md>.
+//!
+//! We hand out a random challenge, `k1`, inside an LNURL the wallet scans. The wallet derives a
+//! key of its own for our domain, the linking key, signs `k1` with it and sends both to the
+//! callback in the LNURL. The linking key is then what tells a returning customer apart, and the
+//! same wallet always gets the same one from us.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use bitcoin::bech32;
+use bitcoin::hex::FromHex;
+use secp256k1::ecdsa::Signature;
+use secp256k1::Message;
+use secp256k1::PublicKey;
+use secp256k1::Secp256k1;
+
+/// How long, in seconds, a wallet has to sign a challenge before it's no good anymore.
+pub const CHALLENGE_SECONDS: u64 = 10 * 60;
+
+/// The path of the callback wallets send their signature to.
+pub const CALLBACK_PATH: &str = "/auth/lnurl/callback";
+
+/// Why a wallet's answer to a challenge was refused by [verify_login].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginError {
+    /// The signature or linking key isn't hex of what we expect.
+    Malformed,
+    /// The signature isn't one by the linking key over the challenge.
+    BadSignature,
+}
+
+impl Display for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoginError::Malformed => write!(f, "malformed signature or key"),
+            LoginError::BadSignature => write!(f, "bad signature"),
+        }
+    }
+}
+
+/// The URL a wallet should call to log in with the challenge `k1`, on the API at `api_url`.
+pub fn login_url(api_url: &str, k1: &str) -> String {
+    format!("{api_url}{CALLBACK_PATH}?tag=login&k1={k1}&action=login")
+}
+
+/// Encodes `url` as an LNURL, in upper case since that makes for smaller QR codes.
+pub fn encode(url: &str) -> String {
+    let hrp = bech32::Hrp::parse("lnurl").expect("lnurl is a valid prefix");
+    bech32::encode::<bech32::Bech32>(hrp, url.as_bytes())
+        .expect("our urls are short enough to encode")
+        .to_uppercase()
+}
+
+/// Checks `sig`, a DER encoded ECDSA signature in hex, was made over the challenge `k1` by `key`,
+/// a compressed public key in hex, returning the key.
+pub fn verify_login(k1: &[u8; 32], sig: &str, key: &str) -> Result<PublicKey, LoginError> {
+    let key = PublicKey::from_str(key).map_err(|_| LoginError::Malformed)?;
+    let mut signature = Vec::<u8>::from_hex(sig)
+        .ok()
+        .and_then(|sig| Signature::from_der(&sig).ok())
+        .ok_or(LoginError::Malformed)?;
+    // libsecp256k1 only accepts the low form of `s`, which not every wallet sticks to
+    signature.normalize_s();
+
+    Secp256k1::verification_only()
+        .verify_ecdsa(Message::from_digest(*k1), &signature, &key)
+        .map_err(|_| LoginError::BadSignature)?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hex::DisplayHex;
+    use secp256k1::Message;
+    use secp256k1::Secp256k1;
+    use secp256k1::SecretKey;
+
+    use super::LoginError;
+
+    const K1: [u8; 32] = [0x42; 32];
+
+    #[test]
+    fn lnurls_match_vectors() {
+        // from LUD-01
+        assert_eq!(
+            super::encode(
+                "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+            ),
+            "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS"
+        );
+    }
+
+    #[test]
+    fn logins_are_verified() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_byte_array([0x01; 32]).unwrap();
+        let key = secret_key.public_key(&secp);
+        let sig = secp
+            .sign_ecdsa(Message::from_digest(K1), &secret_key)
+            .serialize_der()
+            .to_lower_hex_string();
+
+        assert_eq!(super::verify_login(&K1, &sig, &key.to_string()), Ok(key));
+
+        // another challenge
+        assert_eq!(
+            super::verify_login(&[0x43; 32], &sig, &key.to_string()),
+            Err(LoginError::BadSignature)
+        );
+
+        // another wallet's key
+        let other = SecretKey::from_byte_array([0x02; 32])
+            .unwrap()
+            .public_key(&secp);
+        assert_eq!(
+            super::verify_login(&K1, &sig, &other.to_string()),
+            Err(LoginError::BadSignature)
+        );
+
+        for (sig, key) in [
+            ("abc", key.to_string()),
+            (&sig[2..], key.to_string()),
+            (&sig, key.x_only_public_key().0.to_string()),
+        ] {
+            assert_eq!(
+                super::verify_login(&K1, sig, &key),
+                Err(LoginError::Malformed)
+            );
+        }
+    }
+}
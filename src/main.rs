@@ -4,419 +4,6306 @@
 //! retrieving things after a certain time.
 
 use std::env;
-use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::OriginalUri;
+use axum::extract::Query;
 use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::routing::post;
-use axum::{http::Method, routing::get, Router};
-use bitcoin::hashes::HashEngine;
+use axum::{routing::get, Router};
+use api::ApiResponse;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
 use bitcoin::hex::DisplayHex;
+use bitcoin::hex::FromHex;
+use base64::Engine;
 use ln::LnBackend;
+#[cfg(feature = "mock-ln")]
+use ln::MockLnBackend;
 use ln::PhoenixdClient;
-use secp256k1::{Keypair, Secp256k1};
+use enrollment::CodeError;
+use error::StoreError;
+use secret::Secret;
+use secp256k1::Keypair;
 use serde::Deserialize;
 use serde::Serialize;
-use tokio::sync::Mutex;
+use tower_http::cors::AllowOrigin;
 use tower_http::cors::CorsLayer;
 
 /// This is the main entry point for the server. It will start a web server that will listen for
 /// incoming requests and handle them. It will also handle the JWT token generation and validation.
 struct Server<Ln: LnBackend> {
-    /// The server will use this secret to sign the JWT tokens.
-    keypair: Keypair,
+    /// The keys the server signs authorizations and JWT tokens with, see [keys].
+    keys: keys::Keyring,
     /// The server will use this database to store the lockers and their state.
-    database: Arc<Mutex<sqlite::Connection>>,
+    database: Arc<db::Pool>,
     ln: Ln,
+    config: config::Config,
+    metrics: metrics::Metrics,
+    /// Limits how often each locker can call `/verify_token`, keyed by locker id.
+    verify_token_limiter: ratelimit::RateLimiter<i64>,
+    /// Limits how often anyone can ask for the receipt of each payment, keyed by payment hash.
+    receipt_poll_limiter: ratelimit::RateLimiter<String>,
+    /// The payments the lightning backend recently told us weren't settled yet, keyed by payment
+    /// hash, so polls for their receipt don't each ask it again.
+    unpaid_payments: ratelimit::Cooldown<String>,
+    /// Limits how often each client can call the cheap public routes, keyed by IP address.
+    read_limiter: ratelimit::RateLimiter<IpAddr>,
+    /// Limits how often each client can call the expensive public routes, keyed by IP address.
+    expensive_limiter: ratelimit::RateLimiter<IpAddr>,
+    /// Where changes of the lockers' state are published for `/ws/lockers`.
+    locker_feed: feed::LockerFeed,
+    /// Who follows which pending payment on `/payments/{payment_hash}/events`.
+    payment_watch: payment_events::PaymentWatch,
+    /// Wakes [tasks::deliver_webhooks] up when webhooks were queued.
+    webhooks_queued: tokio::sync::Notify,
 }
 
-async fn get_locker(
-    Path(locker_id): Path<i64>,
-    state: State<Arc<Server<PhoenixdClient>>>,
-) -> Result<Body, error::Error> {
-    let lockers = state.list_lockers().await?;
-    let locker = lockers
+async fn get_locker<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    // read before the locker, so if it changes meanwhile the next request gets it again
+    let etag = lockers_etag(state.database.locker_version());
+    if if_none_match(&headers, &etag) {
+        return Ok(with_etag(etag, StatusCode::NOT_MODIFIED));
+    }
+
+    let locker = state.get_locker(locker_id).await?;
+    if !locker.active {
+        return Err(error::Error::NotFound);
+    }
+
+    Ok(with_etag(etag, ApiResponse::ok(locker)))
+}
+
+/// The version of the API described by `/.well-known/locker-server.json`, bumped whenever we
+/// change it in a way existing lockers or clients would notice.
+const API_VERSION: u32 = 1;
+
+/// The versions of the API, each served under its own prefix by [build_router], so one that
+/// changes what routes answer can be served next to the one clients already use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// The version we point clients to, in the server document and in the links we hand out.
+    pub const LATEST: ApiVersion = ApiVersion::V1;
+
+    /// What the version's paths start with.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/v1",
+        }
+    }
+}
+
+/// How long devices may reuse what the key and discovery routes return before asking again.
+const DISCOVERY_MAX_AGE_SECONDS: u64 = 300;
+
+/// Lists every server key with its validity window, so lockers can update the set of keys they
+/// trust before we start signing with a new one. Keys are `active` if we sign with them, `valid`
+/// if lockers should still trust them, `pending` until their window starts and `retired` once it
+/// ended.
+async fn get_keys<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let body = ApiResponse::ok(list_keys(&state, now()));
+
+    cacheable(&headers, &body)
+}
+
+/// Returns the key we sign with, so lockers and clients don't have to copy it from our logs,
+/// along with every other key as listed by `/keys`.
+async fn get_pubkey<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let active = state.keys.active();
+    let body = ApiResponse::ok(serde_json::json!({
+        "kid": active.id,
+        "pubkey": active.keypair.expose().x_only_public_key().0.to_string(),
+        "keys": list_keys(&state, now()),
+    }));
+
+    cacheable(&headers, &body)
+}
+
+/// Describes the server to devices setting themselves up: the key we sign with, the version of
+/// the API and what using a locker costs. Unlike the other routes it's a bare JSON document, as
+/// found under `/.well-known`.
+async fn get_server_document<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let active = state.keys.active();
+    let document = serde_json::json!({
+        "api_version": API_VERSION,
+        "api_prefix": ApiVersion::LATEST.prefix(),
+        "server_version": env!("CARGO_PKG_VERSION"),
+        "kid": active.id,
+        "pubkey": active.keypair.expose().x_only_public_key().0.to_string(),
+        "keys_url": format!("{}/keys", ApiVersion::LATEST.prefix()),
+        "revocations_url": format!("{}/revocations", ApiVersion::LATEST.prefix()),
+        "token_ttl_seconds": state.config.token_ttl_seconds,
+        // sites and lockers may set their own price, which `/lockers` lists
+        "pricing": {
+            "default_price_per_minute_msat": state.config.price_per_minute_msat,
+            "rounding": "up_to_sat",
+        },
+    });
+
+    cacheable(&headers, &document)
+}
+
+/// Every key with its status at `now`, see [get_keys].
+fn list_keys<Ln: LnBackend>(state: &Server<Ln>, now: u64) -> Vec<serde_json::Value> {
+    state
+        .keys
+        .keys()
+        .iter()
+        .map(|key| {
+            let status = if key.id == state.keys.active().id {
+                "active"
+            } else if key.not_after.is_some_and(|not_after| not_after <= now) {
+                "retired"
+            } else if key.not_before.is_some_and(|not_before| now < not_before) {
+                "pending"
+            } else {
+                "valid"
+            };
+
+            serde_json::json!({
+                "kid": key.id,
+                "pubkey": key.keypair.expose().x_only_public_key().0.to_string(),
+                "status": status,
+                "not_before": key.not_before,
+                "not_after": key.not_after,
+            })
+        })
+        .collect()
+}
+
+/// Responds with `body` as JSON, letting devices cache it for [DISCOVERY_MAX_AGE_SECONDS] and
+/// then check whether it changed with `If-None-Match`, which gets them an empty `304` if it
+/// didn't.
+fn cacheable(headers: &HeaderMap, body: &impl Serialize) -> Result<Response, error::Error> {
+    let body = serde_json::to_vec(body).map_err(|_| error::Error::Server)?;
+    let etag = format!(
+        "\"{}\"",
+        &sha256::Hash::hash(&body).to_byte_array().to_lower_hex_string()[..32]
+    );
+    let unchanged = if_none_match(headers, &etag);
+
+    let caching = [
+        (
+            header::CACHE_CONTROL,
+            format!("public, max-age={DISCOVERY_MAX_AGE_SECONDS}"),
+        ),
+        (header::ETAG, etag),
+    ];
+
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, caching).into_response());
+    }
+
+    Ok((
+        caching,
+        [(header::CONTENT_TYPE, api::JSON_CONTENT_TYPE)],
+        body,
+    )
+        .into_response())
+}
+
+/// Whether the client already has what's tagged `etag`, according to its `If-None-Match`.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag))
+}
+
+/// The `ETag` of what `/lockers` and `/lockers/{id}` return while the lockers are at `version`,
+/// see [db::locker_version]. Our own version is part of it, in case we start returning lockers
+/// differently.
+fn lockers_etag(version: u64) -> String {
+    format!("\"{}-{version}\"", env!("CARGO_PKG_VERSION"))
+}
+
+/// Tags `response` with `etag`, asking clients to check whether it changed each time they use it.
+fn with_etag(etag: String, response: impl IntoResponse) -> Response {
+    (
+        [
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+            (header::ETAG, etag),
+        ],
+        response,
+    )
+        .into_response()
+}
+
+/// The most lockers we return in a single page of `/lockers`.
+const MAX_LOCKERS_PER_PAGE: u64 = 500;
+
+/// The states `/lockers` can be filtered by. Lockers waiting to be approved are never listed.
+const LISTED_LOCKER_STATES: [&str; 5] =
+    ["available", "reserved", "in_use", "awaiting_payment", "maintenance"];
+
+#[derive(Debug, Deserialize)]
+struct LockersQuery {
+    /// Only return the lockers at this site.
+    site_id: Option<i64>,
+    /// Only return the lockers in this state, one of [LISTED_LOCKER_STATES].
+    state: Option<String>,
+    /// Only return the lockers of this size.
+    size: Option<String>,
+    /// Only return the lockers at this location, in any case.
+    location: Option<String>,
+    /// How many lockers to return, defaults to 100.
+    limit: Option<u64>,
+    /// How many lockers to skip, used to fetch the next pages.
+    offset: Option<u64>,
+    /// Only return lockers with a greater id, to fetch the page after the one ending with it.
+    after_id: Option<i64>,
+}
+
+/// Returns the available lockers and their state, a page at a time, ordered by id, along with how
+/// many match the filters in total. This will be used to display the lockers to the user.
+///
+/// Kiosks poll this, so it's tagged with [lockers_etag], and clients sending it back in
+/// `If-None-Match` get an empty `304` until a locker changes.
+async fn get_lockers<Ln: LnBackend>(
+    Query(query): Query<LockersQuery>,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    if let Some(locker_state) = &query.state {
+        if !LISTED_LOCKER_STATES.contains(&locker_state.as_str()) {
+            return Err(error::Error::InvalidParam(format!(
+                "state: expected one of {}",
+                LISTED_LOCKER_STATES.join(", ")
+            )));
+        }
+    }
+    let size = match &query.size {
+        Some(size) => Some(size.parse::<LockerSize>().map_err(|_| {
+            error::Error::InvalidParam("size: expected small, medium or large".to_string())
+        })?),
+        None => None,
+    };
+
+    let etag = lockers_etag(state.database.locker_version());
+    if if_none_match(&headers, &etag) {
+        return Ok(with_etag(etag, StatusCode::NOT_MODIFIED));
+    }
+
+    let filter = LockerFilter {
+        site_id: query.site_id,
+        state: query.state,
+        size,
+        location: query.location,
+    };
+    let limit = query.limit.unwrap_or(100).min(MAX_LOCKERS_PER_PAGE);
+    let (lockers, total) = state
+        .list_lockers_page(filter, query.after_id, limit, query.offset.unwrap_or(0))
+        .await?;
+    Ok(with_etag(etag, ApiResponse::ok(lockers).with_total(total)))
+}
+
+async fn use_locker<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<UseLockerResponse>, error::Error> {
+    let now = now();
+
+    // customers who logged in can find the session again under `/me/sessions`
+    let linking_key = customer::customer(&state, &headers).await?;
+    let nostr_pubkey = customer::nostr_user(&state, &method, &uri, &headers)?;
+
+    // the check and the transition must happen atomically, otherwise two clients could both see
+    // the locker as available and both get a signature for it
+    let reservation = reservation::presented(&headers);
+    let session_id = state
+        .claim_locker(locker_id, now, linking_key, reservation)
+        .await?;
+    // the session was just created, so it has no key yet
+    if let Some(nostr_pubkey) = &nostr_pubkey {
+        state.set_session_nostr_pubkey(session_id, nostr_pubkey).await?;
+    }
+    state
+        .record_event(
+            locker_id,
+            "claimed",
+            "customer",
+            serde_json::json!({ "session_id": session_id }),
+        )
+        .await;
+
+    let auth = state.get_locker_auth(locker_id).await?;
+    let key = state.keys.active();
+    let expires_at = now + state.config.token_ttl_seconds;
+    // never in the format old firmware expects, since anyone can claim a locker and any of those
+    // signatures opens any locker running it
+    let signature = authorize(key, &auth, locker_id, signing::Action::Store, now, expires_at);
+
+    let claims = token::Claims::open(
+        locker_id,
+        session_id,
+        signing::Action::Store,
+        None,
+        now,
+        state.config.token_ttl_seconds,
+    );
+    let signed_receipt = receipt::sign(
+        receipt::Receipt {
+            action: signing::Action::Store,
+            actor: None,
+            expires_at,
+            issued_at: now,
+            kid: key.id.clone(),
+            locker_id,
+            payment_hash: None,
+        },
+        key,
+    );
+    Ok(ApiResponse::ok(UseLockerResponse {
+        locker_id,
+        session_id,
+        action: signing::Action::Store,
+        start_time: now,
+        expires_at,
+        signature,
+        expiring_signature: None,
+        kid: key.id.clone(),
+        token: token::issue(&claims, key),
+        signed_receipt,
+    }))
+}
+
+async fn pay_for_usage<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<InvoiceResponse>, error::Error> {
+    let nostr_pubkey = customer::nostr_user(&state, &method, &uri, &headers)?;
+    let locker = state.get_locker(locker_id).await?;
+    if locker.state != "in_use" && locker.state != "awaiting_payment" {
+        return Err(error::Error::Conflict("Nobody is using the locker".to_string()));
+    }
+
+    let session = state.get_active_session(locker_id).await?;
+    if let Some(nostr_pubkey) = &nostr_pubkey {
+        if !state.set_session_nostr_pubkey(session.id, nostr_pubkey).await? {
+            return Err(error::Error::Conflict(
+                "The session belongs to another nostr user".to_string(),
+            ));
+        }
+    }
+
+    // once the customer ended their usage they owe what it came to then, otherwise the time they
+    // had the locker until now
+    let (lease_time, amount) = billable_usage(&state, &locker, &session, now());
+    let invoice = invoice_session(&state, locker_id, session.id, amount).await?;
+
+    Ok(ApiResponse::ok(InvoiceResponse {
+        locker_id,
+        lease_time,
+        amount,
+        invoice,
+    }))
+}
+
+/// Ends the billable part of a session: the customer is done with the locker, so what they owe is
+/// fixed now, however long they then take to pay. The locker waits in `awaiting_payment` until
+/// it's emptied, and `/pay_for_usage` invoices the amount fixed here. Ending it again returns the
+/// same amount.
+async fn end_usage<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let locker = state.get_locker(locker_id).await?;
+    if locker.state != "in_use" && locker.state != "awaiting_payment" {
+        return Err(error::Error::Conflict("Nobody is using the locker".to_string()));
+    }
+
+    let session = state.get_active_session(locker_id).await?;
+    let now = now();
+    let (lease_time, amount) = billable_usage(&state, &locker, &session, now);
+
+    // ending it again only tells what was fixed the first time
+    if session.usage_ended_at.is_none() {
+        // someone else may have ended it in the meantime, with another amount
+        if !state.end_usage(locker_id, session.id, now, amount).await? {
+            return Err(error::Error::Conflict("Usage was ended by another request".to_string()));
+        }
+
+        state
+            .record_event(
+                locker_id,
+                "usage_ended",
+                "customer",
+                serde_json::json!({
+                    "session_id": session.id,
+                    "lease_time": lease_time,
+                    "amount": amount,
+                }),
+            )
+            .await;
+    }
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "locker_id": locker_id,
+        "session_id": session.id,
+        "state": "awaiting_payment",
+        "lease_time": lease_time,
+        "amount": amount,
+    })))
+}
+
+/// How long `session` was billed for and what it costs, in satoshis: what it came to when the
+/// customer ended their usage, or else the time they had `locker` until `now`.
+fn billable_usage<Ln: LnBackend>(
+    state: &Server<Ln>,
+    locker: &Locker,
+    session: &UsageSession,
+    now: u64,
+) -> (u64, u64) {
+    if let Some(usage_ended_at) = session.usage_ended_at {
+        let lease_time = usage_ended_at.saturating_sub(session.started_at);
+        return (lease_time, session.amount_sat.unwrap_or(0));
+    }
+
+    let lease_time = now.saturating_sub(session.started_at);
+    let price_per_minute_msat = session_price(state, locker, session);
+
+    (lease_time, pricing::amount_sat(lease_time, price_per_minute_msat))
+}
+
+/// The rate `session` is billed at, in millisatoshis per minute: the one it started with, so
+/// prices changed meanwhile only apply from the next session on. Sessions from before we kept it
+/// are billed at what `locker` costs now.
+fn session_price<Ln: LnBackend>(
+    state: &Server<Ln>,
+    locker: &Locker,
+    session: &UsageSession,
+) -> u64 {
+    session.price_per_minute_msat.unwrap_or_else(|| {
+        pricing::price_per_minute_msat(
+            locker.price_per_minute_msat,
+            locker.site.as_ref().and_then(|site| site.price_per_minute_msat),
+            state.config.price_per_minute_msat,
+        )
+    })
+}
+
+/// What `/lockers/{locker_id}/meter` answers: what the customer using a locker owes so far.
+#[derive(Debug, Clone, Serialize)]
+struct Meter {
+    locker_id: i64,
+    session_id: i64,
+    started_at: u64,
+    elapsed_seconds: u64,
+    /// The rate the session started with, which it's billed at whatever the price is now.
+    price_per_minute_msat: u64,
+    amount_sat: u64,
+    /// How `amount_sat` came about, see [pricing::breakdown].
+    breakdown: Vec<pricing::Charge>,
+}
+
+/// Tells what the customer using a locker owes so far and how it came to that, for kiosks to show
+/// a running meter. Unlike `/pay_for_usage` nothing is invoiced or recorded, so it can be polled.
+async fn get_locker_meter<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Meter>, error::Error> {
+    let locker = state.get_locker(locker_id).await?;
+    if !locker.active {
+        return Err(error::Error::NotFound);
+    }
+    // once the customer ended their usage the amount is fixed, `/end_usage` says what it is
+    if locker.state != "in_use" {
+        return Err(error::Error::Conflict("The locker isn't in use".to_string()));
+    }
+
+    let session = state.get_active_session(locker_id).await?;
+    let elapsed_seconds = now().saturating_sub(session.started_at);
+    let price_per_minute_msat = session_price(&state, &locker, &session);
+
+    Ok(ApiResponse::ok(Meter {
+        locker_id,
+        session_id: session.id,
+        started_at: session.started_at,
+        elapsed_seconds,
+        price_per_minute_msat,
+        amount_sat: pricing::amount_sat(elapsed_seconds, price_per_minute_msat),
+        breakdown: pricing::breakdown(elapsed_seconds, price_per_minute_msat),
+    }))
+}
+
+/// Makes an invoice for `amount` and records it as the payment for `session_id`.
+async fn invoice_session<Ln: LnBackend>(
+    state: &Server<Ln>,
+    locker_id: i64,
+    session_id: i64,
+    amount: u64,
+) -> Result<ln::Invoice, error::Error> {
+    let invoice = state
+        .ln
+        .get_invoice(amount)
+        .map_err(|_| error::Error::LnBackend)?;
+
+    state
+        .insert_payment(amount, &invoice.payment_hash, &invoice.bolt11, locker_id)
+        .await?;
+    state
+        .set_session_invoice(session_id, amount, &invoice.payment_hash)
+        .await?;
+    state
+        .record_event(
+            locker_id,
+            "invoiced",
+            "customer",
+            serde_json::json!({
+                "session_id": session_id,
+                "payment_hash": invoice.payment_hash,
+                "amount": amount,
+            }),
+        )
+        .await;
+
+    Ok(invoice)
+}
+
+/// Cancels a pending payment and, as far as the backend lets us, its invoice, recording that
+/// `actor` did it. Returns whether the invoice was cancelled, or `None` if the payment wasn't
+/// pending anymore.
+async fn cancel_pending_payment<Ln: LnBackend>(
+    state: &Server<Ln>,
+    locker_id: i64,
+    payment_hash: &str,
+    actor: &str,
+    now: u64,
+) -> Result<Option<bool>, error::Error> {
+    if !state.cancel_payment(payment_hash, now).await? {
+        return Ok(None);
+    }
+    state.payment_watch.notify(payment_hash);
+
+    // if the invoice stays payable and gets paid, the customer is refused a receipt and the
+    // payment is flagged for a refund
+    let invoice_cancelled = match state.ln.cancel_invoice(payment_hash.to_string()) {
+        Ok(cancelled) => cancelled,
+        Err(_) => {
+            eprintln!("[{actor}] failed to cancel the invoice for {payment_hash}");
+            false
+        }
+    };
+    state
+        .record_event(
+            locker_id,
+            "payment_cancelled",
+            actor,
+            serde_json::json!({
+                "payment_hash": payment_hash,
+                "invoice_cancelled": invoice_cancelled,
+            }),
+        )
+        .await;
+
+    Ok(Some(invoice_cancelled))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CancelUsage {
+    /// The token `/use_locker` handed out when the locker was claimed.
+    token: String,
+}
+
+/// Lets whoever claimed a locker back out. If the locker never reported being opened, the session
+/// is cancelled and the locker is available again for free. Otherwise something may be inside, so
+/// they get an invoice for [config::Config::cancel_fee_sat] instead of the usual price, whose
+/// receipt opens the locker to take it out. Either way, an invoice we were still waiting on for
+/// the session is cancelled.
+async fn cancel_usage<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<CancelUsage>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let now = now();
+
+    // only the token for claiming the locker, during its current session, proves who claimed it
+    let claims = token::verify_scoped(
+        &body.token,
+        &state.keys,
+        locker_id,
+        Some(signing::Action::Store),
+        now,
+    )
+    .map_err(|_| error::Error::Unauthorized)?;
+    if !state.is_token_session(&claims).await? {
+        return Err(error::Error::Unauthorized);
+    }
+
+    let session = state.get_active_session(locker_id).await?;
+    if let Some(payment_hash) = &session.payment_hash {
+        let payment = state.get_payment(payment_hash.clone()).await?;
+        // they already paid, their receipt is waiting for them
+        if payment.status == "paid" {
+            return Err(error::Error::Conflict(
+                "Payment was already received, its receipt is waiting".to_string(),
+            ));
+        }
+
+        cancel_pending_payment(&state, locker_id, payment_hash, "customer", now).await?;
+    }
+
+    // the locker may report being opened while we're at it, so only the store decides
+    let released = state.cancel_session(locker_id, session.id, now).await?;
+    let (amount, invoice) = if released {
+        (0, None)
+    } else {
+        // it wasn't cancelled because the locker was opened, unless the session ended meanwhile
+        if !state.is_token_session(&claims).await? {
+            return Err(error::Error::Conflict("Session ended while cancelling it".to_string()));
+        }
+
+        let fee = state.config.cancel_fee_sat;
+        (fee, Some(invoice_session(&state, locker_id, session.id, fee).await?))
+    };
+
+    state
+        .record_event(
+            locker_id,
+            "usage_cancelled",
+            "customer",
+            serde_json::json!({
+                "session_id": session.id,
+                "released": released,
+                "amount": amount,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "locker_id": locker_id,
+        "session_id": session.id,
+        "released": released,
+        "amount": amount,
+        "invoice": invoice,
+    })))
+}
+
+/// This will return a signed receipt for the payment. This receipt will be used to unlock
+/// the locker. The receipt will be signed by the server and will contain the locker id, and the
+/// current timestamp. The client will use this receipt to unlock the locker.
+async fn get_payment_receipt<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<ReceiptResponse>, error::Error> {
+    let payment = settled_payment(&state, String::from(payment_hash)).await?;
+
+    // a payment buys exactly one receipt, if we already issued it we hand out the same one again
+    if payment.status == "redeemed" {
+        return Ok(ApiResponse::ok(stored_receipt(&state, &payment).await?));
+    }
+
+    Ok(ApiResponse::ok(issue_receipt(&state, &payment).await?))
+}
+
+/// Like [get_payment_receipt], but returns the receipts for every locker the payment covers, by
+/// locker, for payments made for several at once, see [batch]. Receipts the locker was already
+/// opened with or that were revoked are left out.
+async fn get_payment_receipts<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Vec<ReceiptResponse>>, error::Error> {
+    let payment = settled_payment(&state, String::from(payment_hash)).await?;
+    let sessions = covered_sessions(&state, &payment).await?;
+
+    if payment.status == "redeemed" {
+        return Ok(ApiResponse::ok(stored_receipts(&state, &payment, &sessions).await?));
+    }
+
+    Ok(ApiResponse::ok(issue_receipts(&state, &payment, sessions).await?))
+}
+
+/// Returns the payment with `payment_hash` once it settled, `paid` or already `redeemed`, asking
+/// the lightning backend if it's still pending. Payments that can't buy receipts, or not yet, get
+/// the error telling why.
+async fn settled_payment<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment_hash: String,
+) -> Result<PendingPayment, error::Error> {
+    // clients poll this while waiting for the payment, often many of them from one kiosk, so we
+    // limit polls for each payment rather than for each client
+    let polled_at = Instant::now();
+    state
+        .receipt_poll_limiter
+        .check(payment_hash.clone(), polled_at)
+        .map_err(|wait| error::Error::RateLimited {
+            retry_after: wait.as_secs().max(1),
+        })?;
+
+    // the backend told us moments ago that it wasn't settled, so it most likely still isn't
+    if let Some(wait) = state.unpaid_payments.remaining(&payment_hash, polled_at) {
+        return Err(error::Error::PaymentRequired {
+            retry_after: wait.as_secs().max(1),
+        });
+    }
+
+    let payment = state.get_payment(payment_hash).await?;
+
+    match payment.status.as_str() {
+        "paid" | "redeemed" => Ok(payment),
+        "cancelled" => Err(cancelled_payment(state, &payment).await),
+        "pending" => match check_settlement(state, &payment).await? {
+            ln::InvoiceStatus::Paid => Ok(payment),
+            ln::InvoiceStatus::Expired => Err(error::Error::InvoiceGone),
+            ln::InvoiceStatus::Unpaid => Err(error::Error::PaymentRequired {
+                retry_after: state.config.receipt_poll_cache_seconds.max(1),
+            }),
+        },
+        _ => Err(error::Error::InvoiceGone),
+    }
+}
+
+/// Asks the lightning backend whether a pending payment settled, recording it if it did or if its
+/// invoice expired, and telling whoever follows it on `/payments/{payment_hash}/events`.
+async fn check_settlement<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> Result<ln::InvoiceStatus, error::Error> {
+    let payment_hash = &payment.payment_hash;
+    let payment_status = state
+        .ln
+        .get_invoice_status(payment_hash.clone())
+        .map_err(|_| error::Error::LnBackend)?;
+
+    match payment_status {
+        ln::InvoiceStatus::Paid => {
+            if state.set_payment_paid(payment_hash, now()).await? {
+                state
+                    .send_webhooks(
+                        "payment.settled",
+                        payment.locker_id,
+                        serde_json::json!({
+                            "payment_hash": payment_hash,
+                            "locker_id": payment.locker_id,
+                            "amount": payment.amount,
+                        }),
+                    )
+                    .await;
+            }
+            state
+                .payment_watch
+                .notify(payment_hash);
+        }
+        ln::InvoiceStatus::Expired => {
+            state.set_payment_expired(payment_hash, now()).await?;
+            state.payment_watch.notify(payment_hash);
+        }
+        ln::InvoiceStatus::Unpaid => {
+            state
+                .unpaid_payments
+                .start(payment_hash.clone(), Instant::now());
+        }
+    }
+
+    Ok(payment_status)
+}
+
+/// The sessions a payment covers: those of every locker it was made for, see [batch], or the one
+/// session it was made for alone.
+async fn covered_sessions<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> Result<Vec<UsageSession>, error::Error> {
+    let sessions = state.get_payment_sessions(&payment.payment_hash).await?;
+    if !sessions.is_empty() {
+        return Ok(sessions);
+    }
+
+    let session = state
+        .get_session_by_payment(&payment.payment_hash, payment.locker_id)
+        .await?;
+    Ok(vec![session])
+}
+
+/// The one session a payment covers, refusing payments made for several lockers, whose receipts
+/// are under `/payments/{payment_hash}/receipts`.
+async fn only_session<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> Result<UsageSession, error::Error> {
+    let mut sessions = covered_sessions(state, payment).await?;
+    if sessions.len() != 1 {
+        return Err(error::Error::Conflict(
+            "Payment covers several lockers, get its receipts from \
+             /payments/{payment_hash}/receipts"
+                .to_string(),
+        ));
+    }
+
+    Ok(sessions.remove(0))
+}
+
+/// Issues the receipt a settled payment buys, or hands out the one a concurrent request issued.
+async fn issue_receipt<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> Result<ReceiptResponse, error::Error> {
+    let session = only_session(state, payment).await?;
+    let mut receipts = issue_receipts(state, payment, vec![session]).await?;
+    Ok(receipts.remove(0))
+}
+
+/// Issues the receipts a settled payment buys, one for the locker of each of `sessions`, or hands
+/// out those a concurrent request issued.
+///
+/// The receipts are stored all at once or not at all, so if anything goes wrong along the way the
+/// payment is still `paid` and asking again issues every one of them: no locker is left paid for
+/// without a receipt to open it.
+async fn issue_receipts<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+    sessions: Vec<UsageSession>,
+) -> Result<Vec<ReceiptResponse>, error::Error> {
+    let payment_hash = payment.payment_hash.clone();
+    let now = now();
+    let key = state.keys.active();
+    let expires_at = now + state.config.token_ttl_seconds;
+
+    let mut issued = Vec::new();
+    for session in sessions {
+        let locker_id = session.locker_id;
+        let site_id = state.get_locker(locker_id).await?.site.map(|site| site.id);
+        let auth = state.get_locker_auth(locker_id).await?;
+        let signature =
+            authorize(key, &auth, locker_id, signing::Action::Retrieve, now, expires_at);
+        // signed once and for all, so the receipt is the same however many times it's fetched
+        let legacy_signature = match auth {
+            LockerAuth::Schnorr(_) if state.config.legacy_signatures(now) => {
+                let digest = signing::legacy_digest(locker_id, now);
+                Some(sign_authorization(key.keypair.expose(), digest))
+            }
+            _ => None,
+        };
+
+        let claims = token::Claims::open(
+            locker_id,
+            session.id,
+            signing::Action::Retrieve,
+            Some(payment_hash.clone()),
+            now,
+            state.config.token_ttl_seconds,
+        );
+        let mut receipt = Receipt {
+            payment_hash: payment_hash.clone(),
+            locker_id,
+            site_id,
+            session_id: session.id,
+            issued_at: now,
+            expires_at: Some(expires_at),
+            signature,
+            kid: Some(key.id.clone()),
+            token: Some(token::issue(&claims, key)),
+            receipt_signature: None,
+            legacy_signature,
+            consumed_at: None,
+        };
+        let signed = receipt::sign(signed_fields(&receipt, &key.id, expires_at), key);
+        receipt.receipt_signature = Some(signed.signature);
+        issued.push((receipt, session, auth));
+    }
+
+    // a concurrent request may have beaten us to it, in which case we hand out theirs
+    let receipts: Vec<_> = issued.iter().map(|(receipt, _, _)| receipt.clone()).collect();
+    if !state.redeem_payment(&receipts).await? {
+        let payment = state.get_payment(payment_hash).await?;
+        if payment.status == "cancelled" {
+            return Err(cancelled_payment(state, &payment).await);
+        }
+
+        let sessions: Vec<_> = issued.into_iter().map(|(_, session, _)| session).collect();
+        return stored_receipts(state, &payment, &sessions).await;
+    }
+
+    for (receipt, session, _) in &issued {
+        state
+            .record_event(
+                receipt.locker_id,
+                "receipt_issued",
+                "customer",
+                serde_json::json!({ "session_id": session.id, "payment_hash": payment_hash }),
+            )
+            .await;
+    }
+
+    Ok(issued
         .iter()
-        .find(|l| l.id == locker_id)
-        .ok_or(error::Error::NotFound)?;
+        .map(|(receipt, session, auth)| receipt_body(state, receipt, session, auth))
+        .collect())
+}
+
+/// How far along a payment is, see [get_payment_status].
+#[derive(Debug, Clone, Serialize)]
+struct PaymentStatus {
+    payment_hash: String,
+    /// One of `pending`, `paid`, `expired`, `redeemed` or `cancelled`.
+    status: String,
+    amount: u64,
+    locker_id: i64,
+    created_at: Option<u64>,
+    settled_at: Option<u64>,
+}
+
+/// Tells whether a payment arrived, without issuing its receipt, so clients can wait for it
+/// before calling `/payments/{payment_hash}/receipt`. Pending payments are checked with the
+/// lightning backend, unless it told us moments ago that they weren't settled, and stay pending if
+/// it can't be reached.
+async fn get_payment_status<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<PaymentStatus>, error::Error> {
+    let payment_hash = String::from(payment_hash);
+    let mut payment = state.get_payment(payment_hash.clone()).await?;
+
+    let polled_recently = state
+        .unpaid_payments
+        .remaining(&payment_hash, Instant::now())
+        .is_some();
+    if payment.status == "pending" && !polled_recently {
+        match check_settlement(&state, &payment).await {
+            Ok(ln::InvoiceStatus::Unpaid) => {}
+            Ok(_) => payment = state.get_payment(payment_hash).await?,
+            Err(e) => eprintln!("[payments] failed to check payment {payment_hash}: {e:?}"),
+        }
+    }
+
+    Ok(ApiResponse::ok(PaymentStatus {
+        payment_hash: payment.payment_hash,
+        status: payment.status,
+        amount: payment.amount,
+        locker_id: payment.locker_id,
+        created_at: payment.created_at,
+        settled_at: payment.paid_at,
+    }))
+}
+
+/// An invoice handed out again, see [get_payment_invoice] and [get_locker_invoice].
+#[derive(Debug, Clone, Serialize)]
+struct PaymentInvoice {
+    payment_hash: String,
+    locker_id: i64,
+    amount: u64,
+    bolt11: String,
+    /// Payments made before we kept when they were created don't say when they expire.
+    expires_at: Option<u64>,
+}
+
+/// Returns the invoice for a payment again, for clients that lost it, as long as it can still be
+/// paid.
+async fn get_payment_invoice<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<PaymentInvoice>, error::Error> {
+    let payment_hash = String::from(payment_hash);
+    let payment = state.get_payment(payment_hash).await?;
+
+    match payment.status.as_str() {
+        "pending" => {}
+        "expired" => return Err(error::Error::InvoiceGone),
+        _ => return Err(error::Error::Conflict("Payment isn't pending anymore".to_string())),
+    }
+
+    let expires_at = payment
+        .created_at
+        .map(|created_at| created_at + ln::INVOICE_EXPIRY_SECONDS);
+    if expires_at.is_some_and(|expires_at| expires_at <= now()) {
+        return Err(error::Error::InvoiceGone);
+    }
+
+    let Some(bolt11) = payment.bolt11 else {
+        return Err(error::Error::NotFound);
+    };
+
+    Ok(ApiResponse::ok(PaymentInvoice {
+        payment_hash: payment.payment_hash,
+        locker_id: payment.locker_id,
+        amount: payment.amount,
+        bolt11,
+        expires_at,
+    }))
+}
+
+/// Returns the invoice a locker is waiting on, for customers whose app lost it after
+/// `/pay_for_usage`, as long as it can still be paid. Lockers with no such invoice get a `404`.
+async fn get_locker_invoice<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<PaymentInvoice>, error::Error> {
+    let now = now();
+    let payment = state
+        .get_payable_payment(locker_id, now.saturating_sub(ln::INVOICE_EXPIRY_SECONDS))
+        .await?;
+    let (Some(bolt11), Some(created_at)) = (payment.bolt11, payment.created_at) else {
+        return Err(error::Error::NotFound);
+    };
+
+    Ok(ApiResponse::ok(PaymentInvoice {
+        payment_hash: payment.payment_hash,
+        locker_id: payment.locker_id,
+        amount: payment.amount,
+        bolt11,
+        expires_at: Some(created_at + ln::INVOICE_EXPIRY_SECONDS),
+    }))
+}
+
+/// The fields of the signed receipt handed out along with `receipt`, see [receipt].
+fn signed_fields(receipt: &Receipt, kid: &str, expires_at: u64) -> receipt::Receipt {
+    receipt::Receipt {
+        action: signing::Action::Retrieve,
+        actor: None,
+        expires_at,
+        issued_at: receipt.issued_at,
+        kid: kid.to_string(),
+        locker_id: receipt.locker_id,
+        payment_hash: Some(receipt.payment_hash.clone()),
+    }
+}
+
+fn receipt_body<Ln: LnBackend>(
+    state: &Server<Ln>,
+    receipt: &Receipt,
+    session: &UsageSession,
+    auth: &LockerAuth,
+) -> ReceiptResponse {
+    // we hand out the signatures stored when the receipt was issued rather than signing again,
+    // which would give different ones each time. Receipts issued before authorizations expired or
+    // before we had several keys only have the authorization they were issued with, and so do
+    // receipts whose key was since removed. Only the signatures of receipts issued before we kept
+    // them, or the legacy ones of receipts issued while we didn't make those, are made again
+    let key = receipt.kid.as_deref().and_then(|kid| state.keys.get(kid));
+    let (signature, expiring_signature, signed_receipt) = match (receipt.expires_at, key) {
+        (Some(expires_at), Some(key)) => {
+            let (signature, expiring_signature) = match &receipt.legacy_signature {
+                Some(legacy) if state.config.legacy_signatures(now()) => {
+                    (legacy.clone(), Some(receipt.signature.clone()))
+                }
+                _ => authorization_signatures(
+                    state,
+                    key,
+                    auth,
+                    receipt.locker_id,
+                    receipt.issued_at,
+                    receipt.signature.clone(),
+                ),
+            };
+            let fields = signed_fields(receipt, &key.id, expires_at);
+            let signed_receipt = match &receipt.receipt_signature {
+                Some(signature) => receipt::SignedReceipt {
+                    receipt: fields,
+                    signature: signature.clone(),
+                },
+                None => receipt::sign(fields, key),
+            };
+            (signature, expiring_signature, Some(signed_receipt))
+        }
+        _ => (receipt.signature.clone(), None, None),
+    };
+
+    ReceiptResponse {
+        locker_id: receipt.locker_id,
+        site_id: receipt.site_id,
+        session_id: receipt.session_id,
+        action: signing::Action::Retrieve,
+        start_time: session.started_at,
+        issued_at: receipt.issued_at,
+        expires_at: receipt.expires_at,
+        signature,
+        expiring_signature,
+        kid: receipt.kid.clone(),
+        token: receipt.token.clone(),
+        signed_receipt,
+    }
+}
+
+/// Signs an authorization digest from [signing] with the server key, returning the signature in
+/// hex.
+fn sign_authorization(keypair: &Keypair, digest: [u8; 32]) -> String {
+    signing::sign_schnorr(keypair, &digest)
+        .to_byte_array()
+        .to_upper_hex_string()
+}
+
+/// Authorizes opening `locker_id` from `timestamp` until `expires_at` the way the locker checks
+/// it: signed with `key`, or tagged with the secret we share if it uses [AuthMode::Hmac]. Either
+/// way in hex.
+fn authorize(
+    key: &keys::ServerKey,
+    auth: &LockerAuth,
+    locker_id: i64,
+    action: signing::Action,
+    timestamp: u64,
+    expires_at: u64,
+) -> String {
+    match auth {
+        LockerAuth::Schnorr(_) => sign_authorization(
+            key.keypair.expose(),
+            signing::open_auth_digest(locker_id, action, timestamp, expires_at),
+        ),
+        LockerAuth::Hmac(secret) => {
+            signing::open_auth_hmac(secret.expose(), locker_id, action, timestamp, expires_at)
+                .to_upper_hex_string()
+        }
+    }
+}
+
+/// Returns the `signature` and `expiring_signature` fields of the response for an authorization
+/// to open `locker_id` from `timestamp`, given the `expiring` one made by [authorize].
+///
+/// While [config::Config::legacy_signatures] holds, `signature` is what old firmware expects and
+/// the one covering the expiry goes in `expiring_signature`. Otherwise `signature` covers the
+/// expiry and there's no `expiring_signature`. Lockers using HMAC never ran old firmware.
+fn authorization_signatures<Ln: LnBackend>(
+    state: &Server<Ln>,
+    key: &keys::ServerKey,
+    auth: &LockerAuth,
+    locker_id: i64,
+    timestamp: u64,
+    expiring: String,
+) -> (String, Option<String>) {
+    if !state.config.legacy_signatures(now()) || matches!(auth, LockerAuth::Hmac(_)) {
+        return (expiring, None);
+    }
+
+    let legacy = sign_authorization(
+        key.keypair.expose(),
+        signing::legacy_digest(locker_id, timestamp),
+    );
+    (legacy, Some(expiring))
+}
+
+/// Rejects requests whose body is larger than [config::Config::max_body_bytes], reading at most
+/// that much of it whether or not it says how long it is.
+///
+/// The JSON in the bodies we accept is parsed by serde_json, which gives up past 128 levels of
+/// nesting, so a small body can't blow our stack either.
+async fn limit_body<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<axum::response::Response, error::Error> {
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, state.config.max_body_bytes)
+        .await
+        .map_err(|_| error::Error::PayloadTooLarge)?;
+
+    let request = axum::extract::Request::from_parts(parts, Body::from(body));
+    Ok(next.run(request).await)
+}
+
+/// Answers GET requests to the routes that claim lockers or create invoices with a `405` pointing
+/// at POST, so link prefetchers, crawlers and refreshes can't do either. While
+/// [config::Config::legacy_get_mutations] is set they still work, with a `Deprecation` header.
+async fn reject_get<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response, error::Error> {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return Ok(next.run(request).await);
+    }
+
+    if !state.config.legacy_get_mutations {
+        return Err(error::Error::MethodNotAllowed);
+    }
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert("Deprecation", header::HeaderValue::from_static("true"));
+    Ok(response)
+}
+
+/// Marks answers from `/payment_receipt/{payment_hash}`, the old path of
+/// `/payments/{payment_hash}/receipt`, as deprecated, pointing at the new one in `version`, and
+/// logs who's still using it, so we can tell when [config::Config::legacy_receipt_path] can be
+/// turned off.
+async fn deprecated_receipt_path(
+    State(version): State<ApiVersion>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let payment_hash = request
+        .uri()
+        .path()
+        .trim_start_matches("/payment_receipt/")
+        .to_string();
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+    println!("[deprecated_receipt_path] /payment_receipt used by {user_agent}");
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", header::HeaderValue::from_static("true"));
+    let prefix = version.prefix();
+    let link = format!("<{prefix}/payments/{payment_hash}/receipt>; rel=\"successor-version\"");
+    if let Ok(link) = link.parse() {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
+
+/// Marks answers to paths without a version prefix, as the API was served before `/v1`, as
+/// deprecated, pointing at the same path under `/v1` unless the route already links to where it
+/// moved, and logs who's still using them, so we can tell when
+/// [config::Config::legacy_unprefixed_paths] can be turned off.
+async fn deprecated_unprefixed_path(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+    println!("[deprecated_unprefixed_path] {path} used by {user_agent}");
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", header::HeaderValue::from_static("true"));
+    if !headers.contains_key(header::LINK) {
+        let prefix = ApiVersion::V1.prefix();
+        let link = format!("<{prefix}{path}>; rel=\"successor-version\"");
+        if let Ok(link) = link.parse() {
+            headers.insert(header::LINK, link);
+        }
+    }
+    response
+}
+
+/// The request header asking us to sign the response, whatever its value.
+const SIGN_RESPONSE_HEADER: &str = "x-sign-response";
+
+/// The response header with when we signed the response, as a unix timestamp.
+const SERVER_TIMESTAMP_HEADER: &str = "x-server-timestamp";
+
+/// The response header with our BIP340 signature over [signing::response_digest], in hex.
+const SERVER_SIGNATURE_HEADER: &str = "x-server-signature";
+
+/// The response header with the id of the key in [SERVER_SIGNATURE_HEADER], see [keys].
+const SERVER_KEY_HEADER: &str = "x-server-key";
+
+/// Signs the responses to requests carrying [SIGN_RESPONSE_HEADER], or every response if
+/// [config::Config::sign_responses] is set, so kiosks can tell when something between us tampered
+/// with them, even over plain HTTP. The signature is by our active key over the timestamp and the
+/// SHA-256 of the body, see [signing::response_digest] and `test/verify_response.py`.
+async fn sign_response<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let requested = request.headers().contains_key(SIGN_RESPONSE_HEADER);
+    let response = next.run(request).await;
+    if !requested && !state.config.sign_responses {
+        return response;
+    }
+    // event streams don't end, so there's no body to sign
+    if response.headers().get(header::CONTENT_TYPE).is_some_and(|t| t == "text/event-stream") {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("[sign_response] failed to read the response body: {e:?}");
+            return axum::response::IntoResponse::into_response(error::Error::Server);
+        }
+    };
+
+    let timestamp = now();
+    let key = state.keys.active();
+    let signature = signing::sign_schnorr(
+        key.keypair.expose(),
+        &signing::response_digest(timestamp, &body),
+    );
+
+    for (name, value) in [
+        (SERVER_TIMESTAMP_HEADER, timestamp.to_string()),
+        (SERVER_SIGNATURE_HEADER, signature.to_byte_array().to_lower_hex_string()),
+        (SERVER_KEY_HEADER, key.id.clone()),
+    ] {
+        parts.headers.insert(name, value.parse().expect("hex and digits are valid"));
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Lets the pages at [config::CorsConfig::allowed_origins] call us from a browser. Preflight
+/// requests from anywhere else get no `Access-Control-Allow-Origin`, so browsers refuse them.
+fn cors_layer(config: &config::CorsConfig) -> CorsLayer {
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.parse().expect("origins are checked when loading")),
+        )
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(config.allowed_methods.clone())
+        .allow_headers(config.allowed_headers.clone())
+        .allow_private_network(config.allow_private_network)
+        .expose_headers([
+            header::HeaderName::from_static(SERVER_TIMESTAMP_HEADER),
+            header::HeaderName::from_static(SERVER_SIGNATURE_HEADER),
+            header::HeaderName::from_static(SERVER_KEY_HEADER),
+            header::ETAG,
+            header::HeaderName::from_static(request_id::HEADER),
+        ])
+}
+
+/// What we answer for a payment whose receipt was already issued: the receipt itself, unless the
+/// locker already reported it opened.
+async fn stored_receipt<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> Result<ReceiptResponse, error::Error> {
+    let session = only_session(state, payment).await?;
+    stored_receipt_for(state, payment, &session).await
+}
+
+/// The receipts already issued for the lockers of `sessions`, by locker, leaving out those the
+/// locker was opened with or that were revoked, see [stored_receipt]. If none are left, the
+/// reason the first one was left out.
+async fn stored_receipts<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+    sessions: &[UsageSession],
+) -> Result<Vec<ReceiptResponse>, error::Error> {
+    let mut receipts = Vec::new();
+    let mut refused = None;
+    for session in sessions {
+        match stored_receipt_for(state, payment, session).await {
+            Ok(receipt) => receipts.push(receipt),
+            Err(e @ (error::Error::AlreadyRedeemed(_) | error::Error::Revoked)) => {
+                refused.get_or_insert(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match refused {
+        Some(e) if receipts.is_empty() => Err(e),
+        _ => Ok(receipts),
+    }
+}
+
+/// The receipt already issued for the locker of `session`, see [stored_receipt].
+async fn stored_receipt_for<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+    session: &UsageSession,
+) -> Result<ReceiptResponse, error::Error> {
+    let payment_hash = &payment.payment_hash;
+    let receipt = match state.get_receipt(payment_hash, session.locker_id).await {
+        Ok(receipt) if receipt.consumed_at.is_none() => receipt,
+        // the locker was opened with it, so it mustn't open it again
+        Ok(_) => return Err(error::Error::AlreadyRedeemed(None)),
+        // payments redeemed before we kept receipts don't have one, all we can tell is how they
+        // were settled
+        Err(StoreError::NotFound) => {
+            return Err(error::Error::AlreadyRedeemed(Some(serde_json::json!({
+                "locker_id": payment.locker_id,
+                "amount": payment.amount,
+                "status": payment.status,
+                "paid_at": payment.paid_at,
+                "redeemed_at": payment.redeemed_at,
+            }))))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if state
+        .is_receipt_revoked(payment_hash, receipt.locker_id)
+        .await?
+    {
+        return Err(error::Error::Revoked);
+    }
+
+    let auth = state.get_locker_auth(receipt.locker_id).await?;
+    Ok(receipt_body(state, &receipt, session, &auth))
+}
+
+/// Refuses a receipt for a payment staff cancelled. If the customer paid it anyway, which they may
+/// if the backend couldn't cancel the invoice, we flag it so staff know to refund them.
+async fn cancelled_payment<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment: &PendingPayment,
+) -> error::Error {
+    if payment.needs_refund {
+        return error::Error::Cancelled;
+    }
+
+    let paid = matches!(
+        state.ln.get_invoice_status(payment.payment_hash.clone()),
+        Ok(ln::InvoiceStatus::Paid)
+    );
+    if !paid {
+        return error::Error::Cancelled;
+    }
+
+    match state.flag_for_refund(&payment.payment_hash).await {
+        Ok(true) => {
+            state
+                .record_event(
+                    payment.locker_id,
+                    "refund_needed",
+                    "server",
+                    serde_json::json!({
+                        "payment_hash": payment.payment_hash,
+                        "amount": payment.amount,
+                    }),
+                )
+                .await
+        }
+        Ok(false) => {}
+        Err(e) => return e.into(),
+    }
+
+    error::Error::Cancelled
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// What reports get when they leave out what their authorization was for or when it expires,
+/// which only old firmware may, and only while `LEGACY_SIGNATURES` is on.
+fn missing_expiry() -> error::Error {
+    error::Error::UnprocessableEntity("action and expires_at must be set".to_string())
+}
+
+async fn update_locker_open<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<UpdateLockerOpen>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let locker_id = body.locker_id;
+    device::require(&state, &headers, locker_id).await?;
+    let auth = state.get_locker_auth(locker_id).await?;
+
+    // only old firmware reports opens without saying what for and when their authorization
+    // expires
+    let signed = match (body.action, body.expires_at) {
+        (Some(action), Some(expires_at)) => Some((action, expires_at)),
+        (None, None) if state.config.legacy_signatures(now()) => None,
+        _ => return Err(missing_expiry()),
+    };
+
+    match &auth {
+        LockerAuth::Schnorr(pk) => {
+            let signature = body.signature.parse::<params::SchnorrSignature>()?.0;
+            let secp = secp256k1::Secp256k1::new();
+
+            // hash the locker_id, action and timestamps, verify the signature over the hash
+            let hash = match signed {
+                Some((action, expires_at)) => {
+                    signing::open_report_digest(locker_id, action, body.timestamp, expires_at)
+                }
+                None => signing::legacy_digest(locker_id, body.timestamp),
+            };
+            let pk = secp256k1::XOnlyPublicKey::from_str(pk).map_err(|_| error::Error::BadRequest)?;
+            secp.verify_schnorr(&signature, &hash, &pk).map_err(|_| error::Error::BadRequest)?;
+        }
+        LockerAuth::Hmac(secret) => {
+            // no firmware using HMAC ever left out expires_at
+            let (action, expires_at) = signed.ok_or_else(missing_expiry)?;
+            let tag = body.signature.parse::<params::HmacTag>()?.0;
+            let expected =
+                signing::open_report_hmac(secret.expose(), locker_id, action, body.timestamp, expires_at);
+            if !signing::constant_time_eq(&tag, &expected) {
+                return Err(error::Error::BadRequest);
+            }
+        }
+    }
+
+    // keep track of how far off each locker's clock is, and refuse reports too far off to judge
+    // whether they're fresh
+    let now = now();
+    let drift = body.timestamp as i64 - now as i64;
+    state.record_clock_drift(locker_id, drift, now).await?;
+    if drift.unsigned_abs() > state.config.open_report_max_drift_seconds {
+        println!("[clock] locker {locker_id} is {drift} seconds off, rejecting its report");
+        return Err(error::Error::ClockDrift { server_time: now });
+    }
+
+    // the locker was opened with an authorization that had already expired
+    if signed.is_some_and(|(_, expires_at)| expires_at < body.timestamp) {
+        return Err(error::Error::UnprocessableEntity(
+            "Authorization had expired when the locker was opened".to_string(),
+        ));
+    }
+
+    // the report is only recorded once it passed every check, so one we turn down doesn't use up
+    // its timestamp
+    let report = OpenReport {
+        timestamp: body.timestamp,
+        signed,
+        authorized_at: body.authorized_at,
+        max_drift: state.config.open_report_max_drift_seconds,
+    };
+    let redeemed = match state.accept_open_report(locker_id, report, now).await? {
+        OpenReportOutcome::Accepted { redeemed } => redeemed,
+        OpenReportOutcome::NotNewer => {
+            return Err(error::Error::Conflict("Report isn't newer than the last one".to_string()))
+        }
+        OpenReportOutcome::UnknownReceipt => {
+            return Err(error::Error::Conflict(
+                "Receipt wasn't issued for this locker or was already used".to_string(),
+            ))
+        }
+        OpenReportOutcome::OutOfOrder => {
+            return Err(error::Error::Conflict(
+                "Locker must be opened to store before it's opened to retrieve".to_string(),
+            ))
+        }
+    };
+    let action = signed.map(|(action, _)| action);
+
+    // only retrieving what's inside frees the locker and uses up its receipts
+    let consumed = if action == Some(signing::Action::Store) {
+        Vec::new()
+    } else {
+        state.free_locker(locker_id).await?;
+        state.consume_receipts(locker_id, now).await?
+    };
+
+    state
+        .record_event(
+            locker_id,
+            "opened",
+            "locker",
+            serde_json::json!({
+                "timestamp": body.timestamp,
+                "action": action,
+                "redeemed": redeemed,
+                "consumed_receipts": consumed,
+            }),
+        )
+        .await;
+    state
+        .send_webhooks(
+            "locker.opened",
+            locker_id,
+            serde_json::json!({
+                "locker_id": locker_id,
+                "action": action,
+                "timestamp": body.timestamp,
+                "redeemed": redeemed,
+            }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "locker_id": locker_id,
+        "action": action,
+        "timestamp": body.timestamp,
+        "redeemed": redeemed,
+    })))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VerifyToken {
+    locker_id: i64,
+    token: String,
+    /// What the locker is about to be opened for. Old firmware leaves it out, and should do what
+    /// the `action` in the response says.
+    action: Option<signing::Action>,
+}
+
+/// Lets lockers that can't check tokens themselves ask us instead. A token passes if we signed
+/// it, it hasn't expired, it's for this locker and action, its session is still the locker's
+/// current one, staff didn't revoke the receipt it came with and it wasn't used yet, in which
+/// case it's used up by this call, along with the receipt it came with if any.
+///
+/// Whether the token passes is in the response body, the status code only tells whether we could
+/// check it.
+async fn verify_token<Ln: LnBackend>(
+    headers: HeaderMap,
+    state: State<Arc<Server<Ln>>>,
+    body: axum::Json<VerifyToken>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    device::require(&state, &headers, body.locker_id).await?;
+    state
+        .verify_token_limiter
+        .check(body.locker_id, std::time::Instant::now())
+        .map_err(|wait| error::Error::RateLimited {
+            retry_after: wait.as_secs().max(1),
+        })?;
+
+    let now = now();
+    let verified =
+        token::verify_scoped(&body.token, &state.keys, body.locker_id, body.action, now);
+    let reason = match &verified {
+        Err(token::TokenError::Malformed) => Some("malformed"),
+        Err(token::TokenError::BadSignature) => Some("bad_signature"),
+        Err(token::TokenError::Expired) => Some("expired"),
+        Err(token::TokenError::WrongLocker) => Some("wrong_locker"),
+        Err(token::TokenError::WrongAction) => Some("wrong_action"),
+        Ok(token::Claims {
+            payment_hash: Some(payment_hash),
+            locker_id,
+            ..
+        }) if state.is_receipt_revoked(payment_hash, *locker_id).await? => Some("revoked"),
+        Ok(claims)
+            if state
+                .issued_before_key_rotation(claims.locker_id, claims.iat)
+                .await? =>
+        {
+            Some("revoked")
+        }
+        Ok(claims) if !state.is_token_session(claims).await? => Some("wrong_session"),
+        Ok(claims) => {
+            let consumed = state
+                .consume_token(&token::digest_hex(&body.token), claims, now)
+                .await?;
+
+            (!consumed).then_some("used")
+        }
+    };
+
+    if reason.is_none() {
+        state
+            .record_event(
+                body.locker_id,
+                "token_verified",
+                "locker",
+                serde_json::json!({ "digest": token::digest_hex(&body.token) }),
+            )
+            .await;
+    }
+
+    let action = verified
+        .ok()
+        .filter(|_| reason.is_none())
+        .map(|claims| claims.action);
+    Ok(ApiResponse::ok(serde_json::json!({
+        "valid": reason.is_none(),
+        "reason": reason,
+        "action": action,
+    })))
+}
+
+/// What `/verify_receipt` found out about a receipt.
+#[derive(Debug, Clone, Serialize)]
+struct ReceiptVerification {
+    valid: bool,
+    /// Why the receipt doesn't pass, the first of `bad_signature`, `expired`, `revoked` and `used`
+    /// that applies.
+    reason: Option<&'static str>,
+    /// The receipt's fields, as they were checked.
+    receipt: receipt::Receipt,
+    /// Whether the receipt expired, was revoked and was used to open the locker. Only set for
+    /// receipts we signed, there's no telling for anything else.
+    expired: Option<bool>,
+    revoked: Option<bool>,
+    consumed: Option<bool>,
+}
+
+/// Checks a signed receipt, for clients and lockers that would rather ask us. A receipt passes if
+/// it was signed by the key its `kid` names, which we must still trust, it hasn't expired, staff
+/// didn't revoke it and the locker wasn't opened with it yet. Unlike [verify_token], this never
+/// changes anything.
+///
+/// Whether the receipt passes is in the response body, the status code only tells whether we
+/// could check it: receipts whose fields are malformed get a `400` naming the field.
+async fn verify_receipt<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<receipt::SignedReceipt>,
+) -> Result<ApiResponse<ReceiptVerification>, error::Error> {
+    let signed = body.0;
+    signed.signature.parse::<params::SchnorrSignature>()?;
+    if let Some(payment_hash) = &signed.receipt.payment_hash {
+        // receipts carry the hash the way we hand it out, any other spelling was changed
+        if payment_hash.parse::<params::PaymentHash>()?.as_str() != payment_hash {
+            return Err(error::Error::InvalidParam(
+                "payment_hash: expected lowercase hex".to_string(),
+            ));
+        }
+    }
+
+    let now = now();
+    let key = state
+        .keys
+        .get(&signed.receipt.kid)
+        .filter(|key| key.is_valid_at(now));
+    let authentic = key.is_some_and(|key| {
+        let pubkey = key.keypair.expose().x_only_public_key().0;
+        receipt::verify_receipt(&signed, &pubkey).is_ok()
+    });
+    if !authentic {
+        return Ok(ApiResponse::ok(ReceiptVerification {
+            valid: false,
+            reason: Some("bad_signature"),
+            receipt: signed.receipt,
+            expired: None,
+            revoked: None,
+            consumed: None,
+        }));
+    }
+
+    let expired = signed.receipt.expires_at <= now;
+    let (revoked, consumed) = match &signed.receipt.payment_hash {
+        Some(payment_hash) => {
+            let locker_id = signed.receipt.locker_id;
+            let consumed = match state.get_receipt(payment_hash, locker_id).await {
+                Ok(receipt) => receipt.consumed_at.is_some(),
+                Err(StoreError::NotFound) => false,
+                Err(e) => return Err(e.into()),
+            };
+            let revoked = state.is_receipt_revoked(payment_hash, locker_id).await?;
+            (revoked, consumed)
+        }
+        // paid receipts are revoked when the key is rotated, see [Server::rotate_locker_key]
+        None => {
+            let revoked = state
+                .issued_before_key_rotation(signed.receipt.locker_id, signed.receipt.issued_at)
+                .await?;
+            (revoked, false)
+        }
+    };
+
+    let reason = [
+        (expired, "expired"),
+        (revoked, "revoked"),
+        (consumed, "used"),
+    ]
+    .into_iter()
+    .find_map(|(applies, reason)| applies.then_some(reason));
+
+    Ok(ApiResponse::ok(ReceiptVerification {
+        valid: reason.is_none(),
+        reason,
+        receipt: signed.receipt,
+        expired: Some(expired),
+        revoked: Some(revoked),
+        consumed: Some(consumed),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationsQuery {
+    /// Only return receipts revoked at or after this unix timestamp, defaults to all of them.
+    since: Option<u64>,
+    /// Only return receipts for this locker.
+    locker_id: Option<i64>,
+}
+
+/// Lists the receipts staff revoked that haven't expired yet, oldest first, for lockers to poll
+/// so they refuse them even while offline. Pollers should pass the `server_time` of the last
+/// response as `since`, and may see the same revocation twice.
+async fn get_revocations<Ln: LnBackend>(
+    Query(query): Query<RevocationsQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let now = now();
+    let revocations: Vec<_> = state
+        .list_revocations(query.since.unwrap_or(0), query.locker_id, now)
+        .await?
+        .into_iter()
+        .map(|revocation| {
+            serde_json::json!({
+                "payment_hash": revocation.payment_hash,
+                "locker_id": revocation.locker_id,
+                "revoked_at": revocation.revoked_at,
+                "expires_at": revocation.expires_at,
+            })
+        })
+        .collect();
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "revocations": revocations,
+        "server_time": now,
+    })))
+}
+
+/// What a new locker sends to `/provision/register`.
+#[derive(Debug, Clone, Deserialize)]
+struct RegisterLocker {
+    /// The x-only public key the locker will sign its reports with.
+    pk: params::XOnlyPubkey,
+    /// The signature of the provisioning key over [signing::provision_digest] of `pk`, unless
+    /// the locker has a `code`.
+    signature: Option<params::SchnorrSignature>,
+    /// A one-time code staff handed the installer, instead of a `signature`, see [enrollment].
+    code: Option<String>,
+    name: Option<String>,
+    location: Option<String>,
+    size: Option<LockerSize>,
+    description: Option<String>,
+}
+
+/// Registers a new locker, with the public key an installer vouched for, either by signing it with
+/// the provisioning key or with a one-time code from staff, which is used up. The locker can't be
+/// used until an admin approves it, and should keep the id and device token we return, see
+/// [device].
+async fn register_locker<Ln: LnBackend>(
+    state: State<Arc<Server<Ln>>>,
+    body: params::ValidJson<RegisterLocker>,
+) -> Result<ApiResponse<serde_json::Value>, error::Error> {
+    let code_digest = match (&body.code, &body.signature) {
+        (Some(code), _) => Some(enrollment::digest(code)),
+        (None, Some(signature)) => {
+            let Some(provisioning_pubkey) = state.config.provisioning_pubkey else {
+                return Err(error::Error::NotFound);
+            };
+
+            secp256k1::Secp256k1::new()
+                .verify_schnorr(
+                    &signature.0,
+                    &signing::provision_digest(&body.pk.0.serialize()),
+                    &provisioning_pubkey,
+                )
+                .map_err(|_| error::Error::BadRequest)?;
+            None
+        }
+        (None, None) => {
+            return Err(error::Error::InvalidParam(
+                "expected a signature or a code".to_string(),
+            ))
+        }
+    };
+
+    let (device_token, device_token_digest) = device::generate();
+    let (locker_id, code_id) = state
+        .register_locker(&body, code_digest, &device_token_digest)
+        .await?;
+    state.publish_locker_state(locker_id).await;
+    state
+        .record_event(
+            locker_id,
+            "registered",
+            "locker",
+            serde_json::json!({ "pk": body.pk.0.to_string(), "provisioning_code_id": code_id }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(serde_json::json!({
+        "locker_id": locker_id,
+        "state": "provisioning",
+        "device_token": device_token,
+    })))
+}
+
+/// A one-time code for registering a locker, as admins see it, without the code itself.
+#[derive(Debug, Clone, Serialize)]
+struct ProvisioningCode {
+    id: i64,
+    /// What staff noted the code is for, like where it's being installed.
+    label: Option<String>,
+    created_at: u64,
+    expires_at: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UpdateLockerOpen {
+    locker_id: i64,
+    /// The locker's signature over the report, or its HMAC tag if it uses [AuthMode::Hmac], in
+    /// hex.
+    signature: String,
+    timestamp: u64,
+    /// When the authorization the locker was opened with expires, covered by the signature. Old
+    /// firmware leaves it out.
+    expires_at: Option<u64>,
+    /// What the locker was opened for, covered by the signature. Old firmware leaves it out.
+    action: Option<signing::Action>,
+    /// The timestamp of the authorization the locker was opened with, the `issued_at` of the
+    /// receipt when retrieving, telling which receipt was used. Old firmware leaves it out.
+    authorized_at: Option<u64>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize)]
+struct PendingPayment {
+    amount: u64,
+    payment_hash: String,
+    /// One of `pending`, `paid`, `expired`, `redeemed` or `cancelled`.
+    status: String,
+    locker_id: i64,
+    created_at: Option<u64>,
+    paid_at: Option<u64>,
+    expired_at: Option<u64>,
+    redeemed_at: Option<u64>,
+    /// The invoice for this payment, payments made before we kept invoices don't have it.
+    bolt11: Option<String>,
+    cancelled_at: Option<u64>,
+    /// Whether the payment was settled after staff cancelled it, so we owe the customer a
+    /// refund.
+    needs_refund: bool,
+    /// The operator whose locker the payment is for, who gets the money when we settle up with
+    /// them, see [OperatorAccount]. Unset for lockers of no operator and payments made before we
+    /// kept it.
+    operator_id: Option<i64>,
+}
+
+/// An open-authorization we handed out for a payment.
+#[derive(Debug, Clone)]
+struct Receipt {
+    payment_hash: String,
+    locker_id: i64,
+    /// The site the locker was at when we issued the receipt, for lockers that check it.
+    site_id: Option<i64>,
+    session_id: i64,
+    /// The timestamp covered by the signature.
+    issued_at: u64,
+    /// When the receipt stops being valid, also covered by the signature. Receipts issued before
+    /// authorizations expired don't have it, and their signature is in the legacy format.
+    expires_at: Option<u64>,
+    signature: String,
+    /// The id of the server key the signature and token were made with, receipts issued before we
+    /// had several keys don't have one.
+    kid: Option<String>,
+    /// A JWT allowing the locker to be opened, see [token]. Handed out again as is along with the
+    /// rest of the receipt, so it may have expired by then.
+    token: Option<String>,
+    /// Our signature over the signed receipt handed out along with this one, see [receipt].
+    /// Receipts issued before we kept it don't have one.
+    receipt_signature: Option<String>,
+    /// The authorization in the format old firmware expects, for receipts issued while
+    /// [config::Config::legacy_signatures] held.
+    legacy_signature: Option<String>,
+    /// When the locker reported being opened with this receipt, after which it's spent.
+    consumed_at: Option<u64>,
+}
+
+/// What `/use_locker` answers: the authorization to open the locker and store something.
+#[derive(Debug, Clone, Serialize)]
+struct UseLockerResponse {
+    locker_id: i64,
+    session_id: i64,
+    action: signing::Action,
+    /// The timestamp covered by the signature.
+    start_time: u64,
+    expires_at: u64,
+    signature: String,
+    /// Always `None`, `signature` covers the expiry. It was the signature over the expiring
+    /// authorization while we handed out the legacy one, which only receipts still carry.
+    expiring_signature: Option<String>,
+    kid: String,
+    token: String,
+    signed_receipt: receipt::SignedReceipt,
+}
+
+/// What `/pay_for_usage` answers: what the session costs so far, and the invoice to pay it.
+#[derive(Debug, Clone, Serialize)]
+struct InvoiceResponse {
+    locker_id: i64,
+    /// How long the locker was used for, in seconds.
+    lease_time: u64,
+    /// In satoshis.
+    amount: u64,
+    invoice: ln::Invoice,
+}
+
+/// What `/payments/{payment_hash}/receipt` answers once a payment settled, see [Receipt].
+#[derive(Debug, Clone, Serialize)]
+struct ReceiptResponse {
+    locker_id: i64,
+    site_id: Option<i64>,
+    session_id: i64,
+    action: signing::Action,
+    start_time: u64,
+    issued_at: u64,
+    expires_at: Option<u64>,
+    signature: String,
+    expiring_signature: Option<String>,
+    kid: Option<String>,
+    token: Option<String>,
+    /// Only for receipts that expire and whose key we still have.
+    signed_receipt: Option<receipt::SignedReceipt>,
+}
+
+/// The columns [Server::read_locker] expects, in order, selected from [LOCKER_TABLES].
+const LOCKER_COLUMNS: &str = "l.id, l.state, l.name, l.location, l.size, l.description, l.active, l.price_per_minute_msat, s.id, s.name, s.address, s.timezone, s.price_per_minute_msat, l.auth_mode";
+
+/// Each locker along with its site, if it has one.
+const LOCKER_TABLES: &str = "lockers l LEFT JOIN sites s ON s.id = l.site_id";
+
+/// The columns [Server::read_site] expects, in order.
+const SITE_COLUMNS: &str = "id, name, address, timezone, price_per_minute_msat";
+
+/// The columns [Server::read_session] expects, in order.
+const SESSION_COLUMNS: &str = "id, locker_id, started_at, ended_at, amount_sat, payment_hash, state, nostr_pubkey, usage_ended_at, price_per_minute_msat, linking_key";
+
+/// The columns [Server::read_payment] expects, in order.
+const PAYMENT_COLUMNS: &str =
+    "amount, payment_hash, status, locker_id, created_at, paid_at, expired_at, redeemed_at, bolt11, cancelled_at, needs_refund, operator_id";
+
+/// Which lockers to return from [Server::list_lockers_page], unset fields match everything.
+#[derive(Debug, Default)]
+struct LockerFilter {
+    site_id: Option<i64>,
+    state: Option<String>,
+    size: Option<LockerSize>,
+    /// Matched regardless of case.
+    location: Option<String>,
+}
+
+/// Which payments to return from [Server::list_payments], unset fields match everything.
+#[derive(Debug, Default)]
+struct PaymentFilter {
+    status: Option<String>,
+    locker_id: Option<i64>,
+    /// Only payments created at or after this unix timestamp.
+    from: Option<u64>,
+    /// Only payments created before this unix timestamp.
+    to: Option<u64>,
+    /// Only payments for sessions used by the customer with this linking key, see [lnurl].
+    linking_key: Option<String>,
+    /// Only payments for lockers of this operator, see [admin::Scope].
+    operator_id: Option<i64>,
+}
+
+/// A single usage of a locker, from the moment someone claims it until the payment for it is
+/// redeemed.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize)]
+struct UsageSession {
+    id: i64,
+    locker_id: i64,
+    started_at: u64,
+    ended_at: Option<u64>,
+    /// How much we invoiced for this session, set once the user asks to pay for it.
+    amount_sat: Option<u64>,
+    /// The payment covering this session, set once the user asks to pay for it.
+    payment_hash: Option<String>,
+    /// Either `active`, `closed`, or `cancelled` if the customer backed out before using it.
+    state: String,
+    /// The nostr public key of the customer, if the client said who they were, see [nostr].
+    nostr_pubkey: Option<String>,
+    /// When the customer said they were done with the locker, after which they aren't billed
+    /// anymore and `amount_sat` is what they owe.
+    usage_ended_at: Option<u64>,
+    /// The rate the session is billed at, fixed when it started, in millisatoshis per minute.
+    /// Sessions from before we kept it don't have one, see [session_price].
+    price_per_minute_msat: Option<u64>,
+    /// The linking key of the customer, if they were logged in, see [customer].
+    #[serde(skip)]
+    linking_key: Option<String>,
+}
+
+/// A session of a locker as support staff see it in the locker's history, with what it cost and
+/// how it ended.
+#[derive(Debug, Clone, Serialize)]
+struct SessionHistory {
+    session_id: i64,
+    started_at: u64,
+    ended_at: Option<u64>,
+    /// How long the locker was used for, up to now if the session is still open.
+    duration_seconds: u64,
+    /// How much the customer paid, set once their payment settled.
+    amount_paid_sat: Option<u64>,
+    payment_hash: Option<String>,
+    /// `in_progress` while the session is open, `paid` if it ended with the customer paying for
+    /// it, `cancelled` if the customer backed out before using it, or `released` if the
+    /// consistency check or staff closed it without a payment.
+    outcome: &'static str,
+    /// Whether staff closed the session without charging, taking a broken locker out of service,
+    /// and should check in with the customer.
+    needs_follow_up: bool,
+}
+
+/// Something that happened to a locker, kept around so we can tell what happened after the fact.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct LockerEvent {
+    id: i64,
+    locker_id: i64,
+    /// What happened, e.g. `claimed` or `opened`.
+    event: String,
+    /// Who made it happen: `customer`, `locker`, `admin` or `server`.
+    actor: String,
+    timestamp: u64,
+    details: serde_json::Value,
+}
+
+/// One of the locker owners we host, who only sees and manages their own lockers, see
+/// [admin::Scope].
+#[derive(Debug, Clone, Serialize)]
+struct OperatorAccount {
+    id: i64,
+    name: String,
+    created_at: u64,
+}
+
+/// Something that belongs to an operator, see [Server::operator_of].
+#[derive(Debug, Clone)]
+enum Owned {
+    Locker(i64),
+    Site(i64),
+    /// A payment, by its hash.
+    Payment(String),
+    Webhook(i64),
+    ProvisioningCode(i64),
+}
+
+/// A call to one of the `/admin` routes, see [Server::record_admin_call].
+#[derive(Debug, Clone, Serialize)]
+struct AdminCall {
+    id: i64,
+    /// The id of the admin token the call was made with.
+    token_id: String,
+    /// The role of that token, see [config::AdminRole]. Unset for calls recorded before we kept
+    /// it.
+    role: Option<String>,
+    method: String,
+    /// The path and query of the call.
+    path: String,
+    /// The route the call matched, like `/admin/lockers/{locker_id}`. Unset for calls recorded
+    /// before we kept it.
+    route: Option<String>,
+    /// The body of the call, with secrets redacted, see [admin::summarize_body].
+    summary: Option<String>,
+    /// The status we answered with.
+    status: u16,
+    timestamp: u64,
+    /// The operator the token is scoped to, unset for super-admins, see [admin::Scope].
+    operator_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Locker {
+    id: i64,
+    /// One of `provisioning`, `available`, `in_use`, `awaiting_payment` or `maintenance`.
+    state: String,
+    /// A human friendly name, e.g. "A3".
+    name: Option<String>,
+    /// Where to find the locker, e.g. "Entrance B, second floor".
+    location: Option<String>,
+    size: Option<LockerSize>,
+    description: Option<String>,
+    /// Decommissioned lockers are kept for their history, but hidden from customers and can't
+    /// be used.
+    active: bool,
+    /// What using this locker costs, overriding the price of its site and the configured one.
+    price_per_minute_msat: Option<u64>,
+    site: Option<Site>,
+    /// How the locker checks the authorizations we hand out for it.
+    auth_mode: AuthMode,
+}
+
+/// A locker as admins see it, along with the key it authenticates with.
+#[derive(Debug, Clone, Serialize)]
+struct AdminLocker {
+    #[serde(flatten)]
+    locker: Locker,
+    /// The locker's public key, or what identifies its controller if it uses [AuthMode::Hmac].
+    pk: String,
+    /// The operator the locker belongs to, see [OperatorAccount].
+    operator_id: Option<i64>,
+}
+
+/// Somewhere we send webhooks, see [webhook]. Its secret is only handed out when it's created.
+#[derive(Debug, Clone, Serialize)]
+struct Webhook {
+    id: i64,
+    url: String,
+    /// The events it wants, every one of [webhook::EVENTS] if `None`.
+    events: Option<Vec<String>>,
+    created_at: u64,
+    /// How many deliveries to it are waiting to be tried, for the first time or again.
+    pending_deliveries: u64,
+    /// How many deliveries to it we gave up on.
+    dead_deliveries: u64,
+}
+
+/// An event we queued for a [Webhook], and how delivering it went so far.
+#[derive(Debug, Clone, Serialize)]
+struct WebhookDelivery {
+    id: i64,
+    webhook_id: i64,
+    /// The id of the event, the same for every subscription it was queued for.
+    event_id: String,
+    event: String,
+    /// One of `pending`, `delivered` or `dead`, once we gave up on it.
+    status: String,
+    attempts: u32,
+    /// What the receiver answered the last attempt with, `None` if it didn't.
+    last_status: Option<i32>,
+    /// Why the last attempt didn't reach the receiver, if it didn't.
+    last_error: Option<String>,
+    /// When it's tried next, while it's `pending`.
+    next_attempt_at: Option<u64>,
+    created_at: u64,
+    delivered_at: Option<u64>,
+}
+
+/// A [WebhookDelivery] that's due, with what it takes to send it.
+struct DueDelivery {
+    id: i64,
+    url: String,
+    secret: Secret<Vec<u8>>,
+    body: Vec<u8>,
+    attempts: u32,
+}
+
+/// A physical location where some of our lockers are.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Site {
+    id: i64,
+    name: String,
+    address: Option<String>,
+    /// The IANA name of the site's timezone, e.g. "America/Los_Angeles".
+    timezone: String,
+    /// What using a locker here costs, if it isn't the configured default.
+    price_per_minute_msat: Option<u64>,
+}
+
+/// The fields of a site, as accepted by `POST` and `PATCH /admin/sites`. Fields that are left out
+/// are kept as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct SiteMetadata {
+    name: Option<String>,
+    address: Option<String>,
+    timezone: Option<String>,
+    price_per_minute_msat: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum LockerSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl LockerSize {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LockerSize::Small => "small",
+            LockerSize::Medium => "medium",
+            LockerSize::Large => "large",
+        }
+    }
+}
+
+impl FromStr for LockerSize {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "small" => Ok(LockerSize::Small),
+            "medium" => Ok(LockerSize::Medium),
+            "large" => Ok(LockerSize::Large),
+            _ => Err(error::Error::BadRequest),
+        }
+    }
+}
+
+/// How a locker checks our authorizations and proves its reports are its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AuthMode {
+    /// With BIP340 signatures, made with our key and its own.
+    #[default]
+    Schnorr,
+    /// With HMAC-SHA256 tags keyed with a secret we share, for controllers too small to verify
+    /// signatures.
+    Hmac,
+}
+
+impl AuthMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuthMode::Schnorr => "schnorr",
+            AuthMode::Hmac => "hmac",
+        }
+    }
+}
+
+impl FromStr for AuthMode {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schnorr" => Ok(AuthMode::Schnorr),
+            "hmac" => Ok(AuthMode::Hmac),
+            _ => Err(error::Error::BadRequest),
+        }
+    }
+}
+
+/// What we need to authenticate a locker, see [AuthMode].
+enum LockerAuth {
+    /// The x-only public key the locker signs with, in hex.
+    Schnorr(String),
+    /// The secret we share with the locker.
+    Hmac(Secret<Vec<u8>>),
+}
+
+/// How [Server::revenue_by_period] groups payments together.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Granularity {
+    #[default]
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// An sqlite expression for the first day of the period `column`, a unix timestamp, falls in.
+    fn period_start(&self, column: &str) -> String {
+        match self {
+            Granularity::Day => format!("date({column}, 'unixepoch')"),
+            Granularity::Week => format!("date({column}, 'unixepoch', 'weekday 0', '-6 days')"),
+            Granularity::Month => format!("date({column}, 'unixepoch', 'start of month')"),
+        }
+    }
+}
+
+/// A payment we're still waiting on, along with what it's for, see
+/// [Server::list_pending_payments].
+#[derive(Debug, Clone)]
+struct AwaitingPayment {
+    payment_hash: String,
+    amount: u64,
+    created_at: Option<u64>,
+    locker_id: i64,
+    locker_name: Option<String>,
+    locker_state: String,
+    /// The session the payment is for, payments made before we kept sessions don't have one.
+    session_id: Option<i64>,
+    session_started_at: Option<u64>,
+}
+
+/// An open report whose signature checked out, for [Server::accept_open_report] to check against
+/// what we know of the locker.
+#[derive(Debug, Clone, Copy)]
+struct OpenReport {
+    /// When the locker says it was opened.
+    timestamp: u64,
+    /// What the authorization it was opened with was for and when it expires, which old firmware
+    /// doesn't say.
+    signed: Option<(signing::Action, u64)>,
+    /// The `start_time` or `issued_at` of that authorization, if the report says.
+    authorized_at: Option<u64>,
+    /// How far behind ours the locker's clock may be, see
+    /// [config::Config::open_report_max_drift_seconds].
+    max_drift: u64,
+}
+
+/// What [Server::accept_open_report] made of a report.
+#[derive(Debug, Clone, PartialEq)]
+enum OpenReportOutcome {
+    /// The report was recorded, having used the receipt with this payment hash, if any.
+    Accepted { redeemed: Option<String> },
+    /// The locker already reported an open at or after this one.
+    NotNewer,
+    /// The receipt it names wasn't issued for the locker, or was already used.
+    UnknownReceipt,
+    /// The locker was opened to retrieve before it was opened to store, or to store after it was
+    /// emptied.
+    OutOfOrder,
+}
+
+/// A receipt staff revoked, see [Server::revoke_receipt].
+#[derive(Debug, Clone, Serialize)]
+struct Revocation {
+    payment_hash: String,
+    locker_id: i64,
+    /// Why staff revoked the receipt, only shown to them.
+    reason: Option<String>,
+    revoked_at: u64,
+    /// When the receipt would have expired anyway, after which lockers can forget about it.
+    expires_at: Option<u64>,
+}
+
+/// How far off a locker's clock was when it last reported being opened, see
+/// [Server::list_clock_drift].
+#[derive(Debug, Clone, Serialize)]
+struct ClockDrift {
+    locker_id: i64,
+    name: Option<String>,
+    /// How many seconds the locker's clock was ahead of ours, negative if it was behind.
+    drift_seconds: i64,
+    measured_at: u64,
+}
+
+/// How much a locker made over some period, see [Server::revenue_by_period].
+#[derive(Debug, Clone, Serialize)]
+struct LockerRevenue {
+    locker_id: i64,
+    /// The sum of every payment settled in the period, or of the locker's share of those made
+    /// for several lockers at once.
+    total_sat: u64,
+    /// How many payments were settled in the period, those made for several lockers at once
+    /// counting for each of them.
+    payments: u64,
+    /// How many of those payments we know the session of.
+    sessions: u64,
+    /// How long those sessions lasted on average, up to when they were paid for if they're still
+    /// open.
+    average_session_seconds: Option<f64>,
+}
+
+/// How long a locker was in use over some period, see [Server::occupancy].
+#[derive(Debug, Clone)]
+struct LockerOccupancy {
+    locker_id: i64,
+    occupied_seconds: u64,
+    /// How many sessions overlapped the period, even if only partly.
+    sessions: u64,
+}
+
+/// A locker staff commission through `POST /admin/lockers`.
+#[derive(Debug, Clone, Deserialize)]
+struct NewLocker {
+    /// The x-only public key the locker will sign its reports with.
+    pk: params::XOnlyPubkey,
+    name: Option<String>,
+    location: Option<String>,
+    size: Option<LockerSize>,
+    description: Option<String>,
+    /// What using the locker costs, if it shouldn't be the price of its site or the configured one.
+    price_per_minute_msat: Option<u64>,
+    /// The operator the locker belongs to. Tokens scoped to an operator always add lockers for
+    /// theirs, see [admin::Scope].
+    operator_id: Option<i64>,
+}
+
+/// The descriptive fields of a locker, as accepted by `PATCH /admin/lockers/{id}`. Fields that
+/// are left out are kept as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct LockerMetadata {
+    name: Option<String>,
+    location: Option<String>,
+    size: Option<LockerSize>,
+    description: Option<String>,
+    site_id: Option<i64>,
+}
+
+/// Every route of the API as served in `version`, without its prefix, see [ApiVersion::prefix].
+/// For now there's only `/v1`, which is also what answers unprefixed paths.
+fn build_router<Ln: LnBackend>(
+    state: &Arc<Server<Ln>>,
+    version: ApiVersion,
+) -> Router<Arc<Server<Ln>>> {
+    let mutating = Router::new()
+        .route(
+            "/use_locker/{locker_id}",
+            post(use_locker).get(use_locker),
+        )
+        .route(
+            "/pay_for_usage/{locker_id}",
+            post(pay_for_usage).get(pay_for_usage),
+        )
+        .route("/pay_for_usage_batch", post(batch::pay_for_usage_batch))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            idempotency::idempotent::<Ln>,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            reject_get::<Ln>,
+        ));
+
+    // routes that claim a locker or reach the lightning backend get a tighter limit
+    let mut expensive = Router::new()
+        .merge(mutating)
+        .route("/end_usage/{locker_id}", post(end_usage))
+        .route("/cancel_usage/{locker_id}", post(cancel_usage))
+        .route("/reserve/{locker_id}", post(reservation::reserve_locker))
+        .route("/payments/{payment_hash}/receipt", get(get_payment_receipt))
+        .route("/payments/{payment_hash}/receipts", get(get_payment_receipts))
+        .route("/payments/{payment_hash}", get(get_payment_status))
+        .route("/payments/{payment_hash}/invoice", get(get_payment_invoice))
+        .route("/lockers/{locker_id}/invoice", get(get_locker_invoice))
+        .route("/provision/register", post(register_locker))
+        .route("/auth/lnurl", get(customer::get_lnurl_auth));
+    if version == ApiVersion::V1 && state.config.legacy_receipt_path {
+        expensive = expensive.route(
+            "/payment_receipt/{payment_hash}",
+            get(get_payment_receipt).layer(axum::middleware::from_fn_with_state(
+                version,
+                deprecated_receipt_path,
+            )),
+        );
+    }
+    let expensive = expensive.route_layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        ratelimit::limit_expensive::<Ln>,
+    ));
+
+    let cheap = Router::new()
+        .route("/health", get(health::get_health))
+        .route("/version", get(version::get_version::<Ln>))
+        .route("/openapi.json", get(openapi::get_openapi))
+        .route("/keys", get(get_keys))
+        .route("/pubkey", get(get_pubkey))
+        .route("/lockers", get(get_lockers))
+        .route("/lockers/{locker_id}", get(get_locker))
+        .route("/lockers/{locker_id}/meter", get(get_locker_meter))
+        .route("/ws/lockers", get(feed::get_ws_lockers))
+        .route(
+            "/payments/{payment_hash}/events",
+            get(payment_events::get_payment_events),
+        )
+        .route("/payments/{payment_hash}/qr.png", get(qr::get_qr_png))
+        .route("/payments/{payment_hash}/qr.svg", get(qr::get_qr_svg))
+        .route("/update_locker_open", post(update_locker_open))
+        .route("/verify_token", post(verify_token))
+        .route("/verify_receipt", post(verify_receipt))
+        .route("/revocations", get(get_revocations))
+        .route(lnurl::CALLBACK_PATH, get(customer::lnurl_auth_callback))
+        .route("/me", get(customer::get_me))
+        .route("/me/sessions", get(customer::get_my_sessions))
+        .route("/me/payments", get(customer::get_my_payments));
+    #[cfg(feature = "swagger-ui")]
+    let cheap = cheap.route("/docs", get(openapi::get_docs));
+    let cheap = cheap.route_layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        ratelimit::limit_reads::<Ln>,
+    ));
+
+    Router::new()
+        .merge(expensive)
+        .merge(cheap)
+        .nest("/admin", admin::router(state.clone()))
+}
+
+impl<Ln: LnBackend> Server<Ln> {
+    pub async fn run(
+        address: String,
+        keys: keys::Keyring,
+        database: Arc<db::Pool>,
+        ln: Ln,
+        config: config::Config,
+    ) {
+        println!(
+            "[+] Starting server: {}",
+            serde_json::to_string(&version::Version::current::<Ln>()).unwrap()
+        );
+        // checked before anything else, so a certificate that can't be used stops us right away
+        let https = config.tls.as_ref().map(|tls| {
+            let certificate = tls::load(tls)
+                .unwrap_or_else(|e| panic!("failed to load the TLS certificate: {e}"));
+            (certificate, tls.redirect_address.clone())
+        });
+        let listener = match tokio::net::TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(_) => {
+                std::process::exit(-1);
+            }
+        };
+
+        let state = Arc::new(Server {
+            keys,
+            database,
+            ln,
+            metrics: metrics::Metrics::default(),
+            verify_token_limiter: ratelimit::RateLimiter::per_minute(
+                config.verify_token_rate_limit,
+            ),
+            receipt_poll_limiter: ratelimit::RateLimiter::per_minute(
+                config.receipt_poll_rate_limit,
+            ),
+            unpaid_payments: ratelimit::Cooldown::new(Duration::from_secs(
+                config.receipt_poll_cache_seconds,
+            )),
+            read_limiter: ratelimit::RateLimiter::per_minute(config.read_rate_limit),
+            expensive_limiter: ratelimit::RateLimiter::per_minute(config.expensive_rate_limit),
+            locker_feed: feed::LockerFeed::new(),
+            payment_watch: payment_events::PaymentWatch::new(),
+            webhooks_queued: tokio::sync::Notify::new(),
+            config,
+        });
+        state.metrics.started_at.store(now(), Ordering::Relaxed);
+
+        if let Err(e) = consistency::check(&state, state.config.repair_inconsistencies).await {
+            eprintln!("[run] failed to check the database for inconsistencies: {e:?}");
+        }
+        match state.list_lockers(true, None).await {
+            Ok(lockers) => state.locker_feed.seed(
+                lockers
+                    .into_iter()
+                    .map(|locker| (locker.id, feed::state_of(&locker))),
+            ),
+            Err(e) => eprintln!("[run] failed to list the lockers: {e:?}"),
+        }
+
+        tokio::spawn(tasks::cleanup_payments(state.clone()));
+        if let Some(dir) = state.config.backup_dir.clone() {
+            tokio::spawn(tasks::backup_database(state.clone(), dir));
+        }
+        tokio::spawn(tasks::maintain_database(state.clone()));
+        tokio::spawn(tasks::check_ln_backend(state.clone()));
+        tokio::spawn(tasks::deliver_webhooks(state.clone()));
+        tokio::spawn(tasks::expire_reservations(state.clone()));
+
+        let mut router = Router::new()
+            .nest(ApiVersion::V1.prefix(), build_router(&state, ApiVersion::V1))
+            // devices look for it there, whatever version of the API they speak
+            .route(
+                "/.well-known/locker-server.json",
+                get(get_server_document).route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    ratelimit::limit_reads::<Ln>,
+                )),
+            );
+        if state.config.legacy_unprefixed_paths {
+            router = router.merge(
+                build_router(&state, ApiVersion::V1)
+                    .layer(axum::middleware::from_fn(deprecated_unprefixed_path)),
+            );
+        }
+
+        let router = router
+            .layer(axum::middleware::from_fn(api::wrap_rejections))
+            .layer(axum::middleware::from_fn(api::negotiate))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                limit_body::<Ln>,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                sign_response::<Ln>,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                request_id::assign::<Ln>,
+            ))
+            .layer(cors_layer(&state.config.cors))
+            .with_state(state);
+
+        let Some((certificate, redirect_address)) = https else {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .expect("failed to start rpc server");
+            return;
+        };
+
+        let listener = listener.into_std().expect("failed to start rpc server");
+        if let Some(redirect_address) = redirect_address {
+            let https_port = listener.local_addr().expect("failed to start rpc server").port();
+            tokio::spawn(tls::redirect(redirect_address, https_port));
+        }
+        println!("[+] Serving HTTPS");
+        axum_server::from_tcp_rustls(listener, certificate)
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .expect("failed to start rpc server");
+    }
+    
+    /// Returns what we need to authenticate a locker, its public key or the secret we share.
+    async fn get_locker_auth(&self, locker_id: i64) -> Result<LockerAuth, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement =
+                    database.prepare("SELECT pk, auth_mode, hmac_secret FROM lockers WHERE id = ?")?;
+                statement.bind((1, locker_id))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                let auth_mode = statement
+                    .read::<String, _>(1)?
+                    .parse()
+                    .map_err(|_| StoreError::Other("invalid locker auth mode".to_string()))?;
+                match auth_mode {
+                    AuthMode::Schnorr => Ok(LockerAuth::Schnorr(statement.read(0)?)),
+                    AuthMode::Hmac => {
+                        let secret = statement.read::<Option<String>, _>(2)?.unwrap_or_default();
+                        Vec::<u8>::from_hex(&secret)
+                            .map(|secret| LockerAuth::Hmac(Secret::new(secret)))
+                            .map_err(|_| StoreError::Other("invalid locker hmac secret".to_string()))
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Records a new pending payment for a locker, and for the locker's operator.
+    ///
+    /// Payment hashes are unique, if we already have a payment with this hash this returns
+    /// [StoreError::Constraint] and leaves the existing one untouched. Payments for lockers that
+    /// don't exist are rejected with [StoreError::NotFound].
+    async fn insert_payment(
+        &self,
+        amount: u64,
+        payment_hash: &str,
+        bolt11: &str,
+        locker_id: i64,
+    ) -> Result<(), StoreError> {
+        let payment_hash = payment_hash.to_string();
+        let bolt11 = bolt11.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO pending_payments (amount, payment_hash, status, locker_id, created_at, bolt11, operator_id) VALUES (?1, ?2, 'pending', ?3, ?4, ?5, (SELECT operator_id FROM lockers WHERE id = ?3))",
+                )?;
+                statement.bind((1, amount as i64))?;
+                statement.bind((2, payment_hash.as_str()))?;
+                statement.bind((3, locker_id))?;
+                statement.bind((4, now() as i64))?;
+                statement.bind((5, bolt11.as_str()))?;
+
+                match statement.next() {
+                    Ok(_) => Ok(()),
+                    // SQLITE_CONSTRAINT, either the locker doesn't exist or we already have this hash
+                    Err(sqlite::Error {
+                        code: Some(19),
+                        message,
+                    }) => match message {
+                        Some(message) if message.contains("FOREIGN KEY") => Err(StoreError::NotFound),
+                        message => Err(StoreError::Constraint(
+                            message.unwrap_or_else(|| "duplicate payment hash".to_string()),
+                        )),
+                    },
+                    Err(e) => Err(e.into()),
+                }
+            })
+            .await
+    }
+
+    async fn get_payment(&self, payment_hash: String) -> Result<PendingPayment, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {PAYMENT_COLUMNS} FROM pending_payments WHERE payment_hash = ?"
+                ))?;
+                statement.bind((1, payment_hash.as_str()))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                let payment = Self::read_payment(&statement)?;
+
+                // payment hashes are unique, if we see two rows something is really wrong with the db
+                if let sqlite::State::Row = statement.next()? {
+                    return Err(StoreError::Other(format!(
+                        "more than one payment with hash {payment_hash}"
+                    )));
+                }
+
+                Ok(payment)
+            })
+            .await
+    }
+
+    /// Returns the newest payment of a locker that's still pending and was created after
+    /// `created_after`, so its invoice can still be paid, if it has one.
+    async fn get_payable_payment(
+        &self,
+        locker_id: i64,
+        created_after: u64,
+    ) -> Result<PendingPayment, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {PAYMENT_COLUMNS} FROM pending_payments
+                     WHERE locker_id = ? AND status = 'pending' AND bolt11 IS NOT NULL AND created_at > ?
+                     ORDER BY id DESC LIMIT 1"
+                ))?;
+                statement.bind((1, locker_id))?;
+                statement.bind((2, created_after as i64))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Self::read_payment(&statement)
+            })
+            .await
+    }
+
+    /// Returns a page of payments matching `filter`, newest first, along with how many payments
+    /// match it in total.
+    async fn list_payments(
+        &self,
+        filter: &PaymentFilter,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<PendingPayment>, u64), StoreError> {
+        let (conditions, values) = Self::payment_conditions(filter);
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT COUNT(*) FROM pending_payments WHERE {conditions}"
+                ))?;
+                for (i, value) in values.iter().enumerate() {
+                    statement.bind((i + 1, value))?;
+                }
+                statement.next()?;
+                let total = statement.read::<i64, _>(0)? as u64;
+
+                let mut statement = database.prepare(format!(
+                    "SELECT {PAYMENT_COLUMNS} FROM pending_payments WHERE {conditions} ORDER BY id DESC LIMIT ? OFFSET ?"
+                ))?;
+                for (i, value) in values.iter().enumerate() {
+                    statement.bind((i + 1, value))?;
+                }
+                statement.bind((values.len() + 1, limit as i64))?;
+                statement.bind((values.len() + 2, offset as i64))?;
+
+                let mut payments = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    payments.push(Self::read_payment(&statement)?);
+                }
+
+                Ok((payments, total))
+            })
+            .await
+    }
+
+    /// Returns up to `limit` payments matching `filter` with an id greater than `after`, oldest
+    /// first, along with their ids.
+    ///
+    /// Unlike [Server::list_payments], paging through this doesn't skip or repeat payments when
+    /// new ones come in meanwhile.
+    async fn list_payments_after(
+        &self,
+        filter: &PaymentFilter,
+        after: i64,
+        limit: u64,
+    ) -> Result<Vec<(i64, PendingPayment)>, StoreError> {
+        let (conditions, values) = Self::payment_conditions(filter);
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {PAYMENT_COLUMNS}, id FROM pending_payments WHERE {conditions} AND id > ? ORDER BY id LIMIT ?"
+                ))?;
+                for (i, value) in values.iter().enumerate() {
+                    statement.bind((i + 1, value))?;
+                }
+                statement.bind((values.len() + 1, after))?;
+                statement.bind((values.len() + 2, limit as i64))?;
+
+                let mut payments = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    payments.push((statement.read(12)?, Self::read_payment(&statement)?));
+                }
+
+                Ok(payments)
+            })
+            .await
+    }
+
+    /// Builds the `WHERE` clause selecting the payments that match `filter`, along with the
+    /// values to bind to it.
+    fn payment_conditions(filter: &PaymentFilter) -> (String, Vec<sqlite::Value>) {
+        let mut conditions = vec!["1 = 1"];
+        let mut values = Vec::new();
+
+        if let Some(status) = &filter.status {
+            conditions.push("status = ?");
+            values.push(sqlite::Value::String(status.clone()));
+        }
+
+        if let Some(locker_id) = filter.locker_id {
+            conditions.push("locker_id = ?");
+            values.push(sqlite::Value::Integer(locker_id));
+        }
+
+        if let Some(from) = filter.from {
+            conditions.push("created_at >= ?");
+            values.push(sqlite::Value::Integer(from as i64));
+        }
+
+        if let Some(to) = filter.to {
+            conditions.push("created_at < ?");
+            values.push(sqlite::Value::Integer(to as i64));
+        }
+
+        if let Some(operator_id) = filter.operator_id {
+            conditions.push("operator_id = ?");
+            values.push(sqlite::Value::Integer(operator_id));
+        }
+
+        if let Some(linking_key) = &filter.linking_key {
+            conditions.push(
+                "payment_hash IN (SELECT payment_hash FROM usage_sessions WHERE linking_key = ?)",
+            );
+            values.push(sqlite::Value::String(linking_key.clone()));
+        }
+
+        (conditions.join(" AND "), values)
+    }
+
+    /// Returns every pending payment created at or before `created_before`, oldest first, along
+    /// with its locker and session, optionally only those of an operator. Payments too old to know
+    /// when they were created are always included. Payments made for several lockers at once are
+    /// listed once for each, with its share as the amount.
+    async fn list_pending_payments(
+        &self,
+        created_before: u64,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<AwaitingPayment>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT p.payment_hash, COALESCE(ps.amount_sat, p.amount), p.created_at, l.id, l.name, l.state, s.id, s.started_at
+                     FROM pending_payments p LEFT JOIN payment_sessions ps ON ps.payment_hash = p.payment_hash
+                     JOIN lockers l ON l.id = COALESCE(ps.locker_id, p.locker_id)
+                     LEFT JOIN usage_sessions s ON s.id = ps.session_id OR (ps.session_id IS NULL AND s.payment_hash = p.payment_hash)
+                     WHERE p.status = 'pending' AND (p.created_at IS NULL OR p.created_at <= ?1) AND (?2 IS NULL OR p.operator_id = ?2)
+                     ORDER BY p.created_at, p.id",
+                )?;
+                statement.bind((1, created_before.min(i64::MAX as u64) as i64))?;
+                statement.bind((2, operator_id))?;
+
+                let mut payments = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    payments.push(AwaitingPayment {
+                        payment_hash: statement.read(0)?,
+                        amount: statement.read::<i64, _>(1)? as u64,
+                        created_at: statement.read::<Option<i64>, _>(2)?.map(|t| t as u64),
+                        locker_id: statement.read(3)?,
+                        locker_name: statement.read(4)?,
+                        locker_state: statement.read(5)?,
+                        session_id: statement.read(6)?,
+                        session_started_at: statement.read::<Option<i64>, _>(7)?.map(|t| t as u64),
+                    });
+                }
+
+                Ok(payments)
+            })
+            .await
+    }
+
+    /// Reads a payment from the current row of a statement selecting [PAYMENT_COLUMNS].
+    fn read_payment(statement: &sqlite::Statement) -> Result<PendingPayment, StoreError> {
+        let read_timestamp =
+            |i: usize| -> Result<Option<u64>, sqlite::Error> {
+                Ok(statement.read::<Option<i64>, _>(i)?.map(|t| t as u64))
+            };
+
+        Ok(PendingPayment {
+            amount: statement.read::<i64, _>(0)? as u64,
+            payment_hash: statement.read(1)?,
+            status: statement.read(2)?,
+            locker_id: statement.read(3)?,
+            created_at: read_timestamp(4)?,
+            paid_at: read_timestamp(5)?,
+            expired_at: read_timestamp(6)?,
+            redeemed_at: read_timestamp(7)?,
+            bolt11: statement.read(8)?,
+            cancelled_at: read_timestamp(9)?,
+            needs_refund: statement.read::<i64, _>(10)? != 0,
+            operator_id: statement.read(11)?,
+        })
+    }
+
+    /// Marks a pending payment as paid, returning whether it was still pending.
+    async fn set_payment_paid(&self, payment_hash: &str, paid_at: u64) -> Result<bool, StoreError> {
+        self.transition_payment(payment_hash, "pending", "paid", "paid_at", paid_at)
+            .await
+    }
+
+    /// Marks a pending payment as expired. Does nothing if the payment isn't pending anymore.
+    async fn set_payment_expired(
+        &self,
+        payment_hash: &str,
+        expired_at: u64,
+    ) -> Result<(), StoreError> {
+        self.transition_payment(payment_hash, "pending", "expired", "expired_at", expired_at)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks a pending payment as cancelled, returning whether it was still pending.
+    async fn cancel_payment(
+        &self,
+        payment_hash: &str,
+        cancelled_at: u64,
+    ) -> Result<bool, StoreError> {
+        self.transition_payment(
+            payment_hash,
+            "pending",
+            "cancelled",
+            "cancelled_at",
+            cancelled_at,
+        )
+        .await
+    }
+
+    /// Flags a cancelled payment as needing a refund, returning whether it wasn't flagged yet.
+    async fn flag_for_refund(&self, payment_hash: &str) -> Result<bool, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE pending_payments SET needs_refund = 1 WHERE payment_hash = ? AND status = 'cancelled' AND needs_refund = 0",
+                )?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.next()?;
+
+                Ok(database.change_count() > 0)
+            })
+            .await
+    }
+
+    /// Marks a paid payment as redeemed, storing the receipts we issued for it, one for each
+    /// locker it paid for, and closing the sessions it paid for, all at once. Returns whether this
+    /// call was the one that redeemed it.
+    async fn redeem_payment(&self, receipts: &[Receipt]) -> Result<bool, StoreError> {
+        let receipts = receipts.to_vec();
+        let Some(first) = receipts.first().cloned() else {
+            return Ok(false);
+        };
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "UPDATE pending_payments SET status = 'redeemed', redeemed_at = ? WHERE payment_hash = ? AND status = 'paid'",
+                    )?;
+                    statement.bind((1, first.issued_at as i64))?;
+                    statement.bind((2, first.payment_hash.as_str()))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Ok(false);
+                    }
+
+                    for receipt in &receipts {
+                        let mut statement = database.prepare(
+                            "INSERT INTO receipts (payment_hash, locker_id, session_id, issued_at, signature, site_id, token, expires_at, kid, receipt_signature, legacy_signature) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                        )?;
+                        statement.bind((1, receipt.payment_hash.as_str()))?;
+                        statement.bind((2, receipt.locker_id))?;
+                        statement.bind((3, receipt.session_id))?;
+                        statement.bind((4, receipt.issued_at as i64))?;
+                        statement.bind((5, receipt.signature.as_str()))?;
+                        statement.bind((6, receipt.site_id))?;
+                        statement.bind((7, receipt.token.as_deref()))?;
+                        statement.bind((8, receipt.expires_at.map(|t| t as i64)))?;
+                        statement.bind((9, receipt.kid.as_deref()))?;
+                        statement.bind((10, receipt.receipt_signature.as_deref()))?;
+                        statement.bind((11, receipt.legacy_signature.as_deref()))?;
+                        statement.next()?;
+
+                        let mut statement = database.prepare(
+                            "UPDATE usage_sessions SET state = 'closed', ended_at = ? WHERE id = ? AND state = 'active'",
+                        )?;
+                        statement.bind((1, receipt.issued_at as i64))?;
+                        statement.bind((2, receipt.session_id))?;
+                        statement.next()?;
+                    }
+
+                    Ok::<_, StoreError>(true)
+                })
+            })
+            .await
+    }
+
+    /// Returns the receipt bought with `payment_hash` for `locker_id`.
+    async fn get_receipt(&self, payment_hash: &str, locker_id: i64) -> Result<Receipt, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT payment_hash, locker_id, session_id, issued_at, signature, consumed_at, site_id, token, expires_at, kid, receipt_signature, legacy_signature FROM receipts WHERE payment_hash = ? AND locker_id = ?",
+                )?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.bind((2, locker_id))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Ok(Receipt {
+                    payment_hash: statement.read(0)?,
+                    locker_id: statement.read(1)?,
+                    site_id: statement.read(6)?,
+                    session_id: statement.read(2)?,
+                    issued_at: statement.read::<i64, _>(3)? as u64,
+                    expires_at: statement.read::<Option<i64>, _>(8)?.map(|t| t as u64),
+                    signature: statement.read(4)?,
+                    kid: statement.read(9)?,
+                    token: statement.read(7)?,
+                    receipt_signature: statement.read(10)?,
+                    legacy_signature: statement.read(11)?,
+                    consumed_at: statement.read::<Option<i64>, _>(5)?.map(|t| t as u64),
+                })
+            })
+            .await
+    }
+
+    /// Returns the payment hash of the last receipt for `locker_id` issued by `issued_by` that
+    /// wasn't consumed yet, if any.
+    fn find_last_unconsumed_receipt(
+        database: &sqlite::Connection,
+        locker_id: i64,
+        issued_by: u64,
+    ) -> Result<Option<String>, StoreError> {
+        let mut statement = database.prepare(
+            "SELECT payment_hash FROM receipts WHERE locker_id = ? AND issued_at <= ? AND consumed_at IS NULL ORDER BY issued_at DESC LIMIT 1",
+        )?;
+        statement.bind((1, locker_id))?;
+        statement.bind((2, issued_by.min(i64::MAX as u64) as i64))?;
+
+        match statement.next()? {
+            sqlite::State::Row => Ok(Some(statement.read(0)?)),
+            sqlite::State::Done => Ok(None),
+        }
+    }
+
+    /// Returns the payment hash of the receipt for `locker_id` authorizing it to be opened from
+    /// `issued_at` until `expires_at`, as long as it wasn't consumed yet.
+    fn find_unconsumed_receipt(
+        database: &sqlite::Connection,
+        locker_id: i64,
+        issued_at: u64,
+        expires_at: u64,
+    ) -> Result<Option<String>, StoreError> {
+        let mut statement = database.prepare(
+            "SELECT payment_hash FROM receipts WHERE locker_id = ? AND issued_at = ? AND expires_at = ? AND consumed_at IS NULL",
+        )?;
+        statement.bind((1, locker_id))?;
+        statement.bind((2, issued_at.min(i64::MAX as u64) as i64))?;
+        statement.bind((3, expires_at.min(i64::MAX as u64) as i64))?;
+
+        match statement.next()? {
+            sqlite::State::Row => Ok(Some(statement.read(0)?)),
+            sqlite::State::Done => Ok(None),
+        }
+    }
+
+    /// Marks every outstanding receipt for a locker as consumed, returning their payment hashes.
+    async fn consume_receipts(
+        &self,
+        locker_id: i64,
+        consumed_at: u64,
+    ) -> Result<Vec<String>, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE receipts SET consumed_at = ? WHERE locker_id = ? AND consumed_at IS NULL RETURNING payment_hash",
+                )?;
+                statement.bind((1, consumed_at as i64))?;
+                statement.bind((2, locker_id))?;
+
+                let mut consumed = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    consumed.push(statement.read(0)?);
+                }
+
+                Ok(consumed)
+            })
+            .await
+    }
+
+    /// Revokes the receipt bought with `payment_hash` for `locker_id`, so lockers refuse it from
+    /// now on. The locker may be left out if the payment bought a single receipt.
+    ///
+    /// Returns [StoreError::NotFound] if we never issued that receipt, and
+    /// [StoreError::Constraint] if it was already revoked, or if the payment bought receipts for
+    /// several lockers and none was given.
+    async fn revoke_receipt(
+        &self,
+        payment_hash: &str,
+        locker_id: Option<i64>,
+        reason: Option<String>,
+        revoked_at: u64,
+    ) -> Result<Revocation, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT count(*) FROM receipts WHERE payment_hash = ?1 AND (?2 IS NULL OR locker_id = ?2)",
+                )?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.bind((2, locker_id))?;
+                statement.next()?;
+                if statement.read::<i64, _>(0)? > 1 {
+                    return Err(StoreError::Constraint(format!(
+                        "{payment_hash} bought receipts for several lockers, say which one"
+                    )));
+                }
+
+                let mut statement = database.prepare(
+                    "INSERT INTO revoked_receipts (payment_hash, locker_id, reason, revoked_at, expires_at)
+                     SELECT payment_hash, locker_id, ?3, ?4, expires_at FROM receipts
+                     WHERE payment_hash = ?1 AND (?2 IS NULL OR locker_id = ?2)
+                     RETURNING locker_id, expires_at",
+                )?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.bind((2, locker_id))?;
+                statement.bind((3, reason.as_deref()))?;
+                statement.bind((4, revoked_at as i64))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Ok(Revocation {
+                    locker_id: statement.read(0)?,
+                    expires_at: statement.read::<Option<i64>, _>(1)?.map(|t| t as u64),
+                    payment_hash,
+                    reason,
+                    revoked_at,
+                })
+            })
+            .await
+    }
+
+    /// Whether the receipt bought with `payment_hash` for `locker_id` was revoked.
+    async fn is_receipt_revoked(
+        &self,
+        payment_hash: &str,
+        locker_id: i64,
+    ) -> Result<bool, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT EXISTS (SELECT 1 FROM revoked_receipts WHERE payment_hash = ? AND locker_id = ?)",
+                )?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.bind((2, locker_id))?;
+                statement.next()?;
+
+                Ok(statement.read::<i64, _>(0)? != 0)
+            })
+            .await
+    }
+
+    /// Returns the receipts revoked at or after `since` that haven't expired by `now`, oldest
+    /// first, optionally only those for one locker.
+    async fn list_revocations(
+        &self,
+        since: u64,
+        locker_id: Option<i64>,
+        now: u64,
+    ) -> Result<Vec<Revocation>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT payment_hash, locker_id, reason, revoked_at, expires_at FROM revoked_receipts
+                     WHERE revoked_at >= ?1 AND (?2 IS NULL OR locker_id = ?2)
+                     AND (expires_at IS NULL OR expires_at > ?3)
+                     ORDER BY revoked_at, payment_hash",
+                )?;
+                statement.bind((1, since.min(i64::MAX as u64) as i64))?;
+                statement.bind((2, locker_id))?;
+                statement.bind((3, now as i64))?;
+
+                let mut revocations = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    revocations.push(Revocation {
+                        payment_hash: statement.read(0)?,
+                        locker_id: statement.read(1)?,
+                        reason: statement.read(2)?,
+                        revoked_at: statement.read::<i64, _>(3)? as u64,
+                        expires_at: statement.read::<Option<i64>, _>(4)?.map(|t| t as u64),
+                    });
+                }
+
+                Ok(revocations)
+            })
+            .await
+    }
+
+    /// Marks a token as used, by its `jti` and `digest`, along with the receipt it came with if it
+    /// was bought with a payment. Returns whether it wasn't used yet, and its receipt wasn't
+    /// consumed.
+    async fn consume_token(
+        &self,
+        digest: &str,
+        claims: &token::Claims,
+        used_at: u64,
+    ) -> Result<bool, StoreError> {
+        let digest = digest.to_string();
+        let claims = claims.clone();
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "SELECT EXISTS (SELECT 1 FROM used_tokens WHERE digest = ? OR jti = ?)",
+                    )?;
+                    statement.bind((1, digest.as_str()))?;
+                    statement.bind((2, claims.jti.as_str()))?;
+                    statement.next()?;
+                    if statement.read::<i64, _>(0)? != 0 {
+                        return Ok(false);
+                    }
+
+                    if let Some(payment_hash) = &claims.payment_hash {
+                        let mut statement = database.prepare(
+                            "UPDATE receipts SET consumed_at = ? WHERE payment_hash = ? AND locker_id = ? AND consumed_at IS NULL",
+                        )?;
+                        statement.bind((1, used_at as i64))?;
+                        statement.bind((2, payment_hash.as_str()))?;
+                        statement.bind((3, claims.locker_id))?;
+                        statement.next()?;
+
+                        if database.change_count() == 0 {
+                            return Ok(false);
+                        }
+                    }
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO used_tokens (digest, locker_id, used_at, expires_at, jti) VALUES (?, ?, ?, ?, ?)",
+                    )?;
+                    statement.bind((1, digest.as_str()))?;
+                    statement.bind((2, claims.locker_id))?;
+                    statement.bind((3, used_at as i64))?;
+                    statement.bind((4, claims.exp.min(i64::MAX as u64) as i64))?;
+                    statement.bind((5, claims.jti.as_str()))?;
+                    statement.next()?;
+
+                    Ok::<_, StoreError>(true)
+                })
+            })
+            .await
+    }
+
+    /// Forgets the used tokens that expired before `expired_before`, which can't be replayed
+    /// anymore anyway. Returns how many were forgotten.
+    async fn delete_expired_tokens(&self, expired_before: u64) -> Result<u64, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("DELETE FROM used_tokens WHERE expires_at < ?")?;
+                statement.bind((1, expired_before as i64))?;
+                statement.next()?;
+
+                Ok(database.change_count() as u64)
+            })
+            .await
+    }
+
+    /// Returns whether anyone is in the middle of paying for a locker: they asked for an invoice
+    /// after `since` and didn't get their receipt yet.
+    async fn has_payments_in_flight(&self, since: u64) -> Result<bool, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT EXISTS (SELECT 1 FROM pending_payments WHERE status IN ('pending', 'paid') AND created_at >= ?)",
+                )?;
+                statement.bind((1, since as i64))?;
+                statement.next()?;
+
+                Ok(statement.read::<i64, _>(0)? != 0)
+            })
+            .await
+    }
+
+    /// Marks every pending payment created before `created_before` as expired, returning the
+    /// locker id and hash of each one.
+    async fn expire_pending_payments(
+        &self,
+        created_before: u64,
+        expired_at: u64,
+    ) -> Result<Vec<(i64, String)>, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE pending_payments SET status = 'expired', expired_at = ? WHERE status = 'pending' AND created_at < ? RETURNING locker_id, payment_hash",
+                )?;
+                statement.bind((1, expired_at as i64))?;
+                statement.bind((2, created_before as i64))?;
+
+                let mut expired = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    expired.push((statement.read(0)?, statement.read(1)?));
+                }
+
+                Ok(expired)
+            })
+            .await
+    }
+
+    /// Deletes payments that expired before `expired_before`, returning how many were deleted.
+    async fn delete_expired_payments(&self, expired_before: u64) -> Result<u64, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "DELETE FROM pending_payments WHERE status = 'expired' AND expired_at < ?",
+                )?;
+                statement.bind((1, expired_before as i64))?;
+                statement.next()?;
+
+                Ok(database.change_count() as u64)
+            })
+            .await
+    }
+
+    /// Moves a payment from `from` to `to`, recording when it happened in `timestamp_column`.
+    ///
+    /// The transition only happens if the payment is currently in `from`, and we return whether
+    /// it did.
+    async fn transition_payment(
+        &self,
+        payment_hash: &str,
+        from: &str,
+        to: &str,
+        timestamp_column: &str,
+        timestamp: u64,
+    ) -> Result<bool, StoreError> {
+        let query = format!(
+            "UPDATE pending_payments SET status = ?, {timestamp_column} = ? WHERE payment_hash = ? AND status = ?"
+        );
+        let payment_hash = payment_hash.to_string();
+        let from = from.to_string();
+        let to = to.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(query)?;
+                statement.bind((1, to.as_str()))?;
+                statement.bind((2, timestamp as i64))?;
+                statement.bind((3, payment_hash.as_str()))?;
+                statement.bind((4, from.as_str()))?;
+                statement.next()?;
+
+                Ok(database.change_count() > 0)
+            })
+            .await
+    }
+
+    /// Makes a locker whose contents were taken out available again, unless it's under
+    /// maintenance, where staff opening it shouldn't end the maintenance.
+    async fn free_locker(&self, locker_id: i64) -> Result<(), StoreError> {
+        let result = self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE lockers SET state = 'available' WHERE id = ? AND state != 'maintenance'",
+                )?;
+                statement.bind((1, locker_id))?;
+                statement.next()?;
+                Ok(())
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Checks an open report against what we know of the locker and, only if it passes, records
+    /// it, all in one transaction:
+    ///
+    /// - each report must come after the last one, so a captured report can't be replayed;
+    /// - retrieving must be done with a receipt that wasn't used yet. Reports that don't tell
+    ///   which one it was are taken to have used the last one issued by the time the locker was
+    ///   opened, going by its clock, which may be behind ours by as much as we let it;
+    /// - a locker must be opened to store something before it's opened to retrieve it, see
+    ///   [Server::record_open_action]. Old firmware doesn't tell which it was.
+    async fn accept_open_report(
+        &self,
+        locker_id: i64,
+        report: OpenReport,
+        now: u64,
+    ) -> Result<OpenReportOutcome, StoreError> {
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement =
+                        database.prepare("SELECT last_open_report_at FROM lockers WHERE id = ?")?;
+                    statement.bind((1, locker_id))?;
+                    let sqlite::State::Row = statement.next()? else {
+                        return Err(StoreError::NotFound);
+                    };
+                    let last = statement.read::<Option<i64>, _>(0)?;
+                    if last.is_some_and(|last| last >= report.timestamp as i64) {
+                        return Ok(OpenReportOutcome::NotNewer);
+                    }
+
+                    let redeemed = match (report.signed, report.authorized_at) {
+                        (Some((signing::Action::Store, _)), _) => None,
+                        (Some((signing::Action::Retrieve, expires_at)), Some(authorized_at)) => {
+                            match Self::find_unconsumed_receipt(
+                                database,
+                                locker_id,
+                                authorized_at,
+                                expires_at,
+                            )? {
+                                Some(payment_hash) => Some(payment_hash),
+                                None => return Ok(OpenReportOutcome::UnknownReceipt),
+                            }
+                        }
+                        _ => {
+                            let opened_by = report.timestamp.saturating_add(report.max_drift);
+                            Self::find_last_unconsumed_receipt(database, locker_id, opened_by)?
+                        }
+                    };
+
+                    if let Some((action, _)) = report.signed {
+                        if !Self::record_open_action(database, locker_id, action, now)? {
+                            return Ok(OpenReportOutcome::OutOfOrder);
+                        }
+                    }
+
+                    let mut statement = database
+                        .prepare("UPDATE lockers SET last_open_report_at = ? WHERE id = ?")?;
+                    statement.bind((1, report.timestamp as i64))?;
+                    statement.bind((2, locker_id))?;
+                    statement.next()?;
+
+                    Ok(OpenReportOutcome::Accepted { redeemed })
+                })
+            })
+            .await
+    }
+
+    /// Records that a locker was opened to `action` during its latest session. Returns false if
+    /// that's out of order: storing needs a session that's still open and nothing was retrieved
+    /// from yet, retrieving needs something to have been stored and not retrieved already.
+    fn record_open_action(
+        database: &sqlite::Connection,
+        locker_id: i64,
+        action: signing::Action,
+        now: u64,
+    ) -> Result<bool, StoreError> {
+        let query = match action {
+            signing::Action::Store => {
+                "UPDATE usage_sessions SET stored_at = COALESCE(stored_at, ?1) WHERE id = (SELECT MAX(id) FROM usage_sessions WHERE locker_id = ?2) AND state = 'active' AND retrieved_at IS NULL"
+            }
+            signing::Action::Retrieve => {
+                "UPDATE usage_sessions SET retrieved_at = ?1 WHERE id = (SELECT MAX(id) FROM usage_sessions WHERE locker_id = ?2) AND stored_at IS NOT NULL AND retrieved_at IS NULL"
+            }
+        };
+        let mut statement = database.prepare(query)?;
+        statement.bind((1, now as i64))?;
+        statement.bind((2, locker_id))?;
+        statement.next()?;
+
+        Ok(database.change_count() > 0)
+    }
+
+    /// Remembers how many seconds a locker's clock was ahead of ours, negative if behind, when it
+    /// last reported being opened.
+    async fn record_clock_drift(
+        &self,
+        locker_id: i64,
+        drift: i64,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE lockers SET clock_drift_seconds = ?, clock_drift_at = ? WHERE id = ?",
+                )?;
+                statement.bind((1, drift))?;
+                statement.bind((2, now as i64))?;
+                statement.bind((3, locker_id))?;
+                statement.next()?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Returns the last drift we measured for every locker that ever reported being opened, the
+    /// furthest off first, optionally only the lockers of an operator.
+    async fn list_clock_drift(
+        &self,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<ClockDrift>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT id, name, clock_drift_seconds, clock_drift_at FROM lockers
+                     WHERE clock_drift_at IS NOT NULL AND (?1 IS NULL OR operator_id = ?1)
+                     ORDER BY abs(clock_drift_seconds) DESC, id",
+                )?;
+                statement.bind((1, operator_id))?;
+
+                let mut drifts = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    drifts.push(ClockDrift {
+                        locker_id: statement.read(0)?,
+                        name: statement.read(1)?,
+                        drift_seconds: statement.read(2)?,
+                        measured_at: statement.read::<i64, _>(3)? as u64,
+                    });
+                }
+
+                Ok(drifts)
+            })
+            .await
+    }
+
+    /// Atomically moves a locker from `available` to `in_use`, starting a new usage session for
+    /// it, recording the customer's `linking_key` if they logged in. Returns the id of the new
+    /// session.
+    ///
+    /// Returns [StoreError::Constraint] if the locker exists but isn't available,
+    /// [StoreError::Decommissioned] if it was decommissioned and [StoreError::NotFound] if
+    /// there's no such locker.
+    /// Claims an available locker, or a reserved one if `reservation` is the digest of its
+    /// reservation token, returning the session it opened.
+    async fn claim_locker(
+        &self,
+        locker_id: i64,
+        start_time: u64,
+        linking_key: Option<String>,
+        reservation: Option<String>,
+    ) -> Result<i64, StoreError> {
+        let default_price = self.config.price_per_minute_msat;
+        let result = self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    Self::end_expired_reservation(database, locker_id, start_time)?;
+
+                    // lockers.start_time is superseded by the sessions, but we keep it up to date for
+                    // anything still reading it
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET state = 'in_use', start_time = ? WHERE id = ? AND active = 1 AND (state = 'available' OR (state = 'reserved' AND EXISTS (SELECT 1 FROM reservations WHERE locker_id = lockers.id AND token_digest = ? AND used_at IS NULL AND ended_at IS NULL)))",
+                    )?;
+                    statement.bind((1, start_time as i64))?;
+                    statement.bind((2, locker_id))?;
+                    statement.bind((3, reservation.as_deref()))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Err(Self::unavailable(database, locker_id)?);
+                    }
+
+                    // the session is billed at what the locker costs now, whatever it's changed to
+                    // later
+                    let mut statement = database.prepare(format!(
+                        "SELECT l.price_per_minute_msat, s.price_per_minute_msat FROM {LOCKER_TABLES} WHERE l.id = ?"
+                    ))?;
+                    statement.bind((1, locker_id))?;
+                    statement.next()?;
+                    let price_per_minute_msat = pricing::price_per_minute_msat(
+                        statement.read::<Option<i64>, _>(0)?.map(|price| price as u64),
+                        statement.read::<Option<i64>, _>(1)?.map(|price| price as u64),
+                        default_price,
+                    );
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO usage_sessions (locker_id, started_at, state, linking_key, price_per_minute_msat) VALUES (?, ?, 'active', ?, ?) RETURNING id",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, start_time as i64))?;
+                    statement.bind((3, linking_key.as_deref()))?;
+                    statement.bind((4, price_per_minute_msat as i64))?;
+                    statement.next()?;
+                    let session_id = statement.read::<i64, _>(0)?;
+
+                    let mut statement = database.prepare(
+                        "UPDATE reservations SET used_at = ?, session_id = ? WHERE locker_id = ? AND used_at IS NULL AND ended_at IS NULL",
+                    )?;
+                    statement.bind((1, start_time as i64))?;
+                    statement.bind((2, session_id))?;
+                    statement.bind((3, locker_id))?;
+                    statement.next()?;
+
+                    Ok(session_id)
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Holds an available locker for whoever has the reservation token whose digest is
+    /// `token_digest`, until `expires_at`, see [reservation].
+    async fn reserve_locker(
+        &self,
+        locker_id: i64,
+        token_digest: &str,
+        now: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let token_digest = token_digest.to_string();
+
+        let result = self
+            .database
+            .write(move |database| {
+                db::transaction(database, || {
+                    Self::end_expired_reservation(database, locker_id, now)?;
+
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET state = 'reserved' WHERE id = ? AND state = 'available' AND active = 1",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Err(Self::unavailable(database, locker_id)?);
+                    }
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO reservations (locker_id, token_digest, created_at, expires_at) VALUES (?, ?, ?, ?)",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, token_digest.as_str()))?;
+                    statement.bind((3, now as i64))?;
+                    statement.bind((4, expires_at as i64))?;
+                    statement.next()?;
+
+                    Ok(())
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Ends the reservations that expired by `now`, making their lockers available again, and
+    /// returns those lockers.
+    async fn expire_reservations(&self, now: u64) -> Result<Vec<i64>, StoreError> {
+        let lockers = self
+            .database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "UPDATE reservations SET ended_at = ? WHERE used_at IS NULL AND ended_at IS NULL AND expires_at <= ? RETURNING locker_id",
+                    )?;
+                    statement.bind((1, now as i64))?;
+                    statement.bind((2, now as i64))?;
+
+                    let mut lockers = Vec::new();
+                    while let sqlite::State::Row = statement.next()? {
+                        lockers.push(statement.read::<i64, _>(0)?);
+                    }
+
+                    for locker_id in &lockers {
+                        let mut statement = database.prepare(
+                            "UPDATE lockers SET state = 'available' WHERE id = ? AND state = 'reserved'",
+                        )?;
+                        statement.bind((1, *locker_id))?;
+                        statement.next()?;
+                    }
+
+                    Ok::<_, StoreError>(lockers)
+                })
+            })
+            .await?;
+
+        for locker_id in &lockers {
+            self.publish_locker_state(*locker_id).await;
+        }
+        Ok(lockers)
+    }
+
+    /// Ends the reservation of a locker within a transaction if it expired by `now`, making the
+    /// locker available again, rather than waiting for [tasks::expire_reservations] to.
+    fn end_expired_reservation(
+        database: &sqlite::Connection,
+        locker_id: i64,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        let mut statement = database.prepare(
+            "UPDATE reservations SET ended_at = ? WHERE locker_id = ? AND used_at IS NULL AND ended_at IS NULL AND expires_at <= ?",
+        )?;
+        statement.bind((1, now as i64))?;
+        statement.bind((2, locker_id))?;
+        statement.bind((3, now as i64))?;
+        statement.next()?;
+
+        if database.change_count() > 0 {
+            let mut statement = database.prepare(
+                "UPDATE lockers SET state = 'available' WHERE id = ? AND state = 'reserved'",
+            )?;
+            statement.bind((1, locker_id))?;
+            statement.next()?;
+        }
+
+        Ok(())
+    }
+
+    /// Why a locker that wasn't available or reserved for the caller can't be claimed or
+    /// reserved.
+    fn unavailable(database: &sqlite::Connection, locker_id: i64) -> Result<StoreError, StoreError> {
+        let mut statement = database.prepare("SELECT active, state FROM lockers WHERE id = ?")?;
+        statement.bind((1, locker_id))?;
+        let sqlite::State::Row = statement.next()? else {
+            return Ok(StoreError::NotFound);
+        };
+
+        Ok(match statement.read::<i64, _>(0)? {
+            0 => StoreError::Decommissioned,
+            _ => match statement.read::<String, _>(1)?.as_str() {
+                "maintenance" => StoreError::UnderMaintenance,
+                "reserved" => StoreError::Reserved,
+                _ => StoreError::Constraint(format!("locker {locker_id} isn't available")),
+            },
+        })
+    }
+
+    /// Looks for lockers, sessions and payments that disagree with each other, see
+    /// [consistency::Anomaly]. Sessions are only considered stale if `stale_before` is set and
+    /// they started before it.
+    async fn find_anomalies(
+        &self,
+        stale_before: Option<u64>,
+    ) -> Result<Vec<consistency::Anomaly>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut anomalies = Vec::new();
+
+                let mut statement = database.prepare(
+                    "SELECT l.id FROM lockers l WHERE l.state IN ('in_use', 'awaiting_payment') AND NOT EXISTS (SELECT 1 FROM usage_sessions s WHERE s.locker_id = l.id AND s.state = 'active') ORDER BY l.id",
+                )?;
+                while let sqlite::State::Row = statement.next()? {
+                    anomalies.push(consistency::Anomaly::InUseWithoutSession {
+                        locker_id: statement.read(0)?,
+                    });
+                }
+
+                if let Some(stale_before) = stale_before {
+                    let mut statement = database.prepare(
+                        "SELECT s.locker_id, s.id, s.started_at FROM usage_sessions s JOIN lockers l ON l.id = s.locker_id
+                         WHERE s.state = 'active' AND l.state IN ('in_use', 'awaiting_payment') AND s.started_at < ?
+                         AND NOT EXISTS (SELECT 1 FROM pending_payments p WHERE p.locker_id = s.locker_id AND p.status IN ('pending', 'paid'))
+                         ORDER BY s.id",
+                    )?;
+                    statement.bind((1, stale_before as i64))?;
+                    while let sqlite::State::Row = statement.next()? {
+                        anomalies.push(consistency::Anomaly::StaleSession {
+                            locker_id: statement.read(0)?,
+                            session_id: statement.read(1)?,
+                            started_at: statement.read::<i64, _>(2)? as u64,
+                        });
+                    }
+                }
+
+                let mut statement = database.prepare(
+                    "SELECT s.locker_id, s.id FROM usage_sessions s JOIN lockers l ON l.id = s.locker_id WHERE s.state = 'active' AND l.state = 'available' ORDER BY s.id",
+                )?;
+                while let sqlite::State::Row = statement.next()? {
+                    anomalies.push(consistency::Anomaly::SessionOnAvailableLocker {
+                        locker_id: statement.read(0)?,
+                        session_id: statement.read(1)?,
+                    });
+                }
+
+                let mut statement = database.prepare(
+                    "SELECT p.locker_id, p.payment_hash FROM pending_payments p JOIN lockers l ON l.id = p.locker_id WHERE p.status = 'pending' AND l.state = 'available' ORDER BY p.id",
+                )?;
+                while let sqlite::State::Row = statement.next()? {
+                    anomalies.push(consistency::Anomaly::PendingPaymentOnAvailableLocker {
+                        locker_id: statement.read(0)?,
+                        payment_hash: statement.read(1)?,
+                    });
+                }
+
+                Ok(anomalies)
+            })
+            .await
+    }
+
+    /// Makes a locker that is in use available again, closing `session_id` along with it if set.
+    /// Returns whether the locker was still in use, and the session still open.
+    async fn release_locker(
+        &self,
+        locker_id: i64,
+        session_id: Option<i64>,
+        ended_at: u64,
+    ) -> Result<bool, StoreError> {
+        let result = self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET state = 'available' WHERE id = ?1 AND state IN ('in_use', 'awaiting_payment')
+                         AND (?2 IS NULL OR EXISTS (SELECT 1 FROM usage_sessions WHERE id = ?2 AND state = 'active'))",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, session_id))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Ok(false);
+                    }
+
+                    if let Some(session_id) = session_id {
+                        let mut statement = database.prepare(
+                            "UPDATE usage_sessions SET state = 'closed', ended_at = ? WHERE id = ?",
+                        )?;
+                        statement.bind((1, ended_at as i64))?;
+                        statement.bind((2, session_id))?;
+                        statement.next()?;
+                    }
+
+                    Ok::<_, StoreError>(true)
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Moves a locker in use to `awaiting_payment`, fixing `amount_sat` as what `session_id` owes
+    /// as of `ended_at`. Returns false if the locker wasn't in use anymore.
+    async fn end_usage(
+        &self,
+        locker_id: i64,
+        session_id: i64,
+        ended_at: u64,
+        amount_sat: u64,
+    ) -> Result<bool, StoreError> {
+        let result = self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET state = 'awaiting_payment' WHERE id = ? AND state = 'in_use'",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Ok(false);
+                    }
+
+                    let mut statement = database.prepare(
+                        "UPDATE usage_sessions SET usage_ended_at = ?, amount_sat = ? WHERE id = ? AND state = 'active'",
+                    )?;
+                    statement.bind((1, ended_at as i64))?;
+                    statement.bind((2, amount_sat as i64))?;
+                    statement.bind((3, session_id))?;
+                    statement.next()?;
+
+                    Ok::<_, StoreError>(true)
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Cancels `session_id`, the open session of a locker, and makes the locker available again,
+    /// unless the locker was already opened for it. Returns whether it was cancelled.
+    async fn cancel_session(
+        &self,
+        locker_id: i64,
+        session_id: i64,
+        ended_at: u64,
+    ) -> Result<bool, StoreError> {
+        let result = self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "UPDATE usage_sessions SET state = 'cancelled', ended_at = ?1 WHERE id = ?2 AND locker_id = ?3 AND state = 'active' AND stored_at IS NULL",
+                    )?;
+                    statement.bind((1, ended_at as i64))?;
+                    statement.bind((2, session_id))?;
+                    statement.bind((3, locker_id))?;
+                    statement.next()?;
+
+                    if database.change_count() == 0 {
+                        return Ok(false);
+                    }
+
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET state = 'available' WHERE id = ? AND state IN ('in_use', 'awaiting_payment')",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.next()?;
+
+                    Ok::<_, StoreError>(true)
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Closes a session that is still open, returning whether it was.
+    async fn close_session(&self, session_id: i64, ended_at: u64) -> Result<bool, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE usage_sessions SET state = 'closed', ended_at = ? WHERE id = ? AND state = 'active'",
+                )?;
+                statement.bind((1, ended_at as i64))?;
+                statement.bind((2, session_id))?;
+                statement.next()?;
+
+                Ok(database.change_count() > 0)
+            })
+            .await
+    }
+
+    /// Returns the session that is currently open for a locker.
+    async fn get_active_session(&self, locker_id: i64) -> Result<UsageSession, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {SESSION_COLUMNS} FROM usage_sessions WHERE locker_id = ? AND state = 'active' ORDER BY id DESC LIMIT 1"
+                ))?;
+                statement.bind((1, locker_id))?;
+
+                Self::read_session(&mut statement)
+            })
+            .await
+    }
+
+    /// Whether a token may still be used in the session it was issued for: storing only while
+    /// it's the locker's current session, retrieving only in the session its payment covered.
+    async fn is_token_session(&self, claims: &token::Claims) -> Result<bool, StoreError> {
+        let session = match (claims.action, &claims.payment_hash) {
+            (signing::Action::Store, _) => self.get_active_session(claims.locker_id).await,
+            (signing::Action::Retrieve, Some(payment_hash)) => {
+                self.get_session_by_payment(payment_hash, claims.locker_id).await
+            }
+            // nobody paid for it
+            (signing::Action::Retrieve, None) => return Ok(false),
+        };
+
+        match session {
+            Ok(session) => Ok(session.id == claims.session_id),
+            Err(StoreError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the session of `locker_id` covered by a given payment, whether it was made for that
+    /// session alone or for several, see [batch].
+    async fn get_session_by_payment(
+        &self,
+        payment_hash: &str,
+        locker_id: i64,
+    ) -> Result<UsageSession, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {SESSION_COLUMNS} FROM usage_sessions WHERE locker_id = ?2 AND (payment_hash = ?1 OR id IN (SELECT session_id FROM payment_sessions WHERE payment_hash = ?1)) ORDER BY id DESC LIMIT 1"
+                ))?;
+                statement.bind((1, payment_hash.as_str()))?;
+                statement.bind((2, locker_id))?;
+
+                Self::read_session(&mut statement)
+            })
+            .await
+    }
+
+    /// Returns how the payments with an id greater than `after` and up to `last` that were made
+    /// for several lockers at once split between them, as the id of each payment along with each
+    /// locker and its share, ordered by payment and then by locker, see [batch].
+    async fn list_payment_shares(
+        &self,
+        after: i64,
+        last: i64,
+    ) -> Result<Vec<(i64, i64, u64)>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT p.id, ps.locker_id, ps.amount_sat FROM pending_payments p JOIN payment_sessions ps ON ps.payment_hash = p.payment_hash
+                     WHERE p.id > ? AND p.id <= ? ORDER BY p.id, ps.locker_id",
+                )?;
+                statement.bind((1, after))?;
+                statement.bind((2, last))?;
+
+                let mut shares = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    shares.push((
+                        statement.read(0)?,
+                        statement.read(1)?,
+                        statement.read::<i64, _>(2)? as u64,
+                    ));
+                }
+
+                Ok(shares)
+            })
+            .await
+    }
+
+    /// Returns the sessions a payment made for several at once covers, by locker, or none if it
+    /// was made for a single session, see [batch].
+    async fn get_payment_sessions(
+        &self,
+        payment_hash: &str,
+    ) -> Result<Vec<UsageSession>, StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {SESSION_COLUMNS} FROM usage_sessions WHERE id IN (SELECT session_id FROM payment_sessions WHERE payment_hash = ?) ORDER BY locker_id"
+                ))?;
+                statement.bind((1, payment_hash.as_str()))?;
+
+                let mut sessions = Vec::new();
+                loop {
+                    match Self::read_session(&mut statement) {
+                        Ok(session) => sessions.push(session),
+                        Err(StoreError::NotFound) => return Ok(sessions),
+                        Err(e) => return Err(e),
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Returns up to `limit` sessions with an id greater than `after`, oldest first, optionally
+    /// only those of an operator's lockers.
+    async fn list_sessions_after(
+        &self,
+        after: i64,
+        limit: u64,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<UsageSession>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {SESSION_COLUMNS} FROM usage_sessions
+                     WHERE id > ?1 AND (?3 IS NULL OR locker_id IN (SELECT id FROM lockers WHERE operator_id = ?3))
+                     ORDER BY id LIMIT ?2"
+                ))?;
+                statement.bind((1, after))?;
+                statement.bind((2, limit as i64))?;
+                statement.bind((3, operator_id))?;
+
+                let mut sessions = Vec::new();
+                loop {
+                    match Self::read_session(&mut statement) {
+                        Ok(session) => sessions.push(session),
+                        Err(StoreError::NotFound) => return Ok(sessions),
+                        Err(e) => return Err(e),
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Sums up the payments settled between `from` and `to`, grouped by the period they were
+    /// settled in and by locker. Returns the first day of each period, as `YYYY-MM-DD`, along with
+    /// what each locker made in it, ordered by period and then by locker. With an `operator_id`,
+    /// only that operator's payments are summed up.
+    ///
+    /// A payment made for several lockers at once is split between them, each getting its own
+    /// session's share, see [batch].
+    async fn revenue_by_period(
+        &self,
+        granularity: Granularity,
+        from: u64,
+        to: u64,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<(String, LockerRevenue)>, StoreError> {
+        let period = granularity.period_start("p.paid_at");
+
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {period} AS period, COALESCE(ps.locker_id, p.locker_id) AS locker, SUM(COALESCE(ps.amount_sat, p.amount)), COUNT(DISTINCT p.payment_hash), COUNT(s.id), AVG(COALESCE(s.ended_at, p.paid_at) - s.started_at)
+                     FROM pending_payments p LEFT JOIN payment_sessions ps ON ps.payment_hash = p.payment_hash
+                     LEFT JOIN usage_sessions s ON s.id = ps.session_id OR (ps.session_id IS NULL AND s.payment_hash = p.payment_hash)
+                     WHERE p.status IN ('paid', 'redeemed') AND p.paid_at >= ?1 AND p.paid_at < ?2 AND (?3 IS NULL OR p.operator_id = ?3)
+                     GROUP BY period, locker ORDER BY period, locker"
+                ))?;
+                statement.bind((1, from as i64))?;
+                statement.bind((2, to.min(i64::MAX as u64) as i64))?;
+                statement.bind((3, operator_id))?;
+
+                let mut rows = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    rows.push((
+                        statement.read(0)?,
+                        LockerRevenue {
+                            locker_id: statement.read(1)?,
+                            total_sat: statement.read::<i64, _>(2)? as u64,
+                            payments: statement.read::<i64, _>(3)? as u64,
+                            sessions: statement.read::<i64, _>(4)? as u64,
+                            average_session_seconds: statement.read(5)?,
+                        },
+                    ));
+                }
+
+                Ok(rows)
+            })
+            .await
+    }
+
+    /// Returns how long each locker was in use between `from` and `to`, ordered by locker,
+    /// optionally only the lockers of an operator.
+    ///
+    /// Sessions that started before `from` or ended after `to` only count for the part that falls
+    /// within the period, and sessions that are still open count as ending at `now`.
+    async fn occupancy(
+        &self,
+        from: u64,
+        to: u64,
+        now: u64,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<LockerOccupancy>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT l.id, SUM(MAX(0, MIN(COALESCE(s.ended_at, ?3), ?2) - MAX(s.started_at, ?1))), COUNT(s.id)
+                     FROM lockers l LEFT JOIN usage_sessions s ON s.locker_id = l.id AND s.started_at < ?2 AND COALESCE(s.ended_at, ?3) > ?1
+                     WHERE ?4 IS NULL OR l.operator_id = ?4
+                     GROUP BY l.id ORDER BY l.id",
+                )?;
+                statement.bind((1, from as i64))?;
+                statement.bind((2, to as i64))?;
+                statement.bind((3, now as i64))?;
+                statement.bind((4, operator_id))?;
+
+                let mut lockers = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    lockers.push(LockerOccupancy {
+                        locker_id: statement.read(0)?,
+                        occupied_seconds: statement.read::<Option<i64>, _>(1)?.unwrap_or(0) as u64,
+                        sessions: statement.read::<i64, _>(2)? as u64,
+                    });
+                }
+
+                Ok(lockers)
+            })
+            .await
+    }
+
+    fn read_session(statement: &mut sqlite::Statement) -> Result<UsageSession, StoreError> {
+        let sqlite::State::Row = statement.next()? else {
+            return Err(StoreError::NotFound);
+        };
+
+        Ok(UsageSession {
+            id: statement.read(0)?,
+            locker_id: statement.read(1)?,
+            started_at: statement.read::<i64, _>(2)? as u64,
+            ended_at: statement.read::<Option<i64>, _>(3)?.map(|t| t as u64),
+            amount_sat: statement.read::<Option<i64>, _>(4)?.map(|a| a as u64),
+            payment_hash: statement.read(5)?,
+            state: statement.read(6)?,
+            nostr_pubkey: statement.read(7)?,
+            usage_ended_at: statement.read::<Option<i64>, _>(8)?.map(|t| t as u64),
+            price_per_minute_msat: statement.read::<Option<i64>, _>(9)?.map(|p| p as u64),
+            linking_key: statement.read(10)?,
+        })
+    }
+
+    /// Records the nostr public key of the customer using a session, see [nostr]. Once a session
+    /// has a key it keeps it, returning whether `nostr_pubkey` is the one recorded, so nobody can
+    /// take over someone else's session by paying for it with their own key.
+    async fn set_session_nostr_pubkey(
+        &self,
+        session_id: i64,
+        nostr_pubkey: &str,
+    ) -> Result<bool, StoreError> {
+        let nostr_pubkey = nostr_pubkey.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE usage_sessions SET nostr_pubkey = ? WHERE id = ? AND (nostr_pubkey IS NULL OR nostr_pubkey = ?)",
+                )?;
+                statement.bind((1, nostr_pubkey.as_str()))?;
+                statement.bind((2, session_id))?;
+                statement.bind((3, nostr_pubkey.as_str()))?;
+                statement.next()?;
+
+                Ok(database.change_count() > 0)
+            })
+            .await
+    }
+
+    /// Records the invoice we created for a session. If the user asks to pay more than once, the
+    /// latest invoice wins.
+    async fn set_session_invoice(
+        &self,
+        session_id: i64,
+        amount_sat: u64,
+        payment_hash: &str,
+    ) -> Result<(), StoreError> {
+        let payment_hash = payment_hash.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database
+                    .prepare("UPDATE usage_sessions SET amount_sat = ?, payment_hash = ? WHERE id = ?")?;
+                statement.bind((1, amount_sat as i64))?;
+                statement.bind((2, payment_hash.as_str()))?;
+                statement.bind((3, session_id))?;
+                statement.next()?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Records a new pending payment covering several sessions, given as their locker, id and
+    /// amount, along with the invoice for each of them, all at once. The payment is listed under
+    /// the first locker, and its operator, which must be every locker's, see [batch].
+    ///
+    /// Like [Server::insert_payment], a payment hash we already have is a [StoreError::Constraint].
+    async fn insert_batch_payment(
+        &self,
+        amount: u64,
+        payment_hash: &str,
+        bolt11: &str,
+        sessions: &[(i64, i64, u64)],
+    ) -> Result<(), StoreError> {
+        let payment_hash = payment_hash.to_string();
+        let bolt11 = bolt11.to_string();
+        let sessions = sessions.to_vec();
+        let Some(&(first_locker_id, _, _)) = sessions.first() else {
+            return Err(StoreError::NotFound);
+        };
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "INSERT INTO pending_payments (amount, payment_hash, status, locker_id, created_at, bolt11, operator_id) VALUES (?1, ?2, 'pending', ?3, ?4, ?5, (SELECT operator_id FROM lockers WHERE id = ?3))",
+                    )?;
+                    statement.bind((1, amount as i64))?;
+                    statement.bind((2, payment_hash.as_str()))?;
+                    statement.bind((3, first_locker_id))?;
+                    statement.bind((4, now() as i64))?;
+                    statement.bind((5, bolt11.as_str()))?;
+                    statement.next()?;
+
+                    for &(locker_id, session_id, amount_sat) in &sessions {
+                        let mut statement = database.prepare(
+                            "INSERT INTO payment_sessions (payment_hash, session_id, locker_id, amount_sat) VALUES (?, ?, ?, ?)",
+                        )?;
+                        statement.bind((1, payment_hash.as_str()))?;
+                        statement.bind((2, session_id))?;
+                        statement.bind((3, locker_id))?;
+                        statement.bind((4, amount_sat as i64))?;
+                        statement.next()?;
+
+                        let mut statement = database.prepare(
+                            "UPDATE usage_sessions SET amount_sat = ?, payment_hash = ? WHERE id = ?",
+                        )?;
+                        statement.bind((1, amount_sat as i64))?;
+                        statement.bind((2, payment_hash.as_str()))?;
+                        statement.bind((3, session_id))?;
+                        statement.next()?;
+                    }
+
+                    Ok::<_, StoreError>(())
+                })
+            })
+            .await
+    }
+
+    /// Appends an event to a locker's log.
+    ///
+    /// The log is only there to help us understand what happened, so failing to write to it must
+    /// never fail the operation being logged: errors are printed and otherwise ignored.
+    async fn record_event(
+        &self,
+        locker_id: i64,
+        event: &str,
+        actor: &str,
+        details: serde_json::Value,
+    ) {
+        let event = event.to_string();
+        let actor = actor.to_string();
+
+        self.database
+            .write(move |database| {
+                let result = database
+                    .prepare("INSERT INTO locker_events (locker_id, event, actor, timestamp, details) VALUES (?, ?, ?, ?, ?)")
+                    .and_then(|mut statement| {
+                        statement.bind((1, locker_id))?;
+                        statement.bind((2, event.as_str()))?;
+                        statement.bind((3, actor.as_str()))?;
+                        statement.bind((4, now() as i64))?;
+                        statement.bind((5, details.to_string().as_str()))?;
+                        statement.next()
+                    });
+
+                if let Err(e) = result {
+                    eprintln!("[record_event] failed to record {event} for locker {locker_id}: {e}");
+                }
+            })
+            .await
+    }
+
+    /// Tells `/ws/lockers` subscribers about the state `locker_id` is in now, if it changed since
+    /// we last told them, see [feed].
+    async fn publish_locker_state(&self, locker_id: i64) {
+        match self.get_locker(locker_id).await {
+            Ok(locker) => {
+                self.locker_feed
+                    .publish(locker_id, feed::state_of(&locker), now());
+            }
+            // the call that was changing it failed for the same reason
+            Err(StoreError::NotFound) => {}
+            Err(e) => eprintln!("[publish_locker_state] failed to read locker {locker_id}: {e:?}"),
+        }
+    }
+
+    /// Appends an admin call to the audit log. Like [Server::record_event], failing to write to it
+    /// never fails the call.
+    async fn record_admin_call(
+        &self,
+        token: &config::AdminToken,
+        method: &str,
+        path: &str,
+        route: Option<String>,
+        summary: Option<String>,
+        status: u16,
+    ) {
+        let token_id = token.id.clone();
+        let role = token.role;
+        let operator_id = token.operator_id;
+        let method = method.to_string();
+        let path = path.to_string();
+
+        self.database
+            .write(move |database| {
+                let result = database
+                    .prepare("INSERT INTO admin_audit (token_id, role, method, path, route, summary, status, timestamp, operator_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+                    .and_then(|mut statement| {
+                        statement.bind((1, token_id.as_str()))?;
+                        statement.bind((2, role.as_str()))?;
+                        statement.bind((3, method.as_str()))?;
+                        statement.bind((4, path.as_str()))?;
+                        statement.bind((5, route.as_deref()))?;
+                        statement.bind((6, summary.as_deref()))?;
+                        statement.bind((7, status as i64))?;
+                        statement.bind((8, now() as i64))?;
+                        statement.bind((9, operator_id))?;
+                        statement.next()
+                    });
+
+                if let Err(e) = result {
+                    eprintln!("[record_admin_call] failed to record {method} {path} by {token_id}: {e}");
+                }
+            })
+            .await
+    }
+
+    /// Adds an operator, returning it. Names are unique, taken ones return
+    /// [StoreError::Constraint].
+    async fn insert_operator(&self, name: &str) -> Result<OperatorAccount, StoreError> {
+        let name = name.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO operators (name, created_at) VALUES (?, ?) RETURNING id, created_at",
+                )?;
+                statement.bind((1, name.as_str()))?;
+                statement.bind((2, now() as i64))?;
+                statement.next()?;
+
+                Ok(OperatorAccount {
+                    id: statement.read(0)?,
+                    name,
+                    created_at: statement.read::<i64, _>(1)? as u64,
+                })
+            })
+            .await
+    }
+
+    async fn list_operators(&self) -> Result<Vec<OperatorAccount>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement =
+                    database.prepare("SELECT id, name, created_at FROM operators ORDER BY id")?;
+
+                let mut operators = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    operators.push(OperatorAccount {
+                        id: statement.read(0)?,
+                        name: statement.read(1)?,
+                        created_at: statement.read::<i64, _>(2)? as u64,
+                    });
+                }
+
+                Ok(operators)
+            })
+            .await
+    }
+
+    /// Returns the operator `owned` belongs to, `None` if it doesn't belong to any, or
+    /// [StoreError::NotFound] if there's no such thing.
+    async fn operator_of(&self, owned: Owned) -> Result<Option<i64>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = match &owned {
+                    Owned::Locker(id) => {
+                        let mut statement =
+                            database.prepare("SELECT operator_id FROM lockers WHERE id = ?")?;
+                        statement.bind((1, *id))?;
+                        statement
+                    }
+                    Owned::Site(id) => {
+                        let mut statement =
+                            database.prepare("SELECT operator_id FROM sites WHERE id = ?")?;
+                        statement.bind((1, *id))?;
+                        statement
+                    }
+                    Owned::Payment(payment_hash) => {
+                        let mut statement = database.prepare(
+                            "SELECT operator_id FROM pending_payments WHERE payment_hash = ?",
+                        )?;
+                        statement.bind((1, payment_hash.as_str()))?;
+                        statement
+                    }
+                    Owned::Webhook(id) => {
+                        let mut statement =
+                            database.prepare("SELECT operator_id FROM webhooks WHERE id = ?")?;
+                        statement.bind((1, *id))?;
+                        statement
+                    }
+                    Owned::ProvisioningCode(id) => {
+                        let mut statement = database
+                            .prepare("SELECT operator_id FROM provisioning_codes WHERE id = ?")?;
+                        statement.bind((1, *id))?;
+                        statement
+                    }
+                };
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Ok(statement.read(0)?)
+            })
+            .await
+    }
+
+    /// Returns up to `limit` admin calls, newest first, optionally only those older than the one
+    /// with id `before` and only those made with the tokens of an operator.
+    async fn list_admin_calls(
+        &self,
+        limit: u64,
+        before: Option<i64>,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<AdminCall>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT id, token_id, method, path, route, summary, status, timestamp, role, operator_id FROM admin_audit WHERE id < ?1 AND (?3 IS NULL OR operator_id = ?3) ORDER BY id DESC LIMIT ?2",
+                )?;
+                statement.bind((1, before.unwrap_or(i64::MAX)))?;
+                statement.bind((2, limit as i64))?;
+                statement.bind((3, operator_id))?;
+
+                let mut calls = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    calls.push(AdminCall {
+                        id: statement.read(0)?,
+                        token_id: statement.read(1)?,
+                        method: statement.read(2)?,
+                        path: statement.read(3)?,
+                        route: statement.read(4)?,
+                        summary: statement.read(5)?,
+                        status: statement.read::<i64, _>(6)? as u16,
+                        timestamp: statement.read::<i64, _>(7)? as u64,
+                        role: statement.read(8)?,
+                        operator_id: statement.read(9)?,
+                    });
+                }
+
+                Ok(calls)
+            })
+            .await
+    }
+
+    /// Returns up to `limit` events for a locker, newest first, optionally only those older than
+    /// the event with id `before`.
+    async fn list_locker_events(
+        &self,
+        locker_id: i64,
+        limit: u64,
+        before: Option<i64>,
+    ) -> Result<Vec<LockerEvent>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT id, locker_id, event, actor, timestamp, details FROM locker_events WHERE locker_id = ? AND id < ? ORDER BY id DESC LIMIT ?",
+                )?;
+                statement.bind((1, locker_id))?;
+                statement.bind((2, before.unwrap_or(i64::MAX)))?;
+                statement.bind((3, limit as i64))?;
+
+                let mut events = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    let details: String = statement.read(5)?;
+                    events.push(LockerEvent {
+                        id: statement.read(0)?,
+                        locker_id: statement.read(1)?,
+                        event: statement.read(2)?,
+                        actor: statement.read(3)?,
+                        timestamp: statement.read::<i64, _>(4)? as u64,
+                        details: serde_json::from_str(&details).unwrap_or(serde_json::Value::Null),
+                    });
+                }
+
+                Ok(events)
+            })
+            .await
+    }
+
+    /// Returns the sessions of a locker started between `from` and `to`, newest first, along with
+    /// how many there are in total. Open sessions are counted as lasting until `now`.
+    async fn list_locker_history(
+        &self,
+        locker_id: i64,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: u64,
+        offset: u64,
+        now: u64,
+    ) -> Result<(Vec<SessionHistory>, u64), StoreError> {
+        self.database
+            .read(move |database| {
+                let from = from.map(|from| from.min(i64::MAX as u64) as i64);
+                let to = to.map(|to| to.min(i64::MAX as u64) as i64);
+
+                let mut statement = database.prepare(
+                    "SELECT COUNT(*) FROM usage_sessions s WHERE s.locker_id = ?1 AND (?2 IS NULL OR s.started_at >= ?2) AND (?3 IS NULL OR s.started_at < ?3)",
+                )?;
+                statement.bind((1, locker_id))?;
+                statement.bind((2, from))?;
+                statement.bind((3, to))?;
+                statement.next()?;
+                let total = statement.read::<i64, _>(0)? as u64;
+
+                let mut statement = database.prepare(
+                    "SELECT s.id, s.started_at, s.ended_at, s.amount_sat, s.payment_hash, s.state, p.status IN ('paid', 'redeemed'), EXISTS (SELECT 1 FROM receipts r WHERE r.session_id = s.id), s.needs_follow_up
+                     FROM usage_sessions s LEFT JOIN pending_payments p ON p.payment_hash = s.payment_hash
+                     WHERE s.locker_id = ?1 AND (?2 IS NULL OR s.started_at >= ?2) AND (?3 IS NULL OR s.started_at < ?3)
+                     ORDER BY s.id DESC LIMIT ?4 OFFSET ?5",
+                )?;
+                statement.bind((1, locker_id))?;
+                statement.bind((2, from))?;
+                statement.bind((3, to))?;
+                statement.bind((4, limit.min(i64::MAX as u64) as i64))?;
+                statement.bind((5, offset.min(i64::MAX as u64) as i64))?;
+
+                let mut sessions = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    let started_at = statement.read::<i64, _>(1)? as u64;
+                    let ended_at = statement.read::<Option<i64>, _>(2)?.map(|t| t as u64);
+                    let paid = statement.read::<Option<i64>, _>(6)? == Some(1);
+                    let session_state = statement.read::<String, _>(5)?;
+                    let receipted = statement.read::<i64, _>(7)? != 0;
+                    let outcome = match (session_state.as_str(), receipted) {
+                        ("active", _) => "in_progress",
+                        ("cancelled", _) => "cancelled",
+                        (_, true) => "paid",
+                        (_, false) => "released",
+                    };
+
+                    sessions.push(SessionHistory {
+                        session_id: statement.read(0)?,
+                        started_at,
+                        ended_at,
+                        duration_seconds: ended_at.unwrap_or(now).saturating_sub(started_at),
+                        amount_paid_sat: statement
+                            .read::<Option<i64>, _>(3)?
+                            .filter(|_| paid)
+                            .map(|amount| amount as u64),
+                        payment_hash: statement.read(4)?,
+                        outcome,
+                        needs_follow_up: statement.read::<i64, _>(8)? != 0,
+                    });
+                }
+
+                Ok((sessions, total))
+            })
+            .await
+    }
+
+    /// Returns every locker with its key, including those waiting to be approved and the
+    /// decommissioned ones, or only the lockers of an operator.
+    async fn list_admin_lockers(
+        &self,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<AdminLocker>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {LOCKER_COLUMNS}, l.pk, l.operator_id FROM {LOCKER_TABLES} WHERE ?1 IS NULL OR l.operator_id = ?1 ORDER BY l.id"
+                ))?;
+                statement.bind((1, operator_id))?;
+
+                let mut lockers = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    lockers.push(AdminLocker {
+                        locker: Self::read_locker(&statement)?,
+                        pk: statement.read(14)?,
+                        operator_id: statement.read(15)?,
+                    });
+                }
+
+                Ok(lockers)
+            })
+            .await
+    }
+
+    /// Adds a locker staff commissioned for `operator_id`, ready to be used, returning its id.
+    /// Keys that are already registered are refused.
+    async fn insert_locker(
+        &self,
+        locker: &NewLocker,
+        operator_id: Option<i64>,
+    ) -> Result<i64, StoreError> {
+        let locker = locker.clone();
+        let pk = locker.pk.0.to_string();
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement =
+                        database.prepare("SELECT id FROM lockers WHERE lower(pk) = lower(?)")?;
+                    statement.bind((1, pk.as_str()))?;
+                    if let sqlite::State::Row = statement.next()? {
+                        return Err(StoreError::Constraint(format!(
+                            "locker {pk} is already registered"
+                        )));
+                    }
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO lockers (pk, state, start_time, name, location, size, description, price_per_minute_msat, operator_id) VALUES (?, 'available', 0, ?, ?, ?, ?, ?, ?) RETURNING id",
+                    )?;
+                    statement.bind((1, pk.as_str()))?;
+                    statement.bind((2, locker.name.as_deref()))?;
+                    statement.bind((3, locker.location.as_deref()))?;
+                    statement.bind((4, locker.size.map(|size| size.as_str())))?;
+                    statement.bind((5, locker.description.as_deref()))?;
+                    statement.bind((6, locker.price_per_minute_msat.map(|price| price as i64)))?;
+                    statement.bind((7, operator_id))?;
+                    statement.next()?;
+
+                    Ok(statement.read::<i64, _>(0)?)
+                })
+            })
+            .await
+    }
+
+    /// Returns every locker, or only those at `site_id` if set, leaving out decommissioned ones
+    /// unless `include_inactive` is set.
+    async fn list_lockers(
+        &self,
+        include_inactive: bool,
+        site_id: Option<i64>,
+    ) -> Result<Vec<Locker>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {LOCKER_COLUMNS} FROM {LOCKER_TABLES} WHERE ((l.active = 1 AND l.state != 'provisioning') OR ?1) AND (?2 IS NULL OR l.site_id = ?2) ORDER BY l.id"
+                ))?;
+                statement.bind((1, include_inactive as i64))?;
+                statement.bind((2, site_id))?;
+
+                let mut lockers = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    lockers.push(Self::read_locker(&statement)?);
+                }
+
+                Ok(lockers)
+            })
+            .await
+    }
+
+    /// Returns up to `limit` of the lockers customers can see, or only those at `site_id` if set,
+    /// ordered by id, skipping `offset` of them and those with an id up to `after_id`. Also returns
+    /// how many there are in total, whatever the page.
+    async fn list_lockers_page(
+        &self,
+        filter: LockerFilter,
+        after_id: Option<i64>,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<Locker>, u64), StoreError> {
+        self.database
+            .read(move |database| {
+                let size = filter.size.map(|size| size.as_str());
+                let mut statement = database.prepare(
+                    "SELECT COUNT(*) FROM lockers l WHERE l.active = 1 AND l.state != 'provisioning' AND (?1 IS NULL OR l.site_id = ?1) AND (?2 IS NULL OR l.state = ?2) AND (?3 IS NULL OR l.size = ?3) AND (?4 IS NULL OR l.location = ?4 COLLATE NOCASE)",
+                )?;
+                statement.bind((1, filter.site_id))?;
+                statement.bind((2, filter.state.as_deref()))?;
+                statement.bind((3, size))?;
+                statement.bind((4, filter.location.as_deref()))?;
+                statement.next()?;
+                let total = statement.read::<i64, _>(0)? as u64;
+
+                let mut statement = database.prepare(format!(
+                    "SELECT {LOCKER_COLUMNS} FROM {LOCKER_TABLES} WHERE l.active = 1 AND l.state != 'provisioning' AND (?1 IS NULL OR l.site_id = ?1) AND (?2 IS NULL OR l.state = ?2) AND (?3 IS NULL OR l.size = ?3) AND (?4 IS NULL OR l.location = ?4 COLLATE NOCASE) AND l.id > ?5 ORDER BY l.id LIMIT ?6 OFFSET ?7"
+                ))?;
+                statement.bind((1, filter.site_id))?;
+                statement.bind((2, filter.state.as_deref()))?;
+                statement.bind((3, size))?;
+                statement.bind((4, filter.location.as_deref()))?;
+                statement.bind((5, after_id.unwrap_or(0)))?;
+                statement.bind((6, limit.min(i64::MAX as u64) as i64))?;
+                statement.bind((7, offset.min(i64::MAX as u64) as i64))?;
+
+                let mut lockers = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    lockers.push(Self::read_locker(&statement)?);
+                }
+
+                Ok((lockers, total))
+            })
+            .await
+    }
+
+    async fn get_locker(&self, locker_id: i64) -> Result<Locker, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement =
+                    database.prepare(format!("SELECT {LOCKER_COLUMNS} FROM {LOCKER_TABLES} WHERE l.id = ?"))?;
+                statement.bind((1, locker_id))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Self::read_locker(&statement)
+            })
+            .await
+    }
+
+    /// Reads a locker from the current row of a statement selecting [LOCKER_COLUMNS].
+    fn read_locker(statement: &sqlite::Statement) -> Result<Locker, StoreError> {
+        let size = statement
+            .read::<Option<String>, _>(4)?
+            .map(|size| size.parse())
+            .transpose()
+            .map_err(|_| StoreError::Other("invalid locker size".to_string()))?;
+
+        Ok(Locker {
+            id: statement.read(0)?,
+            state: statement.read(1)?,
+            name: statement.read(2)?,
+            location: statement.read(3)?,
+            size,
+            description: statement.read(5)?,
+            active: statement.read::<i64, _>(6)? != 0,
+            price_per_minute_msat: statement.read::<Option<i64>, _>(7)?.map(|price| price as u64),
+            site: match statement.read::<Option<i64>, _>(8)? {
+                Some(_) => Some(Self::read_site(statement, 8)?),
+                None => None,
+            },
+            auth_mode: statement
+                .read::<String, _>(13)?
+                .parse()
+                .map_err(|_| StoreError::Other("invalid locker auth mode".to_string()))?,
+        })
+    }
+
+    /// Reads a site from the current row of a statement selecting [SITE_COLUMNS], starting at
+    /// column `first`.
+    fn read_site(statement: &sqlite::Statement, first: usize) -> Result<Site, StoreError> {
+        Ok(Site {
+            id: statement.read(first)?,
+            name: statement.read(first + 1)?,
+            address: statement.read(first + 2)?,
+            timezone: statement.read(first + 3)?,
+            price_per_minute_msat: statement
+                .read::<Option<i64>, _>(first + 4)?
+                .map(|price| price as u64),
+        })
+    }
+
+    /// Returns every site, or only those of an operator.
+    async fn list_sites(&self, operator_id: Option<i64>) -> Result<Vec<Site>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(format!(
+                    "SELECT {SITE_COLUMNS} FROM sites WHERE ?1 IS NULL OR operator_id = ?1 ORDER BY id"
+                ))?;
+                statement.bind((1, operator_id))?;
+
+                let mut sites = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    sites.push(Self::read_site(&statement, 0)?);
+                }
+
+                Ok(sites)
+            })
+            .await
+    }
+
+    async fn get_site(&self, site_id: i64) -> Result<Site, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement =
+                    database.prepare(format!("SELECT {SITE_COLUMNS} FROM sites WHERE id = ?"))?;
+                statement.bind((1, site_id))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Self::read_site(&statement, 0)
+            })
+            .await
+    }
+
+    /// Adds a new site for `operator_id`, returning its id. Sites without a timezone are in UTC.
+    async fn insert_site(
+        &self,
+        name: &str,
+        metadata: &SiteMetadata,
+        operator_id: Option<i64>,
+    ) -> Result<i64, StoreError> {
+        let name = name.to_string();
+        let metadata = metadata.clone();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO sites (name, address, timezone, price_per_minute_msat, operator_id) VALUES (?, ?, COALESCE(?, 'UTC'), ?, ?) RETURNING id",
+                )?;
+                statement.bind((1, name.as_str()))?;
+                statement.bind((2, metadata.address.as_deref()))?;
+                statement.bind((3, metadata.timezone.as_deref()))?;
+                statement.bind((4, metadata.price_per_minute_msat.map(|price| price as i64)))?;
+                statement.bind((5, operator_id))?;
+                statement.next()?;
+
+                Ok(statement.read::<i64, _>(0)?)
+            })
+            .await
+    }
+
+    /// Updates the fields of a site, leaving the ones that aren't set untouched.
+    async fn update_site(&self, site_id: i64, metadata: &SiteMetadata) -> Result<(), StoreError> {
+        let metadata = metadata.clone();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE sites SET name = COALESCE(?, name), address = COALESCE(?, address), timezone = COALESCE(?, timezone), price_per_minute_msat = COALESCE(?, price_per_minute_msat) WHERE id = ?",
+                )?;
+                statement.bind((1, metadata.name.as_deref()))?;
+                statement.bind((2, metadata.address.as_deref()))?;
+                statement.bind((3, metadata.timezone.as_deref()))?;
+                statement.bind((4, metadata.price_per_minute_msat.map(|price| price as i64)))?;
+                statement.bind((5, site_id))?;
+                statement.next()?;
+
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Deletes a site. Sites that still have lockers can't be deleted, and return
+    /// [StoreError::Constraint].
+    async fn delete_site(&self, site_id: i64) -> Result<(), StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare("DELETE FROM sites WHERE id = ?")?;
+                statement.bind((1, site_id))?;
+                statement.next()?;
+
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Returns every subscription, or only those of an operator.
+    async fn list_webhooks(&self, operator_id: Option<i64>) -> Result<Vec<Webhook>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT id, url, events, created_at,
+                        (SELECT COUNT(*) FROM webhook_deliveries WHERE webhook_id = webhooks.id AND status = 'pending'),
+                        (SELECT COUNT(*) FROM webhook_deliveries WHERE webhook_id = webhooks.id AND status = 'dead')
+                     FROM webhooks WHERE ?1 IS NULL OR operator_id = ?1 ORDER BY id",
+                )?;
+                statement.bind((1, operator_id))?;
+
+                let mut webhooks = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    webhooks.push(Webhook {
+                        id: statement.read(0)?,
+                        url: statement.read(1)?,
+                        events: statement
+                            .read::<Option<String>, _>(2)?
+                            .map(|events| events.split(',').map(str::to_string).collect()),
+                        created_at: statement.read::<i64, _>(3)? as u64,
+                        pending_deliveries: statement.read::<i64, _>(4)? as u64,
+                        dead_deliveries: statement.read::<i64, _>(5)? as u64,
+                    });
+                }
 
-    let body = serde_json::json!({
-        "data": locker,
-        "error": null,
-    });
+                Ok(webhooks)
+            })
+            .await
+    }
 
-    Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()))
-}
+    /// Adds somewhere to send webhooks to, signed with `secret`, returning it. It only gets
+    /// `events`, if given, and only those about the lockers of `operator_id`, if set.
+    async fn insert_webhook(
+        &self,
+        url: &str,
+        events: Option<Vec<String>>,
+        secret: &[u8],
+        operator_id: Option<i64>,
+    ) -> Result<Webhook, StoreError> {
+        let url = url.to_string();
+        let secret = Secret::new(secret.to_lower_hex_string());
 
-/// Returns the available lockers and their state. This will be used to display the lockers to the
-/// user.
-async fn get_lockers(state: State<Arc<Server<PhoenixdClient>>>) -> Result<Body, error::Error> {
-    let lockers = state.list_lockers().await?;
-    let body = serde_json::json!({
-        "data": lockers,
-        "error": null,
-    });
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO webhooks (url, events, secret, created_at, operator_id) VALUES (?, ?, ?, ?, ?) RETURNING id, created_at",
+                )?;
+                statement.bind((1, url.as_str()))?;
+                statement.bind((2, events.as_ref().map(|events| events.join(",")).as_deref()))?;
+                statement.bind((3, secret.expose().as_str()))?;
+                statement.bind((4, now() as i64))?;
+                statement.bind((5, operator_id))?;
+                statement.next()?;
 
-    Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()))
-}
+                Ok(Webhook {
+                    id: statement.read(0)?,
+                    url,
+                    events,
+                    created_at: statement.read::<i64, _>(1)? as u64,
+                    pending_deliveries: 0,
+                    dead_deliveries: 0,
+                })
+            })
+            .await
+    }
 
-async fn use_locker(
-    Path(locker_id): Path<i64>,
-    state: State<Arc<Server<PhoenixdClient>>>,
-) -> Result<Body, error::Error> {
-    let locker_state = state.get_locker_state(locker_id).await?;
-    if locker_state != "available" {
-        let body = serde_json::json!({
-            "data": null,
-            "error": "Locker is not available",
-        });
+    /// Removes a subscription, along with the record of what we delivered to it.
+    async fn delete_webhook(&self, webhook_id: i64) -> Result<(), StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("DELETE FROM webhook_deliveries WHERE webhook_id = ?")?;
+                statement.bind((1, webhook_id))?;
+                statement.next()?;
+
+                let mut statement = database.prepare("DELETE FROM webhooks WHERE id = ?")?;
+                statement.bind((1, webhook_id))?;
+                statement.next()?;
 
-        return Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()));
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await
     }
 
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    /// Returns the last `limit` deliveries queued for a subscription, newest first, only those
+    /// with `status` if given.
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<String>,
+        limit: u64,
+    ) -> Result<Vec<WebhookDelivery>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare("SELECT 1 FROM webhooks WHERE id = ?")?;
+                statement.bind((1, webhook_id))?;
+                if statement.next()? != sqlite::State::Row {
+                    return Err(StoreError::NotFound);
+                }
 
-    state
-        .set_locker_state(locker_id, "in_use".to_string())
-        .await?;
+                let mut statement = database.prepare(
+                    "SELECT id, webhook_id, event_id, event, status, attempts, last_status, last_error, next_attempt_at, created_at, delivered_at
+                     FROM webhook_deliveries WHERE webhook_id = ? AND (? IS NULL OR status = ?)
+                     ORDER BY id DESC LIMIT ?",
+                )?;
+                statement.bind((1, webhook_id))?;
+                statement.bind((2, status.as_deref()))?;
+                statement.bind((3, status.as_deref()))?;
+                statement.bind((4, limit as i64))?;
 
-    state.set_locker_start_time(locker_id, now).await?;
+                let mut deliveries = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    deliveries.push(WebhookDelivery {
+                        id: statement.read(0)?,
+                        webhook_id: statement.read(1)?,
+                        event_id: statement.read(2)?,
+                        event: statement.read(3)?,
+                        status: statement.read(4)?,
+                        attempts: statement.read::<i64, _>(5)? as u32,
+                        last_status: statement.read::<Option<i64>, _>(6)?.map(|s| s as i32),
+                        last_error: statement.read(7)?,
+                        next_attempt_at: statement.read::<Option<i64>, _>(8)?.map(|t| t as u64),
+                        created_at: statement.read::<i64, _>(9)? as u64,
+                        delivered_at: statement.read::<Option<i64>, _>(10)?.map(|t| t as u64),
+                    });
+                }
 
-    let signature = {
-        let mut hasher = bitcoin::hashes::sha256::HashEngine::default();
-        hasher.write_all(format!("{}{}", locker_id, now).as_bytes())?;
+                Ok(deliveries)
+            })
+            .await
+    }
 
-        let hash = hasher.midstate().0;
-        let secp = secp256k1::Secp256k1::new();
-        let signature = secp.sign_schnorr_no_aux_rand(&hash, &state.keypair);
+    /// Queues `body`, the event `event` about `locker_id` with the id `event_id`, for every
+    /// subscription that wants it, returning for how many it was. Subscriptions of an operator
+    /// only want events about their lockers.
+    async fn queue_webhook_deliveries(
+        &self,
+        event_id: &str,
+        event: &str,
+        locker_id: i64,
+        body: String,
+        now: u64,
+    ) -> Result<usize, StoreError> {
+        let event_id = event_id.to_string();
+        let event = event.to_string();
 
-        signature.to_byte_array().to_upper_hex_string()
-    };
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO webhook_deliveries (webhook_id, event_id, event, body, status, next_attempt_at, created_at)
+                     SELECT id, ?1, ?2, ?3, 'pending', ?4, ?4 FROM webhooks
+                     WHERE (events IS NULL OR instr(',' || events || ',', ',' || ?2 || ',') > 0)
+                     AND (operator_id IS NULL OR operator_id = (SELECT operator_id FROM lockers WHERE id = ?5))",
+                )?;
+                statement.bind((1, event_id.as_str()))?;
+                statement.bind((2, event.as_str()))?;
+                statement.bind((3, body.as_str()))?;
+                statement.bind((4, now as i64))?;
+                statement.bind((5, locker_id))?;
+                statement.next()?;
 
-    let body = serde_json::json!({
-        "data": {
-            "locker_id": locker_id,
-            "start_time": now,
-            "signature": signature,
-        },
-        "error": null,
-    });
+                Ok(database.change_count())
+            })
+            .await
+    }
 
-    Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()))
-}
+    /// Returns up to `limit` deliveries that are due by `now`, those waiting the longest first.
+    async fn due_webhook_deliveries(
+        &self,
+        now: u64,
+        limit: u64,
+    ) -> Result<Vec<DueDelivery>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT d.id, w.url, w.secret, d.body, d.attempts
+                     FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+                     WHERE d.status = 'pending' AND d.next_attempt_at <= ?
+                     ORDER BY d.next_attempt_at, d.id LIMIT ?",
+                )?;
+                statement.bind((1, now as i64))?;
+                statement.bind((2, limit as i64))?;
 
-async fn pay_for_usage(
-    Path(locker_id): Path<i64>,
-    state: State<Arc<Server<PhoenixdClient>>>,
-) -> Result<Body, error::Error> {
-    let locker_state = state.get_locker_state(locker_id).await?;
-    if locker_state != "in_use" {
-        return Err(error::Error::BadRequest);
+                let mut deliveries = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    let secret = Secret::new(statement.read::<String, _>(2)?);
+                    let secret = Vec::<u8>::from_hex(secret.expose())
+                        .map_err(|_| StoreError::Other("invalid webhook secret".to_string()))?;
+                    deliveries.push(DueDelivery {
+                        id: statement.read(0)?,
+                        url: statement.read(1)?,
+                        secret: Secret::new(secret),
+                        body: statement.read::<String, _>(3)?.into_bytes(),
+                        attempts: statement.read::<i64, _>(4)? as u32,
+                    });
+                }
+
+                Ok(deliveries)
+            })
+            .await
     }
 
-    let start_time = state.get_locker_start_time(locker_id.clone()).await?;
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let lease_time = now - start_time;
+    /// Records an attempt at a delivery, after which it's `status`, tried again at
+    /// `next_attempt_at` if it's still `pending`.
+    async fn record_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        status: &'static str,
+        last_status: Option<i32>,
+        last_error: Option<String>,
+        next_attempt_at: Option<u64>,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        let delivered_at = (status == "delivered").then_some(now);
 
-    let invoice = state
-        .ln
-        .get_invoice(lease_time)
-        .map_err(|_| error::Error::Server)?;
-
-    let database = state.database.lock().await;
-    let query = format!("INSERT INTO pending_payments (amount, payment_hash, status, locker_id) VALUES ({}, '{}', 'pending', '{}')", lease_time, invoice.payment_hash, locker_id);
-    database.execute(query)?;
-
-    let body = serde_json::json!({
-        "data": {
-            "locker_id": locker_id,
-            "lease_time": lease_time,
-            "invoice": invoice,
-        },
-        "error": null,
-    });
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE webhook_deliveries SET status = ?, attempts = attempts + 1, last_status = ?, last_error = ?, next_attempt_at = ?, delivered_at = ? WHERE id = ?",
+                )?;
+                statement.bind((1, status))?;
+                statement.bind((2, last_status.map(i64::from)))?;
+                statement.bind((3, last_error.as_deref()))?;
+                statement.bind((4, next_attempt_at.map(|t| t as i64)))?;
+                statement.bind((5, delivered_at.map(|t| t as i64)))?;
+                statement.bind((6, delivery_id))?;
+                statement.next()?;
 
-    Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()))
-}
+                Ok(())
+            })
+            .await
+    }
 
-/// This will return a signed receipt for the payment. This receipt will be used to unlock
-/// the locker. The receipt will be signed by the server and will contain the locker id, and the
-/// current timestamp. The client will use this receipt to unlock the locker.
-async fn get_pament_receipt(
-    Path(payment_hash): Path<String>,
-    state: State<Arc<Server<PhoenixdClient>>>,
-) -> Result<Body, error::Error> {
-    let payment_status = state
-        .ln
-        .get_invoice_status(payment_hash.clone())
-        .map_err(|_| error::Error::BadRequest)?;
+    /// Queues `event`, which is about `locker_id`, for every subscription that wants it, see
+    /// [webhook], for [tasks::deliver_webhooks] to send. Like [Server::record_event], failing to
+    /// queue it never fails the call that caused it.
+    async fn send_webhooks(&self, event: &str, locker_id: i64, data: serde_json::Value) {
+        let now = now();
+        let event_id = rand::random::<[u8; 16]>().to_lower_hex_string();
+        let body = serde_json::json!({
+            "id": event_id,
+            "event": event,
+            "timestamp": now,
+            "data": data,
+        });
 
-    if payment_status != ln::InvoiceStatus::Paid {
-        return Err(error::Error::BadRequest);
+        match self
+            .queue_webhook_deliveries(&event_id, event, locker_id, body.to_string(), now)
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => self.webhooks_queued.notify_one(),
+            Err(e) => eprintln!("[send_webhooks] failed to queue {event}: {e:?}"),
+        }
     }
 
-    let PendingPayment { locker_id, .. } = state.get_payment(payment_hash.clone()).await?;
+    /// Records a login challenge we handed out, along with the digest of the bearer token that
+    /// logs in once a wallet signs it.
+    async fn insert_customer_login(
+        &self,
+        k1: &str,
+        token_digest: &str,
+        created_at: u64,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let k1 = k1.to_string();
+        let token_digest = token_digest.to_string();
 
-    let start_time = state.get_locker_start_time(locker_id.clone()).await?;
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO customer_logins (k1, token_digest, created_at, expires_at) VALUES (?, ?, ?, ?)",
+                )?;
+                statement.bind((1, k1.as_str()))?;
+                statement.bind((2, token_digest.as_str()))?;
+                statement.bind((3, created_at as i64))?;
+                statement.bind((4, expires_at as i64))?;
+                statement.next()?;
 
-    let signature = {
-        let mut hasher = bitcoin::hashes::sha256::HashEngine::default();
-        hasher.write_all(format!("{}{}", locker_id, now).as_bytes())?;
+                Ok(())
+            })
+            .await
+    }
 
-        let hash = hasher.midstate().0;
-        let secp = secp256k1::Secp256k1::new();
-        let signature = secp.sign_schnorr_no_aux_rand(&hash, &state.keypair);
+    /// Logs in whoever holds the token of challenge `k1` as the customer with `linking_key`, until
+    /// `expires_at`. Returns false if we don't know the challenge, it expired at `now`, or a
+    /// wallet already signed it.
+    async fn link_customer_login(
+        &self,
+        k1: &str,
+        linking_key: &str,
+        now: u64,
+        expires_at: u64,
+    ) -> Result<bool, StoreError> {
+        let k1 = k1.to_string();
+        let linking_key = linking_key.to_string();
 
-        signature.to_byte_array().to_upper_hex_string()
-    };
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE customer_logins SET linking_key = ?, linked_at = ?, expires_at = ? WHERE k1 = ? AND linking_key IS NULL AND expires_at > ?",
+                )?;
+                statement.bind((1, linking_key.as_str()))?;
+                statement.bind((2, now as i64))?;
+                statement.bind((3, expires_at as i64))?;
+                statement.bind((4, k1.as_str()))?;
+                statement.bind((5, now as i64))?;
+                statement.next()?;
 
-    let body = serde_json::json!({
-        "locker_id": locker_id,
-        "start_time": start_time,
-        "signature": signature,
-    });
+                Ok(database.change_count() > 0)
+            })
+            .await
+    }
 
-    Ok(axum::body::Body::from(serde_json::to_vec(&body).unwrap()))
-}
+    /// Returns the linking key of the customer logged in with the token whose digest is
+    /// `token_digest`, and when their login expires. Tokens whose challenge no wallet signed yet,
+    /// or that expired at `now`, aren't found.
+    async fn get_customer_login(
+        &self,
+        token_digest: &str,
+        now: u64,
+    ) -> Result<(String, u64), StoreError> {
+        let token_digest = token_digest.to_string();
 
-async fn update_locker_open(
-    state: State<Arc<Server<PhoenixdClient>>>,
-    body: axum::Json<UpdateLockerOpen>,
-) -> Result<Body, error::Error> {
-    let locker_id = body.locker_id;
-    let signature = secp256k1::schnorr::Signature::from_str(&body.signature).map_err(|_| error::Error::BadRequest)?;
-    let pk = state.get_locker_pk(locker_id).await?;
-    
-    let secp = secp256k1::Secp256k1::new();
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT linking_key, expires_at FROM customer_logins WHERE token_digest = ? AND linking_key IS NOT NULL AND expires_at > ?",
+                )?;
+                statement.bind((1, token_digest.as_str()))?;
+                statement.bind((2, now as i64))?;
 
-    // hash the timestamp and locker_id, verify the signature over the hash
-    let mut hasher = bitcoin::hashes::sha256::HashEngine::default();
-    hasher.write_all(format!("{}{}", locker_id, body.timestamp).as_bytes())?;
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
 
-    let hash = hasher.midstate().0;
-    let pk = secp256k1::XOnlyPublicKey::from_str(&pk).map_err(|_| error::Error::BadRequest)?;
-    secp.verify_schnorr(&signature, &hash, &pk).map_err(|_| error::Error::BadRequest)?;
+                Ok((statement.read(0)?, statement.read::<i64, _>(1)? as u64))
+            })
+            .await
+    }
 
+    /// Deletes the logins and unsigned challenges that expired before `expired_before`.
+    async fn delete_expired_logins(&self, expired_before: u64) -> Result<u64, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("DELETE FROM customer_logins WHERE expires_at < ?")?;
+                statement.bind((1, expired_before as i64))?;
+                statement.next()?;
 
-    state.set_locker_state(locker_id, "available".to_string()).await?;
-    Ok(axum::body::Body::from("Locker opened"))
-}
+                Ok(database.change_count() as u64)
+            })
+            .await
+    }
 
+    /// Claims the idempotency key whose digest is `key_digest` for the request identified by
+    /// `fingerprint` until `expires_at`, unless it was already used and didn't expire by `now`,
+    /// see [idempotency].
+    async fn claim_idempotency_key(
+        &self,
+        key_digest: &str,
+        fingerprint: &str,
+        now: u64,
+        expires_at: u64,
+    ) -> Result<idempotency::Claim, StoreError> {
+        let key_digest = key_digest.to_string();
+        let fingerprint = fingerprint.to_string();
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct UpdateLockerOpen {
-    locker_id: i64,
-    signature: String,
-    timestamp: u64,
-}
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare(
+                        "DELETE FROM idempotency_keys WHERE key_digest = ? AND expires_at <= ?",
+                    )?;
+                    statement.bind((1, key_digest.as_str()))?;
+                    statement.bind((2, now as i64))?;
+                    statement.next()?;
 
-#[allow(dead_code)]
-struct PendingPayment {
-    amount: u64,
-    payment_hash: String,
-    status: String,
-    locker_id: i64,
-}
+                    let mut statement = database.prepare(
+                        "SELECT fingerprint, status, content_type, body FROM idempotency_keys WHERE key_digest = ?",
+                    )?;
+                    statement.bind((1, key_digest.as_str()))?;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct Locker {
-    id: i64,
-    state: String,
-}
+                    if let sqlite::State::Row = statement.next()? {
+                        if statement.read::<String, _>("fingerprint")? != fingerprint {
+                            return Ok(idempotency::Claim::Mismatch);
+                        }
 
-impl Server<PhoenixdClient> {
-    pub async fn run(address: String, keypair: Keypair, database: sqlite::Connection, ln: PhoenixdClient) {
-        let listener = match tokio::net::TcpListener::bind(address).await {
-            Ok(listener) => listener,
-            Err(_) => {
-                std::process::exit(-1);
-            }
-        };
+                        let Some(status) = statement.read::<Option<i64>, _>("status")? else {
+                            return Ok(idempotency::Claim::InFlight);
+                        };
+                        return Ok(idempotency::Claim::Answered(idempotency::StoredResponse {
+                            status: status as u16,
+                            content_type: statement.read("content_type")?,
+                            body: statement.read::<Option<Vec<u8>>, _>("body")?.unwrap_or_default(),
+                        }));
+                    }
 
-        let router = Router::new()
-            .route("/use_locker/{locker_id}", get(use_locker))
-            .route("/pay_for_usage/{locker_id}", get(pay_for_usage))
-            .route("/payment_receipt/{payment_hash}", get(get_pament_receipt))
-            .route("/lockers", get(get_lockers))
-            .route("/lockers/{locker_id}", get(get_locker))
-            .route("/update_locker_open", post(update_locker_open))
-            .layer(
-                CorsLayer::new()
-                    .allow_private_network(true)
-                    .allow_methods([Method::POST, Method::HEAD]),
-            )
-            .with_state(Arc::new(Server {
-                keypair,
-                database: Arc::new(Mutex::new(database)),
-                ln,
-            }));
+                    let mut statement = database.prepare(
+                        "INSERT INTO idempotency_keys (key_digest, fingerprint, created_at, expires_at) VALUES (?, ?, ?, ?)",
+                    )?;
+                    statement.bind((1, key_digest.as_str()))?;
+                    statement.bind((2, fingerprint.as_str()))?;
+                    statement.bind((3, now as i64))?;
+                    statement.bind((4, expires_at as i64))?;
+                    statement.next()?;
 
-        axum::serve(listener, router)
+                    Ok(idempotency::Claim::Claimed)
+                })
+            })
             .await
-            .expect("failed to start rpc server");
     }
-    
-    async fn get_locker_pk(
+
+    /// Keeps the response to the request that claimed the idempotency key whose digest is
+    /// `key_digest`, until `expires_at`.
+    async fn save_idempotent_response(
+        &self,
+        key_digest: &str,
+        response: idempotency::StoredResponse,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let key_digest = key_digest.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE idempotency_keys SET status = ?, content_type = ?, body = ?, expires_at = ? WHERE key_digest = ?",
+                )?;
+                statement.bind((1, response.status as i64))?;
+                statement.bind((2, response.content_type.as_deref()))?;
+                statement.bind((3, response.body.as_slice()))?;
+                statement.bind((4, expires_at as i64))?;
+                statement.bind((5, key_digest.as_str()))?;
+                statement.next()?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Lets the idempotency key whose digest is `key_digest` be used again, if the request that
+    /// claimed it wasn't answered.
+    async fn release_idempotency_key(&self, key_digest: &str) -> Result<(), StoreError> {
+        let key_digest = key_digest.to_string();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "DELETE FROM idempotency_keys WHERE key_digest = ? AND status IS NULL",
+                )?;
+                statement.bind((1, key_digest.as_str()))?;
+                statement.next()?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Deletes the idempotency keys that expired by `now`.
+    async fn delete_expired_idempotency_keys(&self, now: u64) -> Result<u64, StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("DELETE FROM idempotency_keys WHERE expires_at <= ?")?;
+                statement.bind((1, now as i64))?;
+                statement.next()?;
+
+                Ok(database.change_count() as u64)
+            })
+            .await
+    }
+
+    /// Returns a page of the sessions used by the customer with `linking_key`, newest first, along
+    /// with how many they used in total.
+    async fn list_customer_sessions(
+        &self,
+        linking_key: &str,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<UsageSession>, u64), StoreError> {
+        let linking_key = linking_key.to_string();
+
+        self.database
+            .read(move |database| {
+                let mut statement = database
+                    .prepare("SELECT COUNT(*) FROM usage_sessions WHERE linking_key = ?")?;
+                statement.bind((1, linking_key.as_str()))?;
+                statement.next()?;
+                let total = statement.read::<i64, _>(0)? as u64;
+
+                let mut statement = database.prepare(format!(
+                    "SELECT {SESSION_COLUMNS} FROM usage_sessions WHERE linking_key = ? ORDER BY id DESC LIMIT ? OFFSET ?"
+                ))?;
+                statement.bind((1, linking_key.as_str()))?;
+                statement.bind((2, limit as i64))?;
+                statement.bind((3, offset as i64))?;
+
+                let mut sessions = Vec::new();
+                loop {
+                    match Self::read_session(&mut statement) {
+                        Ok(session) => sessions.push(session),
+                        Err(StoreError::NotFound) => return Ok((sessions, total)),
+                        Err(e) => return Err(e),
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Sets the price of a locker, or clears it so the price of its site or the configured one
+    /// applies again.
+    async fn set_locker_price(
         &self,
         locker_id: i64,
-    ) -> Result<String, error::Error> {
-        let database = self.database.lock().await;
-        let query = format!("SELECT pk FROM lockers WHERE id = '{}'", locker_id);
-        let mut statement = database.prepare(query)?;
+        price_per_minute_msat: Option<u64>,
+    ) -> Result<(), StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("UPDATE lockers SET price_per_minute_msat = ? WHERE id = ?")?;
+                statement.bind((1, price_per_minute_msat.map(|price| price as i64)))?;
+                statement.bind((2, locker_id))?;
+                statement.next()?;
 
-        let sqlite::State::Row = statement.next()? else {
-            return Err(error::Error::NotFound);
-        };
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
 
-        let pk: String = statement.read(0)?;
-        Ok(pk)
+                Ok(())
+            })
+            .await
     }
 
-    async fn get_payment(&self, payment_hash: String) -> Result<PendingPayment, error::Error> {
-        let database = self.database.lock().await;
-        let query = format!("SELECT amount, payment_hash, status, locker_id FROM pending_payments WHERE payment_hash = '{}'", payment_hash);
-        let mut statement = database.prepare(query)?;
+    /// Adds a locker that registered itself, waiting for an admin to approve it, along with the
+    /// digest of its device token, and returns its id. If there's already a locker with the same
+    /// public key this returns [StoreError::Constraint].
+    ///
+    /// Lockers registering with a provisioning code use it up, in the same transaction so it
+    /// can't register two, and we also return its id. They belong to the operator the code was
+    /// made for, if any. Codes that can't be used return [StoreError::ProvisioningCode].
+    async fn register_locker(
+        &self,
+        locker: &RegisterLocker,
+        code_digest: Option<String>,
+        device_token_digest: &str,
+    ) -> Result<(i64, Option<i64>), StoreError> {
+        let locker = locker.clone();
+        let pk = locker.pk.0.to_string();
+        let device_token_digest = device_token_digest.to_string();
+        let registered_at = now();
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let code_id = match code_digest {
+                        Some(code_digest) => Some(Self::check_provisioning_code(
+                            database,
+                            &code_digest,
+                            registered_at,
+                        )?),
+                        None => None,
+                    };
+
+                    let mut statement =
+                        database.prepare("SELECT id FROM lockers WHERE lower(pk) = lower(?)")?;
+                    statement.bind((1, pk.as_str()))?;
+                    if let sqlite::State::Row = statement.next()? {
+                        return Err(StoreError::Constraint(format!(
+                            "locker {pk} is already registered"
+                        )));
+                    }
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO lockers (pk, state, start_time, name, location, size, description, operator_id) VALUES (?, 'provisioning', 0, ?, ?, ?, ?, (SELECT operator_id FROM provisioning_codes WHERE id = ?)) RETURNING id",
+                    )?;
+                    statement.bind((1, pk.as_str()))?;
+                    statement.bind((2, locker.name.as_deref()))?;
+                    statement.bind((3, locker.location.as_deref()))?;
+                    statement.bind((4, locker.size.map(|size| size.as_str())))?;
+                    statement.bind((5, locker.description.as_deref()))?;
+                    statement.bind((6, code_id))?;
+                    statement.next()?;
+                    let locker_id = statement.read::<i64, _>(0)?;
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO device_tokens (locker_id, token_digest, created_at) VALUES (?, ?, ?)",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, device_token_digest.as_str()))?;
+                    statement.bind((3, registered_at as i64))?;
+                    statement.next()?;
+
+                    if let Some(code_id) = code_id {
+                        let mut statement = database.prepare(
+                            "UPDATE provisioning_codes SET used_at = ?, locker_id = ? WHERE id = ?",
+                        )?;
+                        statement.bind((1, registered_at as i64))?;
+                        statement.bind((2, locker_id))?;
+                        statement.bind((3, code_id))?;
+                        statement.next()?;
+                    }
+
+                    Ok((locker_id, code_id))
+                })
+            })
+            .await
+    }
 
+    /// Returns the id of the provisioning code with `code_digest` if it can still register a
+    /// locker at `now`, or why not.
+    fn check_provisioning_code(
+        database: &sqlite::Connection,
+        code_digest: &str,
+        now: u64,
+    ) -> Result<i64, StoreError> {
+        let mut statement = database.prepare(
+            "SELECT id, expires_at, used_at FROM provisioning_codes WHERE code_digest = ? AND revoked_at IS NULL",
+        )?;
+        statement.bind((1, code_digest))?;
         let sqlite::State::Row = statement.next()? else {
-            return Err(error::Error::NotFound);
+            return Err(StoreError::ProvisioningCode(CodeError::Unknown));
         };
 
-        let amount: u64 = statement.read::<i64, _>(0)? as u64;
-        let payment_hash: String = statement.read(1)?;
-        let status: String = statement.read(2)?;
-        let locker_id: i64 = statement.read(3)?;
+        if statement.read::<Option<i64>, _>(2)?.is_some() {
+            return Err(StoreError::ProvisioningCode(CodeError::Used));
+        }
 
-        Ok(PendingPayment {
-            amount,
-            payment_hash,
-            status,
-            locker_id,
-        })
+        if statement.read::<i64, _>(1)? as u64 <= now {
+            return Err(StoreError::ProvisioningCode(CodeError::Expired));
+        }
+
+        Ok(statement.read(0)?)
     }
 
-    async fn get_locker_state(&self, locker_id: i64) -> Result<String, error::Error> {
-        let database = self.database.lock().await;
-        let query = format!("SELECT state FROM lockers WHERE id = '{}'", locker_id);
-        let mut statement = database.prepare(query)?;
+    /// Keeps a new provisioning code, of which we only get the digest, returning its id. Lockers
+    /// registering with it belong to `operator_id`.
+    async fn insert_provisioning_code(
+        &self,
+        code_digest: &str,
+        label: Option<String>,
+        created_at: u64,
+        expires_at: u64,
+        operator_id: Option<i64>,
+    ) -> Result<i64, StoreError> {
+        let code_digest = code_digest.to_string();
 
-        let sqlite::State::Row = statement.next()? else {
-            return Err(error::Error::NotFound);
-        };
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "INSERT INTO provisioning_codes (code_digest, label, created_at, expires_at, operator_id) VALUES (?, ?, ?, ?, ?) RETURNING id",
+                )?;
+                statement.bind((1, code_digest.as_str()))?;
+                statement.bind((2, label.as_deref()))?;
+                statement.bind((3, created_at as i64))?;
+                statement.bind((4, expires_at as i64))?;
+                statement.bind((5, operator_id))?;
+                statement.next()?;
 
-        let state = statement.read::<String, _>(0)?;
-        Ok(state)
+                Ok(statement.read(0)?)
+            })
+            .await
     }
 
-    async fn set_locker_state(&self, locker_id: i64, state: String) -> Result<(), error::Error> {
-        let database = self.database.lock().await;
-        let query = format!(
-            "UPDATE lockers SET state = '{}' WHERE id = '{}'",
-            state, locker_id
-        );
-        database.execute(query)?;
-        Ok(())
+    /// Returns the provisioning codes that can still register a locker at `now`, oldest first,
+    /// optionally only those of an operator.
+    async fn list_provisioning_codes(
+        &self,
+        now: u64,
+        operator_id: Option<i64>,
+    ) -> Result<Vec<ProvisioningCode>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT id, label, created_at, expires_at FROM provisioning_codes WHERE used_at IS NULL AND revoked_at IS NULL AND expires_at > ?1 AND (?2 IS NULL OR operator_id = ?2) ORDER BY id",
+                )?;
+                statement.bind((1, now as i64))?;
+                statement.bind((2, operator_id))?;
+
+                let mut codes = Vec::new();
+                while let sqlite::State::Row = statement.next()? {
+                    codes.push(ProvisioningCode {
+                        id: statement.read(0)?,
+                        label: statement.read(1)?,
+                        created_at: statement.read::<i64, _>(2)? as u64,
+                        expires_at: statement.read::<i64, _>(3)? as u64,
+                    });
+                }
+
+                Ok(codes)
+            })
+            .await
     }
 
-    async fn get_locker_start_time(&self, locker_id: i64) -> Result<u64, error::Error> {
-        let database = self.database.lock().await;
-        let query = format!("SELECT start_time FROM lockers WHERE id = '{}'", locker_id);
-        let mut statement = database.prepare(query)?;
+    /// Revokes a provisioning code that wasn't used yet, returning [StoreError::NotFound] if there's
+    /// no such code or it was already used or revoked.
+    async fn revoke_provisioning_code(&self, id: i64, revoked_at: u64) -> Result<(), StoreError> {
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE provisioning_codes SET revoked_at = ? WHERE id = ? AND used_at IS NULL AND revoked_at IS NULL",
+                )?;
+                statement.bind((1, revoked_at as i64))?;
+                statement.bind((2, id))?;
+                statement.next()?;
+
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Issues a locker the device token with the digest `token_digest`, revoking the ones it had,
+    /// see [device]. Returns how many were revoked.
+    async fn issue_device_token(
+        &self,
+        locker_id: i64,
+        token_digest: &str,
+        issued_at: u64,
+    ) -> Result<u64, StoreError> {
+        let token_digest = token_digest.to_string();
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let revoked = Self::revoke_device_tokens_in(database, locker_id, issued_at)?;
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO device_tokens (locker_id, token_digest, created_at) VALUES (?, ?, ?)",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, token_digest.as_str()))?;
+                    statement.bind((3, issued_at as i64))?;
+                    statement.next()?;
+
+                    Ok(revoked)
+                })
+            })
+            .await
+    }
+
+    /// Revokes every device token of a locker, locking the device out until it's issued another.
+    /// Returns how many were revoked.
+    async fn revoke_device_tokens(&self, locker_id: i64, revoked_at: u64) -> Result<u64, StoreError> {
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    Self::revoke_device_tokens_in(database, locker_id, revoked_at)
+                })
+            })
+            .await
+    }
 
+    /// Revokes the device tokens of a locker within a transaction, returning
+    /// [StoreError::NotFound] if there's no such locker.
+    fn revoke_device_tokens_in(
+        database: &sqlite::Connection,
+        locker_id: i64,
+        revoked_at: u64,
+    ) -> Result<u64, StoreError> {
+        let mut statement = database.prepare("SELECT id FROM lockers WHERE id = ?")?;
+        statement.bind((1, locker_id))?;
         let sqlite::State::Row = statement.next()? else {
-            return Err(error::Error::NotFound);
+            return Err(StoreError::NotFound);
         };
 
-        let start_time = statement.read::<i64, _>(0)? as u64;
-        Ok(start_time)
+        let mut statement = database.prepare(
+            "UPDATE device_tokens SET revoked_at = ? WHERE locker_id = ? AND revoked_at IS NULL",
+        )?;
+        statement.bind((1, revoked_at as i64))?;
+        statement.bind((2, locker_id))?;
+        statement.next()?;
+
+        Ok(database.change_count() as u64)
     }
 
-    async fn set_locker_start_time(
+    /// Whether `token_digest` is the digest of the current device token of a locker, or `None` if
+    /// the locker was never issued one.
+    async fn check_device_token(
         &self,
         locker_id: i64,
-        start_time: u64,
-    ) -> Result<(), error::Error> {
-        let database = self.database.lock().await;
-        let query = format!(
-            "UPDATE lockers SET start_time = {} WHERE id = '{}'",
-            start_time, locker_id
-        );
-        database.execute(query)?;
-        Ok(())
+        token_digest: Option<String>,
+    ) -> Result<Option<bool>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement = database.prepare(
+                    "SELECT COUNT(*), COALESCE(SUM(token_digest = ? AND revoked_at IS NULL), 0) FROM device_tokens WHERE locker_id = ?",
+                )?;
+                statement.bind((1, token_digest.as_deref()))?;
+                statement.bind((2, locker_id))?;
+                statement.next()?;
+
+                if statement.read::<i64, _>(0)? == 0 {
+                    return Ok(None);
+                }
+
+                Ok(Some(statement.read::<i64, _>(1)? > 0))
+            })
+            .await
     }
 
-    async fn list_lockers(&self) -> Result<Vec<Locker>, error::Error> {
-        let database = self.database.lock().await;
-        let query = "SELECT id, state FROM lockers";
-        let mut statement = database.prepare(query)?;
+    /// Replaces the public key of a locker using [AuthMode::Schnorr] with `pk`, once its controller
+    /// was replaced, returning the old key and how many receipts were revoked.
+    ///
+    /// Everything we authorized for the old controller is invalidated: the receipts that weren't
+    /// used yet are revoked, and tokens issued before `rotated_at` are refused, see
+    /// [Server::key_rotated_at]. The old key is retired, so no locker can use it again. Keys
+    /// already used by a locker, including this one, return [StoreError::Constraint].
+    async fn rotate_locker_key(
+        &self,
+        locker_id: i64,
+        pk: &str,
+        rotated_at: u64,
+    ) -> Result<(String, u64), StoreError> {
+        let pk = pk.to_lowercase();
+
+        self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement =
+                        database.prepare("SELECT pk, auth_mode FROM lockers WHERE id = ?")?;
+                    statement.bind((1, locker_id))?;
+                    let sqlite::State::Row = statement.next()? else {
+                        return Err(StoreError::NotFound);
+                    };
+                    let old_pk = statement.read::<String, _>(0)?;
+                    if statement.read::<String, _>(1)? != AuthMode::Schnorr.as_str() {
+                        return Err(StoreError::Constraint(format!(
+                            "locker {locker_id} doesn't sign with a key"
+                        )));
+                    }
+
+                    let mut statement = database.prepare(
+                        "SELECT EXISTS (SELECT 1 FROM lockers WHERE lower(pk) = ?1)
+                             OR EXISTS (SELECT 1 FROM retired_locker_keys WHERE pk = ?1)",
+                    )?;
+                    statement.bind((1, pk.as_str()))?;
+                    statement.next()?;
+                    if statement.read::<i64, _>(0)? != 0 {
+                        return Err(StoreError::Constraint(format!("{pk} was already used")));
+                    }
+
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET pk = ?, key_rotated_at = ? WHERE id = ?",
+                    )?;
+                    statement.bind((1, pk.as_str()))?;
+                    statement.bind((2, rotated_at as i64))?;
+                    statement.bind((3, locker_id))?;
+                    statement.next()?;
 
-        let mut lockers = Vec::new();
-        while let sqlite::State::Row = statement.next()? {
-            let id: i64 = statement.read(0)?;
-            let state: String = statement.read(1)?;
-            let locker = Locker { id, state };
+                    let mut statement = database.prepare(
+                        "INSERT INTO retired_locker_keys (pk, locker_id, retired_at) VALUES (lower(?), ?, ?)",
+                    )?;
+                    statement.bind((1, old_pk.as_str()))?;
+                    statement.bind((2, locker_id))?;
+                    statement.bind((3, rotated_at as i64))?;
+                    statement.next()?;
+
+                    let mut statement = database.prepare(
+                        "INSERT INTO revoked_receipts (payment_hash, locker_id, reason, revoked_at, expires_at)
+                         SELECT payment_hash, locker_id, 'key_rotated', ?2, expires_at FROM receipts
+                         WHERE locker_id = ?1 AND consumed_at IS NULL AND (expires_at IS NULL OR expires_at > ?2)
+                         AND payment_hash NOT IN (SELECT payment_hash FROM revoked_receipts WHERE locker_id = ?1)",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.bind((2, rotated_at as i64))?;
+                    statement.next()?;
+
+                    Ok::<_, StoreError>((old_pk, database.change_count() as u64))
+                })
+            })
+            .await
+    }
 
-            lockers.push(locker);
+    /// Whether something we issued for `locker_id` at `issued_at` was meant for the controller it
+    /// had before its key was last rotated, see [Server::rotate_locker_key]. Things issued the
+    /// second the key was rotated count as before, to be safe.
+    async fn issued_before_key_rotation(
+        &self,
+        locker_id: i64,
+        issued_at: u64,
+    ) -> Result<bool, StoreError> {
+        match self.key_rotated_at(locker_id).await {
+            Ok(rotated_at) => Ok(rotated_at.is_some_and(|rotated_at| issued_at <= rotated_at)),
+            Err(StoreError::NotFound) => Ok(false),
+            Err(e) => Err(e),
         }
+    }
 
-        Ok(lockers)
+    /// When the key of a locker was last rotated, if ever.
+    async fn key_rotated_at(&self, locker_id: i64) -> Result<Option<u64>, StoreError> {
+        self.database
+            .read(move |database| {
+                let mut statement =
+                    database.prepare("SELECT key_rotated_at FROM lockers WHERE id = ?")?;
+                statement.bind((1, locker_id))?;
+
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+
+                Ok(statement.read::<Option<i64>, _>(0)?.map(|t| t as u64))
+            })
+            .await
+    }
+
+    /// Lets a locker that registered itself be used. Lockers that aren't waiting for approval
+    /// return [StoreError::Constraint].
+    async fn approve_locker(&self, locker_id: i64) -> Result<(), StoreError> {
+        let result = self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE lockers SET state = 'available' WHERE id = ? AND state = 'provisioning'",
+                )?;
+                statement.bind((1, locker_id))?;
+                statement.next()?;
+
+                if database.change_count() > 0 {
+                    return Ok(());
+                }
+
+                let mut statement = database.prepare("SELECT 1 FROM lockers WHERE id = ?")?;
+                statement.bind((1, locker_id))?;
+                match statement.next()? {
+                    sqlite::State::Row => Err(StoreError::Constraint(format!(
+                        "locker {locker_id} isn't waiting for approval"
+                    ))),
+                    sqlite::State::Done => Err(StoreError::NotFound),
+                }
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Takes a locker out of service for good, refusing with [StoreError::LockerBusy] while it's
+    /// occupied or has payments that aren't settled. With `force`, an occupied locker is taken out
+    /// anyway, closing its session at `now` without charging for it and flagging the session for
+    /// staff to follow up on, whose id is returned.
+    async fn retire_locker(
+        &self,
+        locker_id: i64,
+        force: bool,
+        now: u64,
+    ) -> Result<Option<i64>, StoreError> {
+        let result = self.database
+            .write(move |database| {
+                db::transaction(database, || {
+                    let mut statement = database.prepare("SELECT state FROM lockers WHERE id = ?")?;
+                    statement.bind((1, locker_id))?;
+                    let sqlite::State::Row = statement.next()? else {
+                        return Err(StoreError::NotFound);
+                    };
+                    let state: String = statement.read(0)?;
+
+                    let mut statement = database.prepare(
+                        "SELECT payment_hash FROM pending_payments WHERE locker_id = ? AND status IN ('pending', 'paid') ORDER BY id",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    let mut unsettled_payments = Vec::new();
+                    while let sqlite::State::Row = statement.next()? {
+                        unsettled_payments.push(statement.read::<String, _>(0)?);
+                    }
+
+                    let occupied = state == "in_use" || state == "awaiting_payment";
+                    if !unsettled_payments.is_empty() || (occupied && !force) {
+                        return Err(StoreError::LockerBusy {
+                            state,
+                            unsettled_payments,
+                        });
+                    }
+
+                    let mut statement = database.prepare(
+                        "UPDATE usage_sessions SET state = 'closed', ended_at = ?, amount_sat = 0, needs_follow_up = 1 WHERE locker_id = ? AND state = 'active' RETURNING id",
+                    )?;
+                    statement.bind((1, now as i64))?;
+                    statement.bind((2, locker_id))?;
+                    let session_id = match statement.next()? {
+                        sqlite::State::Row => Some(statement.read::<i64, _>(0)?),
+                        sqlite::State::Done => None,
+                    };
+                    // the statement must be run to completion for the update to apply to every row
+                    while let sqlite::State::Row = statement.next()? {}
+
+                    let mut statement = database.prepare(
+                        "UPDATE reservations SET ended_at = ? WHERE locker_id = ? AND used_at IS NULL AND ended_at IS NULL",
+                    )?;
+                    statement.bind((1, now as i64))?;
+                    statement.bind((2, locker_id))?;
+                    statement.next()?;
+
+                    let mut statement = database.prepare(
+                        "UPDATE lockers SET active = 0, state = CASE WHEN state = 'provisioning' THEN state ELSE 'available' END WHERE id = ?",
+                    )?;
+                    statement.bind((1, locker_id))?;
+                    statement.next()?;
+
+                    Ok(session_id)
+                })
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Puts an available locker under maintenance, or makes one under maintenance available again.
+    /// A locker someone is using can't be put under maintenance, that's a [StoreError::LockerBusy],
+    /// and neither can one waiting to be approved. Asking for the state it's already in is fine.
+    async fn set_locker_maintenance(
+        &self,
+        locker_id: i64,
+        maintenance: bool,
+    ) -> Result<(), StoreError> {
+        let (from, to) = match maintenance {
+            true => ("available", "maintenance"),
+            false => ("maintenance", "available"),
+        };
+
+        let result = self.database
+            .write(move |database| {
+                let mut statement =
+                    database.prepare("UPDATE lockers SET state = ? WHERE id = ? AND state = ?")?;
+                statement.bind((1, to))?;
+                statement.bind((2, locker_id))?;
+                statement.bind((3, from))?;
+                statement.next()?;
+
+                if database.change_count() > 0 {
+                    return Ok(());
+                }
+
+                let mut statement = database.prepare("SELECT state FROM lockers WHERE id = ?")?;
+                statement.bind((1, locker_id))?;
+                let sqlite::State::Row = statement.next()? else {
+                    return Err(StoreError::NotFound);
+                };
+                let state: String = statement.read(0)?;
+
+                match state.as_str() {
+                    state if state == to => Ok(()),
+                    "in_use" | "awaiting_payment" => Err(StoreError::LockerBusy {
+                        state,
+                        unsettled_payments: Vec::new(),
+                    }),
+                    _ => Err(StoreError::Constraint(format!(
+                        "locker {locker_id} is {state}, not {from}"
+                    ))),
+                }
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Decommissions or reactivates a locker.
+    async fn set_locker_active(&self, locker_id: i64, active: bool) -> Result<(), StoreError> {
+        let result = self.database
+            .write(move |database| {
+                let mut statement = database.prepare("UPDATE lockers SET active = ? WHERE id = ?")?;
+                statement.bind((1, active as i64))?;
+                statement.bind((2, locker_id))?;
+                statement.next()?;
+
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await;
+        self.publish_locker_state(locker_id).await;
+        result
+    }
+
+    /// Updates the descriptive fields of a locker, leaving the ones that aren't set untouched.
+    ///
+    /// Moving a locker to a site that doesn't exist, or is another operator's, returns
+    /// [StoreError::NotFound].
+    async fn update_locker_metadata(
+        &self,
+        locker_id: i64,
+        metadata: &LockerMetadata,
+    ) -> Result<(), StoreError> {
+        let metadata = metadata.clone();
+
+        self.database
+            .write(move |database| {
+                let mut statement = database.prepare(
+                    "UPDATE lockers SET name = COALESCE(?, name), location = COALESCE(?, location), size = COALESCE(?, size), description = COALESCE(?, description), site_id = COALESCE(?, site_id) WHERE id = ?",
+                )?;
+                statement.bind((1, metadata.name.as_deref()))?;
+                statement.bind((2, metadata.location.as_deref()))?;
+                statement.bind((3, metadata.size.map(|size| size.as_str())))?;
+                statement.bind((4, metadata.description.as_deref()))?;
+                statement.bind((5, metadata.site_id))?;
+                statement.bind((6, locker_id))?;
+
+                // lockers can only be at the sites of their own operator
+                if let Some(site_id) = metadata.site_id {
+                    let mut statement = database.prepare(
+                        "SELECT 1 FROM sites s JOIN lockers l ON l.id = ?2 WHERE s.id = ?1 AND s.operator_id IS l.operator_id",
+                    )?;
+                    statement.bind((1, site_id))?;
+                    statement.bind((2, locker_id))?;
+                    let sqlite::State::Row = statement.next()? else {
+                        return Err(StoreError::NotFound);
+                    };
+                }
+
+                match statement.next() {
+                    Err(sqlite::Error {
+                        code: Some(19),
+                        message: Some(message),
+                    }) if message.contains("FOREIGN KEY") => return Err(StoreError::NotFound),
+                    result => result?,
+                };
+
+                if database.change_count() == 0 {
+                    return Err(StoreError::NotFound);
+                }
+
+                Ok(())
+            })
+            .await
     }
 }
 
+mod admin;
+mod api;
+mod batch;
+mod config;
+mod consistency;
+mod csv;
+mod customer;
+mod db;
+mod device;
+mod enrollment;
 mod error;
+mod feed;
+mod health;
+mod idempotency;
+mod keys;
 mod ln;
+mod lnurl;
+mod nostr;
+mod metrics;
+mod openapi;
+mod params;
+mod payment_events;
+mod pricing;
+mod qr;
+mod ratelimit;
+mod request_id;
+mod receipt;
+mod reservation;
+mod secret;
+mod signing;
+mod tasks;
+mod tls;
+mod token;
+mod version;
+mod webhook;
 
 #[tokio::main]
 async fn main() { 
-    let password = env::var("PASSWORD").expect("PASSWORD not set");
+    let config = config::Config::load();
+    let keyring = keys::load(&config, now())
+        .unwrap_or_else(|e| panic!("failed to load the server key: {e}"));
 
-    let phoenix = PhoenixdClient::new(
-        "http://127.0.0.1:9740".to_string(),
-        base64::encode(format!(":{password}"))
+    // lets whoever provisions the lockers get our public key without starting the server
+    if env::args().any(|arg| arg == "--show-pubkey") {
+        println!("{}", keyring.active().keypair.expose().x_only_public_key().0);
+        return;
+    }
+
+    let database = db::Pool::open(&config.database_path, &config.database)
+        .map(Arc::new)
+        .unwrap_or_else(|e| panic!("failed to open the database: {e}"));
+
+    let lockers = config.lockers.clone();
+    let (added, updated) = database
+        .write(move |database| db::sync_lockers(database, &lockers))
+        .await
+        .expect("failed to sync the lockers");
+
+    println!("[+] Keypair loaded");
+    println!(
+        "[+] Server pubkey: {} (kid {})",
+        keyring.active().keypair.expose().x_only_public_key().0,
+        keyring.active().id
     );
+    println!("[+] Database created");
+    println!("[+] Lockers synced: {added} added, {updated} updated");
+
+    if config.admin_tokens.is_empty() {
+        println!("[+] ADMIN_TOKEN not set, admin routes are disabled");
+    }
 
-    let database = sqlite::open(":memory:").unwrap();
-    database
-        .execute("CREATE TABLE IF NOT EXISTS lockers (id INTEGER PRIMARY KEY AUTOINCREMENT, pk TEXT NOT NULL, state TEXT NOT NULL, start_time INTEGER NOT NULL)")
-        .unwrap();
+    if config.public_url.is_none() {
+        println!("[+] PUBLIC_URL not set, customers can't log in");
+    }
 
-    // create the table pending payments
-    database
-        .execute("CREATE TABLE IF NOT EXISTS pending_payments (id INTEGER PRIMARY KEY AUTOINCREMENT, amount INTEGER NOT NULL, payment_hash TEXT NOT NULL, status TEXT NOT NULL, locker_id TEXT NOT NULL, FOREIGN KEY (locker_id) REFERENCES lockers(id))")
-        .unwrap();
+    if let Some(until) = config.legacy_signatures_until {
+        eprintln!(
+            "[!] WARNING: LEGACY_SIGNATURES is on until {until}. Until then, any legacy \
+             authorization we hand out with a receipt opens any locker running old firmware, and \
+             their reports can be replayed. Update the lockers and turn it off."
+        );
+    }
 
-    // add two lockers to the database
-    database
-        .execute("INSERT OR IGNORE INTO lockers (state, start_time, pk) VALUES ('available', 0, 'c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5')")
-        .unwrap();
+    // the mock backend considers every invoice paid, it's only meant for running the tests
+    // without a phoenixd instance, so release builds don't even have it
+    let mock = env::var("LN_BACKEND").is_ok_and(|backend| backend == "mock");
+    #[cfg(not(feature = "mock-ln"))]
+    if mock {
+        panic!("LN_BACKEND=mock needs a build with the mock-ln feature, meant only for tests");
+    }
+    #[cfg(feature = "mock-ln")]
+    if mock {
+        println!("[+] Using the mock lightning backend, every invoice counts as paid");
+        let reuse_hashes = env::var("MOCK_LN_REUSE_HASHES").is_ok_and(|reuse| reuse == "true");
+        Server::run(
+            "0.0.0.0:8080".to_string(),
+            keyring,
+            database,
+            MockLnBackend::new(reuse_hashes),
+            config,
+        )
+        .await;
+        return;
+    }
 
-    database
-        .execute("INSERT OR IGNORE INTO lockers (state, start_time, pk) VALUES ('available', 0, 'c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5')")
-        .unwrap();
+    let password = Secret::new(env::var("PASSWORD").expect("PASSWORD not set"));
+    let credentials = Secret::new(format!(":{}", password.expose()));
 
-    let keypair = Keypair::from_seckey_str(
-        &Secp256k1::default(),
-        "0000000000000000000000000000000000000000000000000000000000000001",
-    )
-    .expect("failed to create keypair");
+    let phoenix = PhoenixdClient::new(
+        "http://127.0.0.1:9740".to_string(),
+        base64::engine::general_purpose::STANDARD.encode(credentials.expose())
+    );
 
-    println!("[+] Keypair created");
-    println!("[+] Server pubkey: {}", keypair.x_only_public_key().0.to_string());
-    println!("[+] Database created");
     println!("[+] Phoenix client created");
-    println!("[+] Starting server...");
     // create the server
-    Server::run("0.0.0.0:8080".to_string(), keypair, database, phoenix).await;
+    Server::run(
+        "0.0.0.0:8080".to_string(),
+        keyring,
+        database,
+        phoenix,
+        config,
+    )
+    .await;
 }
@@ -0,0 +1,41 @@
+//! Counters describing what the server has been doing, exposed to operators through
+//! `/admin/metrics`.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// When the server started, as a unix timestamp.
+    pub started_at: AtomicU64,
+    /// How many pending payments the cleanup task marked as expired.
+    pub expired_payments: AtomicU64,
+    /// How many expired payments the cleanup task deleted.
+    pub deleted_payments: AtomicU64,
+    /// When the cleanup task last ran, as a unix timestamp.
+    pub last_payment_cleanup: AtomicU64,
+    /// When the backup task last wrote a backup, as a unix timestamp.
+    pub last_backup: AtomicU64,
+    /// When the maintenance task last ran, as a unix timestamp.
+    pub last_maintenance: AtomicU64,
+    /// When we last checked the lightning backend is reachable, as a unix timestamp.
+    pub last_ln_check: AtomicU64,
+    /// When the lightning backend last answered that check, as a unix timestamp.
+    pub last_ln_reachable: AtomicU64,
+}
+
+impl Metrics {
+    /// Returns the current value of every counter as a JSON object.
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "started_at": self.started_at.load(Ordering::Relaxed),
+            "expired_payments": self.expired_payments.load(Ordering::Relaxed),
+            "deleted_payments": self.deleted_payments.load(Ordering::Relaxed),
+            "last_payment_cleanup": self.last_payment_cleanup.load(Ordering::Relaxed),
+            "last_backup": self.last_backup.load(Ordering::Relaxed),
+            "last_maintenance": self.last_maintenance.load(Ordering::Relaxed),
+            "last_ln_check": self.last_ln_check.load(Ordering::Relaxed),
+            "last_ln_reachable": self.last_ln_reachable.load(Ordering::Relaxed),
+        })
+    }
+}
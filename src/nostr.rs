@@ -0,0 +1,268 @@
+//! NIP-98 HTTP auth, which lets clients holding a nostr key, like our kiosk app, say who a request
+//! is made for, see <https://github.com/nostr-protocol/nips/blob/master/98.md>.
+//!
+//! The `Authorization` header carries `Nostr ` followed by a base64 encoded nostr event of kind
+//! [KIND_HTTP_AUTH], signed by the user's key, whose `u` and `method` tags name the request it
+//! was made for. Events are only good for [MAX_AGE_SECONDS] either way of our clock.
+
+use std::fmt::Display;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::FromHex;
+use secp256k1::schnorr::Signature;
+use secp256k1::Secp256k1;
+use secp256k1::XOnlyPublicKey;
+use serde::Deserialize;
+
+/// The kind of the events NIP-98 authorizations carry.
+pub const KIND_HTTP_AUTH: u64 = 27235;
+
+/// How far, in seconds, the `created_at` of an event may be from our clock.
+pub const MAX_AGE_SECONDS: u64 = 60;
+
+/// The scheme of the `Authorization` header carrying an event.
+pub const SCHEME: &str = "Nostr ";
+
+/// A nostr event, as described in NIP-01.
+#[derive(Debug, Clone, Deserialize)]
+struct Event {
+    id: String,
+    pubkey: String,
+    created_at: u64,
+    kind: u64,
+    tags: Vec<Vec<String>>,
+    content: String,
+    sig: String,
+}
+
+/// Why an authorization was refused by [verify_auth].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// The header isn't a base64 encoded event, or the event's fields aren't what we expect.
+    Malformed,
+    /// The event's id isn't its hash, or it wasn't signed by its pubkey.
+    BadSignature,
+    /// The event isn't of kind [KIND_HTTP_AUTH].
+    WrongKind,
+    /// The event was created more than [MAX_AGE_SECONDS] away from now.
+    Stale,
+    /// The event's `u` tag isn't the URL of the request.
+    WrongUrl,
+    /// The event's `method` tag isn't the method of the request.
+    WrongMethod,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Malformed => write!(f, "malformed nostr event"),
+            AuthError::BadSignature => write!(f, "bad nostr event signature"),
+            AuthError::WrongKind => write!(f, "nostr event isn't of kind {KIND_HTTP_AUTH}"),
+            AuthError::Stale => write!(f, "stale nostr event"),
+            AuthError::WrongUrl => write!(f, "nostr event is for another url"),
+            AuthError::WrongMethod => write!(f, "nostr event is for another method"),
+        }
+    }
+}
+
+/// Checks `header`, the value of an `Authorization` header, carries an event authorizing a
+/// `method` request to `path_and_query`, created around `now`, returning who signed it.
+///
+/// If we know our `public_url`, the `u` tag must be exactly that followed by `path_and_query`.
+/// Otherwise any host will do, since we can't tell which names we're reached by.
+pub fn verify_auth(
+    header: &str,
+    method: &str,
+    path_and_query: &str,
+    public_url: Option<&str>,
+    now: u64,
+) -> Result<XOnlyPublicKey, AuthError> {
+    let event = header
+        .strip_prefix(SCHEME)
+        .and_then(|event| STANDARD.decode(event.trim()).ok())
+        .and_then(|event| serde_json::from_slice::<Event>(&event).ok())
+        .ok_or(AuthError::Malformed)?;
+
+    let pubkey = event
+        .pubkey
+        .parse::<XOnlyPublicKey>()
+        .map_err(|_| AuthError::Malformed)?;
+    let id = <[u8; 32]>::from_hex(&event.id).map_err(|_| AuthError::Malformed)?;
+    let sig = <[u8; 64]>::from_hex(&event.sig).map_err(|_| AuthError::Malformed)?;
+
+    if id != event_id(&event) {
+        return Err(AuthError::BadSignature);
+    }
+
+    Secp256k1::verification_only()
+        .verify_schnorr(&Signature::from_byte_array(sig), &id, &pubkey)
+        .map_err(|_| AuthError::BadSignature)?;
+
+    if event.kind != KIND_HTTP_AUTH {
+        return Err(AuthError::WrongKind);
+    }
+
+    if event.created_at.abs_diff(now) > MAX_AGE_SECONDS {
+        return Err(AuthError::Stale);
+    }
+
+    let url = tag(&event, "u").ok_or(AuthError::WrongUrl)?;
+    let matches = match public_url {
+        Some(public_url) => url.strip_prefix(public_url) == Some(path_and_query),
+        None => {
+            url.strip_prefix("https://")
+                .or_else(|| url.strip_prefix("http://"))
+                .and_then(|url| url.find('/').map(|path| &url[path..]))
+                == Some(path_and_query)
+        }
+    };
+    if !matches {
+        return Err(AuthError::WrongUrl);
+    }
+
+    if !tag(&event, "method").is_some_and(|tag| tag.eq_ignore_ascii_case(method)) {
+        return Err(AuthError::WrongMethod);
+    }
+
+    Ok(pubkey)
+}
+
+/// The id of `event`, the SHA-256 of its fields serialized as NIP-01 says.
+fn event_id(event: &Event) -> [u8; 32] {
+    let serialized = serde_json::json!([
+        0,
+        event.pubkey,
+        event.created_at,
+        event.kind,
+        event.tags,
+        event.content,
+    ]);
+
+    sha256::Hash::hash(&serde_json::to_vec(&serialized).unwrap()).to_byte_array()
+}
+
+/// The value of the first tag of `event` named `name`.
+fn tag<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event
+        .tags
+        .iter()
+        .find(|tag| tag.first().is_some_and(|tag| tag == name))
+        .and_then(|tag| tag.get(1))
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use super::AuthError;
+
+    /// Made with `test/nostr.py 30...01 GET http://127.0.0.1:8080/use_locker/1 1700000000`.
+    const EVENT: &str = r#"{"id": "45c9fafc3e74b6316a9448b8775ad928c78df92b6587a5989af524993280c519", "pubkey": "a6469c5b80419de916498141a68fcf4085d89b02dad3966df1f8915c356902b4", "created_at": 1700000000, "kind": 27235, "tags": [["u", "http://127.0.0.1:8080/use_locker/1"], ["method", "GET"]], "content": "", "sig": "34a92dd0e34bb853b0ccf6efe8a08abaf994e14bbc8dc501d0fa6b335dbadfc7ccfd825b47ede14d30533e95f3649bbbe80aafad9b5e3638ab885fc50ca56db8"}"#;
+
+    fn header(event: &str) -> String {
+        format!("Nostr {}", STANDARD.encode(event))
+    }
+
+    #[test]
+    fn valid_events_are_accepted() {
+        let header = header(EVENT);
+        for public_url in [Some("http://127.0.0.1:8080"), None] {
+            assert_eq!(
+                super::verify_auth(&header, "GET", "/use_locker/1", public_url, 1700000000)
+                    .unwrap()
+                    .to_string(),
+                "a6469c5b80419de916498141a68fcf4085d89b02dad3966df1f8915c356902b4"
+            );
+        }
+
+        // a client whose clock is a bit off either way
+        for now in [1700000000 - 60, 1700000000 + 60] {
+            assert!(super::verify_auth(&header, "GET", "/use_locker/1", None, now).is_ok());
+        }
+    }
+
+    #[test]
+    fn events_for_other_requests_are_refused() {
+        let header = header(EVENT);
+        for (method, path, public_url, now, error) in [
+            (
+                "GET",
+                "/use_locker/2",
+                None,
+                1700000000,
+                AuthError::WrongUrl,
+            ),
+            (
+                "GET",
+                "/pay_for_usage/1",
+                None,
+                1700000000,
+                AuthError::WrongUrl,
+            ),
+            (
+                "GET",
+                "/use_locker/1",
+                Some("https://lockers.example.com"),
+                1700000000,
+                AuthError::WrongUrl,
+            ),
+            (
+                "POST",
+                "/use_locker/1",
+                None,
+                1700000000,
+                AuthError::WrongMethod,
+            ),
+            (
+                "GET",
+                "/use_locker/1",
+                None,
+                1700000000 + 61,
+                AuthError::Stale,
+            ),
+            (
+                "GET",
+                "/use_locker/1",
+                None,
+                1700000000 - 61,
+                AuthError::Stale,
+            ),
+        ] {
+            assert_eq!(
+                super::verify_auth(&header, method, path, public_url, now),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_events_are_refused() {
+        // pointing the event at another locker, or making it newer
+        for tampered in [
+            EVENT.replace("use_locker/1", "use_locker/2"),
+            EVENT.replace("1700000000", "1700000100"),
+        ] {
+            assert_eq!(
+                super::verify_auth(&header(&tampered), "GET", "/use_locker/2", None, 1700000000),
+                Err(AuthError::BadSignature)
+            );
+        }
+
+        for header in [
+            "Bearer abc".to_string(),
+            "Nostr !!!".to_string(),
+            header("{}"),
+            header(&EVENT.replace("\"sig\": \"34", "\"sig\": \"")),
+        ] {
+            assert_eq!(
+                super::verify_auth(&header, "GET", "/use_locker/1", None, 1700000000),
+                Err(AuthError::Malformed)
+            );
+        }
+    }
+}
@@ -0,0 +1,2331 @@
+//! The OpenAPI 3 description of the HTTP API, served at `GET /v1/openapi.json`, and browsable at
+//! `/v1/docs` when built with the `swagger-ui` feature.
+//!
+//! It's written by hand with [Operation] rather than derived from the handlers, so a route that's
+//! added or changed has to be described here too. Each operation lists every status it can answer
+//! with, including those of the extractors and middleware in front of it: the rate limits, the
+//! body limit, the admin tokens, and the database being busy.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::batch;
+use crate::cacheable;
+use crate::error;
+use crate::idempotency;
+use crate::ln::LnBackend;
+use crate::lnurl;
+use crate::reservation;
+use crate::webhook;
+use crate::ApiVersion;
+use crate::Server;
+use crate::MAX_LOCKERS_PER_PAGE;
+
+/// The version of OpenAPI the description follows.
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// One route and method, built up with the methods below and then added to the description by
+/// [spec].
+struct Operation {
+    method: &'static str,
+    path: String,
+    /// The fields of the operation object, other than its responses.
+    fields: serde_json::Map<String, Value>,
+    /// What the operation answers with when it succeeds, by status.
+    responses: BTreeMap<u16, Value>,
+    /// Why the operation may fail, by status. Several reasons for a status are joined together.
+    errors: BTreeMap<u16, Vec<String>>,
+    /// Whether the operation reads or writes the database, which may be busy.
+    store: bool,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &str, tag: &str, summary: &str) -> Self {
+        let mut fields = serde_json::Map::new();
+        fields.insert("tags".to_string(), json!([tag]));
+        fields.insert("summary".to_string(), json!(summary));
+        fields.insert("parameters".to_string(), json!([]));
+
+        Self {
+            method,
+            path: path.to_string(),
+            fields,
+            responses: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            store: true,
+        }
+    }
+
+    fn describe(mut self, description: &str) -> Self {
+        self.fields
+            .insert("description".to_string(), json!(description));
+        self
+    }
+
+    fn parameter(mut self, parameter: Value) -> Self {
+        self.fields["parameters"]
+            .as_array_mut()
+            .expect("parameters are a list")
+            .push(parameter);
+        self
+    }
+
+    /// An integer id in the path, rejected with a `400` unless it's one.
+    fn id(self, name: &str, description: &str) -> Self {
+        self.parameter(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "description": description,
+            "schema": { "type": "integer", "format": "int64" },
+        }))
+        .error(400, &format!("`{name}` isn't an integer."))
+    }
+
+    /// The `{locker_id}` of the path, see [crate::params::LockerId].
+    fn locker_id(self) -> Self {
+        self.parameter(json!({
+            "name": "locker_id",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "integer", "format": "int64", "minimum": 1 },
+        }))
+        .error(400, "`locker_id` isn't a positive integer.")
+        .error(404, "There's no such locker.")
+    }
+
+    /// The `{payment_hash}` of the path, see [crate::params::PaymentHash].
+    fn payment_hash(self) -> Self {
+        self.parameter(json!({
+            "name": "payment_hash",
+            "in": "path",
+            "required": true,
+            "schema": { "$ref": "#/components/schemas/PaymentHash" },
+        }))
+        .error(400, "`payment_hash` isn't 64 hex characters.")
+        .error(404, "There's no such payment.")
+    }
+
+    fn query(self, name: &str, schema: Value, description: &str) -> Self {
+        self.parameter(json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": description,
+            "schema": schema,
+        }))
+        .error(400, "The query string doesn't parse.")
+    }
+
+    /// The `limit` and `offset` query parameters of a paginated list.
+    fn page(self, default_limit: u64, max_limit: u64) -> Self {
+        self.query(
+            "limit",
+            json!({ "type": "integer", "minimum": 0, "default": default_limit }),
+            &format!("How many entries to return, at most {max_limit}."),
+        )
+        .query(
+            "offset",
+            json!({ "type": "integer", "minimum": 0, "default": 0 }),
+            "How many entries to skip, used to fetch the next pages.",
+        )
+    }
+
+    /// A JSON request body, as read by [axum::Json].
+    fn body(self, schema: Value) -> Self {
+        self.request_body(schema, true)
+            .error(422, "The body doesn't have the expected fields.")
+    }
+
+    /// A JSON request body that may be left out.
+    fn optional_body(self, schema: Value) -> Self {
+        self.request_body(schema, false)
+            .error(422, "The body doesn't have the expected fields.")
+    }
+
+    /// A JSON request body, as read by [crate::params::ValidJson], which names the field that's
+    /// wrong.
+    fn valid_body(self, schema: Value) -> Self {
+        self.request_body(schema, true)
+            .error(400, "A field of the body is missing or malformed.")
+    }
+
+    fn request_body(mut self, schema: Value, required: bool) -> Self {
+        self.fields.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": required,
+                "content": { "application/json": { "schema": schema } },
+            }),
+        );
+        self.error(400, "The body isn't JSON.")
+            .error(413, "The body is larger than the configured limit.")
+            .error(415, "The body isn't `application/json`.")
+    }
+
+    /// Answers with `data` in the usual envelope, see [envelope].
+    fn ok(self, description: &str, data: Value) -> Self {
+        self.respond(200, description, "application/json", envelope(data))
+    }
+
+    fn respond(
+        mut self,
+        status: u16,
+        description: &str,
+        content_type: &str,
+        schema: Value,
+    ) -> Self {
+        self.responses.insert(
+            status,
+            json!({
+                "description": description,
+                "content": { (content_type): { "schema": schema } },
+            }),
+        );
+        if (200..300).contains(&status) {
+            return self.error(406, "The `Accept` header rules out what this answers.");
+        }
+        self
+    }
+
+    fn error(mut self, status: u16, reason: &str) -> Self {
+        let reasons = self.errors.entry(status).or_default();
+        if !reasons.iter().any(|known| known == reason) {
+            reasons.push(reason.to_string());
+        }
+        self
+    }
+
+    /// Behind the limit for the cheap or the expensive public routes, see [crate::ratelimit].
+    fn rate_limited(self) -> Self {
+        self.error(429, "The client made too many requests, see `Retry-After`.")
+    }
+
+    /// Behind the admin tokens, and a `role` other than `viewer` if it's set, see
+    /// [crate::admin::RequireRole].
+    fn admin(mut self, role: Option<&str>) -> Self {
+        self.fields
+            .insert("security".to_string(), json!([{ "adminToken": [] }]));
+        let this = self.error(401, "The request doesn't carry an admin token.");
+        match role {
+            Some(role) => this.error(403, &format!("The token's role is below `{role}`.")),
+            None => this,
+        }
+    }
+
+    /// Only answers tokens that aren't scoped to an operator, see [crate::admin::SuperAdmin].
+    fn super_admin(self) -> Self {
+        self.error(403, "The token is scoped to an operator.")
+    }
+
+    /// Adds something for the operator the body names, or the token's own, see
+    /// [crate::admin::Scope::owner].
+    fn for_operator(self) -> Self {
+        self.error(400, "There's no such operator.")
+            .error(403, "The token is scoped to another operator.")
+    }
+
+    /// Takes a customer's login or NIP-98 header if there's one, see [crate::customer].
+    fn optional_customer(mut self) -> Self {
+        self.fields.insert(
+            "security".to_string(),
+            json!([{}, { "customerToken": [] }, { "nostr": [] }]),
+        );
+        self.error(401, "The `Authorization` header doesn't log anyone in.")
+    }
+
+    /// Takes the token of the reservation holding the locker, see [crate::reservation].
+    fn reservation_token(self) -> Self {
+        self.parameter(json!({
+            "name": reservation::TOKEN_HEADER,
+            "in": "header",
+            "required": false,
+            "description": "The token `/reserve` handed out, to claim the locker it holds.",
+            "schema": string(),
+        }))
+    }
+
+    /// Answers retries with the same key the way it answered the first request, see
+    /// [crate::idempotency].
+    fn idempotent(self) -> Self {
+        self.parameter(json!({
+            "name": idempotency::KEY_HEADER,
+            "in": "header",
+            "required": false,
+            "description": "A random key, for retries to get the response to the first request \
+                            with it, marked with `Idempotent-Replayed: true`.",
+            "schema": {
+                "type": "string",
+                "minLength": 1,
+                "maxLength": idempotency::MAX_KEY_LENGTH,
+            },
+        }))
+        .error(400, "The `Idempotency-Key` isn't 1 to 255 visible ASCII characters.")
+        .error(
+            409,
+            "The `Idempotency-Key` was used for another request, or the first request with it \
+             is still being handled.",
+        )
+    }
+
+    /// Only answers customers logged in with the token from `GET /auth/lnurl`.
+    fn customer(mut self) -> Self {
+        self.fields
+            .insert("security".to_string(), json!([{ "customerToken": [] }]));
+        self.error(
+            401,
+            "The request doesn't carry a token logged in with a wallet.",
+        )
+    }
+
+    /// Only answers customers logged in, with a wallet or with nostr, see [crate::customer].
+    fn logged_in(mut self) -> Self {
+        self.fields.insert(
+            "security".to_string(),
+            json!([{ "customerToken": [] }, { "nostr": [] }]),
+        );
+        self.error(401, "The `Authorization` header doesn't log anyone in.")
+    }
+
+    /// Takes the device token of the locker the request acts as, see [crate::device].
+    fn device(mut self) -> Self {
+        self.fields
+            .insert("security".to_string(), json!([{}, { "deviceToken": [] }]));
+        self.error(401, "The request doesn't carry the locker's device token.")
+    }
+
+    fn deprecated(mut self) -> Self {
+        self.fields.insert("deprecated".to_string(), json!(true));
+        self
+    }
+
+    /// For operations that never touch the database.
+    fn without_store(mut self) -> Self {
+        self.store = false;
+        self
+    }
+
+    /// The operation object, with the statuses every operation may answer with.
+    fn into_value(mut self) -> Value {
+        if self.store {
+            self = self.error(503, "The database is busy, see `Retry-After`.");
+        }
+        let this = self.error(500, "Something went wrong on our side.");
+
+        let mut responses = serde_json::Map::new();
+        for (status, response) in this.responses {
+            responses.insert(status.to_string(), response);
+        }
+        for (status, reasons) in this.errors {
+            // what the operation itself says it answers with takes precedence
+            if responses.contains_key(&status.to_string()) {
+                continue;
+            }
+
+            let mut response = json!({
+                "description": reasons.join(" "),
+                "content": {
+                    "application/json": { "schema": { "$ref": "#/components/schemas/Error" } },
+                },
+            });
+            if matches!(status, 402 | 429 | 503) {
+                response["headers"] = json!({
+                    "Retry-After": { "$ref": "#/components/headers/Retry-After" },
+                });
+            }
+            responses.insert(status.to_string(), response);
+        }
+
+        let mut operation = this.fields;
+        if operation["parameters"] == json!([]) {
+            operation.remove("parameters");
+        }
+        operation.insert("responses".to_string(), Value::Object(responses));
+        Value::Object(operation)
+    }
+}
+
+/// A success response: `data`, with `error` always `null`.
+fn envelope(data: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": ["data", "error"],
+        "properties": {
+            "data": data,
+            "error": { "nullable": true, "description": "Always `null`." },
+        },
+    })
+}
+
+/// A reference to one of the [schemas].
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// A reference to one of the [schemas] that may be `null`.
+fn nullable(name: &str) -> Value {
+    json!({ "nullable": true, "allOf": [schema(name)] })
+}
+
+fn list(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// An object whose properties are all set, `null` or not.
+fn object(properties: Value) -> Value {
+    let required: Vec<_> = properties
+        .as_object()
+        .expect("properties are an object")
+        .keys()
+        .cloned()
+        .collect();
+    json!({ "type": "object", "required": required, "properties": properties })
+}
+
+/// `items` with the total count of entries matching the query.
+fn page(name: &str, items: Value) -> Value {
+    object(json!({
+        (name): list(items),
+        "total": { "type": "integer", "description": "How many entries match in total." },
+    }))
+}
+
+/// What operations that return nothing put in `data`.
+fn null() -> Value {
+    json!({ "nullable": true, "description": "Always `null`." })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "format": "int64" })
+}
+
+fn optional_integer() -> Value {
+    json!({ "type": "integer", "format": "int64", "nullable": true })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn optional_string() -> Value {
+    json!({ "type": "string", "nullable": true })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn timestamp() -> Value {
+    json!({ "type": "integer", "format": "int64", "description": "A unix timestamp." })
+}
+
+fn optional_timestamp() -> Value {
+    json!({
+        "type": "integer",
+        "format": "int64",
+        "nullable": true,
+        "description": "A unix timestamp.",
+    })
+}
+
+/// The schemas operations refer to with [schema].
+fn schemas() -> Value {
+    json!({
+        "Error": {
+            "type": "object",
+            "description": "What every error looks like.",
+            "required": ["data", "error"],
+            "properties": {
+                "data": {
+                    "nullable": true,
+                    "description": "What the error is about, when there's more to say, like the \
+                        state of a locker that's busy.",
+                },
+                "error": schema("ErrorDetail"),
+            },
+        },
+        "ErrorDetail": {
+            "type": "object",
+            "required": ["code", "message"],
+            "properties": {
+                "code": {
+                    "type": "string",
+                    "description": "What went wrong, for programs, like `not_found`. Codes never \
+                        change, unlike messages.",
+                },
+                "message": { "type": "string", "description": "What went wrong, for people." },
+                "request_id": {
+                    "type": "string",
+                    "description": "The id of the request, also in `X-Request-Id`, to mention \
+                        when reporting the error.",
+                },
+            },
+        },
+        "PaymentHash": {
+            "type": "string",
+            "pattern": "^[0-9a-fA-F]{64}$",
+            "description": "The SHA-256 of an invoice's preimage, in hex.",
+        },
+        "Action": {
+            "type": "string",
+            "enum": ["store", "retrieve"],
+            "description": "Why a locker is opened: `store` after claiming it, `retrieve` after \
+                paying.",
+        },
+        "LockerSize": { "type": "string", "enum": ["small", "medium", "large"] },
+        "AuthMode": {
+            "type": "string",
+            "enum": ["schnorr", "hmac"],
+            "description": "How the locker checks the authorizations we hand out for it.",
+        },
+        "Site": object(json!({
+            "id": integer(),
+            "name": string(),
+            "address": optional_string(),
+            "timezone": {
+                "type": "string",
+                "description": "The IANA name of the site's timezone.",
+                "example": "America/Los_Angeles",
+            },
+            "price_per_minute_msat": optional_integer(),
+        })),
+        "Locker": object(json!({
+            "id": integer(),
+            "state": {
+                "type": "string",
+                "enum": [
+                    "provisioning",
+                    "available",
+                    "reserved",
+                    "in_use",
+                    "awaiting_payment",
+                    "maintenance",
+                ],
+            },
+            "name": optional_string(),
+            "location": optional_string(),
+            "size": nullable("LockerSize"),
+            "description": optional_string(),
+            "active": {
+                "type": "boolean",
+                "description": "Decommissioned lockers are hidden from customers.",
+            },
+            "price_per_minute_msat": {
+                "type": "integer",
+                "nullable": true,
+                "description": "Overrides the price of its site and the configured one.",
+            },
+            "site": nullable("Site"),
+            "auth_mode": schema("AuthMode"),
+        })),
+        "AdminLocker": {
+            "allOf": [
+                schema("Locker"),
+                object(json!({
+                    "pk": {
+                        "type": "string",
+                        "description": "The locker's public key, or what identifies its \
+                            controller if it uses `hmac`.",
+                    },
+                    "operator_id": optional_integer(),
+                })),
+            ],
+        },
+        "Invoice": object(json!({
+            "amount": { "type": "integer", "description": "In satoshis." },
+            "bolt11": string(),
+            "payment_hash": schema("PaymentHash"),
+        })),
+        "Receipt": {
+            "type": "object",
+            "description": "Signed with BIP340 over its canonical serialization, see \
+                `POST /verify_receipt`.",
+            "required": ["action", "expires_at", "issued_at", "kid", "locker_id", "payment_hash"],
+            "properties": {
+                "action": schema("Action"),
+                "actor": {
+                    "type": "string",
+                    "description": "Who had the receipt issued when it wasn't the customer, left \
+                        out otherwise.",
+                },
+                "expires_at": timestamp(),
+                "issued_at": timestamp(),
+                "kid": { "type": "string", "description": "The key that signed it, see /keys." },
+                "locker_id": integer(),
+                "payment_hash": nullable("PaymentHash"),
+            },
+        },
+        "SignedReceipt": object(json!({
+            "receipt": schema("Receipt"),
+            "signature": { "type": "string", "description": "BIP340, in hex." },
+        })),
+        "Authorization": object(json!({
+            "locker_id": integer(),
+            "session_id": integer(),
+            "action": schema("Action"),
+            "start_time": timestamp(),
+            "expires_at": timestamp(),
+            "signature": string(),
+            "expiring_signature": {
+                "type": "string",
+                "nullable": true,
+                "description": "Always null, `signature` covers the expiry.",
+            },
+            "kid": string(),
+            "token": string(),
+            "signed_receipt": schema("SignedReceipt"),
+        })),
+        "PaymentReceipt": object(json!({
+            "locker_id": integer(),
+            "site_id": optional_integer(),
+            "session_id": integer(),
+            "action": schema("Action"),
+            "start_time": timestamp(),
+            "issued_at": timestamp(),
+            "expires_at": optional_timestamp(),
+            "signature": string(),
+            "expiring_signature": optional_string(),
+            "kid": optional_string(),
+            "token": optional_string(),
+            "signed_receipt": nullable("SignedReceipt"),
+        })),
+        "RedeemedPayment": object(json!({
+            "locker_id": integer(),
+            "amount": integer(),
+            "status": { "type": "string", "enum": ["redeemed"] },
+            "paid_at": optional_timestamp(),
+            "redeemed_at": optional_timestamp(),
+        })),
+        "Payment": object(json!({
+            "amount": { "type": "integer", "description": "In satoshis." },
+            "payment_hash": schema("PaymentHash"),
+            "status": {
+                "type": "string",
+                "enum": ["pending", "paid", "expired", "redeemed", "cancelled"],
+            },
+            "locker_id": integer(),
+            "created_at": optional_timestamp(),
+            "paid_at": optional_timestamp(),
+            "expired_at": optional_timestamp(),
+            "redeemed_at": optional_timestamp(),
+            "bolt11": optional_string(),
+            "cancelled_at": optional_timestamp(),
+            "needs_refund": boolean(),
+            "operator_id": {
+                "type": "integer",
+                "format": "int64",
+                "nullable": true,
+                "description": "The operator the payment is settled with.",
+            },
+        })),
+        "Session": object(json!({
+            "id": integer(),
+            "locker_id": integer(),
+            "started_at": timestamp(),
+            "ended_at": optional_timestamp(),
+            "amount_sat": optional_integer(),
+            "payment_hash": nullable("PaymentHash"),
+            "state": { "type": "string", "enum": ["active", "closed", "cancelled"] },
+            "nostr_pubkey": optional_string(),
+            "usage_ended_at": optional_timestamp(),
+        })),
+        "SessionHistory": object(json!({
+            "session_id": integer(),
+            "started_at": timestamp(),
+            "ended_at": optional_timestamp(),
+            "duration_seconds": integer(),
+            "amount_paid_sat": optional_integer(),
+            "payment_hash": nullable("PaymentHash"),
+            "outcome": {
+                "type": "string",
+                "enum": ["in_progress", "paid", "cancelled", "released"],
+            },
+            "needs_follow_up": boolean(),
+        })),
+        "LockerEvent": object(json!({
+            "id": integer(),
+            "locker_id": integer(),
+            "event": string(),
+            "actor": string(),
+            "timestamp": timestamp(),
+            "details": { "type": "object" },
+        })),
+        "AdminCall": object(json!({
+            "id": integer(),
+            "token_id": string(),
+            "role": optional_string(),
+            "method": string(),
+            "path": string(),
+            "route": optional_string(),
+            "summary": optional_string(),
+            "status": integer(),
+            "timestamp": timestamp(),
+            "operator_id": optional_integer(),
+        })),
+        "Operator": object(json!({
+            "id": integer(),
+            "name": string(),
+            "created_at": timestamp(),
+        })),
+        "Key": object(json!({
+            "kid": string(),
+            "pubkey": string(),
+            "status": { "type": "string", "enum": ["active", "valid", "pending", "retired"] },
+            "not_before": optional_timestamp(),
+            "not_after": optional_timestamp(),
+        })),
+        "Revocation": object(json!({
+            "payment_hash": schema("PaymentHash"),
+            "locker_id": integer(),
+            "revoked_at": timestamp(),
+            "expires_at": optional_timestamp(),
+        })),
+        "ProvisioningCode": object(json!({
+            "id": integer(),
+            "label": optional_string(),
+            "created_at": timestamp(),
+            "expires_at": timestamp(),
+        })),
+        "Webhook": object(json!({
+            "id": integer(),
+            "url": string(),
+            "events": {
+                "type": "array",
+                "nullable": true,
+                "description": "The events it gets, every one if `null`.",
+                "items": schema("WebhookEvent"),
+            },
+            "created_at": timestamp(),
+            "pending_deliveries": integer(),
+            "dead_deliveries": integer(),
+        })),
+        "WebhookEvent": { "type": "string", "enum": webhook::EVENTS },
+        "WebhookDelivery": object(json!({
+            "id": integer(),
+            "webhook_id": integer(),
+            "event_id": string(),
+            "event": schema("WebhookEvent"),
+            "status": { "type": "string", "enum": ["pending", "delivered", "dead"] },
+            "attempts": integer(),
+            "last_status": optional_integer(),
+            "last_error": optional_string(),
+            "next_attempt_at": optional_timestamp(),
+            "created_at": timestamp(),
+            "delivered_at": optional_timestamp(),
+        })),
+        "Version": object(json!({
+            "version": string(),
+            "git_commit": string(),
+            "built_at": timestamp(),
+            "ln_backend": string(),
+            "api_version": integer(),
+        })),
+        "Health": object(json!({
+            "status": { "type": "string", "enum": ["ok", "degraded", "failing"] },
+            "failing": list(string()),
+            "version": string(),
+            "checks": { "type": "object" },
+            "last_maintenance": optional_timestamp(),
+        })),
+        "LockerMetadata": {
+            "type": "object",
+            "description": "Fields that are left out are kept as they are.",
+            "properties": {
+                "name": string(),
+                "location": string(),
+                "size": schema("LockerSize"),
+                "description": string(),
+                "site_id": integer(),
+            },
+        },
+        "SiteMetadata": {
+            "type": "object",
+            "description": "Fields that are left out are kept as they are.",
+            "properties": {
+                "name": string(),
+                "address": string(),
+                "timezone": string(),
+                "price_per_minute_msat": integer(),
+            },
+        },
+    })
+}
+
+/// The public routes, see [crate::Server::run].
+fn public_operations() -> Vec<Operation> {
+    let qr_code = |format: &str, content_type: &str| {
+        Operation::new(
+            "get",
+            &format!("/payments/{{payment_hash}}/qr.{format}"),
+            "payments",
+            &format!("Get the invoice for a payment as a QR code, in {format}"),
+        )
+        .describe(
+            "The invoice in uppercase, with error correction level M. Cached until the invoice \
+             expires.",
+        )
+        .payment_hash()
+        .query(
+            "size",
+            json!({ "type": "integer", "default": 300, "minimum": 64, "maximum": 2048 }),
+            "How many pixels across the image may be, quiet zone included.",
+        )
+        .error(400, "The size is out of range.")
+        .error(404, "We didn't keep the invoice.")
+        .error(410, "The invoice was settled or expired.")
+        .respond(200, "The QR code.", content_type, string())
+        .rate_limited()
+    };
+
+    vec![
+        Operation::new("get", "/lockers", "lockers", "List the lockers")
+            .describe("Lockers waiting to be approved and decommissioned ones are never listed.")
+            .query("site_id", integer(), "Only the lockers at this site.")
+            .query(
+                "state",
+                json!({
+                    "type": "string",
+                    "enum": [
+                        "available",
+                        "reserved",
+                        "in_use",
+                        "awaiting_payment",
+                        "maintenance",
+                    ],
+                }),
+                "Only the lockers in this state.",
+            )
+            .query(
+                "size",
+                schema("LockerSize"),
+                "Only the lockers of this size.",
+            )
+            .query(
+                "location",
+                string(),
+                "Only the lockers at this location, in any case.",
+            )
+            .page(100, MAX_LOCKERS_PER_PAGE)
+            .query(
+                "after_id",
+                integer(),
+                "Only lockers with a greater id, to fetch the page after the one ending with it.",
+            )
+            .error(400, "`state` or `size` isn't one of the known ones.")
+            .respond(
+                200,
+                "A page of lockers, ordered by id.",
+                "application/json",
+                json!({
+                    "allOf": [
+                        envelope(list(schema("Locker"))),
+                        object(json!({ "total": integer() })),
+                    ],
+                }),
+            )
+            .respond(
+                304,
+                "No locker changed since `If-None-Match`.",
+                "text/plain",
+                string(),
+            )
+            .rate_limited(),
+        Operation::new("get", "/lockers/{locker_id}", "lockers", "Get a locker")
+            .locker_id()
+            .error(404, "The locker was decommissioned.")
+            .ok("The locker.", schema("Locker"))
+            .respond(
+                304,
+                "No locker changed since `If-None-Match`.",
+                "text/plain",
+                string(),
+            )
+            .rate_limited(),
+        Operation::new(
+            "get",
+            "/lockers/{locker_id}/meter",
+            "lockers",
+            "Get what a locker in use costs so far",
+        )
+        .describe(
+            "Without invoicing anything, so it can be polled for a running meter. Sessions are \
+             billed at the rate they started with, whatever the locker's price is now.",
+        )
+        .locker_id()
+        .error(404, "The locker was decommissioned.")
+        .error(409, "Nobody is using the locker, or they already ended their usage.")
+        .ok(
+            "What the session costs so far.",
+            object(json!({
+                "locker_id": integer(),
+                "session_id": integer(),
+                "started_at": timestamp(),
+                "elapsed_seconds": integer(),
+                "price_per_minute_msat": integer(),
+                "amount_sat": integer(),
+                "breakdown": list(object(json!({
+                    "kind": { "type": "string", "enum": ["usage"] },
+                    "seconds": integer(),
+                    "price_per_minute_msat": integer(),
+                    "amount_sat": integer(),
+                }))),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/ws/lockers",
+            "lockers",
+            "Follow the lockers' state",
+        )
+        .describe(
+            "Upgrades to a WebSocket, which first gets a `snapshot` message with the state of \
+             every locker in service, then a `state_changed` message each time a locker's \
+             state changes, `decommissioned` once it's out of service. Subscribers that fall \
+             too far behind are disconnected, and should connect again.",
+        )
+        .respond(
+            101,
+            "Switching to the WebSocket, whose messages look like this.",
+            "application/json",
+            json!({
+                "oneOf": [
+                    object(json!({
+                        "type": { "type": "string", "enum": ["snapshot"] },
+                        "lockers": list(object(json!({
+                            "locker_id": integer(),
+                            "state": string(),
+                        }))),
+                        "ts": timestamp(),
+                    })),
+                    object(json!({
+                        "type": { "type": "string", "enum": ["state_changed"] },
+                        "locker_id": integer(),
+                        "old_state": optional_string(),
+                        "new_state": string(),
+                        "ts": timestamp(),
+                    })),
+                ],
+            }),
+        )
+        .error(400, "The request isn't a WebSocket upgrade.")
+        .rate_limited(),
+        Operation::new(
+            "post",
+            "/use_locker/{locker_id}",
+            "lockers",
+            "Claim a locker",
+        )
+        .describe("Returns what opens the locker to store something in it.")
+        .locker_id()
+        .optional_customer()
+        .reservation_token()
+        .idempotent()
+        .error(
+            409,
+            "The locker isn't available, is reserved for someone else, was decommissioned or is \
+             under maintenance.",
+        )
+        .ok(
+            "The authorization to open the locker.",
+            schema("Authorization"),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/use_locker/{locker_id}",
+            "lockers",
+            "Claim a locker",
+        )
+        .describe("Only while legacy GET mutations are enabled, use POST instead.")
+        .deprecated()
+        .locker_id()
+        .optional_customer()
+        .reservation_token()
+        .idempotent()
+        .error(405, "Legacy GET mutations are disabled.")
+        .error(
+            409,
+            "The locker isn't available, is reserved for someone else, was decommissioned or is \
+             under maintenance.",
+        )
+        .ok(
+            "The authorization to open the locker.",
+            schema("Authorization"),
+        )
+        .rate_limited(),
+        Operation::new(
+            "post",
+            "/reserve/{locker_id}",
+            "lockers",
+            "Reserve a locker",
+        )
+        .describe(
+            "Holds the locker for whoever has the returned token, which `/use_locker` needs \
+             until the reservation expires. A locker has at most one reservation at a time.",
+        )
+        .locker_id()
+        .error(
+            409,
+            "The locker isn't available, is already reserved, was decommissioned or is under \
+             maintenance.",
+        )
+        .ok(
+            "The reservation, with its token, only returned this once.",
+            object(json!({
+                "locker_id": integer(),
+                "reservation_token": string(),
+                "expires_at": timestamp(),
+            })),
+        )
+        .rate_limited(),
+        pay_for_usage("post"),
+        pay_for_usage("get")
+            .describe("Only while legacy GET mutations are enabled, use POST instead.")
+            .deprecated()
+            .error(405, "Legacy GET mutations are disabled."),
+        Operation::new(
+            "post",
+            "/pay_for_usage_batch",
+            "payments",
+            "Get one invoice for several lockers",
+        )
+        .describe(
+            "Invoices the sum of what each locker costs, as `/pay_for_usage` would. The lockers \
+             must all have been claimed by the logged in customer. Once paid, the receipts are \
+             under `/payments/{payment_hash}/receipts`.",
+        )
+        .logged_in()
+        .idempotent()
+        .body(object(json!({
+            "locker_ids": {
+                "type": "array",
+                "items": integer(),
+                "minItems": 1,
+                "maxItems": batch::MAX_LOCKERS,
+                "uniqueItems": true,
+            },
+        })))
+        .error(400, "The list is empty, too long, or lists a locker twice.")
+        .error(404, "A locker doesn't exist or has no active session.")
+        .error(
+            409,
+            "Nobody is using a locker, someone else is, or the invoice couldn't be recorded.",
+        )
+        .error(502, "The lightning backend couldn't make the invoice.")
+        .ok(
+            "The invoice for every session, and what each costs.",
+            object(json!({
+                "lockers": list(object(json!({
+                    "locker_id": integer(),
+                    "session_id": integer(),
+                    "lease_time": { "type": "integer", "description": "In seconds." },
+                    "amount": { "type": "integer", "description": "In satoshis." },
+                }))),
+                "amount": { "type": "integer", "description": "In satoshis." },
+                "invoice": schema("Invoice"),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "post",
+            "/end_usage/{locker_id}",
+            "lockers",
+            "Stop being billed",
+        )
+        .describe("Fixes what the customer owes now. Ending it again returns the same amount.")
+        .locker_id()
+        .error(
+            409,
+            "Nobody is using the locker, or someone else ended the usage at the same time.",
+        )
+        .ok(
+            "What the customer owes.",
+            object(json!({
+                "locker_id": integer(),
+                "session_id": integer(),
+                "state": { "type": "string", "enum": ["awaiting_payment"] },
+                "lease_time": { "type": "integer", "description": "In seconds." },
+                "amount": { "type": "integer", "description": "In satoshis." },
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "post",
+            "/cancel_usage/{locker_id}",
+            "lockers",
+            "Back out of a locker",
+        )
+        .describe("Frees the locker if it was never opened, otherwise invoices the cancel fee.")
+        .locker_id()
+        .body(object(json!({
+            "token": {
+                "type": "string",
+                "description": "The token `/use_locker` handed out.",
+            },
+        })))
+        .error(
+            401,
+            "The token isn't the one for the locker's current session.",
+        )
+        .error(404, "Nobody is using the locker.")
+        .error(409, "The session was already paid for, or ended meanwhile.")
+        .ok(
+            "Whether the locker was freed, or what backing out costs.",
+            object(json!({
+                "locker_id": integer(),
+                "session_id": integer(),
+                "released": boolean(),
+                "amount": { "type": "integer", "description": "In satoshis." },
+                "invoice": nullable("Invoice"),
+            })),
+        )
+        .rate_limited(),
+        payment_receipt("/payments/{payment_hash}/receipt"),
+        payment_receipt("/payment_receipt/{payment_hash}")
+            .describe(
+                "Only while the legacy receipt path is enabled, use \
+                 `/payments/{payment_hash}/receipt` instead.",
+            )
+            .deprecated(),
+        Operation::new(
+            "get",
+            "/payments/{payment_hash}/receipts",
+            "payments",
+            "Get the receipts for a payment",
+        )
+        .describe(
+            "Like `/payments/{payment_hash}/receipt`, with one receipt for each locker the \
+             payment covers, for payments from `/pay_for_usage_batch`. Receipts already used or \
+             revoked are left out.",
+        )
+        .payment_hash()
+        .ok("The receipts, by locker.", list(schema("PaymentReceipt")))
+        .error(502, "The lightning backend couldn't tell whether the payment settled.")
+        .error(402, "The payment isn't settled yet, see `Retry-After`.")
+        .error(
+            410,
+            "The payment was cancelled, its invoice expired, or all its receipts were revoked or \
+             already used.",
+        )
+        .error(429, "The payment is polled too often, see `Retry-After`.")
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/payments/{payment_hash}",
+            "payments",
+            "Get how far along a payment is",
+        )
+        .describe(
+            "Checks pending payments with the lightning backend, without issuing their receipt. \
+             Payments stay `pending` if it can't be reached.",
+        )
+        .payment_hash()
+        .error(404, "Unknown payment.")
+        .ok(
+            "The payment.",
+            object(json!({
+                "payment_hash": schema("PaymentHash"),
+                "status": {
+                    "type": "string",
+                    "enum": ["pending", "paid", "expired", "redeemed", "cancelled"],
+                },
+                "amount": { "type": "integer", "description": "In satoshis." },
+                "locker_id": integer(),
+                "created_at": optional_timestamp(),
+                "settled_at": optional_timestamp(),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/payments/{payment_hash}/invoice",
+            "payments",
+            "Get the invoice for a payment again",
+        )
+        .payment_hash()
+        .error(404, "We didn't keep the invoice.")
+        .error(409, "The payment isn't pending anymore.")
+        .error(410, "The invoice expired.")
+        .ok(
+            "The invoice.",
+            object(json!({
+                "payment_hash": schema("PaymentHash"),
+                "locker_id": integer(),
+                "amount": integer(),
+                "bolt11": string(),
+                "expires_at": optional_timestamp(),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/lockers/{locker_id}/invoice",
+            "payments",
+            "Get the invoice a locker is waiting on",
+        )
+        .describe("For customers whose app lost the invoice from `/pay_for_usage`.")
+        .locker_id()
+        .error(404, "The locker isn't waiting on an invoice that can still be paid.")
+        .ok(
+            "The invoice.",
+            object(json!({
+                "payment_hash": schema("PaymentHash"),
+                "locker_id": integer(),
+                "amount": integer(),
+                "bolt11": string(),
+                "expires_at": timestamp(),
+            })),
+        )
+        .rate_limited(),
+        qr_code("png", "image/png"),
+        qr_code("svg", "image/svg+xml"),
+        Operation::new(
+            "get",
+            "/payments/{payment_hash}/events",
+            "payments",
+            "Follow a payment until it ends",
+        )
+        .describe(
+            "A stream of server-sent events, so clients don't have to poll \
+             `/payments/{payment_hash}/receipt`. A pending payment first gets a `pending` \
+             event, then every payment ends its stream with `paid`, carrying the receipt, or \
+             `null` if it was revoked or already used, `expired` or `cancelled`. Streams of \
+             payments still pending once their invoice expired end with `expired`, and comments \
+             are sent meanwhile to keep the connection open.
+        )
+        .payment_hash()
+        .respond(
+            200,
+            "The stream, whose events carry data like this.",
+            "text/event-stream",
+            json!({
+                "oneOf": [
+                    object(json!({
+                        "payment_hash": schema("PaymentHash"),
+                        "locker_id": integer(),
+                        "amount": integer(),
+                        "expires_at": timestamp(),
+                    })),
+                    nullable("PaymentReceipt"),
+                    object(json!({})),
+                ],
+            }),
+        )
+        .rate_limited(),
+        Operation::new(
+            "post",
+            "/provision/register",
+            "devices",
+            "Register a new locker",
+        )
+        .describe("The locker can't be used until an admin approves it.")
+        .valid_body(json!({
+            "type": "object",
+            "required": ["pk"],
+            "properties": {
+                "pk": { "type": "string", "description": "An x-only public key, in hex." },
+                "signature": {
+                    "type": "string",
+                    "description": "The provisioning key's signature over `pk`.",
+                },
+                "code": { "type": "string", "description": "A code staff handed out." },
+                "name": string(),
+                "location": string(),
+                "size": schema("LockerSize"),
+                "description": string(),
+            },
+        }))
+        .error(
+            400,
+            "There's neither a signature nor a code, or the signature is wrong.",
+        )
+        .error(
+            404,
+            "There's no provisioning key to check the signature with.",
+        )
+        .error(
+            409,
+            "The key is already registered, or the code was already used.",
+        )
+        .error(410, "The code expired or was revoked.")
+        .ok(
+            "The new locker's id, and the device token it must keep.",
+            object(json!({
+                "locker_id": integer(),
+                "state": { "type": "string", "enum": ["provisioning"] },
+                "device_token": string(),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            "/auth/lnurl",
+            "customers",
+            "Start logging in with a wallet",
+        )
+        .error(404, "The server doesn't know its public URL.")
+        .ok(
+            "The challenge to sign, and the token it logs in.",
+            object(json!({
+                "k1": string(),
+                "lnurl": string(),
+                "callback": string(),
+                "token": string(),
+                "expires_at": timestamp(),
+            })),
+        )
+        .rate_limited(),
+        Operation::new(
+            "get",
+            lnurl::CALLBACK_PATH,
+            "customers",
+            "Log in with a wallet",
+        )
+        .describe("Answers the way LUD-04 says, since wallets are the ones reading it.")
+        .query(
+            "tag",
+            json!({ "type": "string", "enum": ["login"] }),
+            "Must be `login` if set.",
+        )
+        .query("k1", string(), "The challenge.")
+        .query(
+            "sig",
+            string(),
+            "A DER encoded ECDSA signature over `k1`, in hex.",
+        )
+        .query("key", string(), "The wallet's linking key, in hex.")
+        .respond(
+            200,
+            "The wallet is logged in.",
+            "application/json",
+            object(json!({ "status": { "type": "string", "enum": ["OK"] } })),
+        )
+        .respond(
+            400,
+            "The signature doesn't check out, or the challenge is unknown.",
+            "application/json",
+            object(json!({
+                "status": { "type": "string", "enum": ["ERROR"] },
+                "reason": string(),
+            })),
+        )
+        .rate_limited(),
+        Operation::new("get", "/me", "customers", "Who is logged in")
+            .customer()
+            .ok(
+                "The wallet's linking key, and when the login expires.",
+                object(json!({ "linking_key": string(), "expires_at": timestamp() })),
+            )
+            .rate_limited(),
+        Operation::new("get", "/me/sessions", "customers", "List my sessions")
+            .customer()
+            .page(50, 100)
+            .ok(
+                "The customer's sessions, newest first.",
+                page("sessions", schema("Session")),
+            )
+            .rate_limited(),
+        Operation::new("get", "/me/payments", "customers", "List my payments")
+            .customer()
+            .page(50, 100)
+            .ok(
+                "The customer's payments, newest first.",
+                page("payments", schema("Payment")),
+            )
+            .rate_limited(),
+        Operation::new(
+            "post",
+            "/update_locker_open",
+            "devices",
+            "Report a locker was opened",
+        )
+        .device()
+        .body(json!({
+            "type": "object",
+            "required": ["locker_id", "signature", "timestamp"],
+            "properties": {
+                "locker_id": integer(),
+                "signature": {
+                    "type": "string",
+                    "description": "The locker's signature or HMAC tag, in hex.",
+                },
+                "timestamp": timestamp(),
+                "expires_at": timestamp(),
+                "action": schema("Action"),
+                "authorized_at": timestamp(),
+            },
+        }))
+        .error(400, "The signature doesn't check out.")
+        .error(404, "There's no such locker.")
+        .error(
+            409,
+            "The report was replayed, or doesn't follow the previous one.",
+        )
+        .error(
+            422,
+            "The locker's clock is too far off ours, see `X-Server-Time`, the authorization had \
+             already expired, or `action` and `expires_at` are missing.",
+        )
+        .ok(
+            "The report was recorded, with the payment whose receipt it used up, if any.",
+            object(json!({
+                "locker_id": integer(),
+                "action": nullable("Action"),
+                "timestamp": timestamp(),
+                "redeemed": nullable("PaymentHash"),
+            })),
+        )
+        .rate_limited(),
+        Operation::new("post", "/verify_token", "devices", "Check a token")
+            .describe("A token that passes is used up.")
+            .device()
+            .body(json!({
+                "type": "object",
+                "required": ["locker_id", "token"],
+                "properties": {
+                    "locker_id": integer(),
+                    "token": string(),
+                    "action": schema("Action"),
+                },
+            }))
+            .error(
+                429,
+                "The locker checks tokens too often, see `Retry-After`.",
+            )
+            .ok(
+                "Whether the token passes.",
+                object(json!({
+                    "valid": boolean(),
+                    "reason": {
+                        "type": "string",
+                        "nullable": true,
+                        "enum": [
+                            "malformed",
+                            "bad_signature",
+                            "expired",
+                            "wrong_locker",
+                            "wrong_action",
+                            "revoked",
+                            "wrong_session",
+                            "used",
+                            null,
+                        ],
+                    },
+                    "action": nullable("Action"),
+                })),
+            )
+            .rate_limited(),
+        Operation::new(
+            "post",
+            "/verify_receipt",
+            "devices",
+            "Check a signed receipt",
+        )
+        .describe("Never changes anything, unlike `/verify_token`.")
+        .valid_body(schema("SignedReceipt"))
+        .ok(
+            "Whether the receipt passes.",
+            object(json!({
+                "valid": boolean(),
+                "reason": {
+                    "type": "string",
+                    "nullable": true,
+                    "enum": ["bad_signature", "expired", "revoked", "used", null],
+                },
+                "receipt": schema("Receipt"),
+                "expired": {
+                    "type": "boolean",
+                    "nullable": true,
+                    "description": "Only set for receipts we signed, and so are `revoked` and \
+                        `consumed`.",
+                },
+                "revoked": { "type": "boolean", "nullable": true },
+                "consumed": { "type": "boolean", "nullable": true },
+            })),
+        )
+        .rate_limited(),
+        Operation::new("get", "/revocations", "devices", "List revoked receipts")
+            .query(
+                "since",
+                timestamp(),
+                "Only receipts revoked at or after this.",
+            )
+            .query("locker_id", integer(), "Only receipts for this locker.")
+            .ok(
+                "The revoked receipts that haven't expired yet, oldest first.",
+                object(json!({
+                    "revocations": list(schema("Revocation")),
+                    "server_time": timestamp(),
+                })),
+            )
+            .rate_limited(),
+        Operation::new("get", "/keys", "server", "List the server keys")
+            .without_store()
+            .ok("Every key with its status.", list(schema("Key")))
+            .respond(
+                304,
+                "The keys didn't change since `If-None-Match`.",
+                "text/plain",
+                string(),
+            )
+            .rate_limited(),
+        Operation::new("get", "/pubkey", "server", "Get the key we sign with")
+            .without_store()
+            .ok(
+                "The active key, and every other key.",
+                object(json!({
+                    "kid": string(),
+                    "pubkey": string(),
+                    "keys": list(schema("Key")),
+                })),
+            )
+            .respond(
+                304,
+                "The keys didn't change since `If-None-Match`.",
+                "text/plain",
+                string(),
+            )
+            .rate_limited(),
+        Operation::new(
+            "get",
+            "/.well-known/locker-server.json",
+            "server",
+            "Describe the server to devices",
+        )
+        .without_store()
+        .respond(
+            200,
+            "The server document, not wrapped in `data`.",
+            "application/json",
+            object(json!({
+                "api_version": integer(),
+                "api_prefix": string(),
+                "server_version": string(),
+                "kid": string(),
+                "pubkey": string(),
+                "keys_url": string(),
+                "revocations_url": string(),
+                "token_ttl_seconds": integer(),
+                "pricing": { "type": "object" },
+            })),
+        )
+        .respond(
+            304,
+            "The document didn't change since `If-None-Match`.",
+            "text/plain",
+            string(),
+        )
+        .rate_limited(),
+        Operation::new("get", "/health", "server", "Check the server is healthy")
+            .describe("Answers the database being busy like any other failing check.")
+            .without_store()
+            .ok(
+                "Every check passes, or only the lightning backend is down.",
+                schema("Health"),
+            )
+            .respond(
+                503,
+                "Some check is failing, named in the `message` of `error`.",
+                "application/json",
+                json!({
+                    "type": "object",
+                    "required": ["data", "error"],
+                    "properties": { "data": schema("Health"), "error": schema("ErrorDetail") },
+                }),
+            )
+            .rate_limited(),
+        Operation::new("get", "/version", "server", "Tell what's deployed")
+            .without_store()
+            .ok("The running build.", schema("Version"))
+            .rate_limited(),
+        Operation::new("get", "/openapi.json", "server", "Describe the API")
+            .without_store()
+            .respond(
+                200,
+                "This document, not wrapped in `data`.",
+                "application/json",
+                json!({ "type": "object" }),
+            )
+            .respond(
+                304,
+                "The document didn't change since `If-None-Match`.",
+                "text/plain",
+                string(),
+            )
+            .rate_limited(),
+    ]
+}
+
+/// The receipt of a payment, under its canonical path and its deprecated alias.
+fn payment_receipt(path: &str) -> Operation {
+    Operation::new(
+        "get",
+        path,
+        "payments",
+        "Get the receipt for a payment",
+    )
+    .describe(
+        "Clients poll this until the payment settles. A payment buys exactly one receipt, \
+         which is handed out again until the locker is opened with it.",
+    )
+    .payment_hash()
+    .ok("The receipt.", schema("PaymentReceipt"))
+    .error(
+        409,
+        "The payment covers several lockers, its receipts are under \
+         `/payments/{payment_hash}/receipts`.",
+    )
+    .error(502, "The lightning backend couldn't tell whether the payment settled.")
+    .error(402, "The payment isn't settled yet, see `Retry-After`.")
+    .respond(
+        410,
+        "The payment was cancelled, its invoice expired, or its receipt was revoked or already \
+         used. A payment redeemed before we kept receipts comes with its settlement info.",
+        "application/json",
+        json!({
+            "type": "object",
+            "required": ["data", "error"],
+            "properties": {
+                "data": nullable("RedeemedPayment"),
+                "error": schema("ErrorDetail"),
+            },
+        }),
+    )
+    .error(429, "The payment is polled too often, see `Retry-After`.")
+    .rate_limited()
+}
+
+/// `/pay_for_usage`, which takes both methods.
+fn pay_for_usage(method: &'static str) -> Operation {
+    Operation::new(
+        method,
+        "/pay_for_usage/{locker_id}",
+        "payments",
+        "Get an invoice",
+    )
+    .describe("Invoices what the session came to, or what it costs until now.")
+    .locker_id()
+    .optional_customer()
+    .idempotent()
+    .error(404, "The locker has no active session.")
+    .error(
+        409,
+        "Nobody is using the locker, the session belongs to another nostr user, or the invoice \
+         couldn't be recorded.",
+    )
+    .error(502, "The lightning backend couldn't make the invoice.")
+    .ok(
+        "The invoice for the session.",
+        object(json!({
+            "locker_id": integer(),
+            "lease_time": { "type": "integer", "description": "In seconds." },
+            "amount": { "type": "integer", "description": "In satoshis." },
+            "invoice": schema("Invoice"),
+        })),
+    )
+    .rate_limited()
+}
+
+/// The routes under `/admin`, see [crate::admin::router].
+fn admin_operations() -> Vec<Operation> {
+    let operator = Some("operator");
+    let owner = Some("owner");
+    let locker = |method: &'static str, path: &str, summary: &str| {
+        Operation::new(
+            method,
+            &format!("/admin/lockers/{{locker_id}}{path}"),
+            "admin",
+            summary,
+        )
+        .locker_id()
+        .error(404, "The locker is another operator's.")
+    };
+    let page_of_events = |operation: Operation| {
+        operation
+            .query(
+                "limit",
+                json!({ "type": "integer", "default": 50 }),
+                "At most 500.",
+            )
+            .query(
+                "before",
+                integer(),
+                "Only entries with a lower id, for the next page.",
+            )
+    };
+    let period = |operation: Operation, what: &str| {
+        operation
+            .query(
+                "from",
+                timestamp(),
+                &format!("Only {what} at or after this."),
+            )
+            .query("to", timestamp(), &format!("Only {what} before this."))
+    };
+
+    vec![
+        Operation::new("get", "/admin/lockers", "admin", "List every locker")
+            .admin(None)
+            .ok("Every locker, with its key.", list(schema("AdminLocker"))),
+        Operation::new("post", "/admin/lockers", "admin", "Commission a locker")
+            .admin(operator)
+            .valid_body(json!({
+                "type": "object",
+                "required": ["pk"],
+                "properties": {
+                    "pk": { "type": "string", "description": "An x-only public key, in hex." },
+                    "name": string(),
+                    "location": string(),
+                    "size": schema("LockerSize"),
+                    "description": string(),
+                    "price_per_minute_msat": integer(),
+                    "operator_id": integer(),
+                },
+            }))
+            .for_operator()
+            .error(409, "The key is already used by another locker.")
+            .ok("The new locker.", schema("Locker")),
+        locker("patch", "", "Update a locker")
+            .admin(operator)
+            .body(schema("LockerMetadata"))
+            .error(404, "There's no such site, or it isn't the locker's operator's.")
+            .error(409, "The update conflicts with another locker or site.")
+            .ok("The updated locker.", schema("Locker")),
+        locker("delete", "", "Decommission an idle locker")
+            .admin(operator)
+            .query(
+                "force",
+                json!({ "type": "boolean", "default": false }),
+                "Take the locker out even if someone is using it.",
+            )
+            .error(
+                409,
+                "Someone is using the locker or its payments aren't settled.",
+            )
+            .ok("The decommissioned locker.", schema("Locker")),
+        page_of_events(locker("get", "/events", "List a locker's events"))
+            .admin(None)
+            .ok("The events, newest first.", list(schema("LockerEvent"))),
+        period(
+            locker("get", "/history", "List a locker's sessions"),
+            "sessions started",
+        )
+        .admin(None)
+        .page(50, 500)
+        .ok(
+            "The sessions, newest first.",
+            page("sessions", schema("SessionHistory")),
+        ),
+        locker("post", "/decommission", "Decommission a locker")
+            .admin(operator)
+            .ok("The decommissioned locker.", schema("Locker")),
+        locker("post", "/reactivate", "Reactivate a locker")
+            .admin(operator)
+            .ok("The reactivated locker.", schema("Locker")),
+        locker("post", "/maintenance", "Put a locker under maintenance")
+            .admin(operator)
+            .error(
+                409,
+                "Someone is using the locker, or it's waiting to be approved.",
+            )
+            .ok("The locker under maintenance.", schema("Locker")),
+        locker("delete", "/maintenance", "End a locker's maintenance")
+            .admin(operator)
+            .error(409, "The locker is waiting to be approved.")
+            .ok("The available locker.", schema("Locker")),
+        locker("post", "/force_open", "Open a locker for a customer")
+            .admin(operator)
+            .valid_body(json!({
+                "type": "object",
+                "required": ["reason"],
+                "properties": { "reason": string(), "action": schema("Action") },
+            }))
+            .ok(
+                "The authorization to open the locker, whose receipt says `actor: admin`.",
+                object(json!({
+                    "locker_id": integer(),
+                    "action": schema("Action"),
+                    "start_time": timestamp(),
+                    "expires_at": timestamp(),
+                    "signature": string(),
+                    "expiring_signature": optional_string(),
+                    "kid": string(),
+                    "signed_receipt": schema("SignedReceipt"),
+                })),
+            ),
+        locker("post", "/force_release", "Make a locker in use available")
+            .admin(operator)
+            .valid_body(json!({
+                "type": "object",
+                "required": ["reason"],
+                "properties": {
+                    "reason": string(),
+                    "cancel_invoice": { "type": "boolean", "default": false },
+                },
+            }))
+            .error(409, "Nobody is using the locker.")
+            .ok("The released locker.", schema("Locker")),
+        locker("post", "/approve", "Approve a registered locker")
+            .admin(operator)
+            .error(409, "The locker isn't waiting to be approved.")
+            .ok("The approved locker.", schema("Locker")),
+        locker("put", "/price", "Set a locker's price")
+            .admin(operator)
+            .body(object(
+                json!({ "price_per_minute_msat": optional_integer() }),
+            ))
+            .ok("The updated locker.", schema("Locker")),
+        locker("post", "/rotate_key", "Replace a locker's key")
+            .admin(owner)
+            .valid_body(json!({
+                "type": "object",
+                "required": ["pk"],
+                "properties": {
+                    "pk": string(),
+                    "signature": {
+                        "type": "string",
+                        "description": "The old key's signature, if it can still sign.",
+                    },
+                },
+            }))
+            .error(400, "The old key's signature doesn't check out.")
+            .error(409, "The key is already used by another locker.")
+            .ok("The updated locker.", schema("Locker")),
+        locker("post", "/device_token", "Issue a locker a device token")
+            .admin(owner)
+            .ok(
+                "The token, only returned this once.",
+                object(json!({ "locker_id": integer(), "device_token": string() })),
+            ),
+        locker("delete", "/device_token", "Revoke a locker's device tokens")
+            .admin(owner)
+            .ok(
+                "How many tokens were revoked.",
+                object(json!({ "locker_id": integer(), "revoked": integer() })),
+            ),
+        Operation::new(
+            "get",
+            "/admin/provisioning_codes",
+            "admin",
+            "List provisioning codes",
+        )
+        .admin(None)
+        .ok(
+            "The codes that can still be used.",
+            list(schema("ProvisioningCode")),
+        ),
+        Operation::new(
+            "post",
+            "/admin/provisioning_codes",
+            "admin",
+            "Make a provisioning code",
+        )
+        .admin(owner)
+        .optional_body(json!({
+            "type": "object",
+            "properties": {
+                "label": string(),
+                "ttl_seconds": integer(),
+                "operator_id": integer(),
+            },
+        }))
+        .error(400, "`ttl_seconds` is zero.")
+        .for_operator()
+        .ok(
+            "The code, only returned this once.",
+            object(json!({
+                "id": integer(),
+                "code": string(),
+                "label": optional_string(),
+                "created_at": timestamp(),
+                "expires_at": timestamp(),
+                "operator_id": optional_integer(),
+            })),
+        ),
+        Operation::new(
+            "delete",
+            "/admin/provisioning_codes/{code_id}",
+            "admin",
+            "Revoke a provisioning code",
+        )
+        .admin(owner)
+        .id("code_id", "The id of the code.")
+        .error(404, "There's no such code that can still be used, or it's another operator's.")
+        .ok("The code was revoked.", null()),
+        Operation::new("get", "/admin/sites", "admin", "List the sites")
+            .admin(None)
+            .ok("Every site.", list(schema("Site"))),
+        Operation::new("post", "/admin/sites", "admin", "Add a site")
+            .admin(operator)
+            .body(json!({
+                "allOf": [
+                    schema("SiteMetadata"),
+                    object(json!({ "operator_id": integer() })),
+                ],
+            }))
+            .error(400, "There's no name, or the timezone isn't an IANA name.")
+            .for_operator()
+            .ok("The new site.", schema("Site")),
+        Operation::new("get", "/admin/sites/{site_id}", "admin", "Get a site")
+            .admin(None)
+            .id("site_id", "The id of the site.")
+            .error(404, "There's no such site, or it's another operator's.")
+            .ok("The site.", schema("Site")),
+        Operation::new("patch", "/admin/sites/{site_id}", "admin", "Update a site")
+            .admin(operator)
+            .id("site_id", "The id of the site.")
+            .body(schema("SiteMetadata"))
+            .error(
+                400,
+                "The name is empty, or the timezone isn't an IANA name.",
+            )
+            .error(404, "There's no such site, or it's another operator's.")
+            .ok("The updated site.", schema("Site")),
+        Operation::new("delete", "/admin/sites/{site_id}", "admin", "Delete a site")
+            .admin(operator)
+            .id("site_id", "The id of the site.")
+            .error(404, "There's no such site, or it's another operator's.")
+            .error(409, "Some lockers are still at the site.")
+            .ok("The site was deleted.", null()),
+        period(
+            Operation::new("get", "/admin/payments", "admin", "List payments"),
+            "payments created",
+        )
+        .admin(None)
+        .query(
+            "status",
+            json!({
+                "type": "string",
+                "enum": ["pending", "paid", "expired", "redeemed", "cancelled"],
+            }),
+            "Only payments with this status.",
+        )
+        .query("locker_id", integer(), "Only payments for this locker.")
+        .page(50, 500)
+        .ok(
+            "The payments, newest first.",
+            page("payments", schema("Payment")),
+        ),
+        Operation::new(
+            "get",
+            "/admin/payments/pending",
+            "admin",
+            "List pending payments",
+        )
+        .admin(None)
+        .query(
+            "stale_minutes",
+            json!({ "type": "integer", "minimum": 0 }),
+            "Only payments whose invoice was created at least this long ago.",
+        )
+        .ok(
+            "The payments we're waiting on, oldest first.",
+            list(object(json!({
+                "payment_hash": schema("PaymentHash"),
+                "amount": integer(),
+                "created_at": optional_timestamp(),
+                "age_seconds": optional_integer(),
+                "locker_id": integer(),
+                "locker_name": optional_string(),
+                "locker_state": string(),
+                "session_id": optional_integer(),
+                "session_started_at": optional_timestamp(),
+            }))),
+        ),
+        Operation::new(
+            "post",
+            "/admin/payments/{payment_hash}/cancel",
+            "admin",
+            "Cancel a payment",
+        )
+        .admin(operator)
+        .payment_hash()
+        .error(404, "The payment is another operator's.")
+        .error(409, "The payment isn't pending.")
+        .ok(
+            "The cancelled payment.",
+            object(json!({ "payment": schema("Payment"), "invoice_cancelled": boolean() })),
+        ),
+        Operation::new(
+            "post",
+            "/admin/receipts/{payment_hash}/revoke",
+            "admin",
+            "Revoke a receipt",
+        )
+        .admin(operator)
+        .payment_hash()
+        .optional_body(json!({
+            "type": "object",
+            "properties": {
+                "reason": string(),
+                "locker_id": {
+                    "type": "integer",
+                    "description": "Which locker's receipt, for payments covering several.",
+                },
+            },
+        }))
+        .error(404, "No receipt was issued for the payment, or it's another operator's.")
+        .error(
+            409,
+            "The receipt was already revoked, or the payment covers several lockers and none \
+             was given.",
+        )
+        .ok(
+            "The revocation.",
+            json!({
+                "allOf": [
+                    schema("Revocation"),
+                    object(json!({ "reason": optional_string() })),
+                ],
+            }),
+        ),
+        Operation::new(
+            "get",
+            "/admin/metrics",
+            "admin",
+            "Get the server's counters",
+        )
+        .admin(None)
+        .super_admin()
+        .without_store()
+        .ok("The counters.", json!({ "type": "object" })),
+        Operation::new("get", "/admin/backup", "admin", "Download a backup")
+            .admin(owner)
+            .super_admin()
+            .respond(
+                200,
+                "A snapshot of the database.",
+                "application/vnd.sqlite3",
+                json!({ "type": "string", "format": "binary" }),
+            ),
+        period(
+            Operation::new(
+                "get",
+                "/admin/export/payments.csv",
+                "admin",
+                "Export payments",
+            ),
+            "payments created",
+        )
+        .admin(None)
+        .respond(200, "The payments, oldest first.", "text/csv", string()),
+        Operation::new(
+            "get",
+            "/admin/export/sessions.csv",
+            "admin",
+            "Export sessions",
+        )
+        .admin(None)
+        .respond(200, "Every session, oldest first.", "text/csv", string()),
+        Operation::new(
+            "post",
+            "/admin/consistency_check",
+            "admin",
+            "Look for inconsistencies",
+        )
+        .admin(operator)
+        .super_admin()
+        .query(
+            "fix",
+            json!({ "type": "boolean", "default": false }),
+            "Repair what's found.",
+        )
+        .ok("What was found, and repaired.", json!({ "type": "object" })),
+        Operation::new(
+            "get",
+            "/admin/clock_drift",
+            "admin",
+            "List lockers' clock drift",
+        )
+        .admin(None)
+        .ok(
+            "How far off each locker's clock was, furthest first.",
+            list(object(json!({
+                "locker_id": integer(),
+                "name": optional_string(),
+                "drift_seconds": integer(),
+                "measured_at": timestamp(),
+            }))),
+        ),
+        period(
+            Operation::new("get", "/admin/stats/revenue", "admin", "Get revenue stats"),
+            "payments settled",
+        )
+        .admin(None)
+        .query(
+            "granularity",
+            json!({ "type": "string", "enum": ["day", "week", "month"], "default": "day" }),
+            "How to group payments together.",
+        )
+        .ok(
+            "What we made in each period.",
+            list(json!({ "type": "object" })),
+        ),
+        Operation::new(
+            "get",
+            "/admin/stats/occupancy",
+            "admin",
+            "Get occupancy stats",
+        )
+        .admin(None)
+        .query("from", timestamp(), "Defaults to a day before `to`.")
+        .query("to", timestamp(), "Defaults to now.")
+        .error(422, "`from` isn't before `to`.")
+        .ok(
+            "How long each locker was in use.",
+            json!({ "type": "object" }),
+        ),
+        page_of_events(Operation::new(
+            "get",
+            "/admin/audit",
+            "admin",
+            "List admin calls",
+        ))
+        .admin(None)
+        .ok("The admin calls, newest first.", list(schema("AdminCall"))),
+        Operation::new(
+            "get",
+            "/admin/webhooks",
+            "admin",
+            "List webhook subscriptions",
+        )
+        .admin(None)
+        .ok("Every subscription.", list(schema("Webhook"))),
+        Operation::new("post", "/admin/webhooks", "admin", "Subscribe to webhooks")
+            .admin(owner)
+            .body(json!({
+                "type": "object",
+                "required": ["url"],
+                "properties": {
+                    "url": string(),
+                    "events": list(schema("WebhookEvent")),
+                    "operator_id": integer(),
+                },
+            }))
+            .error(400, "`url` isn't an http or https URL, or `events` is empty or unknown.")
+            .for_operator()
+            .ok(
+                "The subscription, with its secret, only returned this once.",
+                json!({
+                    "allOf": [schema("Webhook"), object(json!({ "secret": string() }))],
+                }),
+            ),
+        Operation::new(
+            "delete",
+            "/admin/webhooks/{webhook_id}",
+            "admin",
+            "Unsubscribe from webhooks",
+        )
+        .admin(owner)
+        .id("webhook_id", "The id of the subscription.")
+        .error(404, "There's no such subscription, or it's another operator's.")
+        .ok("The subscription was deleted.", null()),
+        Operation::new(
+            "get",
+            "/admin/webhooks/{webhook_id}/deliveries",
+            "admin",
+            "List a subscription's deliveries",
+        )
+        .admin(None)
+        .id("webhook_id", "The id of the subscription.")
+        .query(
+            "status",
+            json!({ "type": "string", "enum": ["pending", "delivered", "dead"] }),
+            "Only deliveries with this status.",
+        )
+        .query(
+            "limit",
+            json!({ "type": "integer", "minimum": 0, "maximum": 500, "default": 50 }),
+            "How many deliveries to return.",
+        )
+        .error(400, "`status` isn't one of the known ones.")
+        .error(404, "There's no such subscription, or it's another operator's.")
+        .ok("The last deliveries, newest first.", list(schema("WebhookDelivery"))),
+        Operation::new("get", "/admin/operators", "admin", "List operators")
+            .admin(None)
+            .super_admin()
+            .ok("Every operator.", list(schema("Operator"))),
+        Operation::new("post", "/admin/operators", "admin", "Add an operator")
+            .admin(owner)
+            .super_admin()
+            .body(json!({
+                "type": "object",
+                "required": ["name"],
+                "properties": { "name": string() },
+            }))
+            .error(400, "The name is empty.")
+            .error(409, "The name is taken.")
+            .ok("The new operator.", schema("Operator")),
+    ]
+}
+
+/// The whole description.
+pub fn spec() -> Value {
+    let mut paths = serde_json::Map::new();
+    for operation in public_operations().into_iter().chain(admin_operations()) {
+        let path = paths
+            .entry(operation.path.clone())
+            .or_insert_with(|| json!({}));
+        let method = operation.method;
+        let unprefixed = operation.path.starts_with("/.well-known/");
+        path[method] = operation.into_value();
+        // devices look for the server document at the root, whatever version they speak
+        if unprefixed {
+            path[method]["servers"] = json!([{ "url": "/" }]);
+        }
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Locker server",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Rent lockers and pay for them over lightning. Successful responses \
+                wrap what they return in `data`, next to an `error` that's `null`. Paths \
+                without the version prefix still answer as in `/v1`, marked deprecated.",
+        },
+        "servers": [{ "url": ApiVersion::LATEST.prefix() }],
+        "tags": [
+            { "name": "lockers", "description": "Claiming and using lockers." },
+            { "name": "payments", "description": "Paying for lockers and getting receipts." },
+            { "name": "devices", "description": "What lockers call." },
+            { "name": "customers", "description": "Logging in with a lightning wallet." },
+            { "name": "server", "description": "Discovery and monitoring." },
+            { "name": "admin", "description": "Running the lockers, with an admin token." },
+        ],
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "headers": {
+                "Retry-After": {
+                    "description": "How many seconds to wait before trying again.",
+                    "schema": { "type": "integer" },
+                },
+            },
+            "securitySchemes": {
+                "adminToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "One of the configured admin tokens. Operations say which \
+                        role they need beyond `viewer`. Tokens scoped to an operator only see \
+                        that operator's lockers, and what's about them.",
+                },
+                "customerToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The token from `GET /auth/lnurl`, once the wallet signed \
+                        its challenge.",
+                },
+                "deviceToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The locker's device token, required once it was issued one.",
+                },
+                "nostr": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "Authorization",
+                    "description": "A NIP-98 event for the request, as `Nostr <base64>`.",
+                },
+            },
+        },
+    })
+}
+
+/// Serves [spec], letting clients cache it like the discovery routes, since it only changes with
+/// the build.
+pub async fn get_openapi<Ln: LnBackend>(
+    headers: HeaderMap,
+    _state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    cacheable(&headers, &spec())
+}
+
+/// A page rendering [spec] with Swagger UI, loaded from a CDN so we don't have to ship it.
+#[cfg(feature = "swagger-ui")]
+pub async fn get_docs() -> axum::response::Html<String> {
+    axum::response::Html(format!(
+        r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Locker server {version}</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({{ url: "openapi.json", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        version = env!("CARGO_PKG_VERSION"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spec;
+
+    #[test]
+    fn spec_is_openapi_3() {
+        let spec = spec();
+        let parsed: openapiv3::OpenAPI = serde_json::from_value(spec.clone()).unwrap();
+        assert!(parsed.openapi.starts_with("3."));
+        // every operation made it through, as opposed to being dropped as an unknown field
+        assert_eq!(parsed.operations().count(), 81);
+
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                // every parameter in the path is described
+                let declared: Vec<_> = operation["parameters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|parameter| parameter["in"] == "path")
+                    .map(|parameter| format!("{{{}}}", parameter["name"].as_str().unwrap()))
+                    .collect();
+                let templated = path.matches('{').count();
+                assert_eq!(declared.len(), templated, "{method} {path}");
+                assert!(
+                    declared.iter().all(|name| path.contains(name)),
+                    "{method} {path}"
+                );
+
+                let responses = operation["responses"].as_object().unwrap();
+                // a success, or switching to a WebSocket
+                assert!(responses
+                    .keys()
+                    .any(|status| status.starts_with('2') || status == "101"));
+                assert!(responses.contains_key("500"), "{method} {path}");
+            }
+        }
+    }
+
+    #[test]
+    fn admin_operations_need_a_token() {
+        let spec = spec();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let is_admin = path.starts_with("/admin/");
+                assert_eq!(
+                    operation["security"] == serde_json::json!([{ "adminToken": [] }]),
+                    is_admin,
+                    "{method} {path}"
+                );
+                assert_eq!(
+                    operation["responses"].get("429").is_some(),
+                    !is_admin,
+                    "{method} {path}"
+                );
+            }
+        }
+    }
+}
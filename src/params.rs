@@ -0,0 +1,327 @@
+//! The ids and hex strings clients send us, checked when the request is extracted so handlers
+//! never see garbage and clients are told which field was wrong, rather than getting a confusing
+//! miss.
+//!
+//! Each kind of value is a newtype parsed with [FromStr], which is also how the hex ones
+//! deserialize.
+//! [ValidPath] and [ValidJson] extract them from the path and body, rejecting bad ones with a
+//! `400` saying what the field should look like.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+use axum::extract::Path;
+use axum::extract::Request;
+use axum::http::request::Parts;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use bitcoin::hex::DisplayHex;
+use bitcoin::hex::FromHex;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Deserializer;
+
+use crate::error;
+
+/// A field that isn't what we expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamError {
+    pub field: &'static str,
+    /// What the field should look like, like `64 hex characters`.
+    pub expected: &'static str,
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: expected {}", self.field, self.expected)
+    }
+}
+
+/// The hash of a lightning payment, 32 bytes in hex. Either case is accepted, and it's kept in
+/// lowercase, like the backends hand them out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentHash(String);
+
+impl PaymentHash {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<PaymentHash> for String {
+    fn from(hash: PaymentHash) -> Self {
+        hash.0
+    }
+}
+
+impl FromStr for PaymentHash {
+    type Err = ParamError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::<32>(value, "payment_hash", "64 hex characters")?;
+        Ok(Self(bytes.to_lower_hex_string()))
+    }
+}
+
+/// The id of a locker, which is always a positive integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockerId(pub i64);
+
+impl FromStr for LockerId {
+    type Err = ParamError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(id) if id > 0 => Ok(Self(id)),
+            _ => Err(ParamError {
+                field: "locker_id",
+                expected: "a positive integer",
+            }),
+        }
+    }
+}
+
+/// A BIP340 signature, 64 bytes in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchnorrSignature(pub secp256k1::schnorr::Signature);
+
+impl FromStr for SchnorrSignature {
+    type Err = ParamError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::<64>(value, "signature", "128 hex characters")?;
+        Ok(Self(secp256k1::schnorr::Signature::from_byte_array(bytes)))
+    }
+}
+
+/// An HMAC-SHA256 tag, 32 bytes in hex, which lockers sharing a secret with us send in place of a
+/// [SchnorrSignature].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HmacTag(pub [u8; 32]);
+
+impl FromStr for HmacTag {
+    type Err = ParamError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(Self(hex::<32>(value, "signature", "64 hex characters")?))
+    }
+}
+
+/// An x-only public key, 32 bytes in hex, which must be a point on the curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XOnlyPubkey(pub secp256k1::XOnlyPublicKey);
+
+impl FromStr for XOnlyPubkey {
+    type Err = ParamError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::<32>(value, "pk", "64 hex characters")?;
+        secp256k1::XOnlyPublicKey::from_byte_array(bytes)
+            .map(Self)
+            .map_err(|_| ParamError {
+                field: "pk",
+                expected: "an x-only public key",
+            })
+    }
+}
+
+/// Parses exactly `N` bytes of hex, in either case.
+fn hex<const N: usize>(
+    value: &str,
+    field: &'static str,
+    expected: &'static str,
+) -> Result<[u8; N], ParamError> {
+    <[u8; N]>::from_hex(value).map_err(|_| ParamError { field, expected })
+}
+
+macro_rules! from_str_serde {
+    ($($name:ident),*) => {$(
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                // the field name is added by whoever reports the error, see [ValidJson]
+                let value = String::deserialize(deserializer)?;
+                value
+                    .parse()
+                    .map_err(|e: ParamError| serde::de::Error::custom(format!("expected {}", e.expected)))
+            }
+        }
+    )*};
+}
+
+from_str_serde!(PaymentHash, SchnorrSignature, XOnlyPubkey);
+
+/// Extracts the only parameter of the path as a `T`, see the module documentation.
+pub struct ValidPath<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ValidPath<T>
+where
+    S: Send + Sync,
+    T: FromStr<Err = ParamError>,
+{
+    type Rejection = error::Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| error::Error::BadRequest)?;
+
+        Ok(Self(value.parse()?))
+    }
+}
+
+/// Like [axum::Json], but rejects bodies whose fields have the wrong shape, like a malformed
+/// [SchnorrSignature], with a `400` naming the field, rather than a `422`.
+pub struct ValidJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::Json::<T>::from_request(request, state).await {
+            Ok(axum::Json(value)) => Ok(Self(value)),
+            Err(JsonRejection::JsonDataError(e)) => {
+                // reads like `signature: expected 128 hex characters at line 1 column 42`
+                let message = std::error::Error::source(&e)
+                    .map(|source| source.to_string())
+                    .unwrap_or_else(|| e.body_text());
+                let message = match message.rsplit_once(" at line ") {
+                    Some((message, _)) => message.to_string(),
+                    None => message,
+                };
+
+                Err(error::Error::InvalidParam(message).into_response())
+            }
+            Err(rejection) => Err(rejection.into_response()),
+        }
+    }
+}
+
+impl<T> std::ops::Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockerId;
+    use super::ParamError;
+    use super::PaymentHash;
+    use super::SchnorrSignature;
+    use super::XOnlyPubkey;
+
+    #[test]
+    fn payment_hashes_are_64_hex_characters() {
+        let hash = "AB".repeat(32);
+        assert_eq!(
+            hash.parse::<PaymentHash>().unwrap().as_str(),
+            "ab".repeat(32)
+        );
+
+        let error = ParamError {
+            field: "payment_hash",
+            expected: "64 hex characters",
+        };
+        for bad in [
+            String::new(),
+            "ab".repeat(31),
+            format!("{}a", "ab".repeat(32)),
+            "ab".repeat(33),
+            format!("{}zz", "ab".repeat(31)),
+            // hex crates often allow a prefix, we don't
+            format!("0x{}", "ab".repeat(31)),
+        ] {
+            assert_eq!(bad.parse::<PaymentHash>(), Err(error.clone()), "{bad}");
+        }
+    }
+
+    #[test]
+    fn locker_ids_are_positive_integers() {
+        assert_eq!("1".parse::<LockerId>(), Ok(LockerId(1)));
+        assert_eq!(
+            i64::MAX.to_string().parse::<LockerId>(),
+            Ok(LockerId(i64::MAX))
+        );
+
+        for bad in ["0", "-5", "abc", "", "1.5", " 1", "9223372036854775808"] {
+            assert_eq!(
+                bad.parse::<LockerId>(),
+                Err(ParamError {
+                    field: "locker_id",
+                    expected: "a positive integer",
+                }),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn signatures_are_128_hex_characters() {
+        assert!("01".repeat(64).parse::<SchnorrSignature>().is_ok());
+
+        for bad in [
+            "01".repeat(63),
+            "01".repeat(65),
+            format!("{}g", "0".repeat(127)),
+        ] {
+            assert_eq!(
+                bad.parse::<SchnorrSignature>(),
+                Err(ParamError {
+                    field: "signature",
+                    expected: "128 hex characters",
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn pubkeys_are_points_on_the_curve() {
+        let generator = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        assert_eq!(
+            generator
+                .to_uppercase()
+                .parse::<XOnlyPubkey>()
+                .unwrap()
+                .0
+                .to_string(),
+            generator
+        );
+
+        assert_eq!(
+            "ab".repeat(31).parse::<XOnlyPubkey>(),
+            Err(ParamError {
+                field: "pk",
+                expected: "64 hex characters",
+            })
+        );
+        // the right length, but x = 0 isn't on the curve
+        assert_eq!(
+            "00".repeat(32).parse::<XOnlyPubkey>(),
+            Err(ParamError {
+                field: "pk",
+                expected: "an x-only public key",
+            })
+        );
+    }
+
+    #[test]
+    fn fields_deserialize_the_same_way() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Body {
+            #[allow(dead_code)]
+            signature: SchnorrSignature,
+        }
+
+        let error = serde_json::from_str::<Body>(r#"{"signature": "abc"}"#).unwrap_err();
+        assert!(error.to_string().starts_with("expected 128 hex characters"));
+    }
+}
@@ -0,0 +1,226 @@
+//! `GET /payments/{payment_hash}/events`, a stream of server-sent events telling whoever waits for
+//! a payment how it ended, so wallets and kiosks don't have to poll
+//! `/payments/{payment_hash}/receipt`.
+//!
+//! The stream starts with a `pending` event, then ends with `paid`, carrying the receipt
+//! `/payments/{payment_hash}/receipt` would answer, `expired` or `cancelled`. A payment that isn't
+//! pending anymore only gets its last event. While anyone follows a payment, one task asks the
+//! lightning backend whether it settled every [crate::config::Config::receipt_poll_cache_seconds],
+//! and whatever else notices it ending, `/payments/{payment_hash}/receipt`, the cleanup task or
+//! staff cancelling it, tells its followers too, through the [PaymentWatch]. Streams end with
+//! `expired` once the invoice expired, and meanwhile send a comment every [KEEP_ALIVE_SECONDS], so
+//! proxies don't close them for being idle.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use futures_util::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+
+use crate::check_settlement;
+use crate::error;
+use crate::issue_receipt;
+use crate::ln::InvoiceStatus;
+use crate::ln::LnBackend;
+use crate::ln::INVOICE_EXPIRY_SECONDS;
+use crate::now;
+use crate::params;
+use crate::stored_receipt;
+use crate::PendingPayment;
+use crate::Server;
+
+/// How often, in seconds, we send a comment down streams that have nothing else to say.
+pub const KEEP_ALIVE_SECONDS: u64 = 15;
+
+/// Who follows which payment, see the module documentation.
+pub struct PaymentWatch {
+    senders: Mutex<HashMap<String, broadcast::Sender<()>>>,
+}
+
+impl PaymentWatch {
+    pub fn new() -> Self {
+        Self {
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Follows a payment, returning whether nobody did yet, in which case it's up to the caller to
+    /// watch the lightning backend for it.
+    pub fn subscribe(&self, payment_hash: &str) -> (broadcast::Receiver<()>, bool) {
+        let mut senders = self.senders.lock().unwrap();
+        match senders.get(payment_hash) {
+            Some(sender) => (sender.subscribe(), false),
+            None => {
+                let (sender, receiver) = broadcast::channel(1);
+                senders.insert(payment_hash.to_string(), sender);
+                (receiver, true)
+            }
+        }
+    }
+
+    /// Tells whoever follows a payment that it ended, its status says how.
+    pub fn notify(&self, payment_hash: &str) {
+        if let Some(sender) = self.senders.lock().unwrap().remove(payment_hash) {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Whether anyone still follows a payment, forgetting it if nobody does.
+    fn is_followed(&self, payment_hash: &str) -> bool {
+        let mut senders = self.senders.lock().unwrap();
+        match senders.get(payment_hash) {
+            Some(sender) if sender.receiver_count() > 0 => true,
+            Some(_) => {
+                senders.remove(payment_hash);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Streams how a payment ends, see the module documentation.
+pub async fn get_payment_events<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, error::Error> {
+    let payment = state.get_payment(String::from(payment_hash)).await?;
+
+    let (sender, receiver) = mpsc::channel(2);
+    tokio::spawn(follow(state.0, payment, sender));
+    let events = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok(event), receiver))
+    });
+
+    Ok(Sse::new(events).keep_alive(
+        KeepAlive::new().interval(Duration::from_secs(KEEP_ALIVE_SECONDS)),
+    ))
+}
+
+/// Sends the events of a payment to `events`, until it ended or nobody reads them anymore.
+async fn follow<Ln: LnBackend>(
+    state: Arc<Server<Ln>>,
+    payment: PendingPayment,
+    events: mpsc::Sender<Event>,
+) {
+    if payment.status == "pending" {
+        // payments from before we kept when they were created are given a full expiry from now
+        let expires_at = payment.created_at.unwrap_or_else(now) + INVOICE_EXPIRY_SECONDS;
+        let (mut ended, first) = state.payment_watch.subscribe(&payment.payment_hash);
+        if first {
+            tokio::spawn(watch(state.clone(), payment.clone(), expires_at));
+        }
+
+        let pending = event(
+            "pending",
+            serde_json::json!({
+                "payment_hash": payment.payment_hash,
+                "locker_id": payment.locker_id,
+                "amount": payment.amount,
+                "expires_at": expires_at,
+            }),
+        );
+        if events.send(pending).await.is_err() {
+            return;
+        }
+
+        let expiry = Duration::from_secs(expires_at.saturating_sub(now()));
+        tokio::select! {
+            _ = ended.recv() => {}
+            _ = tokio::time::sleep(expiry) => {}
+            _ = events.closed() => return,
+        }
+    }
+
+    // whatever ended the wait, the payment's status says how it ended
+    let payment = match state.get_payment(payment.payment_hash.clone()).await {
+        Ok(payment) => payment,
+        Err(e) => {
+            eprintln!("[payment_events] failed to read payment {}: {e:?}", payment.payment_hash);
+            return;
+        }
+    };
+    let _ = events.send(last_event(&state, &payment).await).await;
+}
+
+/// Asks the lightning backend whether a payment settled until it did, its invoice expired or
+/// nobody follows it anymore. [check_settlement] tells the followers.
+async fn watch<Ln: LnBackend>(state: Arc<Server<Ln>>, payment: PendingPayment, expires_at: u64) {
+    let interval = Duration::from_secs(state.config.receipt_poll_cache_seconds.max(1));
+    while now() < expires_at && state.payment_watch.is_followed(&payment.payment_hash) {
+        match check_settlement(&state, &payment).await {
+            Ok(InvoiceStatus::Unpaid) => {}
+            Ok(_) => return,
+            Err(e) => eprintln!(
+                "[payment_events] failed to check payment {}: {e:?}",
+                payment.payment_hash
+            ),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// The event a payment that isn't pending anymore ends its stream with. A payment still pending
+/// by then timed out.
+async fn last_event<Ln: LnBackend>(state: &Server<Ln>, payment: &PendingPayment) -> Event {
+    let receipt = match payment.status.as_str() {
+        "paid" => issue_receipt(state, payment).await,
+        "redeemed" => stored_receipt(state, payment).await,
+        "cancelled" => return event("cancelled", serde_json::json!({})),
+        _ => return event("expired", serde_json::json!({})),
+    };
+
+    // a receipt that was used or revoked isn't handed out again, the receipt route says why
+    event("paid", receipt.ok())
+}
+
+fn event(name: &str, data: impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .expect("events serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PaymentWatch;
+
+    #[test]
+    fn followers_are_told_once() {
+        let watch = PaymentWatch::new();
+        let (mut first, watching) = watch.subscribe("hash");
+        assert!(watching);
+        let (mut second, watching) = watch.subscribe("hash");
+        assert!(!watching);
+
+        watch.notify("hash");
+        assert!(first.try_recv().is_ok());
+        assert!(second.try_recv().is_ok());
+
+        // the payment is forgotten, so whoever follows it next watches it again
+        watch.notify("hash");
+        assert!(watch.subscribe("hash").1);
+    }
+
+    #[test]
+    fn payments_nobody_follows_are_forgotten() {
+        let watch = PaymentWatch::new();
+        let (follower, _) = watch.subscribe("hash");
+        assert!(watch.is_followed("hash"));
+
+        drop(follower);
+        assert!(!watch.is_followed("hash"));
+        assert!(watch.subscribe("hash").1);
+    }
+}
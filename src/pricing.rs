@@ -0,0 +1,69 @@
+//! How much using a locker costs.
+
+use serde::Serialize;
+
+/// The price that applies to a locker, in millisatoshis per minute: its own override if it has
+/// one, otherwise its site's price, otherwise the configured default.
+pub fn price_per_minute_msat(locker: Option<u64>, site: Option<u64>, default: u64) -> u64 {
+    locker.or(site).unwrap_or(default)
+}
+
+/// What using a locker for `seconds` costs at the given rate, rounded up to a whole satoshi.
+pub fn amount_sat(seconds: u64, price_per_minute_msat: u64) -> u64 {
+    seconds
+        .saturating_mul(price_per_minute_msat)
+        .div_ceil(60_000)
+}
+
+/// One line of what a session costs, see [breakdown].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Charge {
+    /// What the line is for, only `usage`, the time the locker was used at its rate, for now.
+    pub kind: &'static str,
+    pub seconds: u64,
+    pub price_per_minute_msat: u64,
+    pub amount_sat: u64,
+}
+
+/// How what using a locker for `seconds` at the given rate costs comes about, line by line. The
+/// lines add up to [amount_sat].
+pub fn breakdown(seconds: u64, price_per_minute_msat: u64) -> Vec<Charge> {
+    vec![Charge {
+        kind: "usage",
+        seconds,
+        price_per_minute_msat,
+        amount_sat: amount_sat(seconds, price_per_minute_msat),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lockers_override_sites_and_sites_override_the_default() {
+        assert_eq!(super::price_per_minute_msat(Some(3), Some(2), 1), 3);
+        assert_eq!(super::price_per_minute_msat(Some(3), None, 1), 3);
+        assert_eq!(super::price_per_minute_msat(None, Some(2), 1), 2);
+        assert_eq!(super::price_per_minute_msat(None, None, 1), 1);
+    }
+
+    #[test]
+    fn amounts_round_up_to_a_satoshi() {
+        // the default of 60000 msat a minute is one satoshi a second
+        assert_eq!(super::amount_sat(90, 60_000), 90);
+        assert_eq!(super::amount_sat(90, 120_000), 180);
+        assert_eq!(super::amount_sat(1, 1), 1);
+        assert_eq!(super::amount_sat(0, 60_000), 0);
+        assert_eq!(super::amount_sat(u64::MAX, 60_000), u64::MAX / 60_000 + 1);
+    }
+
+    #[test]
+    fn breakdowns_add_up_to_the_amount() {
+        for (seconds, price) in [(90, 60_000), (1, 1), (0, 60_000), (3_600, 1_234)] {
+            let total: u64 = super::breakdown(seconds, price)
+                .iter()
+                .map(|charge| charge.amount_sat)
+                .sum();
+            assert_eq!(total, super::amount_sat(seconds, price));
+        }
+    }
+}
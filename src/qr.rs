@@ -0,0 +1,206 @@
+//! `GET /payments/{payment_hash}/qr.png` and `/qr.svg`, the invoice of a payment as a QR code, so
+//! every kiosk shows the same one rather than each frontend drawing its own.
+//!
+//! The invoice is encoded in uppercase, which BOLT11 allows and which fits the QR alphanumeric
+//! mode, with error correction level M. Images fit in `size` pixels, [DEFAULT_SIZE] by default,
+//! quiet zone included. The image for a payment never changes, so it's cached until the invoice
+//! expires, and once it was settled or expired clients get a `410`, telling screens still showing
+//! it to move on.
+
+use std::sync::Arc;
+
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use qrcode::render::svg;
+use qrcode::render::Canvas;
+use qrcode::render::Pixel;
+use qrcode::EcLevel;
+use qrcode::QrCode;
+use serde::Deserialize;
+
+use crate::error;
+use crate::ln::LnBackend;
+use crate::ln::INVOICE_EXPIRY_SECONDS;
+use crate::now;
+use crate::params;
+use crate::Server;
+
+/// How many pixels across images are when the client doesn't say.
+pub const DEFAULT_SIZE: u32 = 300;
+
+/// The sizes clients may ask for, in pixels. Images are never larger than asked, unless that's
+/// too small to draw each module with a pixel, which most invoices need about 150 for.
+pub const MIN_SIZE: u32 = 64;
+pub const MAX_SIZE: u32 = 2048;
+
+#[derive(Debug, Deserialize)]
+pub struct QrQuery {
+    size: Option<u32>,
+}
+
+/// The invoice of a payment as a PNG, see the module documentation.
+pub async fn get_qr_png<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    Query(query): Query<QrQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let (code, size, max_age) = payable_code(&state, payment_hash, query).await?;
+    Ok(image("image/png", max_age, render_png(&code, size)?))
+}
+
+/// The invoice of a payment as an SVG, see the module documentation.
+pub async fn get_qr_svg<Ln: LnBackend>(
+    params::ValidPath(payment_hash): params::ValidPath<params::PaymentHash>,
+    Query(query): Query<QrQuery>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<Response, error::Error> {
+    let (code, size, max_age) = payable_code(&state, payment_hash, query).await?;
+    let svg = code
+        .render::<svg::Color>()
+        .max_dimensions(size, size)
+        .build();
+    Ok(image("image/svg+xml", max_age, svg.into_bytes()))
+}
+
+/// The QR code of the invoice of a payment that can still be paid, along with the size to draw it
+/// at and how many seconds it can be cached for.
+async fn payable_code<Ln: LnBackend>(
+    state: &Server<Ln>,
+    payment_hash: params::PaymentHash,
+    query: QrQuery,
+) -> Result<(QrCode, u32, u64), error::Error> {
+    let size = query.size.unwrap_or(DEFAULT_SIZE);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+        return Err(error::Error::InvalidParam(format!(
+            "size: expected between {MIN_SIZE} and {MAX_SIZE}"
+        )));
+    }
+
+    let payment = state.get_payment(String::from(payment_hash)).await?;
+    // payments from before we kept when they were created are given a full expiry from now
+    let expires_at = payment.created_at.unwrap_or_else(now) + INVOICE_EXPIRY_SECONDS;
+    let now = now();
+    if payment.status != "pending" || expires_at <= now {
+        return Err(error::Error::InvoiceGone);
+    }
+    let Some(bolt11) = payment.bolt11 else {
+        return Err(error::Error::NotFound);
+    };
+
+    let code = encode(&bolt11)?;
+    Ok((code, size, expires_at - now))
+}
+
+fn encode(bolt11: &str) -> Result<QrCode, error::Error> {
+    QrCode::with_error_correction_level(bolt11.to_uppercase(), EcLevel::M).map_err(|e| {
+        eprintln!("[qr] failed to encode an invoice: {e:?}");
+        error::Error::Server
+    })
+}
+
+fn image(content_type: &'static str, max_age: u64, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CACHE_CONTROL,
+                format!("public, max-age={max_age}, immutable"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn render_png(code: &QrCode, size: u32) -> Result<Vec<u8>, error::Error> {
+    let (width, height, pixels) = code.render::<Gray>().max_dimensions(size, size).build();
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| {
+            eprintln!("[qr] failed to encode a png: {e:?}");
+            error::Error::Server
+        })?;
+
+    Ok(png)
+}
+
+/// A grayscale pixel, for drawing codes into a buffer [render_png] can encode.
+#[derive(Debug, Clone, Copy)]
+struct Gray(u8);
+
+impl Pixel for Gray {
+    type Image = (u32, u32, Vec<u8>);
+    type Canvas = GrayCanvas;
+
+    fn default_color(color: qrcode::Color) -> Self {
+        Gray(color.select(0, 255))
+    }
+}
+
+struct GrayCanvas {
+    width: u32,
+    height: u32,
+    dark: u8,
+    pixels: Vec<u8>,
+}
+
+impl Canvas for GrayCanvas {
+    type Pixel = Gray;
+    type Image = (u32, u32, Vec<u8>);
+
+    fn new(width: u32, height: u32, dark: Gray, light: Gray) -> Self {
+        Self {
+            width,
+            height,
+            dark: dark.0,
+            pixels: vec![light.0; width as usize * height as usize],
+        }
+    }
+
+    fn draw_dark_pixel(&mut self, x: u32, y: u32) {
+        self.pixels[y as usize * self.width as usize + x as usize] = self.dark;
+    }
+
+    fn into_image(self) -> Self::Image {
+        (self.width, self.height, self.pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    use super::render_png;
+
+    const INVOICE: &str = "lnbc10u1pjq8fsppp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+
+    #[test]
+    fn images_fit_the_size() {
+        let code = encode(INVOICE).unwrap();
+        let image = render_png(&code, 300).unwrap();
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(image));
+        let info = decoder.read_info().unwrap().info().clone();
+        assert_eq!(info.width, info.height);
+        assert!(info.width <= 300 && info.width > 200, "{}", info.width);
+    }
+
+    #[test]
+    fn invoices_are_encoded_in_uppercase() {
+        let code = encode(INVOICE).unwrap();
+        assert_eq!(
+            code.to_colors(),
+            encode(&INVOICE.to_uppercase()).unwrap().to_colors()
+        );
+        assert_eq!(code.error_correction_level(), qrcode::EcLevel::M);
+    }
+}
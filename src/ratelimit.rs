@@ -0,0 +1,314 @@
+//! Token buckets, to limit how often anyone can call the endpoints that are cheap to abuse, and the
+//! middleware limiting each client by IP address.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::error;
+use crate::ln::LnBackend;
+use crate::Server;
+
+/// How many buckets we keep before forgetting the full ones, so callers making up keys can't
+/// make us use up all our memory. If none are full, we forget the one that was used the longest
+/// ago.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Lets each key make up to `capacity` calls in a burst, and then one more call every
+/// `1 / refill_per_second` seconds.
+pub struct RateLimiter<K> {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    /// Allows `per_minute` calls a minute for each key, all of which can be made at once.
+    pub fn per_minute(per_minute: u32) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            refill_per_second: per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token out of `key`'s bucket. If it's empty, returns how long until there's one
+    /// again.
+    pub fn check(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| self.tokens(bucket, now) < self.capacity);
+        }
+
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            let idle = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated_at)
+                .map(|(key, _)| key.clone());
+            if let Some(idle) = idle {
+                buckets.remove(&idle);
+            }
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1.0 {
+            if self.refill_per_second == 0.0 {
+                return Err(Duration::MAX);
+            }
+
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_per_second,
+            ));
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// How many tokens `bucket` holds at `now`, counting what was refilled since it was last
+    /// updated.
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity)
+    }
+}
+
+/// Remembers keys for a while after they're started, for answers we can give again without
+/// asking anyone, like a payment not being settled yet.
+pub struct Cooldown<K> {
+    duration: Duration,
+    until: Mutex<HashMap<K, Instant>>,
+}
+
+impl<K: Hash + Eq> Cooldown<K> {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            until: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// If `key` is still cooling down at `now`, returns how long until it's done.
+    pub fn remaining(&self, key: &K, now: Instant) -> Option<Duration> {
+        let until = *self.until.lock().unwrap().get(key)?;
+        (now < until).then(|| until - now)
+    }
+
+    /// Starts cooling `key` down from `now`. Once we remember [MAX_BUCKETS] keys that are still
+    /// cooling down, new ones aren't remembered at all.
+    pub fn start(&self, key: K, now: Instant) {
+        let mut until = self.until.lock().unwrap();
+        if until.len() >= MAX_BUCKETS {
+            until.retain(|_, until| now < *until);
+        }
+
+        if until.len() < MAX_BUCKETS {
+            until.insert(key, now + self.duration);
+        }
+    }
+}
+
+/// Limits each client to [crate::config::Config::read_rate_limit] calls a minute.
+pub async fn limit_reads<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, error::Error> {
+    let ip = client_ip(peer, request.headers(), state.config.trust_forwarded_for);
+    limit(&state.read_limiter, ip)?;
+
+    Ok(next.run(request).await)
+}
+
+/// Limits each client to [crate::config::Config::expensive_rate_limit] calls a minute, for the
+/// routes that cost us a call to the lightning backend or a signature.
+pub async fn limit_expensive<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, error::Error> {
+    let ip = client_ip(peer, request.headers(), state.config.trust_forwarded_for);
+    limit(&state.expensive_limiter, ip)?;
+
+    Ok(next.run(request).await)
+}
+
+fn limit(limiter: &RateLimiter<IpAddr>, ip: IpAddr) -> Result<(), error::Error> {
+    limiter
+        .check(ip, Instant::now())
+        .map_err(|wait| error::Error::RateLimited {
+            retry_after: wait.as_secs().max(1),
+        })
+}
+
+/// The address we limit a client by: the peer we're talking to, or if it's a proxy we trust, the
+/// last address it added to `X-Forwarded-For`, since anything before that is up to the client.
+///
+/// IPv6 clients are limited by their /64, which is usually all one host or network gets, so they
+/// can't dodge the limit by hopping between addresses.
+pub fn client_ip(peer: SocketAddr, headers: &HeaderMap, trust_forwarded_for: bool) -> IpAddr {
+    let forwarded = trust_forwarded_for
+        .then(|| {
+            headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .next_back()?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .flatten();
+
+    match forwarded.unwrap_or(peer.ip()).to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & !(u64::MAX as u128)).into()),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use axum::http::HeaderMap;
+
+    use super::Cooldown;
+    use super::RateLimiter;
+
+    #[test]
+    fn buckets_empty_and_refill() {
+        let limiter = RateLimiter::per_minute(2);
+        let start = Instant::now();
+
+        assert_eq!(limiter.check(1, start), Ok(()));
+        assert_eq!(limiter.check(1, start), Ok(()));
+        assert_eq!(limiter.check(1, start), Err(Duration::from_secs(30)));
+
+        // other keys have their own bucket
+        assert_eq!(limiter.check(2, start), Ok(()));
+
+        // a token comes back every 30 seconds
+        assert_eq!(limiter.check(1, start + Duration::from_secs(30)), Ok(()));
+        assert!(limiter.check(1, start + Duration::from_secs(30)).is_err());
+
+        // but they never pile up past the capacity
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(limiter.check(1, later), Ok(()));
+        assert_eq!(limiter.check(1, later), Ok(()));
+        assert!(limiter.check(1, later).is_err());
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten() {
+        let limiter = RateLimiter::per_minute(60);
+        let start = Instant::now();
+
+        for key in 0..super::MAX_BUCKETS {
+            limiter.check(key, start).unwrap();
+        }
+
+        // a minute later they're all full again, so there's nothing to remember about them
+        limiter
+            .check(super::MAX_BUCKETS, start + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+
+        // if none are full, the one used the longest ago goes
+        let start = start + Duration::from_secs(60);
+        for key in 0..super::MAX_BUCKETS {
+            limiter.check(key, start).unwrap();
+        }
+        let later = start + Duration::from_millis(1);
+        limiter.check(0, later).unwrap();
+        limiter.check(super::MAX_BUCKETS, later).unwrap();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), super::MAX_BUCKETS);
+        assert!(buckets.contains_key(&0));
+    }
+
+    #[test]
+    fn cooldowns_end() {
+        let cooldown = Cooldown::new(Duration::from_secs(2));
+        let start = Instant::now();
+
+        assert_eq!(cooldown.remaining(&1, start), None);
+        cooldown.start(1, start);
+        assert_eq!(cooldown.remaining(&1, start), Some(Duration::from_secs(2)));
+        assert_eq!(
+            cooldown.remaining(&1, start + Duration::from_millis(1500)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(cooldown.remaining(&1, start + Duration::from_secs(2)), None);
+        assert_eq!(cooldown.remaining(&2, start), None);
+
+        // once full, only keys that are done cooling down make room
+        for key in 0..super::MAX_BUCKETS {
+            cooldown.start(key, start);
+        }
+        cooldown.start(super::MAX_BUCKETS, start);
+        assert_eq!(cooldown.remaining(&super::MAX_BUCKETS, start), None);
+
+        let later = start + Duration::from_secs(2);
+        cooldown.start(super::MAX_BUCKETS, later);
+        assert!(cooldown.remaining(&super::MAX_BUCKETS, later).is_some());
+        assert_eq!(cooldown.until.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_address() {
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append("X-Forwarded-For", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.append("X-Forwarded-For", "3.3.3.3".parse().unwrap());
+
+        // only a proxy we trust gets to say who the client is, and only its own entry counts
+        assert_eq!(
+            super::client_ip(peer, &headers, false),
+            "10.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(
+            super::client_ip(peer, &headers, true),
+            "3.3.3.3".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(
+            super::client_ip(peer, &HeaderMap::new(), true),
+            "10.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+
+        // a whole /64 counts as one client
+        let peer: SocketAddr = "[2001:db8:1:2:3:4:5:6]:4000".parse().unwrap();
+        assert_eq!(
+            super::client_ip(peer, &HeaderMap::new(), false),
+            "2001:db8:1:2::".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+}
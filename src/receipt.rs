@@ -0,0 +1,196 @@
+//! Signed receipts, which tell clients what they're allowed to do with a locker in a form they can
+//! check without knowing how lockers check their authorizations.
+//!
+//! A receipt is signed with BIP340 over the `locker/receipt` tagged hash, see [crate::signing], of
+//! its canonical serialization: the JSON object of its fields with the keys sorted, no whitespace,
+//! and fields that aren't set as `null`, except `actor`, which is left out unless it's set.
+//! Clients get the fields back as a JSON object, and verify the receipt by serializing them again
+//! the same way, which is what [verify_receipt] does.
+
+use secp256k1::schnorr::Signature;
+use secp256k1::Secp256k1;
+use secp256k1::XOnlyPublicKey;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::keys::ServerKey;
+use crate::signing;
+use crate::signing::Action;
+
+/// What a receipt vouches for. The fields are declared in the order of their keys, which is the
+/// order they're serialized in.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Receipt {
+    /// What the locker may be opened for.
+    pub action: Action,
+    /// Who had the receipt issued when it wasn't the customer, `admin` for staff opening a locker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    /// When the receipt stops being valid, as a unix timestamp.
+    pub expires_at: u64,
+    /// When the receipt was issued, as a unix timestamp.
+    pub issued_at: u64,
+    /// The id of the server key that signed the receipt, see `GET /keys`.
+    pub kid: String,
+    pub locker_id: i64,
+    /// The payment the receipt was bought with, receipts for claiming a locker don't have one.
+    pub payment_hash: Option<String>,
+}
+
+impl Receipt {
+    /// The bytes we sign, see the module documentation.
+    pub fn canonical(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("receipts always serialize")
+    }
+}
+
+/// A receipt along with our signature over it, as returned under `signed_receipt`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SignedReceipt {
+    pub receipt: Receipt,
+    /// The BIP340 signature over the receipt, in hex.
+    pub signature: String,
+}
+
+/// Why a receipt was rejected by [verify_receipt].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReceiptError {
+    /// The signature isn't 64 bytes of hex.
+    Malformed,
+    /// The receipt wasn't signed with the key, or was changed after it was signed.
+    BadSignature,
+}
+
+/// Signs `receipt` with `key`, which should be the one its `kid` names.
+pub fn sign(receipt: Receipt, key: &ServerKey) -> SignedReceipt {
+    let signature = signing::sign_schnorr(
+        key.keypair.expose(),
+        &signing::receipt_digest(&receipt.canonical()),
+    );
+
+    SignedReceipt {
+        receipt,
+        signature: signature.to_string(),
+    }
+}
+
+/// Checks that `signed` was signed with the key whose x-only public key is `pubkey`, the one
+/// listed under `GET /keys` for its `kid`.
+pub fn verify_receipt(signed: &SignedReceipt, pubkey: &XOnlyPublicKey) -> Result<(), ReceiptError> {
+    let signature: Signature = signed
+        .signature
+        .parse()
+        .map_err(|_| ReceiptError::Malformed)?;
+
+    Secp256k1::verification_only()
+        .verify_schnorr(
+            &signature,
+            &signing::receipt_digest(&signed.receipt.canonical()),
+            pubkey,
+        )
+        .map_err(|_| ReceiptError::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hex::DisplayHex;
+    use secp256k1::Keypair;
+    use secp256k1::Secp256k1;
+
+    use super::Receipt;
+    use super::ReceiptError;
+    use super::SignedReceipt;
+    use crate::keys::Keyring;
+    use crate::signing::Action;
+
+    fn receipt() -> Receipt {
+        Receipt {
+            action: Action::Retrieve,
+            actor: None,
+            expires_at: 1700000900,
+            issued_at: 1700000000,
+            kid: "79be667ef9dcbbac".to_string(),
+            locker_id: 1,
+            payment_hash: Some("00".repeat(32)),
+        }
+    }
+
+    fn keyring() -> Keyring {
+        Keyring::single(
+            Keypair::from_seckey_str(
+                &Secp256k1::new(),
+                "0000000000000000000000000000000000000000000000000000000000000001",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn receipts_match_vectors() {
+        let receipt = receipt();
+        assert_eq!(
+            String::from_utf8(receipt.canonical()).unwrap(),
+            format!(
+                r#"{{"action":"retrieve","expires_at":1700000900,"issued_at":1700000000,"kid":"79be667ef9dcbbac","locker_id":1,"payment_hash":"{}"}}"#,
+                "00".repeat(32)
+            )
+        );
+        assert_eq!(
+            crate::signing::receipt_digest(&receipt.canonical()).to_lower_hex_string(),
+            "33c6b338514e93250c1bb141d8bfafb879342e2e00b4ee2050cab27d045a6ee0"
+        );
+
+        let signed = super::sign(receipt, keyring().active());
+        assert_eq!(
+            signed.signature,
+            "8f658987ae307e5c936842efc663da464b3c9957599d9ce235a193d15b3e55f79f294e66cff144765187d9878656173e5199b7b1a0154b373d831f1a3269eacc"
+        );
+
+        let claiming = Receipt {
+            action: Action::Store,
+            payment_hash: None,
+            ..self::receipt()
+        };
+        assert_eq!(
+            String::from_utf8(claiming.canonical()).unwrap(),
+            r#"{"action":"store","expires_at":1700000900,"issued_at":1700000000,"kid":"79be667ef9dcbbac","locker_id":1,"payment_hash":null}"#
+        );
+
+        let forced = Receipt {
+            actor: Some("admin".to_string()),
+            payment_hash: None,
+            ..self::receipt()
+        };
+        assert_eq!(
+            String::from_utf8(forced.canonical()).unwrap(),
+            r#"{"action":"retrieve","actor":"admin","expires_at":1700000900,"issued_at":1700000000,"kid":"79be667ef9dcbbac","locker_id":1,"payment_hash":null}"#
+        );
+    }
+
+    #[test]
+    fn receipts_verify_until_changed() {
+        let pubkey = keyring().active().keypair.expose().x_only_public_key().0;
+        let signed = super::sign(receipt(), keyring().active());
+        assert_eq!(super::verify_receipt(&signed, &pubkey), Ok(()));
+
+        // what clients get back parses into the same receipt
+        let parsed: SignedReceipt =
+            serde_json::from_str(&serde_json::to_string(&signed).unwrap()).unwrap();
+        assert_eq!(super::verify_receipt(&parsed, &pubkey), Ok(()));
+
+        let mut changed = signed.clone();
+        changed.receipt.action = Action::Store;
+        assert_eq!(
+            super::verify_receipt(&changed, &pubkey),
+            Err(ReceiptError::BadSignature)
+        );
+
+        let mut malformed = signed;
+        malformed.signature = "not a signature".to_string();
+        assert_eq!(
+            super::verify_receipt(&malformed, &pubkey),
+            Err(ReceiptError::Malformed)
+        );
+    }
+}
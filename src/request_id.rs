@@ -0,0 +1,132 @@
+//! Giving each request an id, so what a customer reports can be matched with what we logged.
+//!
+//! [assign] gives every request a random UUID, or keeps the one in [HEADER] if it came through a
+//! proxy we trust, see [crate::config::Config::trust_forwarded_for]. The id is sent back in
+//! [HEADER], and errors carry it in the envelope as `request_id`, see [crate::api::ApiError].
+//! Requests we fail to answer are logged with it.
+
+use std::sync::Arc;
+
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use bitcoin::hex::DisplayHex;
+
+use crate::ln::LnBackend;
+use crate::Server;
+
+/// The header we read ids from proxies in, and send them back in.
+pub const HEADER: &str = "x-request-id";
+
+/// How long an id from a proxy can be, longer ones are replaced.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if there's one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Gives the request an id, see the module documentation.
+pub async fn assign<Ln: LnBackend>(
+    State(state): State<Arc<Server<Ln>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let forwarded = state
+        .config
+        .trust_forwarded_for
+        .then(|| request.headers().get(HEADER))
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid(id))
+        .map(str::to_string);
+    let id = forwarded.unwrap_or_else(generate);
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+
+    if response.status().is_server_error() {
+        eprintln!(
+            "[request_id] {id}: {method} {path} failed with {}",
+            response.status()
+        );
+    }
+    response.headers_mut().insert(
+        HEADER,
+        HeaderValue::from_str(&id).expect("ids are visible ASCII"),
+    );
+    response
+}
+
+/// A random (version 4) UUID.
+fn generate() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes.to_lower_hex_string();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Whether a proxy's id is something we can log and send back as it is.
+fn valid(id: &str) -> bool {
+    (1..=MAX_LENGTH).contains(&id.len()) && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::ApiResponse;
+
+    use super::generate;
+    use super::valid;
+    use super::REQUEST_ID;
+
+    #[test]
+    fn ids_are_uuids() {
+        let id = generate();
+        let groups = id.split('-').map(str::len).collect::<Vec<_>>();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]), "{id}");
+        assert!(valid(&id));
+        assert_ne!(id, generate());
+    }
+
+    #[test]
+    fn ids_from_proxies_are_checked() {
+        assert!(valid("req-1234"));
+        assert!(!valid(""));
+        assert!(!valid("with space"));
+        assert!(!valid(&"a".repeat(129)));
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_id() {
+        let error = REQUEST_ID
+            .scope("req-1".to_string(), async {
+                ApiResponse::<()>::error("not_found", "Not Found", None)
+            })
+            .await
+            .error
+            .unwrap();
+        assert_eq!(error.request_id.as_deref(), Some("req-1"));
+
+        // outside of a request there's none
+        let error = ApiResponse::<()>::error("not_found", "Not Found", None).error.unwrap();
+        assert_eq!(error.request_id, None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Holding a locker for a customer on their way to it, so it's still free when they get there.
+//!
+//! `POST /reserve/{locker_id}` puts an available locker in the `reserved` state for
+//! [crate::config::Config::reservation_seconds], and hands out a token of which we only keep the
+//! SHA-256. Until the reservation expires, `/use_locker` only claims the locker for requests
+//! carrying that token in [TOKEN_HEADER]. A locker has at most one reservation at a time, and
+//! [crate::tasks::expire_reservations] makes lockers whose reservation expired available again.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use bitcoin::hex::DisplayHex;
+use serde::Serialize;
+
+use crate::api::ApiResponse;
+use crate::error;
+use crate::ln::LnBackend;
+use crate::now;
+use crate::params;
+use crate::token;
+use crate::Server;
+
+/// The header `/use_locker` reads the reservation token from.
+pub const TOKEN_HEADER: &str = "X-Reservation-Token";
+
+/// A locker held for whoever has the token.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reservation {
+    pub locker_id: i64,
+    /// What to send in [TOKEN_HEADER] to claim the locker. This is the only time it's handed out.
+    pub reservation_token: String,
+    pub expires_at: u64,
+}
+
+/// Makes a new reservation token, returning it along with the digest we keep.
+pub fn generate() -> (String, String) {
+    let token = rand::random::<[u8; 32]>().to_lower_hex_string();
+    let digest = token::digest_hex(&token);
+    (token, digest)
+}
+
+/// The digest of the reservation token in `headers`, if there's one.
+pub fn presented(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(token::digest_hex)
+}
+
+/// Reserves an available locker, see the module documentation.
+pub async fn reserve_locker<Ln: LnBackend>(
+    params::ValidPath(params::LockerId(locker_id)): params::ValidPath<params::LockerId>,
+    state: State<Arc<Server<Ln>>>,
+) -> Result<ApiResponse<Reservation>, error::Error> {
+    let now = now();
+    let expires_at = now + state.config.reservation_seconds;
+    let (reservation_token, digest) = generate();
+
+    state
+        .reserve_locker(locker_id, &digest, now, expires_at)
+        .await?;
+    state
+        .record_event(
+            locker_id,
+            "reserved",
+            "customer",
+            serde_json::json!({ "expires_at": expires_at }),
+        )
+        .await;
+
+    Ok(ApiResponse::ok(Reservation {
+        locker_id,
+        reservation_token,
+        expires_at,
+    }))
+}
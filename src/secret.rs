@@ -0,0 +1,132 @@
+//! Secrets we hold on to for as long as the server runs, like the phoenixd password and the server
+//! keys.
+//!
+//! [Secret] keeps them out of `Debug` and `Display` output, so logging a struct holding one can't
+//! leak it, and overwrites them with zeros once they're dropped. That only covers the copy it
+//! owns: whatever a secret was parsed from, or handed to, is the caller's to keep short lived.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::sync::atomic::compiler_fence;
+use std::sync::atomic::Ordering;
+
+use serde::Deserialize;
+use serde::Deserializer;
+
+/// What we print in place of a secret.
+pub const REDACTED: &str = "[redacted]";
+
+/// Something that can overwrite itself with zeros.
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        // the spare capacity may hold a copy from before the vector last shrank
+        self.resize(self.capacity(), 0);
+        for byte in self.iter_mut() {
+            // volatile, so the writes aren't optimized away even though nothing reads them
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        // zeros are valid UTF-8, and the string is empty afterwards anyway
+        unsafe { self.as_mut_vec() }.zeroize();
+    }
+}
+
+impl Zeroize for secp256k1::Keypair {
+    fn zeroize(&mut self) {
+        self.non_secure_erase();
+    }
+}
+
+/// A secret, which only [Secret::expose] hands out, see the module documentation.
+#[derive(Clone)]
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(secret: T) -> Self {
+        Self(secret)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T: Zeroize> Display for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+    use super::Zeroize;
+    use crate::config::KeyringConfig;
+    use crate::ln::PhoenixdClient;
+
+    const SECRET: &str = "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35";
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret::new(SECRET.to_string());
+        assert_eq!(format!("{secret:?}"), "[redacted]");
+        assert_eq!(format!("{secret}"), "[redacted]");
+        assert_eq!(secret.expose(), SECRET);
+
+        let client = PhoenixdClient::new("http://127.0.0.1:9740".to_string(), SECRET.to_string());
+        assert!(!format!("{client:?}").contains(SECRET));
+
+        let keyring: KeyringConfig = toml::from_str(&format!(
+            "active = \"a\"\n[[keys]]\nid = \"a\"\nsecret = \"{SECRET}\""
+        ))
+        .unwrap();
+        assert_eq!(keyring.keys[0].secret.as_ref().unwrap().expose(), SECRET);
+        assert!(!format!("{keyring:?}").contains(SECRET));
+
+        let keyring = crate::keys::load_keyring(&keyring, 0).unwrap();
+        assert!(format!("{keyring:?}").contains("[redacted]"));
+        assert!(!format!("{keyring:?}").contains(SECRET));
+    }
+
+    #[test]
+    fn secrets_are_zeroed() {
+        let mut secret = SECRET.as_bytes().to_vec();
+        secret.truncate(8);
+        secret.zeroize();
+        assert!(secret.is_empty());
+        // everything the vector ever held, spare capacity included
+        assert!(
+            unsafe { std::slice::from_raw_parts(secret.as_ptr(), secret.capacity()) }
+                .iter()
+                .all(|byte| *byte == 0)
+        );
+    }
+}
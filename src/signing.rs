@@ -0,0 +1,297 @@
+//! What the server and lockers sign when authorizing and reporting opens.
+//!
+//! Each purpose hashes its fields with its own BIP340 style tagged hash, `sha256(sha256(tag) ||
+//! sha256(tag) || message)`, so a signature made for one can't pass as the other. Fields are
+//! encoded big-endian at a fixed width: the locker id as 8 bytes, the [Action] as a single byte,
+//! then each timestamp as 8 bytes.
+//!
+//! Lockers that can't verify signatures share a secret with us instead, and both sides
+//! authenticate the same digests with HMAC-SHA256 keyed with it.
+//!
+//! Everything we sign goes through [sign_schnorr], which mixes fresh randomness into each
+//! signature. Unit tests, and builds with the `deterministic-signatures` feature, sign without it
+//! instead, so signatures can be checked against vectors.
+
+use bitcoin::hashes::hmac;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
+use secp256k1::schnorr::Signature;
+use secp256k1::Keypair;
+use secp256k1::Secp256k1;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The tag of the digests we sign to let someone open a locker.
+pub const OPEN_AUTH_TAG: &str = "locker/open-auth";
+
+/// The tag of the digests lockers sign when reporting they were opened.
+pub const OPEN_REPORT_TAG: &str = "locker/open-report";
+
+/// The tag of the digests of the receipts we hand out, see [crate::receipt].
+pub const RECEIPT_TAG: &str = "locker/receipt";
+
+/// The tag of the digests installers sign with the provisioning key to register a new locker.
+pub const PROVISION_TAG: &str = "locker/provision";
+
+/// The tag of the digests a locker's old key signs to hand over to the key of its new controller.
+pub const ROTATE_KEY_TAG: &str = "locker/rotate-key";
+
+/// The tag of the digests we sign to vouch for a response, see [response_digest].
+pub const RESPONSE_TAG: &str = "locker/response";
+
+/// Why a locker is opened, so an authorization to store something can't be used to take it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Opened after claiming the locker, to put something in.
+    Store,
+    /// Opened after paying, to take it out.
+    Retrieve,
+}
+
+impl Action {
+    fn as_byte(&self) -> u8 {
+        match self {
+            Action::Store => 1,
+            Action::Retrieve => 2,
+        }
+    }
+}
+
+/// What we sign to authorize opening `locker_id` to `action` from `timestamp` until `expires_at`.
+pub fn open_auth_digest(
+    locker_id: i64,
+    action: Action,
+    timestamp: u64,
+    expires_at: u64,
+) -> [u8; 32] {
+    tagged_hash(
+        OPEN_AUTH_TAG,
+        &encode(locker_id, action, timestamp, expires_at),
+    )
+}
+
+/// What a locker signs to report it was opened to `action` at `timestamp`, with an authorization
+/// valid until `expires_at`.
+pub fn open_report_digest(
+    locker_id: i64,
+    action: Action,
+    timestamp: u64,
+    expires_at: u64,
+) -> [u8; 32] {
+    tagged_hash(
+        OPEN_REPORT_TAG,
+        &encode(locker_id, action, timestamp, expires_at),
+    )
+}
+
+/// What we sign to vouch for a receipt, given its canonical serialization.
+pub fn receipt_digest(serialized: &[u8]) -> [u8; 32] {
+    tagged_hash(RECEIPT_TAG, serialized)
+}
+
+/// What installers sign with [crate::config::Config::provisioning_pubkey] to let the locker with
+/// the x-only public key `pk` register.
+pub fn provision_digest(pk: &[u8; 32]) -> [u8; 32] {
+    tagged_hash(PROVISION_TAG, pk)
+}
+
+/// What the old key of `locker_id` signs to vouch for `pk`, the x-only public key of its new
+/// controller: the locker id as 8 bytes, then the key.
+pub fn rotate_key_digest(locker_id: i64, pk: &[u8; 32]) -> [u8; 32] {
+    let mut message = [0; 40];
+    message[..8].copy_from_slice(&locker_id.to_be_bytes());
+    message[8..].copy_from_slice(pk);
+    tagged_hash(ROTATE_KEY_TAG, &message)
+}
+
+/// What we sign to vouch for a response with `body`, sent at `timestamp`: the timestamp as 8
+/// bytes, then the SHA-256 of the body.
+pub fn response_digest(timestamp: u64, body: &[u8]) -> [u8; 32] {
+    let mut message = [0; 40];
+    message[..8].copy_from_slice(&timestamp.to_be_bytes());
+    message[8..].copy_from_slice(sha256::Hash::hash(body).as_byte_array());
+    tagged_hash(RESPONSE_TAG, &message)
+}
+
+/// The HMAC tag we give lockers sharing `secret` with us to authorize opening `locker_id` to
+/// `action` from `timestamp` until `expires_at`.
+pub fn open_auth_hmac(
+    secret: &[u8],
+    locker_id: i64,
+    action: Action,
+    timestamp: u64,
+    expires_at: u64,
+) -> [u8; 32] {
+    hmac(
+        secret,
+        &open_auth_digest(locker_id, action, timestamp, expires_at),
+    )
+}
+
+/// The HMAC tag a locker sharing `secret` with us sends when reporting it was opened to `action`
+/// at `timestamp`, with an authorization valid until `expires_at`.
+pub fn open_report_hmac(
+    secret: &[u8],
+    locker_id: i64,
+    action: Action,
+    timestamp: u64,
+    expires_at: u64,
+) -> [u8; 32] {
+    hmac(
+        secret,
+        &open_report_digest(locker_id, action, timestamp, expires_at),
+    )
+}
+
+/// Signs `digest` with BIP340, using fresh auxiliary randomness so that a fault while signing
+/// doesn't leak the key and signatures over the same digest can't be linked.
+pub fn sign_schnorr(keypair: &Keypair, digest: &[u8; 32]) -> Signature {
+    let secp = Secp256k1::signing_only();
+    if cfg!(any(test, feature = "deterministic-signatures")) {
+        secp.sign_schnorr_no_aux_rand(digest, keypair)
+    } else {
+        secp.sign_schnorr_with_aux_rand(digest, keypair, &rand::random())
+    }
+}
+
+/// Compares two byte strings without bailing out at the first difference, so the time it takes
+/// doesn't tell an attacker how much of their guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// What firmware from before authorizations expired signs and verifies, for both purposes, only
+/// used while [crate::config::Config::legacy_signatures] holds.
+///
+/// This is the midstate of a sha256 engine fed `{locker_id}{timestamp}`, which is less than a
+/// block, so it's always the sha256 initial state: these signatures don't commit to anything.
+pub fn legacy_digest(locker_id: i64, timestamp: u64) -> [u8; 32] {
+    let mut engine = sha256::HashEngine::default();
+    engine.input(format!("{locker_id}{timestamp}").as_bytes());
+    engine.midstate().0
+}
+
+fn encode(locker_id: i64, action: Action, timestamp: u64, expires_at: u64) -> [u8; 25] {
+    let mut message = [0; 25];
+    message[..8].copy_from_slice(&locker_id.to_be_bytes());
+    message[8] = action.as_byte();
+    message[9..17].copy_from_slice(&timestamp.to_be_bytes());
+    message[17..].copy_from_slice(&expires_at.to_be_bytes());
+    message
+}
+
+fn tagged_hash(tag: &str, message: &[u8]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+
+    let mut engine = sha256::HashEngine::default();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    engine.input(message);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn hmac(secret: &[u8], digest: &[u8; 32]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret);
+    engine.input(digest);
+    hmac::Hmac::from_engine(engine).to_byte_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hex::DisplayHex;
+    use secp256k1::Keypair;
+    use secp256k1::Secp256k1;
+
+    use super::Action;
+
+    #[test]
+    fn digests_match_vectors() {
+        assert_eq!(
+            super::open_auth_digest(1, Action::Store, 1700000000, 1700000900).to_lower_hex_string(),
+            "7df2cefd58ad9c82b2af4fa4f4f7336d328f048ab53f35aeea21adaa1d0ced2a"
+        );
+        assert_eq!(
+            super::open_auth_digest(1, Action::Retrieve, 1700000000, 1700000900)
+                .to_lower_hex_string(),
+            "656845109606a1aa2c815a7221093b7d93f5385190254c742baccf665a3355a3"
+        );
+        assert_eq!(
+            super::open_report_digest(1, Action::Store, 1700000000, 1700000900)
+                .to_lower_hex_string(),
+            "3c6db0842e577232fcfc3c7111ba2eeaa55cacab4e38c2efc6cf1b79e79ae69d"
+        );
+        assert_eq!(
+            super::open_report_digest(1, Action::Retrieve, 1700000000, 1700000900)
+                .to_lower_hex_string(),
+            "a1ac6991c97729efb96db5411aecb38b2f8253d5c9ba0ca6621104e1560b0a80"
+        );
+        assert_eq!(
+            super::rotate_key_digest(1, &[0x42; 32]).to_lower_hex_string(),
+            "d61b1bfb1d5856d72fa1a0344cd9d5712f0b44610bfb74c43ceab46aa52e6720"
+        );
+    }
+
+    #[test]
+    fn hmac_tags_match_vectors() {
+        let secret = [0x42; 32];
+        assert_eq!(
+            super::open_auth_hmac(&secret, 1, Action::Store, 1700000000, 1700000900)
+                .to_lower_hex_string(),
+            "ad91b256eed7a8c52f2ed5b57dc9ddabe1638303001bcad61d9882d1125ef0c9"
+        );
+        assert_eq!(
+            super::open_report_hmac(&secret, 1, Action::Retrieve, 1700000000, 1700000900)
+                .to_lower_hex_string(),
+            "8800e2dc92e266550ef234d43725793c1a2359a3e622600d74094e7c39437ba5"
+        );
+    }
+
+    #[test]
+    fn responses_are_verified() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_byte_array(&secp, [0x42; 32]).unwrap();
+        let body = br#"{"data":{"id":1},"error":null}"#;
+
+        assert_eq!(
+            super::response_digest(1700000000, body).to_lower_hex_string(),
+            "a5a6d8b671c8e6a78c971558664bd231f6a0281c6a73d88fd9da3c3259ad7322"
+        );
+
+        let signature = super::sign_schnorr(&keypair, &super::response_digest(1700000000, body));
+        let verify = |timestamp, body: &[u8]| {
+            secp.verify_schnorr(
+                &signature,
+                &super::response_digest(timestamp, body),
+                &keypair.x_only_public_key().0,
+            )
+        };
+        assert!(verify(1700000000, body).is_ok());
+        assert!(verify(1700000001, body).is_err());
+
+        // flipping any byte of the body
+        for i in 0..body.len() {
+            let mut tampered = body.to_vec();
+            tampered[i] ^= 1;
+            assert!(verify(1700000000, &tampered).is_err());
+        }
+    }
+
+    #[test]
+    fn fields_do_not_run_together() {
+        // "12" + "34" and "1" + "234" used to be the same message
+        assert_ne!(
+            super::open_auth_digest(12, Action::Store, 34, 100),
+            super::open_auth_digest(1, Action::Store, 234, 100)
+        );
+        assert_ne!(
+            super::open_report_digest(12, Action::Store, 34, 100),
+            super::open_report_digest(1, Action::Store, 234, 100)
+        );
+    }
+}
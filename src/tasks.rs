@@ -0,0 +1,314 @@
+//! Background tasks that run for as long as the server does.
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::db;
+use crate::ln::LnBackend;
+use crate::ln::INVOICE_EXPIRY_SECONDS;
+use crate::now;
+use crate::webhook;
+use crate::Server;
+
+/// Periodically marks pending payments whose invoice expired as `expired`, and deletes expired
+/// payments past their retention period, if one is configured.
+///
+/// Expiring a payment doesn't release its locker: the session it was meant to cover is still
+/// open and keeps accruing time, so the customer has to ask for a new invoice before they can
+/// get their things back.
+pub async fn cleanup_payments<Ln: LnBackend>(state: Arc<Server<Ln>>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.cleanup_interval_seconds));
+
+    loop {
+        interval.tick().await;
+
+        let now = now();
+        let expired = match state
+            .expire_pending_payments(now.saturating_sub(INVOICE_EXPIRY_SECONDS), now)
+            .await
+        {
+            Ok(expired) => expired,
+            Err(e) => {
+                eprintln!("[cleanup_payments] failed to expire pending payments: {e:?}");
+                continue;
+            }
+        };
+
+        for (locker_id, payment_hash) in &expired {
+            state.payment_watch.notify(payment_hash);
+            state
+                .record_event(
+                    *locker_id,
+                    "payment_expired",
+                    "server",
+                    serde_json::json!({ "payment_hash": payment_hash }),
+                )
+                .await;
+        }
+
+        let mut deleted = 0;
+        if let Some(days) = state.config.expired_payment_retention_days {
+            let cutoff = now.saturating_sub(days * 24 * 60 * 60);
+            deleted = match state.delete_expired_payments(cutoff).await {
+                Ok(deleted) => deleted,
+                Err(e) => {
+                    eprintln!("[cleanup_payments] failed to delete expired payments: {e:?}");
+                    0
+                }
+            };
+        }
+
+        if let Err(e) = state.delete_expired_tokens(now).await {
+            eprintln!("[cleanup_payments] failed to delete expired tokens: {e:?}");
+        }
+
+        if let Err(e) = state.delete_expired_logins(now).await {
+            eprintln!("[cleanup_payments] failed to delete expired logins: {e:?}");
+        }
+
+        if let Err(e) = state.delete_expired_idempotency_keys(now).await {
+            eprintln!("[cleanup_payments] failed to delete expired idempotency keys: {e:?}");
+        }
+
+        let metrics = &state.metrics;
+        metrics
+            .expired_payments
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+        metrics.deleted_payments.fetch_add(deleted, Ordering::Relaxed);
+        metrics.last_payment_cleanup.store(now, Ordering::Relaxed);
+
+        if !expired.is_empty() || deleted > 0 {
+            println!(
+                "[cleanup_payments] expired {} payments, deleted {deleted}",
+                expired.len()
+            );
+        }
+    }
+}
+
+/// Periodically writes a backup of the database into `dir`, named after when it was taken.
+pub async fn backup_database<Ln: LnBackend>(state: Arc<Server<Ln>>, dir: PathBuf) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.backup_interval_seconds));
+
+    loop {
+        interval.tick().await;
+
+        let now = now();
+        let path = dir.join(format!("backup-{now}.sqlite"));
+        match db::backup(&state.database, &path).await {
+            Ok(()) => {
+                state.metrics.last_backup.store(now, Ordering::Relaxed);
+                println!("[backup_database] wrote {}", path.display());
+            }
+            Err(e) => eprintln!("[backup_database] failed to write {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Periodically checks the lightning backend is reachable, so `/health` can tell without making
+/// every probe reach it.
+pub async fn check_ln_backend<Ln: LnBackend>(state: Arc<Server<Ln>>) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.ln_check_interval_seconds));
+
+    loop {
+        interval.tick().await;
+
+        let backend = state.clone();
+        let reachable = tokio::task::spawn_blocking(move || backend.ln.get_info().is_ok())
+            .await
+            .unwrap_or(false);
+
+        let now = now();
+        let metrics = &state.metrics;
+        if reachable {
+            metrics.last_ln_reachable.store(now, Ordering::Relaxed);
+        } else if metrics.last_ln_reachable.load(Ordering::Relaxed)
+            >= metrics.last_ln_check.load(Ordering::Relaxed)
+        {
+            // only when it stops answering, not on every check while it's down
+            eprintln!("[check_ln_backend] the lightning backend is unreachable");
+        }
+        metrics.last_ln_check.store(now, Ordering::Relaxed);
+    }
+}
+
+/// How often, in seconds, we look for reservations that expired.
+const RESERVATION_SWEEP_SECONDS: u64 = 5;
+
+/// Makes lockers whose reservation expired available again, see [crate::reservation].
+pub async fn expire_reservations<Ln: LnBackend>(state: Arc<Server<Ln>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(RESERVATION_SWEEP_SECONDS));
+
+    loop {
+        interval.tick().await;
+
+        let lockers = match state.expire_reservations(now()).await {
+            Ok(lockers) => lockers,
+            Err(e) => {
+                eprintln!("[expire_reservations] failed to expire reservations: {e:?}");
+                continue;
+            }
+        };
+
+        for locker_id in lockers {
+            state
+                .record_event(
+                    locker_id,
+                    "reservation_expired",
+                    "server",
+                    serde_json::json!({}),
+                )
+                .await;
+        }
+    }
+}
+
+/// How often, in seconds, we look for webhook deliveries due to be tried again.
+const WEBHOOK_POLL_SECONDS: u64 = 1;
+
+/// How many webhook deliveries we send at once.
+const WEBHOOK_BATCH: u64 = 32;
+
+/// Sends the webhooks [Server::send_webhooks] queued, trying those that fail again later, see
+/// [webhook].
+pub async fn deliver_webhooks<Ln: LnBackend>(state: Arc<Server<Ln>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(WEBHOOK_POLL_SECONDS));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.webhooks_queued.notified() => {}
+        }
+
+        let due = match state.due_webhook_deliveries(now(), WEBHOOK_BATCH).await {
+            Ok(due) => due,
+            Err(e) => {
+                eprintln!("[deliver_webhooks] failed to list the due deliveries: {e:?}");
+                continue;
+            }
+        };
+
+        let attempts: Vec<_> = due
+            .into_iter()
+            .map(|delivery| {
+                tokio::task::spawn_blocking(move || {
+                    let result =
+                        webhook::deliver(&delivery.url, delivery.secret.expose(), &delivery.body, now());
+                    (delivery, result)
+                })
+            })
+            .collect();
+
+        for attempt in attempts {
+            let Ok((delivery, result)) = attempt.await else {
+                continue;
+            };
+
+            let now = now();
+            let attempts = delivery.attempts + 1;
+            let (last_status, last_error) = match result {
+                Ok(status) => (Some(status), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let delivered = last_status.is_some_and(|status| (200..300).contains(&status));
+            let (status, next_attempt_at) = if delivered {
+                ("delivered", None)
+            } else if attempts >= state.config.webhook_max_attempts {
+                eprintln!(
+                    "[deliver_webhooks] giving up on delivery {} to {} after {attempts} attempts",
+                    delivery.id, delivery.url
+                );
+                ("dead", None)
+            } else {
+                let delay = webhook::retry_delay(state.config.webhook_retry_seconds, attempts);
+                ("pending", Some(now + delay))
+            };
+
+            if let Err(e) = state
+                .record_webhook_attempt(
+                    delivery.id,
+                    status,
+                    last_status,
+                    last_error,
+                    next_attempt_at,
+                    now,
+                )
+                .await
+            {
+                eprintln!("[deliver_webhooks] failed to record delivery {}: {e:?}", delivery.id);
+            }
+        }
+    }
+}
+
+/// How long we wait before trying again when maintenance was put off.
+const MAINTENANCE_RETRY_SECONDS: u64 = 10 * 60;
+
+/// Checkpoints and optimizes the database every day, or whatever the configured interval is,
+/// starting at the configured quiet hour.
+///
+/// Checkpointing waits for every reader and writer to get out of the way, so if anyone is in the
+/// middle of paying for a locker we put it off for a few minutes rather than making them wait. A
+/// run that was put off is followed by one at the quiet hour again, rather than every interval
+/// after it.
+pub async fn maintain_database<Ln: LnBackend>(state: Arc<Server<Ln>>) {
+    let until_quiet_hour = seconds_until_hour(now(), state.config.maintenance_hour);
+    tokio::time::sleep(Duration::from_secs(until_quiet_hour)).await;
+
+    let mut deferred = false;
+    loop {
+        match state
+            .has_payments_in_flight(now().saturating_sub(INVOICE_EXPIRY_SECONDS))
+            .await
+        {
+            Ok(false) => {}
+            Ok(true) => {
+                println!("[maintain_database] payments in flight, trying again later");
+                deferred = true;
+                tokio::time::sleep(Duration::from_secs(MAINTENANCE_RETRY_SECONDS)).await;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("[maintain_database] failed to look for payments in flight: {e:?}");
+                deferred = true;
+                tokio::time::sleep(Duration::from_secs(MAINTENANCE_RETRY_SECONDS)).await;
+                continue;
+            }
+        }
+
+        let start = Instant::now();
+        match state.database.write(db::maintain).await {
+            Ok(maintenance) => {
+                state.metrics.last_maintenance.store(now(), Ordering::Relaxed);
+                println!(
+                    "[maintain_database] took {:?}, reclaimed {} pages of the WAL's {}{}",
+                    start.elapsed(),
+                    maintenance.reclaimed_pages(),
+                    maintenance.wal_pages,
+                    if maintenance.busy { ", which couldn't be truncated" } else { "" },
+                );
+            }
+            Err(e) => eprintln!("[maintain_database] failed: {e}"),
+        }
+
+        let next_run = if deferred {
+            seconds_until_hour(now(), state.config.maintenance_hour)
+        } else {
+            state.config.maintenance_interval_seconds
+        };
+        deferred = false;
+        tokio::time::sleep(Duration::from_secs(next_run)).await;
+    }
+}
+
+/// How many seconds from `now` until the clock next reads `hour` o'clock, in UTC.
+fn seconds_until_hour(now: u64, hour: u64) -> u64 {
+    const DAY: u64 = 24 * 60 * 60;
+    (hour * 60 * 60 + DAY - now % DAY) % DAY
+}
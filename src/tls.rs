@@ -0,0 +1,161 @@
+//! Serving HTTPS ourselves, for kiosks on networks we don't trust where nobody set up a reverse
+//! proxy to do it for us, see [crate::config::Config::tls].
+//!
+//! The certificate and its key are read once at startup by [load], which refuses them if either
+//! file can't be read or they don't belong together, so a bad configuration stops us right away
+//! rather than failing every handshake. [redirect] answers plain HTTP on another address by
+//! pointing clients still using `http://` to the same URL over HTTPS.
+
+use std::fmt::Display;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::http::header;
+use axum::http::uri::Authority;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+
+use crate::config::TlsConfig;
+
+/// Why we couldn't load the certificate we serve HTTPS with.
+#[derive(Debug)]
+pub enum TlsError {
+    /// We couldn't read one of the files.
+    Read(PathBuf, std::io::Error),
+    /// The certificate file doesn't hold any certificate in PEM.
+    NoCertificate(PathBuf),
+    /// The key file doesn't hold a private key in PEM.
+    NoKey(PathBuf),
+    /// rustls refused the certificate along with its key, usually because they don't match.
+    Rejected(rustls::Error),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Read(path, e) => write!(f, "can't read {}: {e}", path.display()),
+            TlsError::NoCertificate(path) => {
+                write!(f, "{} doesn't hold a PEM certificate", path.display())
+            }
+            TlsError::NoKey(path) => write!(f, "{} doesn't hold a PEM private key", path.display()),
+            TlsError::Rejected(rustls::Error::InconsistentKeys(_)) => {
+                write!(f, "the private key doesn't match the certificate")
+            }
+            TlsError::Rejected(e) => write!(f, "invalid certificate or private key: {e}"),
+        }
+    }
+}
+
+/// Reads the certificate chain and private key `config` points to, checking they match.
+pub fn load(config: &TlsConfig) -> Result<RustlsConfig, TlsError> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| TlsError::Read(path.into(), e));
+
+    let certs = CertificateDer::pem_slice_iter(&read(&config.cert_file)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TlsError::NoCertificate(config.cert_file.clone()))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(config.cert_file.clone()));
+    }
+    let key = PrivateKeyDer::from_pem_slice(&read(&config.key_file)?)
+        .map_err(|_| TlsError::NoKey(config.key_file.clone()))?;
+
+    let mut server_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(TlsError::Rejected)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(RustlsConfig::from_config(Arc::new(server_config)))
+}
+
+/// Answers every plain HTTP request on `address` with a permanent redirect to the same URL over
+/// HTTPS, which we serve on `https_port`. Permanent redirects keep the method and body, so lockers
+/// posting their reports over plain HTTP aren't turned into GETs on the way.
+pub async fn redirect(address: String, https_port: u16) {
+    let listener = match tokio::net::TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("[redirect] failed to listen on {address}: {e}");
+            std::process::exit(-1);
+        }
+    };
+    println!("[+] Redirecting plain HTTP on {address} to HTTPS");
+
+    let router = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+        match https_url(host, https_port, &uri) {
+            Some(url) => Redirect::permanent(&url).into_response(),
+            None => (StatusCode::BAD_REQUEST, "missing or invalid Host header").into_response(),
+        }
+    });
+
+    axum::serve(listener, router)
+        .await
+        .expect("failed to start the HTTPS redirect");
+}
+
+/// Where a request for `uri` on `host`, as sent in its `Host` header, is found over HTTPS.
+fn https_url(host: Option<&str>, https_port: u16, uri: &Uri) -> Option<String> {
+    let authority: Authority = host?.parse().ok()?;
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    Some(format!("https://{}{port}{path}", authority.host()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::http::Uri;
+
+    use super::TlsError;
+    use crate::config::TlsConfig;
+
+    #[test]
+    fn redirects_to_the_same_url_over_https() {
+        let uri: Uri = "/v1/lockers?page=2".parse().unwrap();
+        assert_eq!(
+            super::https_url(Some("lockers.example.com:80"), 8080, &uri).as_deref(),
+            Some("https://lockers.example.com:8080/v1/lockers?page=2")
+        );
+        assert_eq!(
+            super::https_url(Some("[::1]"), 443, &uri).as_deref(),
+            Some("https://[::1]/v1/lockers?page=2")
+        );
+        assert_eq!(super::https_url(None, 443, &uri), None);
+        assert_eq!(super::https_url(Some("not a host"), 443, &uri), None);
+    }
+
+    #[test]
+    fn unreadable_files_are_refused() {
+        let config = TlsConfig {
+            cert_file: PathBuf::from("does-not-exist.pem"),
+            key_file: PathBuf::from("does-not-exist.key"),
+            redirect_address: None,
+        };
+        assert!(matches!(
+            super::load(&config),
+            Err(TlsError::Read(path, _)) if path == config.cert_file
+        ));
+
+        // anything without a PEM certificate in it
+        let config = TlsConfig {
+            cert_file: PathBuf::from("Cargo.toml"),
+            ..config
+        };
+        assert!(matches!(super::load(&config), Err(TlsError::NoCertificate(_))));
+    }
+}
@@ -0,0 +1,374 @@
+//! The JWTs lockers accept as proof that someone may open them.
+//!
+//! Tokens are signed with ES256K, that is ECDSA over secp256k1 with SHA-256 as described in RFC
+//! 8812, using the active server key, so lockers verify them against the same public keys listed
+//! under `GET /keys`. The header's `kid` says which key signed the token. The signature is the 64
+//! byte `r || s` encoding, with `s` always in its low form.
+//!
+//! A token only opens one locker, for one [Action], during one usage session, so a token for
+//! storing something can't later be used to take it out without paying. Each carries a random
+//! `jti`, which [crate::Server::consume_token] remembers so it can only be used once.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use secp256k1::ecdsa::Signature;
+use secp256k1::Message;
+use secp256k1::Secp256k1;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::keys::Keyring;
+use crate::keys::ServerKey;
+use crate::signing::Action;
+
+/// The only algorithm we issue or accept.
+const ALG: &str = "ES256K";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    alg: String,
+    typ: String,
+    /// The id of the key that signed the token, tokens from before we had several keys don't
+    /// have one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+}
+
+/// What a token allows its bearer to do.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Claims {
+    pub locker_id: i64,
+    /// The usage session the locker may be opened during.
+    pub session_id: i64,
+    /// When the token was issued, as a unix timestamp.
+    pub iat: u64,
+    /// When the token stops being valid, as a unix timestamp.
+    pub exp: u64,
+    /// What the bearer may open the locker for.
+    pub action: Action,
+    /// A random id, unique to this token, telling whether it was already used.
+    pub jti: String,
+    /// The payment this token was bought with, tokens for claiming a locker don't have one.
+    pub payment_hash: Option<String>,
+}
+
+impl Claims {
+    /// Claims to open `locker_id` to `action` during `session_id`, valid for `ttl_seconds` after
+    /// `now`.
+    pub fn open(
+        locker_id: i64,
+        session_id: i64,
+        action: Action,
+        payment_hash: Option<String>,
+        now: u64,
+        ttl_seconds: u64,
+    ) -> Self {
+        Self {
+            locker_id,
+            session_id,
+            iat: now,
+            exp: now.saturating_add(ttl_seconds),
+            action,
+            jti: rand::random::<[u8; 16]>().to_lower_hex_string(),
+            payment_hash,
+        }
+    }
+}
+
+/// Why a token was rejected by [verify].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token isn't three base64url parts, or their contents aren't what we expect.
+    Malformed,
+    /// The token wasn't signed by us, was changed after we signed it, or was signed with a key we
+    /// don't trust anymore.
+    BadSignature,
+    /// The token was valid, but isn't anymore.
+    Expired,
+    /// The token is for another locker.
+    WrongLocker,
+    /// The token is for opening the locker for something else.
+    WrongAction,
+}
+
+/// Signs `claims` into a token.
+pub fn issue(claims: &Claims, key: &ServerKey) -> String {
+    let header = Header {
+        alg: ALG.to_string(),
+        typ: "JWT".to_string(),
+        kid: Some(key.id.clone()),
+    };
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).expect("headers always serialize")),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("claims always serialize"))
+    );
+
+    let signature = Secp256k1::signing_only()
+        .sign_ecdsa(digest(&signing_input), &key.keypair.expose().secret_key())
+        .serialize_compact();
+
+    format!("{signing_input}.{}", URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// Checks that `token` was signed with one of our keys that is still valid, and hasn't expired at
+/// `now`, returning its claims. Tokens without a `kid` must be signed with the active key.
+pub fn verify(token: &str, keys: &Keyring, now: u64) -> Result<Claims, TokenError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(TokenError::Malformed);
+    };
+
+    let decode = |part: &str| {
+        URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| TokenError::Malformed)
+    };
+
+    // we only ever sign with one algorithm, anything else isn't ours
+    let parsed: Header =
+        serde_json::from_slice(&decode(header)?).map_err(|_| TokenError::Malformed)?;
+    if parsed.alg != ALG || parsed.typ != "JWT" {
+        return Err(TokenError::Malformed);
+    }
+
+    let key = match &parsed.kid {
+        Some(kid) => keys.get(kid).ok_or(TokenError::BadSignature)?,
+        None => keys.active(),
+    };
+    if !key.is_valid_at(now) {
+        return Err(TokenError::BadSignature);
+    }
+
+    let signature =
+        Signature::from_compact(&decode(signature)?).map_err(|_| TokenError::Malformed)?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(
+            digest(&format!("{header}.{payload}")),
+            &signature,
+            &key.keypair.expose().public_key(),
+        )
+        .map_err(|_| TokenError::BadSignature)?;
+
+    let claims: Claims =
+        serde_json::from_slice(&decode(payload)?).map_err(|_| TokenError::Malformed)?;
+    if claims.exp <= now {
+        return Err(TokenError::Expired);
+    }
+
+    Ok(claims)
+}
+
+/// Like [verify], but also checks the token lets its bearer open `locker_id`, and to `action` if
+/// given. Lockers that leave the action out should do what the returned claims say.
+///
+/// This can't tell whether the token was already used, or its session is over, only we know
+/// that, see [crate::Server::consume_token].
+pub fn verify_scoped(
+    token: &str,
+    keys: &Keyring,
+    locker_id: i64,
+    action: Option<Action>,
+    now: u64,
+) -> Result<Claims, TokenError> {
+    let claims = verify(token, keys, now)?;
+    if claims.locker_id != locker_id {
+        return Err(TokenError::WrongLocker);
+    }
+
+    if action.is_some_and(|action| action != claims.action) {
+        return Err(TokenError::WrongAction);
+    }
+
+    Ok(claims)
+}
+
+/// Identifies a token without keeping the token itself around, for remembering which ones were
+/// already used.
+pub fn digest_hex(token: &str) -> String {
+    bitcoin::hashes::sha256::Hash::hash(token.as_bytes()).to_string()
+}
+
+/// The SHA-256 of the part of a token that gets signed.
+fn digest(signing_input: &str) -> Message {
+    Message::from_digest(
+        bitcoin::hashes::sha256::Hash::hash(signing_input.as_bytes()).to_byte_array(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use secp256k1::Keypair;
+    use secp256k1::Secp256k1;
+
+    use super::Claims;
+    use super::TokenError;
+    use crate::keys::Keyring;
+    use crate::keys::ServerKey;
+    use crate::secret::Secret;
+    use crate::signing::Action;
+
+    fn keypair(seckey: &str) -> Keypair {
+        Keypair::from_seckey_str(&Secp256k1::new(), seckey).unwrap()
+    }
+
+    /// Claims valid from 1000 until 1060, during session 7.
+    fn claims(locker_id: i64, action: Action) -> Claims {
+        Claims::open(locker_id, 7, action, None, 1000, 60)
+    }
+
+    fn server() -> Keyring {
+        Keyring::single(keypair(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        ))
+    }
+
+    #[test]
+    fn issued_tokens_verify() {
+        let claims = Claims::open(3, 7, Action::Retrieve, Some("hash".to_string()), 1000, 60);
+        let token = super::issue(&claims, server().active());
+
+        assert_eq!(super::verify(&token, &server(), 1059), Ok(claims));
+
+        // the header tells generic JWT libraries how to check the signature, and with which key
+        let header = URL_SAFE_NO_PAD
+            .decode(token.split('.').next().unwrap())
+            .unwrap();
+        assert_eq!(
+            header,
+            br#"{"alg":"ES256K","typ":"JWT","kid":"79be667ef9dcbbac"}"#
+        );
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let token = super::issue(&claims(3, Action::Store), server().active());
+
+        assert_eq!(
+            super::verify(&token, &server(), 1060),
+            Err(TokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn tokens_from_rotated_keys() {
+        let key = |id: &str, seckey: &str, not_after: Option<u64>| ServerKey {
+            id: id.to_string(),
+            keypair: Secret::new(keypair(seckey)),
+            not_before: None,
+            not_after,
+        };
+        let old = key(
+            "old",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            Some(1030),
+        );
+        let new = key(
+            "new",
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            None,
+        );
+        let keys = Keyring::new(vec![old.clone(), new.clone()], "new", 1000).unwrap();
+
+        // tokens signed with the old key pass until it's retired
+        let claims = claims(3, Action::Store);
+        let token = super::issue(&claims, &old);
+        assert_eq!(super::verify(&token, &keys, 1029), Ok(claims.clone()));
+        assert_eq!(
+            super::verify(&token, &keys, 1030),
+            Err(TokenError::BadSignature)
+        );
+
+        let token = super::issue(&claims, &new);
+        assert_eq!(super::verify(&token, &keys, 1030), Ok(claims));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = super::issue(&claims(3, Action::Store), server().active());
+        let keys = server();
+        let parts: Vec<&str> = token.split('.').collect();
+
+        // someone wants to open another locker
+        let payload =
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims(4, Action::Store)).unwrap());
+        let forged = format!("{}.{payload}.{}", parts[0], parts[2]);
+        assert_eq!(
+            super::verify(&forged, &keys, 1000),
+            Err(TokenError::BadSignature)
+        );
+
+        // or signs their own token, under their own key id or ours
+        let mut other = Keyring::single(keypair(
+            "0000000000000000000000000000000000000000000000000000000000000002",
+        ))
+        .active()
+        .clone();
+        let forged = super::issue(&claims(4, Action::Store), &other);
+        assert_eq!(
+            super::verify(&forged, &keys, 1000),
+            Err(TokenError::BadSignature)
+        );
+
+        other.id = keys.active().id.clone();
+        let forged = super::issue(&claims(4, Action::Store), &other);
+        assert_eq!(
+            super::verify(&forged, &keys, 1000),
+            Err(TokenError::BadSignature)
+        );
+
+        // or tries to get us to skip the signature
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#);
+        let forged = format!("{header}.{}.", parts[1]);
+        assert_eq!(
+            super::verify(&forged, &keys, 1000),
+            Err(TokenError::Malformed)
+        );
+
+        assert_eq!(
+            super::verify("not a token", &keys, 1000),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn tokens_only_open_what_they_were_issued_for() {
+        let keys = server();
+        let token = super::issue(&claims(3, Action::Store), keys.active());
+
+        assert!(super::verify_scoped(&token, &keys, 3, Some(Action::Store), 1000).is_ok());
+        // firmware that doesn't say what it's about to do is told what the token allows
+        assert_eq!(
+            super::verify_scoped(&token, &keys, 3, None, 1000).map(|claims| claims.action),
+            Ok(Action::Store)
+        );
+
+        // storing something doesn't let you take it out without paying
+        assert_eq!(
+            super::verify_scoped(&token, &keys, 3, Some(Action::Retrieve), 1000),
+            Err(TokenError::WrongAction)
+        );
+        assert_eq!(
+            super::verify_scoped(&token, &keys, 4, Some(Action::Store), 1000),
+            Err(TokenError::WrongLocker)
+        );
+        assert_eq!(
+            super::verify_scoped(&token, &keys, 3, Some(Action::Store), 1060),
+            Err(TokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn tokens_have_unique_ids() {
+        let (first, second) = (claims(3, Action::Store), claims(3, Action::Store));
+        assert_eq!(first.jti.len(), 32);
+        assert_ne!(first.jti, second.jti);
+    }
+}
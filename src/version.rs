@@ -0,0 +1,41 @@
+//! What's deployed, for `GET /version` and the startup log. The commit and build time are
+//! embedded by `build.rs`.
+
+use serde::Serialize;
+
+use crate::api::ApiResponse;
+use crate::error;
+use crate::ln::LnBackend;
+use crate::API_VERSION;
+
+/// Describes the running build. Nothing in it is secret.
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+    /// The version of the crate, from `Cargo.toml`.
+    pub version: &'static str,
+    /// The commit we were built from, `unknown` if we weren't built from a git checkout.
+    pub git_commit: &'static str,
+    /// When we were built, as a unix timestamp.
+    pub built_at: u64,
+    /// The lightning backend we use, see [LnBackend::NAME].
+    pub ln_backend: &'static str,
+    /// The version of the API, see [API_VERSION].
+    pub api_version: u32,
+}
+
+impl Version {
+    pub fn current<Ln: LnBackend>() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("GIT_COMMIT"),
+            built_at: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+            ln_backend: Ln::NAME,
+            api_version: API_VERSION,
+        }
+    }
+}
+
+/// Tells ops what's deployed.
+pub async fn get_version<Ln: LnBackend>() -> Result<ApiResponse<Version>, error::Error> {
+    Ok(ApiResponse::ok(Version::current::<Ln>()))
+}
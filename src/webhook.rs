@@ -0,0 +1,213 @@
+//! The webhooks we send to let kiosks and apps know something happened, like a payment settling,
+//! rather than having them poll us.
+//!
+//! Each subscription has a secret of its own, which we hand out when it's created. Every delivery
+//! is a `POST` of a JSON body carrying the time it was sent in [TIMESTAMP_HEADER], and an
+//! HMAC-SHA256, keyed with the secret, over `{timestamp}.{body}` in [SIGNATURE_HEADER], as
+//! `v1=` followed by the tag in hex. Receivers check it with [verify_webhook], and refuse
+//! deliveries older than [TOLERANCE_SECONDS] so a captured one can't be replayed later.
+//!
+//! Subscriptions can ask for only some of the [EVENTS]. Each event gets an id, in the body, and is
+//! queued for every subscription that wants it, and a background task delivers the queue. A
+//! delivery the receiver doesn't answer with a 2xx is tried again after [retry_delay], and given up
+//! on, as `dead`, after [crate::config::Config::webhook_max_attempts] attempts. Receivers may get
+//! the same event more than once, and should tell them apart by their id.
+
+use std::fmt::Display;
+
+use bitcoin::hashes::hmac;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
+use bitcoin::hex::DisplayHex;
+use bitcoin::hex::FromHex;
+
+use crate::signing;
+
+/// The header holding when a delivery was sent, as a unix timestamp.
+pub const TIMESTAMP_HEADER: &str = "X-Locker-Timestamp";
+
+/// The header holding the signature of a delivery, see the module documentation.
+pub const SIGNATURE_HEADER: &str = "X-Locker-Signature";
+
+/// How far, in seconds, the timestamp of a delivery may be from the receiver's clock.
+pub const TOLERANCE_SECONDS: u64 = 5 * 60;
+
+/// How long, in seconds, we wait for a receiver to answer before giving up on a delivery.
+pub const DELIVERY_TIMEOUT_SECONDS: u64 = 10;
+
+/// The events subscriptions can ask for.
+pub const EVENTS: &[&str] = &["payment.settled", "locker.opened"];
+
+/// The longest, in seconds, we wait before trying a delivery again.
+pub const MAX_RETRY_DELAY_SECONDS: u64 = 60 * 60;
+
+/// Why a delivery was refused.
+// we only send webhooks, receiving them is up to the kiosks and apps
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookError {
+    /// The timestamp or signature header isn't what we send.
+    Malformed,
+    /// The timestamp is more than [TOLERANCE_SECONDS] away from now.
+    Stale,
+    /// The body or timestamp isn't the one we signed, or it was signed with another secret.
+    BadSignature,
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookError::Malformed => write!(f, "malformed webhook headers"),
+            WebhookError::Stale => write!(f, "stale webhook"),
+            WebhookError::BadSignature => write!(f, "bad webhook signature"),
+        }
+    }
+}
+
+/// The value of [SIGNATURE_HEADER] for `body`, sent at `timestamp` to a subscription with
+/// `secret`.
+pub fn sign(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+    format!("v1={}", tag(secret, timestamp, body).to_lower_hex_string())
+}
+
+/// Checks a delivery of `body`, given the values of its [TIMESTAMP_HEADER] and
+/// [SIGNATURE_HEADER], came from us and was sent around `now`.
+#[allow(dead_code)]
+pub fn verify_webhook(
+    secret: &[u8],
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+    now: u64,
+) -> Result<(), WebhookError> {
+    let timestamp: u64 = timestamp.parse().map_err(|_| WebhookError::Malformed)?;
+    let signature = signature
+        .strip_prefix("v1=")
+        .and_then(|signature| <[u8; 32]>::from_hex(signature).ok())
+        .ok_or(WebhookError::Malformed)?;
+
+    if timestamp.abs_diff(now) > TOLERANCE_SECONDS {
+        return Err(WebhookError::Stale);
+    }
+
+    if !signing::constant_time_eq(&signature, &tag(secret, timestamp, body)) {
+        return Err(WebhookError::BadSignature);
+    }
+
+    Ok(())
+}
+
+/// Posts `body` to `url`, signed with `secret`, returning the status the receiver answered with.
+/// This blocks until the receiver answers or [DELIVERY_TIMEOUT_SECONDS] pass.
+pub fn deliver(url: &str, secret: &[u8], body: &[u8], now: u64) -> Result<i32, minreq::Error> {
+    let response = minreq::post(url)
+        .with_header("Content-Type", "application/json")
+        .with_header(TIMESTAMP_HEADER, now.to_string())
+        .with_header(SIGNATURE_HEADER, sign(secret, now, body))
+        .with_body(body)
+        .with_timeout(DELIVERY_TIMEOUT_SECONDS)
+        .send()?;
+
+    Ok(response.status_code)
+}
+
+/// How long to wait before trying a delivery again after its `attempts`th attempt failed,
+/// doubling from `base` seconds each time, up to [MAX_RETRY_DELAY_SECONDS].
+pub fn retry_delay(base: u64, attempts: u32) -> u64 {
+    let factor = 1u64 << attempts.saturating_sub(1).min(32);
+    base.saturating_mul(factor).min(MAX_RETRY_DELAY_SECONDS)
+}
+
+fn tag(secret: &[u8], timestamp: u64, body: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret);
+    engine.input(timestamp.to_string().as_bytes());
+    engine.input(b".");
+    engine.input(body);
+    hmac::Hmac::from_engine(engine).to_byte_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebhookError;
+
+    const SECRET: [u8; 32] = [0x42; 32];
+    const BODY: &[u8] = br#"{"event":"payment.settled","data":{"locker_id":1}}"#;
+
+    #[test]
+    fn signatures_match_vectors() {
+        assert_eq!(
+            super::sign(&SECRET, 1700000000, BODY),
+            "v1=27543078f935ef19a7cbe5a4a83601b20ca3609f24bd42e6fe41cd9687846b08"
+        );
+    }
+
+    #[test]
+    fn valid_deliveries_are_accepted() {
+        let signature = super::sign(&SECRET, 1700000000, BODY);
+        assert_eq!(
+            super::verify_webhook(&SECRET, "1700000000", &signature, BODY, 1700000000),
+            Ok(())
+        );
+        // a receiver whose clock is a bit off either way
+        for now in [1700000000 - 300, 1700000000 + 300] {
+            assert_eq!(
+                super::verify_webhook(&SECRET, "1700000000", &signature, BODY, now),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn stale_deliveries_are_refused() {
+        let signature = super::sign(&SECRET, 1700000000, BODY);
+        for now in [1700000000 - 301, 1700000000 + 301, 1800000000] {
+            assert_eq!(
+                super::verify_webhook(&SECRET, "1700000000", &signature, BODY, now),
+                Err(WebhookError::Stale)
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_deliveries_are_refused() {
+        let signature = super::sign(&SECRET, 1700000000, BODY);
+        let tampered = br#"{"event":"payment.settled","data":{"locker_id":2}}"#;
+        assert_eq!(
+            super::verify_webhook(&SECRET, "1700000000", &signature, tampered, 1700000000),
+            Err(WebhookError::BadSignature)
+        );
+
+        // replaying the body with a fresh timestamp
+        assert_eq!(
+            super::verify_webhook(&SECRET, "1700000100", &signature, BODY, 1700000100),
+            Err(WebhookError::BadSignature)
+        );
+
+        // another subscription's secret
+        assert_eq!(
+            super::verify_webhook(&[0x43; 32], "1700000000", &signature, BODY, 1700000000),
+            Err(WebhookError::BadSignature)
+        );
+
+        for (timestamp, signature) in [
+            ("yesterday", signature.as_str()),
+            ("1700000000", &signature[3..]),
+            ("1700000000", "v1=abc"),
+        ] {
+            assert_eq!(
+                super::verify_webhook(&SECRET, timestamp, signature, BODY, 1700000000),
+                Err(WebhookError::Malformed)
+            );
+        }
+    }
+
+    #[test]
+    fn retries_back_off() {
+        let delays: Vec<u64> = (1..=6).map(|attempts| super::retry_delay(10, attempts)).collect();
+        assert_eq!(delays, [10, 20, 40, 80, 160, 320]);
+
+        assert_eq!(super::retry_delay(10, 20), super::MAX_RETRY_DELAY_SECONDS);
+        assert_eq!(super::retry_delay(u64::MAX, 64), super::MAX_RETRY_DELAY_SECONDS);
+    }
+}
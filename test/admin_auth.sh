@@ -0,0 +1,130 @@
+#!/bin/bash
+# This script checks that the /admin routes only let admin tokens through, and record who used
+# them and what for, while the public routes stay open. It needs a server running with the mock lightning
+# backend, the test lockers, an admin token and a second, hashed, admin token called "ops".
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> ADMIN_TOKENS="ops:$(echo -n <ops token> | sha256sum | cut -d ' ' -f 1)" cargo run --features mock-ln & ADMIN_TOKEN=<token> OPS_TOKEN=<ops token> ./admin_auth.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running admin authentication tests..."
+
+# prints the status code of GET $1, passing the rest of the arguments to curl
+status() {
+  curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "${@:2}" \
+    "$root_api_url$1"
+}
+
+echo -n "Leaving public routes open..."
+for route in /lockers /health /keys; do
+  result=$(status "$route")
+  if [ "$result" != "200" ]; then
+    echo "Error: expected 200 for $route, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Rejecting calls without a token..."
+result=$(status /admin/metrics)
+if [ "$result" != "401" ]; then
+  echo "Error: expected 401, got $result."
+  exit 1
+fi
+
+error=$(curl -X GET --silent "$root_api_url/admin/metrics" | jq -r '.error.message')
+if [ "$error" != "Unauthorized" ]; then
+  echo "Error: expected the error in a JSON envelope, got $error."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting calls with the wrong token..."
+for token in "wrong" "$ADMIN_TOKEN-" ""; do
+  result=$(status /admin/metrics -H "Authorization: Bearer $token")
+  if [ "$result" != "401" ]; then
+    echo "Error: expected 401, got $result."
+    exit 1
+  fi
+done
+
+# the token must come as a bearer token
+result=$(status /admin/metrics -H "Authorization: $ADMIN_TOKEN")
+if [ "$result" != "401" ]; then
+  echo "Error: expected 401, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting either token..."
+for token in "$ADMIN_TOKEN" "$OPS_TOKEN"; do
+  result=$(status /admin/metrics -H "Authorization: Bearer $token")
+  if [ "$result" != "200" ]; then
+    echo "Error: expected 200, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Recording who made each call..."
+calls=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $OPS_TOKEN" \
+  "$root_api_url/admin/audit?limit=2" | jq -r '[.data[] | "\(.token_id) \(.method) \(.path)"] | join(",")')
+
+if [ "$calls" != "ops GET /v1/admin/metrics,default GET /v1/admin/metrics" ]; then
+  echo "Error: expected a metrics call by ops then one by default, got $calls."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Recording calls that change something, even when they fail..."
+curl -X PUT \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"price_per_minute_msat": 60000}' \
+  "$root_api_url/admin/lockers/1/price"
+
+result=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"reason": "testing"}' \
+  "$root_api_url/admin/receipts/$(printf '0%.0s' {1..64})/revoke")
+if [ "$result" != "404" ]; then
+  echo "Error: expected 404 revoking a receipt that doesn't exist, got $result."
+  exit 1
+fi
+
+calls=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/audit?limit=2" | jq -r '[.data[] | "\(.route) \(.status) \(.summary)"] | join(",")')
+
+expected='/v1/admin/receipts/{payment_hash}/revoke 404 {"reason":"testing"}'
+expected+=',/v1/admin/lockers/{locker_id}/price 200 {"price_per_minute_msat":60000}'
+if [ "$calls" != "$expected" ]; then
+  echo "Error: expected the failed revocation then the price change, got $calls."
+  exit 1
+fi
+
+echo "(Done)"
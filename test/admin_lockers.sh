@@ -0,0 +1,90 @@
+#!/bin/bash
+# This script checks staff can commission lockers with POST /admin/lockers, which are ready to be
+# used, that malformed and already registered keys are refused, and that GET /admin/lockers lists
+# every locker with its key, decommissioned ones too. It needs a fresh server running with the
+# mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./admin_lockers.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+# the public key of a locker that isn't in lockers.toml
+pk="a6469c5b80419de916498141a68fcf4085d89b02dad3966df1f8915c356902b4"
+
+echo "Running admin locker tests..."
+
+# adds a locker with the body $1, printing the status code
+add_status() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "$auth" \
+    -H "content-type: application/json" \
+    -d "$1" \
+    "$root_api_url/admin/lockers"
+}
+
+echo -n "Commissioning a locker..."
+locker=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "$auth" \
+  -H "content-type: application/json" \
+  -d "{\"pk\": \"$pk\", \"name\": \"B1\", \"location\": \"Back door\", \"size\": \"large\", \"price_per_minute_msat\": 120000}" \
+  "$root_api_url/admin/lockers" | jq -r '.data | "\(.id) \(.state) \(.name) \(.size) \(.price_per_minute_msat)"')
+if [ "$locker" != "4 available B1 large 120000" ]; then
+  echo "Error: expected locker 4 to be available, got $locker."
+  exit 1
+fi
+
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/4"
+
+echo "(Done)"
+
+echo -n "Refusing malformed and registered keys..."
+for request in \
+  '{"pk": "abc"}:400' \
+  '{"name": "B2"}:400' \
+  "{\"pk\": \"$pk\"}:409" \
+  "{\"pk\": \"$(echo "$pk" | tr a-f A-F)\"}:409"; do
+  result=$(add_status "${request%:*}")
+  if [ "$result" != "${request##*:}" ]; then
+    echo "Error: expected ${request##*:} for ${request%:*}, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Listing every locker with its key..."
+curl -X POST --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/2/decommission"
+
+lockers=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers" | jq -r '[.data[] | "\(.id):\(.active):\(.auth_mode):\(.pk[:8])"] | join(" ")')
+if [ "$lockers" != "1:true:schnorr:347d7902 2:false:schnorr:7aaa7852 3:true:hmac:A3-contr 4:true:schnorr:a6469c5b" ]; then
+  echo "Error: unexpected lockers $lockers."
+  exit 1
+fi
+
+public=$(curl -X GET --silent --fail "$root_api_url/lockers" | jq -r '[.data[].id] | join(",")')
+if [ "$public" != "1,3,4" ]; then
+  echo "Error: expected the public listing to leave out locker 2, got $public."
+  exit 1
+fi
+
+result=$(curl -X GET --silent --output /dev/null --write-out "%{http_code}" "$root_api_url/admin/lockers")
+if [ "$result" != "401" ]; then
+  echo "Error: expected 401 without a token, got $result."
+  exit 1
+fi
+
+echo "(Done)"
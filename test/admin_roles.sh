@@ -0,0 +1,104 @@
+#!/bin/bash
+# This script checks that admin tokens can only do what their role allows, getting a 403 rather
+# than a 401 otherwise, and that the audit log records the role each call was made with. It needs
+# a server running with the mock lightning backend, the test lockers, an admin token and two more
+# called "viewer" and "operator" with those roles.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> ADMIN_TOKENS="viewer:$(echo -n <viewer token> | sha256sum | cut -d ' ' -f 1):viewer,operator:$(echo -n <operator token> | sha256sum | cut -d ' ' -f 1):operator" cargo run --features mock-ln & ADMIN_TOKEN=<token> VIEWER_TOKEN=<viewer token> OPERATOR_TOKEN=<operator token> ./admin_roles.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running admin role tests..."
+
+# prints the status code of $1 to $2 with the token $3, passing the rest of the arguments to curl
+status() {
+  curl -X "$1" \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: Bearer $3" \
+    "${@:4}" \
+    "$root_api_url$2"
+}
+
+# sets the price of locker 1 with the token $1, printing the status code
+set_price() {
+  status PUT /admin/lockers/1/price "$1" \
+    -H "Content-Type: application/json" \
+    -d '{"price_per_minute_msat": 60000}'
+}
+
+echo -n "Letting viewers look, but not change anything..."
+for route in /admin/metrics /admin/payments /admin/audit; do
+  result=$(status GET "$route" "$VIEWER_TOKEN")
+  if [ "$result" != "200" ]; then
+    echo "Error: expected 200 for $route, got $result."
+    exit 1
+  fi
+done
+
+result=$(set_price "$VIEWER_TOKEN")
+if [ "$result" != "403" ]; then
+  echo "Error: expected 403, got $result."
+  exit 1
+fi
+
+error=$(curl -X POST \
+  --silent \
+  -H "Authorization: Bearer $VIEWER_TOKEN" \
+  "$root_api_url/admin/lockers/1/decommission" | jq -r '.error.message')
+if [ "$error" != "Forbidden" ]; then
+  echo "Error: expected the error in a JSON envelope, got $error."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Letting operators run the lockers, but not manage credentials..."
+result=$(set_price "$OPERATOR_TOKEN")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+for route in /admin/lockers/1/device_token /admin/webhooks; do
+  result=$(status POST "$route" "$OPERATOR_TOKEN" -H "Content-Type: application/json" -d '{"url": "http://127.0.0.1:9/"}')
+  if [ "$result" != "403" ]; then
+    echo "Error: expected 403 for $route, got $result."
+    exit 1
+  fi
+done
+
+result=$(status GET /admin/backup "$OPERATOR_TOKEN")
+if [ "$result" != "403" ]; then
+  echo "Error: expected 403 for the backup, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Letting owners do everything..."
+result=$(status DELETE /admin/lockers/1/device_token "$ADMIN_TOKEN")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Recording the role of each call..."
+calls=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/audit?limit=3" | jq -r '[.data[] | "\(.token_id) \(.role) \(.status)"] | join(",")')
+
+if [ "$calls" != "default owner 200,operator operator 403,operator operator 403" ]; then
+  echo "Error: expected the owner's call after the operator's refused ones, got $calls."
+  exit 1
+fi
+
+echo "(Done)"
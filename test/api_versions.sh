@@ -0,0 +1,133 @@
+#!/bin/bash
+# This script checks the API answers the same under /v1 and without a prefix, public and admin
+# routes alike, that only the paths without a prefix are marked deprecated, with a link to where
+# they moved, and that NIP-98 headers are checked against the path the client used. It needs a
+# fresh server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./api_versions.sh
+
+set -euo pipefail
+set -o posix
+
+server_url="http://127.0.0.1:8080"
+script_dir=$(dirname "$0")
+seckey="3000000000000000000000000000000000000000000000000000000000000001"
+headers=$(mktemp)
+trap 'rm -f "$headers"' EXIT
+
+echo "Running API version tests..."
+
+# prints the data of a GET to the path $1 of the server, keeping its headers in $headers
+get() {
+  curl -X GET \
+    --silent \
+    --fail \
+    --dump-header "$headers" \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    "$server_url$1" | jq -S -c '.data'
+}
+
+# prints the value of the header $1 in $headers
+header() {
+  grep -i "^$1:" "$headers" | cut -d' ' -f2- | tr -d '\r'
+}
+
+# prints the status code of a POST to the path $1 of the server, with a NIP-98 header for the
+# path $2, keeping its headers in $headers
+claim() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --dump-header "$headers" \
+    --write-out "%{http_code}" \
+    -H "Authorization: $("$script_dir/nostr.py" "$seckey" POST "$server_url$2")" \
+    "$server_url$1"
+}
+
+echo -n "Answering the same under both prefixes..."
+for route in /lockers /lockers/1 /keys /pubkey /revocations /version /admin/lockers; do
+  current=$(get "/v1$route")
+  if [ -n "$(header deprecation)" ]; then
+    echo "Error: expected /v1$route not to be deprecated, got $(cat "$headers")."
+    exit 1
+  fi
+
+  legacy=$(get "$route")
+  if [ "$legacy" != "$current" ]; then
+    echo "Error: expected $route to answer as /v1$route, got $legacy and $current."
+    exit 1
+  fi
+  if [ "$(header deprecation)" != "true" ]; then
+    echo "Error: expected $route to be deprecated, got $(cat "$headers")."
+    exit 1
+  fi
+  if [ "$(header link)" != "</v1$route>; rel=\"successor-version\"" ]; then
+    echo "Error: expected a link to /v1$route, got $(cat "$headers")."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Answering the same errors under both prefixes..."
+for prefix in /v1 ""; do
+  code=$(curl -X GET --silent "$server_url$prefix/lockers/999999" | jq -r '.error.code')
+  if [ "$code" != "not_found" ]; then
+    echo "Error: expected $prefix/lockers/999999 not to be found, got $code."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Keeping the server document at the root..."
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  --dump-header "$headers" \
+  "$server_url/.well-known/locker-server.json"
+if [ -n "$(header deprecation)" ]; then
+  echo "Error: expected the server document not to be deprecated, got $(cat "$headers")."
+  exit 1
+fi
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$server_url/v1/.well-known/locker-server.json")
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404 for the server document under /v1, got $status."
+  exit 1
+fi
+
+servers=$(curl -X GET --silent --fail "$server_url/v1/openapi.json" | jq -c '[.servers[].url]')
+if [ "$servers" != '["/v1"]' ]; then
+  echo "Error: expected the description to be of /v1, got $servers."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Claiming lockers under both prefixes..."
+status=$(claim /v1/use_locker/1 /v1/use_locker/1)
+if [ "$status" != "200" ] || [ -n "$(header deprecation)" ]; then
+  echo "Error: expected to claim locker 1 under /v1, got $status and $(cat "$headers")."
+  exit 1
+fi
+
+status=$(claim /use_locker/2 /use_locker/2)
+if [ "$status" != "200" ] || [ "$(header deprecation)" != "true" ]; then
+  echo "Error: expected to claim locker 2 without a prefix, got $status and $(cat "$headers")."
+  exit 1
+fi
+
+# the header is for another path than the one it's sent to
+status=$(claim /v1/use_locker/3 /use_locker/3)
+if [ "$status" != "401" ]; then
+  echo "Error: expected 401 for a header signed for the path without a prefix, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,92 @@
+#!/bin/bash
+# This script checks that open-authorizations expire: we say when in the response and cover it with
+# the signature, and lockers reporting opens must say when the authorization they were opened with
+# expires. It needs a server running with the mock lightning backend and the test lockers, and
+# without LEGACY_SIGNATURES set.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./authorization_expiry.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 2, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000002"
+
+echo "Running authorization expiry tests..."
+
+# reports locker 2 being opened with the given arguments for locker.py, printing the status code
+report() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$@")" \
+    "$root_api_url/update_locker_open"
+}
+
+echo -n "Handing out an authorization that expires..."
+authorization=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/2")
+start_time=$(echo "$authorization" | jq -r '.data.start_time')
+expires_at=$(echo "$authorization" | jq -r '.data.expires_at')
+expiring_signature=$(echo "$authorization" | jq -r '.data.expiring_signature')
+
+if [ "$expires_at" == "null" ] || [ "$expires_at" -le "$start_time" ]; then
+  echo "Error: expected the authorization to expire after $start_time, got $expires_at."
+  exit 1
+fi
+
+if [ "$expiring_signature" != "null" ]; then
+  echo "Error: expected no separate expiring signature outside of the rollout."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a report without the expiry..."
+status=$(report "$start_time" legacy)
+if [ "$status" != "422" ]; then
+  echo "Error: expected 422, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a report with an expired authorization..."
+status=$(report "$start_time" "$((start_time - 1))")
+if [ "$status" != "422" ]; then
+  echo "Error: expected 422, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a report changed after it was signed..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$start_time" "$expires_at" | jq -c '.timestamp += 1')" \
+  "$root_api_url/update_locker_open")
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting a report within the authorization..."
+status=$(report "$start_time" "$expires_at")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,164 @@
+#!/bin/bash
+# This script checks a customer can pay for several lockers with a single invoice from
+# `/pay_for_usage_batch`, which buys one receipt for each locker under
+# `/payments/{payment_hash}/receipts`, that lockers claimed by someone else are refused, and that
+# an Idempotency-Key can't be reused for other lockers. It needs a fresh server running with the
+# mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./batch_payment.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+seckey="3000000000000000000000000000000000000000000000000000000000000001"
+key="batch-test-$RANDOM$RANDOM"
+
+echo "Running batch payment tests..."
+
+# prints the NIP-98 header of the test user for a POST to the path $1
+authorization() {
+  "$script_dir/nostr.py" "$seckey" POST "$root_api_url$1"
+}
+
+# claims locker $1 as the test user
+claim() {
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "Authorization: $(authorization "/use_locker/$1")" \
+    "$root_api_url/use_locker/$1"
+}
+
+# prints the status code of a batch payment for the lockers in the JSON list $1, as the test user
+batch_status() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: $(authorization /pay_for_usage_batch)" \
+    -H "Content-Type: application/json" \
+    -d "{\"locker_ids\": $1}" \
+    "$root_api_url/pay_for_usage_batch"
+}
+
+echo -n "Claiming three lockers, one of them anonymously..."
+claim 1
+claim 2
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/3"
+sleep 1
+
+echo "(Done)"
+
+echo -n "Refusing anonymous batch payments..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "Content-Type: application/json" \
+  -d '{"locker_ids": [1, 2]}' \
+  "$root_api_url/pay_for_usage_batch")
+if [ "$status" != "401" ]; then
+  echo "Error: expected 401, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing bad lists of lockers..."
+for locker_ids in "[]" "[1, 1]"; do
+  status=$(batch_status "$locker_ids")
+  if [ "$status" != "400" ]; then
+    echo "Error: expected 400 for $locker_ids, got $status."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Refusing lockers the customer isn't using..."
+for locker_ids in "[1, 3]" "[1, 4]"; do
+  status=$(batch_status "$locker_ids")
+  if [ "$status" != "409" ]; then
+    echo "Error: expected 409 for $locker_ids, got $status."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Paying for two lockers with one invoice..."
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "Authorization: $(authorization /pay_for_usage_batch)" \
+  -H "Content-Type: application/json" \
+  -H "Idempotency-Key: $key" \
+  -d '{"locker_ids": [1, 2]}' \
+  "$root_api_url/pay_for_usage_batch" | jq -c '.data')
+
+sum=$(echo "$result" | jq -r '"\([.lockers[].amount] | add) \(.amount)"')
+if [ "${sum% *}" != "${sum#* }" ] || [ "${sum#* }" -lt 2 ]; then
+  echo "Error: expected the amount to add up, got $result."
+  exit 1
+fi
+
+payment_hash=$(echo "$result" | jq -r '.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Refusing the same Idempotency-Key for other lockers..."
+response=$(curl -X POST \
+  --silent \
+  --write-out "\n%{http_code}" \
+  -H "Authorization: $(authorization /pay_for_usage_batch)" \
+  -H "Content-Type: application/json" \
+  -H "Idempotency-Key: $key" \
+  -d '{"locker_ids": [2]}' \
+  "$root_api_url/pay_for_usage_batch")
+
+status=$(echo "$response" | tail -n 1)
+message=$(echo "$response" | head -n 1 | jq -r '.error.message')
+if [ "$status" != "409" ] || [ "$message" != "Idempotency-Key was used for another request" ]; then
+  echo "Error: expected a 409 for the reused key, got $status $message."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Getting a receipt for each locker..."
+receipts=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipts" | jq -c '[.data[].locker_id]')
+if [ "$receipts" != "[1,2]" ]; then
+  echo "Error: expected receipts for lockers 1 and 2, got $receipts."
+  exit 1
+fi
+
+# and the same ones again, until the lockers are opened with them
+receipts=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipts" | jq -c '[.data[].locker_id]')
+if [ "$receipts" != "[1,2]" ]; then
+  echo "Error: expected the same receipts again, got $receipts."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Pointing single receipt clients to the receipts..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/receipt")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
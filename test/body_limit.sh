@@ -0,0 +1,72 @@
+#!/bin/bash
+# This script checks that oversized and deeply nested request bodies are turned away quickly,
+# rather than tying up the server. It needs a server running with the default MAX_BODY_BYTES.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./body_limit.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running body limit tests..."
+
+body_file=$(mktemp)
+trap 'rm -f "$body_file"' EXIT
+
+# posts the file $2 to $1, printing the response body and then the status code
+post() {
+  curl -X POST \
+    --silent \
+    --max-time 10 \
+    --write-out "\n%{http_code}" \
+    -H "Content-Type: application/json" \
+    "${@:3}" \
+    --data-binary "@$2" \
+    "$root_api_url$1"
+}
+
+check_too_large() {
+  result=$(echo "$1" | tail -n 1)
+  if [ "$result" != "413" ]; then
+    echo "Error: expected 413, got $result."
+    exit 1
+  fi
+
+  error=$(echo "$1" | head -n 1 | jq -r '.error.message')
+  if [ "$error" != "Payload Too Large" ]; then
+    echo "Error: expected the JSON error envelope, got $1."
+    exit 1
+  fi
+}
+
+echo -n "Rejecting a 10MB body..."
+head -c $((10 * 1024 * 1024)) /dev/zero | tr '\0' ' ' > "$body_file"
+check_too_large "$(post /update_locker_open "$body_file")"
+
+echo "(Done)"
+
+echo -n "Rejecting a 10MB body that doesn't say how long it is..."
+check_too_large "$(post /update_locker_open "$body_file" -H "Transfer-Encoding: chunked")"
+
+echo "(Done)"
+
+echo -n "Rejecting deeply nested JSON without crashing..."
+printf '%0.s[' $(seq 1 1000) > "$body_file"
+printf '%0.s]' $(seq 1 1000) >> "$body_file"
+result=$(post /update_locker_open "$body_file" | tail -n 1)
+if [ "$result" != "400" ] && [ "$result" != "422" ]; then
+  echo "Error: expected 400 or 422, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Still serving requests..."
+result=$(curl --silent --output /dev/null --write-out "%{http_code}" "$root_api_url/health")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+echo "(Done)"
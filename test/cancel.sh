@@ -0,0 +1,113 @@
+#!/bin/bash
+# This script checks that staff can cancel a pending payment under /admin, and that a cancelled
+# payment never buys a receipt even if it gets paid. It needs a fresh server running with the mock
+# lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./cancel.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running cancel tests..."
+
+echo -n "Asking to pay for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Cancelling the payment..."
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments/$payment_hash/cancel" | jq -r '"\(.data.payment.status) \(.data.invoice_cancelled)"')
+
+if [ "$result" != "cancelled true" ]; then
+  echo "Error: expected \"cancelled true\", got \"$result\"."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Cancelling it again..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/payments/$payment_hash/cancel")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing a receipt for the cancelled payment..."
+# the mock backend considers every invoice paid, as if the customer paid after the cancellation
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+if [ "$status" != "410" ]; then
+  echo "Error: expected 410, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Flagging the payment for a refund..."
+needs_refund=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments?status=cancelled" \
+  | jq -r ".data.payments[] | select(.payment_hash == \"$payment_hash\") | .needs_refund")
+
+if [ "$needs_refund" != "true" ]; then
+  echo "Error: expected the payment to need a refund, got $needs_refund."
+  exit 1
+fi
+
+events=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers/1/events?limit=2" | jq -r '[.data[].event] | join(",")')
+
+if [ "$events" != "refund_needed,payment_cancelled" ]; then
+  echo "Error: expected refund_needed,payment_cancelled, got $events."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Cancelling a payment that doesn't exist..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/payments/0000000000000000000000000000000000000000000000000000000000000000/cancel")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
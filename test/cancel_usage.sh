@@ -0,0 +1,123 @@
+#!/bin/bash
+# This script checks /cancel_usage lets whoever claimed a locker back out with the token they got:
+# for free if the locker was never opened, making it available again, and for the cancellation fee
+# if it was. It needs a fresh server running with the mock lightning backend, the test lockers and
+# an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./cancel_usage.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 2, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000002"
+
+echo "Running usage cancellation tests..."
+
+# cancels the usage of locker $1 with the token $2, printing the status code
+cancel_status() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d "{\"token\": \"$2\"}" \
+    "$root_api_url/cancel_usage/$1"
+}
+
+# prints the state of locker $1
+locker_state() {
+  curl -X GET --silent --fail "$root_api_url/lockers/$1" | jq -r '.data.state'
+}
+
+claim() {
+  curl -X POST --silent --fail "$root_api_url/use_locker/$1" | jq -r '.data.token'
+}
+
+echo -n "Rejecting tokens that aren't for the locker's session..."
+token=$(claim 1)
+other_token=$(claim 3)
+for bad_token in "abc" "$other_token"; do
+  result=$(cancel_status 1 "$bad_token")
+  if [ "$result" != "401" ]; then
+    echo "Error: expected 401, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Cancelling before the locker was opened..."
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "{\"token\": \"$token\"}" \
+  "$root_api_url/cancel_usage/1" | jq -r '"\(.data.released) \(.data.amount) \(.data.invoice)"')
+if [ "$result" != "true 0 null" ]; then
+  echo "Error: expected the locker to be released for free, got $result."
+  exit 1
+fi
+
+if [ "$(locker_state 1)" != "available" ]; then
+  echo "Error: expected locker 1 to be available again."
+  exit 1
+fi
+
+outcome=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/lockers/1/history" | jq -r '.data.sessions[0].outcome')
+if [ "$outcome" != "cancelled" ]; then
+  echo "Error: expected the session to be cancelled, got $outcome."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to cancel twice..."
+result=$(cancel_status 1 "$token")
+if [ "$result" != "401" ]; then
+  echo "Error: expected 401, got $result."
+  exit 1
+fi
+
+claim 1 >/dev/null
+
+echo "(Done)"
+
+echo -n "Charging the cancellation fee once the locker was opened..."
+token=$(claim 2)
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$(date +%s)")" \
+  "$root_api_url/update_locker_open"
+
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "{\"token\": \"$token\"}" \
+  "$root_api_url/cancel_usage/2")
+if [ "$(echo "$result" | jq -r '"\(.data.released) \(.data.amount)"')" != "false 10" ]; then
+  echo "Error: expected the cancellation fee, got $result."
+  exit 1
+fi
+
+if [ "$(locker_state 2)" != "in_use" ]; then
+  echo "Error: expected locker 2 to stay in use until it's emptied."
+  exit 1
+fi
+
+payment_hash=$(echo "$result" | jq -r '.data.invoice.payment_hash')
+curl -X GET --silent --fail --output /dev/null "$root_api_url/payments/$payment_hash/receipt"
+
+echo "(Done)"
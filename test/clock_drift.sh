@@ -0,0 +1,78 @@
+#!/bin/bash
+# This script checks that lockers reporting opens with a timestamp too far from our clock are told
+# to fix it, and that we keep track of how far off each locker's clock is. It needs a server
+# running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./clock_drift.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 2, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000002"
+
+echo "Running clock drift tests..."
+
+echo -n "Rejecting a report from an hour ago..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/2"
+
+timestamp=$(($(date +%s) - 3600))
+headers=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --dump-header - \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$timestamp")" \
+  "$root_api_url/update_locker_open")
+
+status=$(echo "$headers" | head -n 1 | cut -d ' ' -f 2)
+if [ "$status" != "422" ]; then
+  echo "Error: expected 422, got $status."
+  exit 1
+fi
+
+if ! echo "$headers" | grep -qi "^x-server-time: [0-9]"; then
+  echo "Error: expected the server's time in X-Server-Time."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Reporting the drift..."
+drift=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/clock_drift" | jq -r '.data[] | select(.locker_id == 2) | .drift_seconds')
+
+# the clock may tick between signing the report and checking it
+if [ "$drift" != "-3600" ] && [ "$drift" != "-3601" ]; then
+  echo "Error: expected locker 2 to be an hour behind, got $drift."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting a report once the clock is fixed..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2)" \
+  "$root_api_url/update_locker_open")
+
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
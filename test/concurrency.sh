@@ -0,0 +1,53 @@
+#!/bin/bash
+# This script checks that a locker can only be claimed once, even when many clients race for it.
+
+# Usage: ./concurrency.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+requests=20
+
+echo "Running concurrency tests..."
+
+echo -n "Looking for an available locker..."
+lockers=$(curl -X GET \
+  --silent \
+  -H "accept: application/json" \
+  "$root_api_url/lockers")
+
+available_locker=$(echo "$lockers" | jq -r '.data[] | select(.state == "available") | .id' | head -n 1)
+if [ -z "$available_locker" ]; then
+  echo "No available lockers found."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Firing $requests simultaneous requests for locker $available_locker..."
+results=$(mktemp -d)
+for i in $(seq 1 "$requests"); do
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}\n" \
+    "$root_api_url/use_locker/$available_locker" > "$results/$i" &
+done
+wait
+
+succeeded=$(cat "$results"/* | grep -c "^200$" || true)
+conflicted=$(cat "$results"/* | grep -c "^409$" || true)
+rm -rf "$results"
+
+if [ "$succeeded" != "1" ]; then
+  echo "Error: expected exactly one request to succeed, got $succeeded."
+  exit 1
+fi
+
+if [ "$conflicted" != "$((requests - 1))" ]; then
+  echo "Error: expected $((requests - 1)) conflicts, got $conflicted."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,99 @@
+#!/bin/bash
+# This script checks the consistency check under /admin/consistency_check: a locker that reports
+# being opened without its payment going through leaves behind an open session and a pending
+# payment, which the check finds and repairs. It needs a fresh server running with the mock
+# lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./consistency.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 1, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running consistency tests..."
+
+# runs the check, printing the kind and repaired flag of each finding
+check() {
+  curl -X POST \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/consistency_check?fix=$1" | jq -r '[.data[] | "\(.kind):\(.repaired)"] | join(",")'
+}
+
+echo -n "Finding nothing wrong..."
+result=$(check false)
+if [ "$result" != "" ]; then
+  echo "Error: expected nothing, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Opening locker 1 without paying for it..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+now=$(date +%s)
+for action in store retrieve; do
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "content-type: application/json" \
+    -d "$("$script_dir/locker.py" "$locker_seckey" 1 "$now" "$((now + 900))" "$action")" \
+    "$root_api_url/update_locker_open"
+  now=$((now + 1))
+done
+
+echo "(Done)"
+
+echo -n "Reporting without repairing..."
+result=$(check false)
+if [ "$result" != "session_on_available_locker:false,pending_payment_on_available_locker:false" ]; then
+  echo "Error: expected an open session and a pending payment, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Repairing..."
+result=$(check true)
+if [ "$result" != "session_on_available_locker:true,pending_payment_on_available_locker:true" ]; then
+  echo "Error: expected both to be repaired, got $result."
+  exit 1
+fi
+
+status=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments?status=cancelled" \
+  | jq -r ".data.payments[] | select(.payment_hash == \"$payment_hash\") | .status")
+
+if [ "$status" != "cancelled" ]; then
+  echo "Error: expected the payment to be cancelled, got $status."
+  exit 1
+fi
+
+result=$(check false)
+if [ "$result" != "" ]; then
+  echo "Error: expected nothing left, got $result."
+  exit 1
+fi
+
+echo "(Done)"
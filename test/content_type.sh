@@ -0,0 +1,99 @@
+#!/bin/bash
+# This script checks the routes answering JSON say so in their Content-Type, those that aren't
+# wrapped in the envelope too, so clients that pick how to parse a response by its type can read
+# them, that clients whose Accept rules out what we answer get a 406, and that bodies that aren't
+# JSON get a 415. It needs a server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./content_type.sh
+
+set -euo pipefail
+set -o posix
+
+server_url="http://127.0.0.1:8080"
+root_api_url="$server_url/v1"
+
+echo "Running content type tests..."
+
+# checks a GET request to the path $1 of the server, version prefix included, is answered with
+# the status code $2 and a JSON body
+expect_json() {
+  response=$(curl -X GET \
+    --silent \
+    --write-out "\n%{http_code} %{content_type}" \
+    "$server_url$1")
+
+  result=$(echo "$response" | tail -n 1)
+  if [ "$result" != "$2 application/json; charset=utf-8" ]; then
+    echo "Error: expected $1 to answer $2 in JSON, got $result."
+    exit 1
+  fi
+
+  if ! echo "$response" | head -n -1 | jq -e . >/dev/null; then
+    echo "Error: expected $1 to answer JSON, got $response."
+    exit 1
+  fi
+}
+
+echo -n "Answering JSON in the envelope..."
+for route in /lockers /lockers/1 /keys /pubkey /revocations /health /version; do
+  expect_json "/v1$route" 200
+done
+echo "(Done)"
+
+echo -n "Answering JSON outside the envelope..."
+expect_json /.well-known/locker-server.json 200
+expect_json /v1/openapi.json 200
+expect_json /v1/auth/lnurl/callback 400
+echo "(Done)"
+
+echo -n "Answering errors in JSON..."
+expect_json /v1/lockers/999999 404
+expect_json /v1/lockers/abc 400
+expect_json /v1/nowhere 404
+echo "(Done)"
+
+# prints the status and the error code of a request to $1 with the extra curl arguments after it
+status_and_code() {
+  route=$1
+  shift
+  curl --silent --write-out "\n%{http_code}" "$@" "$root_api_url$route" |
+    jq -rs '"\(.[1]) \(.[0].error.code)"' 2>/dev/null || true
+}
+
+# checks a request to $1, with the extra curl arguments after $2, is answered with the status and
+# error code $2
+expect_refused() {
+  route=$1
+  expected=$2
+  shift 2
+  result=$(status_and_code "$route" "$@")
+  if [ "$result" != "$expected" ]; then
+    echo "Error: expected $expected for $route, got $result."
+    exit 1
+  fi
+}
+
+echo -n "Honoring Accept..."
+expect_refused /lockers "406 not_acceptable" -H "Accept: text/html"
+expect_refused /lockers "406 not_acceptable" -H "Accept: application/json;q=0, */*"
+expect_refused /use_locker/1 "406 not_acceptable" -X POST -H "Accept: text/plain"
+for accept in "application/json" "application/*" "*/*" "text/html, application/json;q=0.1"; do
+  status=$(curl --silent --output /dev/null --write-out "%{http_code}" -H "Accept: $accept" "$root_api_url/lockers")
+  if [ "$status" != "200" ]; then
+    echo "Error: expected 200 for Accept: $accept, got $status."
+    exit 1
+  fi
+done
+# errors say more than a 406 would
+expect_refused /lockers/999999 "404 not_found" -H "Accept: text/html"
+echo "(Done)"
+
+echo -n "Refusing bodies that aren't JSON..."
+expect_refused /verify_token "415 unsupported_media_type" -X POST -d '{"token": "abc"}'
+expect_refused /verify_token "415 unsupported_media_type" -X POST -H "Content-Type: text/plain" -d '{"token": "abc"}'
+result=$(status_and_code /verify_token -X POST -H "Content-Type: application/json; charset=utf-8" -d '{"token": "abc"}')
+if [ "${result%% *}" = "415" ]; then
+  echo "Error: expected JSON with a charset to be accepted, got $result."
+  exit 1
+fi
+echo "(Done)"
@@ -0,0 +1,100 @@
+#!/bin/bash
+# This script checks that browsers are only let call the server from the configured origins. It
+# needs the server to run with CORS_ALLOWED_ORIGINS=http://kiosk.example.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml CORS_ALLOWED_ORIGINS=http://kiosk.example cargo run --features mock-ln & ./cors.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+allowed_origin="http://kiosk.example"
+
+echo "Running CORS tests..."
+
+# sends a preflight request for a $2 to $1 from the origin $3, printing the response headers
+preflight() {
+  curl -X OPTIONS \
+    --silent \
+    --output /dev/null \
+    --dump-header - \
+    -H "Origin: $3" \
+    -H "Access-Control-Request-Method: $2" \
+    "$root_api_url$1"
+}
+
+for method in GET POST; do
+  echo -n "Allowing a $method from the configured origin..."
+  headers=$(preflight /lockers "$method" "$allowed_origin")
+
+  if ! echo "$headers" | grep -qi "^access-control-allow-origin: $allowed_origin"; then
+    echo "Error: expected $allowed_origin to be allowed, got:"
+    echo "$headers"
+    exit 1
+  fi
+
+  if ! echo "$headers" | grep -i "^access-control-allow-methods:" | grep -q "$method"; then
+    echo "Error: expected $method to be allowed, got:"
+    echo "$headers"
+    exit 1
+  fi
+
+  echo "(Done)"
+done
+
+echo -n "Allowing the Authorization header..."
+headers=$(curl -X OPTIONS \
+  --silent \
+  --output /dev/null \
+  --dump-header - \
+  -H "Origin: $allowed_origin" \
+  -H "Access-Control-Request-Method: GET" \
+  -H "Access-Control-Request-Headers: authorization" \
+  "$root_api_url/admin/lockers")
+
+if ! echo "$headers" | grep -i "^access-control-allow-headers:" | grep -qi "authorization"; then
+  echo "Error: expected the Authorization header to be allowed, got:"
+  echo "$headers"
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing other origins..."
+headers=$(preflight /lockers GET "http://attacker.example")
+
+if echo "$headers" | grep -qi "^access-control-allow-origin:"; then
+  echo "Error: expected http://attacker.example to be refused, got:"
+  echo "$headers"
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing methods that aren't configured..."
+headers=$(preflight /lockers DELETE "$allowed_origin")
+
+if echo "$headers" | grep -i "^access-control-allow-methods:" | grep -q "DELETE"; then
+  echo "Error: expected DELETE to be refused, got:"
+  echo "$headers"
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Telling the configured origin it may read responses..."
+headers=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --dump-header - \
+  -H "Origin: $allowed_origin" \
+  "$root_api_url/lockers")
+
+if ! echo "$headers" | grep -qi "^access-control-allow-origin: $allowed_origin"; then
+  echo "Error: expected $allowed_origin to be allowed, got:"
+  echo "$headers"
+  exit 1
+fi
+
+echo "(Done)"
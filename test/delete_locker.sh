@@ -0,0 +1,96 @@
+#!/bin/bash
+# This script checks DELETE /admin/lockers/{id} only takes lockers out of service once nobody is
+# using them and their payments are settled, saying what's in the way otherwise, and that forcing
+# it closes the session of a locker in use without charging for it. It needs a fresh server
+# running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./delete_locker.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running locker deletion tests..."
+
+# deletes the locker at the path $1, printing the response body followed by the status code
+delete() {
+  curl -X DELETE \
+    --silent \
+    --write-out " %{http_code}" \
+    -H "$auth" \
+    "$root_api_url/admin/lockers/$1"
+}
+
+# checks deleting the locker at the path $1 gets the status code $2, and a response the jq filter
+# $3 turns into $4
+expect_delete() {
+  response=$(delete "$1")
+  result="$(echo "${response% *}" | jq -r "$3") ${response##* }"
+  if [ "$result" != "$4 $2" ]; then
+    echo "Error: expected $4 $2 deleting $1, got $result."
+    exit 1
+  fi
+}
+
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/2"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/2" | jq -r '.data.invoice.payment_hash')
+
+echo -n "Refusing to delete a locker in use..."
+expect_delete 1 409 '"\(.data.state) \(.data.unsettled_payments | length)"' "in_use 0"
+echo "(Done)"
+
+echo -n "Refusing to delete a locker with an unsettled payment, even when forced..."
+expect_delete "2?force=true" 409 '.data.unsettled_payments | join(",")' "$payment_hash"
+echo "(Done)"
+
+echo -n "Deleting an available locker..."
+expect_delete 3 200 '.data.active' "false"
+
+event=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers/3/events" | jq -r '.data[0] | "\(.event) \(.actor) \(.details.forced)"')
+if [ "$event" != "decommissioned admin false" ]; then
+  echo "Error: expected a decommissioned event, got $event."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Forcing a broken locker in use out of service..."
+expect_delete "1?force=true" 200 '"\(.data.active) \(.data.state)"' "false available"
+
+session=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers/1/history" | jq -r '.data.sessions[0] | "\(.outcome) \(.needs_follow_up) \(.amount_paid_sat)"')
+if [ "$session" != "released true null" ]; then
+  echo "Error: expected the session to be closed for free and flagged, got $session."
+  exit 1
+fi
+
+lockers=$(curl -X GET --silent --fail "$root_api_url/lockers" | jq -r '[.data[].id] | join(",")')
+if [ "$lockers" != "2" ]; then
+  echo "Error: expected only locker 2 to be listed, got $lockers."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Deleting a locker that doesn't exist..."
+result=$(curl -X DELETE --silent --output /dev/null --write-out "%{http_code}" -H "$auth" "$root_api_url/admin/lockers/1000")
+if [ "$result" != "404" ]; then
+  echo "Error: expected 404, got $result."
+  exit 1
+fi
+
+echo "(Done)"
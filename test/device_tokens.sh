@@ -0,0 +1,86 @@
+#!/bin/bash
+# This script checks that once a locker is issued a device token, reports of it being opened must
+# carry it, that one locker's token doesn't work for another, and that revoked tokens stop working.
+# It needs a fresh server running with the mock lightning backend, the test lockers and an admin
+# token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./device_tokens.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 1, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running device token tests..."
+
+# reports locker 1 opened with the bearer token $1, printing the status code
+report() {
+  sleep 1
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -H "Authorization: Bearer $1" \
+    -d "$("$script_dir/locker.py" "$locker_seckey" 1 "$(date +%s)")" \
+    "$root_api_url/update_locker_open"
+}
+
+# issues locker $1 a device token, printing it
+issue() {
+  curl -X POST \
+    --silent \
+    --fail \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    "$root_api_url/admin/lockers/$1/device_token" | jq -r '.data.device_token'
+}
+
+echo -n "Accepting reports from lockers without a token..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+status=$(report "")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Requiring the token once one is issued..."
+token=$(issue 1)
+other_token=$(issue 2)
+
+for wrong in "" "not-a-token" "$other_token"; do
+  status=$(report "$wrong")
+  if [ "$status" != "401" ]; then
+    echo "Error: expected 401 for '$wrong', got $status."
+    exit 1
+  fi
+done
+
+status=$(report "$token")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting revoked tokens..."
+curl -X DELETE \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/lockers/1/device_token"
+
+status=$(report "$token")
+if [ "$status" != "401" ]; then
+  echo "Error: expected 401, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,86 @@
+#!/bin/bash
+# This script checks the routes devices poll to discover the server, /pubkey and
+# /.well-known/locker-server.json: they agree on the key we sign with, and can be cached. It needs
+# a server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./discovery.sh
+
+set -euo pipefail
+set -o posix
+
+server_url="http://127.0.0.1:8080"
+root_api_url="$server_url/v1"
+
+echo "Running discovery tests..."
+
+echo -n "Getting the public key..."
+pubkey=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/pubkey")
+
+kid=$(echo "$pubkey" | jq -r '.data.kid')
+active=$(echo "$pubkey" | jq -r '[.data.keys[] | select(.status == "active") | .kid] | join(",")')
+
+if [ "$active" != "$kid" ]; then
+  echo "Error: expected $kid to be the only active key, got $active."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Getting the server document..."
+document=$(curl -X GET \
+  --silent \
+  --fail \
+  "$server_url/.well-known/locker-server.json")
+
+if [ "$(echo "$document" | jq -r '.pubkey')" != "$(echo "$pubkey" | jq -r '.data.pubkey')" ]; then
+  echo "Error: the server document and /pubkey disagree on the key, got $document."
+  exit 1
+fi
+
+if [ "$(echo "$document" | jq -r '.api_version')" != "1" ]; then
+  echo "Error: expected API version 1, got $document."
+  exit 1
+fi
+
+if [ "$(echo "$document" | jq -r '"\(.api_prefix) \(.keys_url)"')" != "/v1 /v1/keys" ]; then
+  echo "Error: expected the routes to be under /v1, got $document."
+  exit 1
+fi
+
+if [ "$(echo "$document" | jq -r '.pricing.default_price_per_minute_msat')" == "null" ]; then
+  echo "Error: expected the server document to have the price, got $document."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Revalidating the server document..."
+headers=$(curl -X GET \
+  --silent \
+  --fail \
+  --dump-header - \
+  --output /dev/null \
+  "$server_url/.well-known/locker-server.json")
+
+if ! echo "$headers" | grep -qi "^cache-control: public, max-age="; then
+  echo "Error: expected the server document to be cacheable, got $headers."
+  exit 1
+fi
+
+etag=$(echo "$headers" | grep -i "^etag:" | cut -d' ' -f2 | tr -d '\r')
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "If-None-Match: $etag" \
+  "$server_url/.well-known/locker-server.json")
+
+if [ "$status" != "304" ]; then
+  echo "Error: expected the server document to be unchanged, got $status."
+  exit 1
+fi
+
+echo "(Done)"
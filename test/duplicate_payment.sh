@@ -0,0 +1,66 @@
+#!/bin/bash
+# This script checks that we never store two payments with the same payment hash. It needs the
+# server to run with the mock lightning backend, told to hand out the same hash for every invoice.
+
+# Usage: LN_BACKEND=mock MOCK_LN_REUSE_HASHES=true LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln &
+#        ./duplicate_payment.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running duplicate payment tests..."
+
+echo -n "Using two lockers..."
+lockers=$(curl -X GET \
+  --silent \
+  -H "accept: application/json" \
+  "$root_api_url/lockers")
+
+available_lockers=$(echo "$lockers" | jq -r '.data[] | select(.state == "available") | .id' | head -n 2)
+if [ "$(echo "$available_lockers" | wc -l)" != "2" ]; then
+  echo "Not enough available lockers found."
+  exit 1
+fi
+
+first_locker=$(echo "$available_lockers" | sed -n 1p)
+second_locker=$(echo "$available_lockers" | sed -n 2p)
+
+for locker in $first_locker $second_locker; do
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    "$root_api_url/use_locker/$locker"
+done
+
+echo "(Done)"
+
+echo -n "Paying for locker $first_locker..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/pay_for_usage/$first_locker")
+
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Paying for locker $second_locker with the same payment hash..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/pay_for_usage/$second_locker")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -6,7 +6,7 @@
 set -euo pipefail
 set -o posix
 
-root_api_url="http://127.0.0.1:8080"
+root_api_url="http://127.0.0.1:8080/v1"
 
 echo "Running end-to-end tests..."
 
@@ -29,7 +29,7 @@ echo "(Done)"
 
 echo -n "Using locker $available_locker..."
 # start using that locker
-response=$(curl -X GET \
+response=$(curl -X POST \
   --silent \
   -H "accept: application/json" \
   -H "Content-Type: application/json" \
@@ -60,7 +60,7 @@ echo "(Done)"
 
 echo -n "Paying for locker $available_locker..."
 # stop using the locker
-response=$(curl -X GET \
+response=$(curl -X POST \
   --silent \
   -H "accept: application/json" \
   -H "Content-Type: application/json" \
@@ -81,7 +81,7 @@ response=$(curl -X GET \
   --silent \
   -H "accept: application/json" \
   -H "Content-Type: application/json" \
-  "$root_api_url/payment_receipt/$payment_hash")
+  "$root_api_url/payments/$payment_hash/receipt")
 
 if [ "$(echo "$response" | jq -r '.error')" != "null" ]; then
   echo "Error: $(echo "$response" | jq -r '.error')"
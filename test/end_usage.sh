@@ -0,0 +1,97 @@
+#!/bin/bash
+# This script checks /end_usage fixes what a customer owes when they're done with a locker, however
+# long they then take to pay, and that lockers awaiting payment can't be claimed. It needs a fresh
+# server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./end_usage.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running end usage tests..."
+
+# prints the status code of a POST to the path $1
+status() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url$1"
+}
+
+# prints the lease time and amount from a POST to the path $1
+billed() {
+  curl -X POST \
+    --silent \
+    --fail \
+    "$root_api_url$1" | jq -r '"\(.data.lease_time) \(.data.amount)"'
+}
+
+echo -n "Ending the usage of locker 1..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+sleep 2
+
+ended=$(billed "/end_usage/1")
+if [ "${ended% *}" -lt 2 ]; then
+  echo "Error: expected at least 2 seconds of usage, got $ended."
+  exit 1
+fi
+
+state=$(curl -X GET --silent --fail "$root_api_url/lockers/1" | jq -r '.data.state')
+if [ "$state" != "awaiting_payment" ]; then
+  echo "Error: expected locker 1 to await payment, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to claim a locker awaiting payment..."
+result=$(status "/use_locker/1")
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the amount fixed while the customer takes their time..."
+sleep 2
+result=$(billed "/end_usage/1")
+if [ "$result" != "$ended" ]; then
+  echo "Error: expected $ended when ending again, got $result."
+  exit 1
+fi
+
+result=$(billed "/pay_for_usage/1")
+if [ "$result" != "$ended" ]; then
+  echo "Error: expected to be invoiced $ended, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Listing lockers awaiting payment..."
+result=$(curl -X GET --silent --fail "$root_api_url/lockers?state=awaiting_payment" | jq -r '[.data[].id] | join(",")')
+if [ "$result" != "1" ]; then
+  echo "Error: expected only locker 1, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to end the usage of a locker nobody is using..."
+result=$(status "/end_usage/2")
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409, got $result."
+  exit 1
+fi
+
+result=$(status "/pay_for_usage/2")
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409, got $result."
+  exit 1
+fi
+
+echo "(Done)"
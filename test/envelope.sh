@@ -0,0 +1,104 @@
+#!/bin/bash
+# This script checks every public route answers JSON in the same envelope, `data` and `error`,
+# whether the request succeeded or not. It needs a fresh server running with the mock lightning
+# backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./envelope.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running envelope tests..."
+
+# sends a $1 request to $2, with the JSON body $5 if set, and checks it gets the status code $3 in
+# a JSON envelope with the keys $4, `error` set to a code and a message only if the request failed.
+# The response is left in $body
+expect_envelope() {
+  response=$(curl -X "$1" \
+    --silent \
+    --write-out "\n%{http_code} %{content_type}" \
+    ${5:+-H "content-type: application/json" -d "$5"} \
+    "$root_api_url$2")
+
+  body=$(echo "$response" | head -n -1)
+  result=$(echo "$response" | tail -n 1)
+  if [ "$result" != "$3 application/json; charset=utf-8" ]; then
+    echo "Error: expected $1 $2 to answer $3 in JSON, got $result."
+    exit 1
+  fi
+
+  if [ "$(echo "$body" | jq -c 'keys')" != "$4" ]; then
+    echo "Error: expected the keys $4 from $1 $2, got $body."
+    exit 1
+  fi
+
+  error=$(echo "$body" | jq -r '.error | if . == null then "null" else "\(.code | type) \(.message | type)" end')
+  if { [ "$3" -lt 400 ] && [ "$error" != "null" ]; } \
+    || { [ "$3" -ge 400 ] && [ "$error" != "string string" ]; }; then
+    echo "Error: expected $1 $2 to have an error only if it failed, got $body."
+    exit 1
+  fi
+}
+
+envelope='["data","error"]'
+missing_hash=$(printf '0%.0s' $(seq 1 64))
+
+echo -n "Wrapping what succeeds..."
+expect_envelope GET /lockers 200 '["data","error","total"]'
+expect_envelope GET /lockers/1 200 "$envelope"
+expect_envelope GET /keys 200 "$envelope"
+expect_envelope GET /pubkey 200 "$envelope"
+expect_envelope GET /revocations 200 "$envelope"
+expect_envelope GET /health 200 "$envelope"
+expect_envelope GET /version 200 "$envelope"
+expect_envelope POST /use_locker/1 200 "$envelope"
+# a token that doesn't check out is an answer, not a failure
+expect_envelope POST /verify_token 200 "$envelope" '{"locker_id": 1, "token": "malformed"}'
+expect_envelope POST /pay_for_usage/1 200 "$envelope"
+payment_hash=$(echo "$body" | jq -r '.data.invoice.payment_hash')
+
+# the mock backend considers every invoice paid
+expect_envelope GET "/payments/$payment_hash/receipt" 200 "$envelope"
+echo "(Done)"
+
+echo -n "Wrapping what fails..."
+expect_envelope GET /lockers/999999 404 "$envelope"
+expect_envelope GET /lockers/abc 400 "$envelope"
+expect_envelope POST /use_locker/1 409 "$envelope"
+expect_envelope POST /end_usage/999999 404 "$envelope"
+expect_envelope POST /cancel_usage/1 401 "$envelope" '{"token": "none"}'
+expect_envelope GET "/payments/$missing_hash/receipt" 404 "$envelope"
+expect_envelope GET "/payments/$payment_hash/invoice" 409 "$envelope"
+expect_envelope GET /me 401 "$envelope"
+expect_envelope GET /auth/lnurl 404 "$envelope"
+expect_envelope POST /update_locker_open 404 "$envelope" \
+  '{"locker_id": 999999, "signature": "00", "timestamp": 0}'
+expect_envelope POST /provision/register 400 "$envelope" '{"pk": "not a key"}'
+expect_envelope GET /use_locker/1 405 "$envelope"
+echo "(Done)"
+
+echo -n "Wrapping what axum rejects..."
+expect_envelope POST /cancel_usage/1 422 "$envelope" '{"token": 1}'
+expect_envelope POST /verify_receipt 400 "$envelope" 'not json'
+expect_envelope GET /lockers?limit=abc 400 "$envelope"
+expect_envelope DELETE /lockers 405 "$envelope"
+expect_envelope GET /nowhere 404 "$envelope"
+echo "(Done)"
+
+echo -n "Telling what failed with a code..."
+expect_envelope POST /use_locker/1 409 "$envelope"
+code=$(echo "$body" | jq -r '.error.code')
+if [ "$code" != "conflict" ]; then
+  echo "Error: expected the conflict code, got $body."
+  exit 1
+fi
+
+expect_envelope POST /cancel_usage/1 422 "$envelope" '{"token": 1}'
+code=$(echo "$body" | jq -r '.error.code')
+if [ "$code" != "invalid_body" ]; then
+  echo "Error: expected the invalid_body code, got $body."
+  exit 1
+fi
+echo "(Done)"
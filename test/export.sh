@@ -0,0 +1,102 @@
+#!/bin/bash
+# This script checks the CSV exports under /admin/export. It needs a fresh server running with the
+# mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./export.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running export tests..."
+
+echo -n "Naming locker 1 with a comma and quotes..."
+curl -X PATCH \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "$auth" \
+  -H "content-type: application/json" \
+  -d '{"name": "Hall, \"B\""}' \
+  "$root_api_url/admin/lockers/1"
+
+echo "(Done)"
+
+echo -n "Using and paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/pay_for_usage/1"
+
+echo "(Done)"
+
+echo -n "Exporting payments..."
+payments=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/export/payments.csv" | tr -d '\r')
+
+if [ "$(echo "$payments" | head -n 1)" != "payment_hash,locker_id,locker_name,amount,status,created_at,paid_at,expired_at,redeemed_at,operator_id" ]; then
+  echo "Error: unexpected header in $payments."
+  exit 1
+fi
+
+if ! echo "$payments" | sed -n 2p | grep -Eq '^[0-9a-f]{64},1,"Hall, ""B""",[0-9]+,pending,[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9:]{8}Z,,,,$'; then
+  echo "Error: unexpected row in $payments."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Exporting payments created in the future..."
+rows=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/export/payments.csv?from=$(($(date +%s) + 3600))" | wc -l)
+
+if [ "$rows" != "1" ]; then
+  echo "Error: expected only the header, got $rows lines."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Exporting sessions..."
+sessions=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/export/sessions.csv" | tr -d '\r')
+
+if ! echo "$sessions" | sed -n 2p | grep -Eq '^1,1,"Hall, ""B""",[0-9T:Z-]+,,[0-9]+,[0-9a-f]{64},active,$'; then
+  echo "Error: unexpected row in $sessions."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Exporting without the admin token..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/admin/export/sessions.csv")
+
+if [ "$status" != "401" ]; then
+  echo "Error: expected 401, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,114 @@
+#!/bin/bash
+# This script checks staff can force a locker open, getting a receipt marked as issued by an admin,
+# and force a locker in use back to available, cancelling its invoice if asked to, and that both
+# need a reason that ends up in the event log. It needs a fresh server running with the mock
+# lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./force_actions.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running force actions tests..."
+
+# posts the JSON body $2 to the admin path $1, printing the response body followed by the status
+# code
+force() {
+  curl -X POST \
+    --silent \
+    --write-out " %{http_code}" \
+    -H "$auth" \
+    -H "Content-Type: application/json" \
+    -d "$2" \
+    "$root_api_url/admin/lockers/$1"
+}
+
+# checks posting $2 to $1 gets the status code $3, and a response the jq filter $4 turns into $5
+expect_force() {
+  response=$(force "$1" "$2")
+  result="$(echo "${response% *}" | jq -r "$4") ${response##* }"
+  if [ "$result" != "$5 $3" ]; then
+    echo "Error: expected $5 $3 posting $2 to $1, got $result."
+    exit 1
+  fi
+}
+
+# checks the latest event of locker $1, as the jq filter $2 prints it, is $3
+expect_event() {
+  event=$(curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/lockers/$1/events" | jq -r ".data[0] | $2")
+  if [ "$event" != "$3" ]; then
+    echo "Error: expected the event $3 for locker $1, got $event."
+    exit 1
+  fi
+}
+
+echo -n "Refusing to force a locker without a reason..."
+expect_force 1/force_open '{"reason":" "}' 400 '.error.code' "invalid_param"
+expect_force 1/force_release '{}' 400 '.error.code' "invalid_param"
+echo "(Done)"
+
+echo -n "Forcing a locker open..."
+expect_force 1/force_open '{"reason":"customer lost their phone"}' 200 \
+  '"\(.data.action) \(.data.signed_receipt.receipt.actor) \(.data.signed_receipt.receipt.payment_hash)"' \
+  "retrieve admin null"
+expect_event 1 '"\(.event) \(.actor) \(.details.reason)"' "force_opened admin customer lost their phone"
+
+state=$(curl -X GET --silent --fail "$root_api_url/lockers" | jq -r '.data[] | select(.id == 1) | .state')
+if [ "$state" != "available" ]; then
+  echo "Error: expected forcing the locker open to leave it available, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to release a locker nobody is using..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"reason":"stuck"}' \
+  "$root_api_url/admin/lockers/1/force_release")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/2"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/2" | jq -r '.data.invoice.payment_hash')
+
+echo -n "Releasing a locker in use..."
+expect_force 1/force_release '{"reason":"door jammed"}' 200 '.data.state' "available"
+expect_event 1 '"\(.event) \(.details.reason) \(.details.invoice_cancelled)"' "force_released door jammed false"
+echo "(Done)"
+
+echo -n "Releasing a locker and cancelling its invoice..."
+expect_force 2/force_release '{"reason":"refunded at the desk","cancel_invoice":true}' 200 '.data.state' "available"
+expect_event 2 '"\(.event) \(.details.invoice_cancelled)"' "force_released true"
+
+status=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments" | jq -r ".data.payments[] | select(.payment_hash == \"$payment_hash\") | .status")
+if [ "$status" != "cancelled" ]; then
+  echo "Error: expected the payment to be cancelled, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,47 @@
+#!/bin/bash
+# This script checks GET /health reports a healthy server, with the details of each of its
+# dependencies. It needs a server running with the mock lightning backend.
+
+# Usage: LN_BACKEND=mock cargo run --features mock-ln &
+#        ./health.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running health tests..."
+
+# give the background tasks a moment to run for the first time
+sleep 1
+
+echo -n "Checking a healthy server..."
+response=$(curl -X GET --silent --write-out " %{http_code}" "$root_api_url/health")
+status=${response##* }
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+result=$(echo "${response% *}" | jq -r '.data | "\(.status) \(.failing | length) \(.checks.database.ok) \(.checks.ln.ok) \(.checks.tasks.cleanup_payments.ok)"')
+if [ "$result" != "ok 0 true true true" ]; then
+  echo "Error: expected every check to pass, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Checking the lightning backend was checked in the background..."
+result=$(echo "${response% *}" | jq -r '.data.checks.ln | "\(.required) \(.last_check == .last_reachable) \(.last_check > 0)"')
+if [ "$result" != "true true true" ]; then
+  echo "Error: expected the backend to have been reached, got $result."
+  exit 1
+fi
+
+version=$(echo "${response% *}" | jq -r '.data.version')
+if [ -z "$version" ] || [ "$version" == "null" ]; then
+  echo "Error: expected the build version, got $version."
+  exit 1
+fi
+
+echo "(Done)"
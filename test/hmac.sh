@@ -0,0 +1,114 @@
+#!/bin/bash
+# This script checks lockers using HMAC instead of signatures: their authorizations are tagged with
+# the secret they share with the server, which accepts their reports tagged the same way and never
+# reveals it. It needs a server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./hmac.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret locker 3 shares with the server, see lockers.toml
+locker_secret="4242424242424242424242424242424242424242424242424242424242424242"
+
+echo "Running HMAC locker tests..."
+
+# sends a report, printing the status code
+report() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d "$1" \
+    "$root_api_url/update_locker_open"
+}
+
+echo -n "Listing the locker without its secret..."
+lockers=$(curl -X GET --silent --fail "$root_api_url/lockers")
+
+auth_mode=$(echo "$lockers" | jq -r '.data[] | select(.id == 3) | .auth_mode')
+if [ "$auth_mode" != "hmac" ]; then
+  echo "Error: expected locker 3 to use hmac, got $auth_mode."
+  exit 1
+fi
+
+if echo "$lockers" | grep -q "$locker_secret"; then
+  echo "Error: the secret of locker 3 was listed."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Tagging its authorization with the shared secret..."
+authorization=$(curl -X POST --silent --fail "$root_api_url/use_locker/3")
+
+start_time=$(echo "$authorization" | jq -r '.data.start_time')
+expires_at=$(echo "$authorization" | jq -r '.data.expires_at')
+signature=$(echo "$authorization" | jq -r '.data.signature')
+expected=$(python3 - "$locker_secret" "$start_time" "$expires_at" <<'EOF_PYTHON'
+import hashlib, hmac, struct, sys
+
+tag = hashlib.sha256(b"locker/open-auth").digest()
+# 1 is store, what the locker is opened for after claiming it
+message = struct.pack(">qBQQ", 3, 1, int(sys.argv[2]), int(sys.argv[3]))
+digest = hashlib.sha256(tag + tag + message).digest()
+print(hmac.new(bytes.fromhex(sys.argv[1]), digest, hashlib.sha256).hexdigest())
+EOF_PYTHON
+)
+
+if [ "${signature,,}" != "$expected" ]; then
+  echo "Error: expected the tag $expected, got $signature."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a report tagged with another secret..."
+wrong_secret="2424242424242424242424242424242424242424242424242424242424242424"
+status=$(report "$("$script_dir/locker.py" "hmac:$wrong_secret" 3 "$start_time" "$expires_at")")
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a signed report..."
+# the secret key of locker 1, which signs its reports
+seckey="1000000000000000000000000000000000000000000000000000000000000001"
+status=$(report "$("$script_dir/locker.py" "$seckey" 3 "$start_time" "$expires_at")")
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting reports tagged with the shared secret..."
+status=$(report "$("$script_dir/locker.py" "hmac:$locker_secret" 3 "$start_time" "$expires_at")")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200 storing, got $status."
+  exit 1
+fi
+
+status=$(report "$("$script_dir/locker.py" "hmac:$locker_secret" 3 "$((start_time + 1))" \
+  "$expires_at" retrieve)")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200 retrieving, got $status."
+  exit 1
+fi
+
+state=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers" | jq -r '.data[] | select(.id == 3) | .state')
+if [ "$state" != "available" ]; then
+  echo "Error: expected locker 3 to be available, got $state."
+  exit 1
+fi
+
+echo "(Done)"
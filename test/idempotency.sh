@@ -0,0 +1,165 @@
+#!/bin/bash
+# This script checks that requests retried with the same Idempotency-Key get the first response,
+# and only when the same client retries them.
+# It needs a fresh server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./idempotency.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+seckey="3000000000000000000000000000000000000000000000000000000000000001"
+key="idempotency-test-$RANDOM$RANDOM"
+
+echo "Running Idempotency-Key tests..."
+
+echo -n "Claiming locker 1 twice with the same key..."
+first=$(curl -X POST \
+  --silent \
+  --fail \
+  --header "Idempotency-Key: claim-$key" \
+  "$root_api_url/use_locker/1")
+
+headers=$(mktemp)
+second=$(curl -X POST \
+  --silent \
+  --fail \
+  --header "Idempotency-Key: claim-$key" \
+  --dump-header "$headers" \
+  "$root_api_url/use_locker/1")
+
+if [ "$first" != "$second" ]; then
+  echo "Error: expected the same response, got $first and $second."
+  exit 1
+fi
+
+if ! grep -qi "^idempotent-replayed: true" "$headers"; then
+  echo "Error: expected the response to be marked as replayed, got $(cat "$headers")."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the claim from another client with the same key..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  --header "Idempotency-Key: claim-$key" \
+  --header "Authorization: $("$script_dir/nostr.py" "$seckey" POST "$root_api_url/use_locker/1")" \
+  --dump-header "$headers" \
+  "$root_api_url/use_locker/1")
+
+# the key is another one for this client, so the request reaches the handler, and locker 1 is in
+# use
+if [ "$status" != "409" ] || grep -qi "^idempotent-replayed" "$headers"; then
+  echo "Error: expected a 409 that wasn't replayed, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Asking for an invoice twice with the same key..."
+first=$(curl -X POST \
+  --silent \
+  --fail \
+  --header "Idempotency-Key: invoice-$key" \
+  --dump-header "$headers" \
+  "$root_api_url/pay_for_usage/1")
+
+if grep -qi "^idempotent-replayed" "$headers"; then
+  echo "Error: expected the first response not to be marked as replayed."
+  exit 1
+fi
+
+second=$(curl -X POST \
+  --silent \
+  --fail \
+  --header "Idempotency-Key: invoice-$key" \
+  --dump-header "$headers" \
+  "$root_api_url/pay_for_usage/1")
+
+if [ "$first" != "$second" ]; then
+  echo "Error: expected the same invoice, got $first and $second."
+  exit 1
+fi
+
+if ! grep -qi "^idempotent-replayed: true" "$headers"; then
+  echo "Error: expected the response to be marked as replayed, got $(cat "$headers")."
+  exit 1
+fi
+
+rm "$headers"
+
+echo "(Done)"
+
+echo -n "Reusing the key for another locker..."
+response=$(curl -X POST \
+  --silent \
+  --write-out "\n%{http_code}" \
+  --header "Idempotency-Key: invoice-$key" \
+  "$root_api_url/pay_for_usage/2")
+
+status=$(echo "$response" | tail -n 1)
+code=$(echo "$response" | head -n 1 | jq -r '.error.code')
+if [ "$status" != "409" ] || [ "$code" != "conflict" ]; then
+  echo "Error: expected a 409 conflict, got $status $code."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Sending a malformed key..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  --header "Idempotency-Key: $(printf 'k%.0s' {1..256})" \
+  "$root_api_url/pay_for_usage/1")
+
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Retrying a request that failed with the same key..."
+token=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/2" | jq -r '.data.token')
+
+for attempt in 1 2; do
+  status=$(curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    --header "Idempotency-Key: failed-$key" \
+    "$root_api_url/use_locker/2")
+
+  # locker 2 is in use, so both attempts reach the handler
+  if [ "$status" != "409" ]; then
+    echo "Error: expected attempt $attempt to be a 409, got $status."
+    exit 1
+  fi
+done
+
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "{\"token\": \"$token\"}" \
+  "$root_api_url/cancel_usage/2"
+
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  --header "Idempotency-Key: failed-$key" \
+  "$root_api_url/use_locker/2"
+
+echo "(Done)"
@@ -0,0 +1,114 @@
+#!/bin/bash
+# This script checks that clients can fetch the invoice for a payment again, by its hash or by its
+# locker, until it's settled. It needs a fresh server running with the mock lightning backend and
+# the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./invoice.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running invoice tests..."
+
+echo -n "Using and paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+invoice=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -c '.data.invoice')
+payment_hash=$(echo "$invoice" | jq -r '.payment_hash')
+
+echo "(Done)"
+
+echo -n "Fetching the invoice again..."
+bolt11=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/invoice" | jq -r '.data.bolt11')
+
+if [ "$bolt11" != "$(echo "$invoice" | jq -r '.bolt11')" ]; then
+  echo "Error: expected the invoice from $invoice, got $bolt11."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Fetching the invoice by its locker..."
+active=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers/1/invoice" | jq -r '"\(.data.payment_hash) \(.data.bolt11) \(.data.locker_id) \(.data.expires_at | type)"')
+
+if [ "$active" != "$payment_hash $bolt11 1 number" ]; then
+  echo "Error: expected the invoice from $invoice, got $active."
+  exit 1
+fi
+
+# another locker isn't waiting on it
+for locker_id in 2 999999; do
+  status=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url/lockers/$locker_id/invoice")
+
+  if [ "$status" != "404" ]; then
+    echo "Error: expected 404 for locker $locker_id, got $status."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Fetching the invoice once it's settled..."
+# the mock backend considers every invoice paid
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt"
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/invoice")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/lockers/1/invoice")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected no invoice for the locker once it's settled, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Fetching the invoice of an unknown payment..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/0000000000000000000000000000000000000000000000000000000000000000/invoice")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,133 @@
+#!/bin/bash
+# This script checks that staff can give a locker whose controller was replaced the new
+# controller's key, and that everything authorized for the old one stops working. It needs a fresh
+# server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./key_rotation.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# locker A1 from test/lockers.toml, and the key of its new controller
+locker_id=1
+old_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+new_seckey="1000000000000000000000000000000000000000000000000000000000000003"
+
+echo "Running key rotation tests..."
+
+# rotates the key of locker $1 with the body $2, printing the status code
+rotate() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    -H "Content-Type: application/json" \
+    -d "$2" \
+    "$root_api_url/admin/lockers/$1/rotate_key"
+}
+
+echo -n "Claiming locker $locker_id with its old controller..."
+token=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/$locker_id" | jq -r '.data.token')
+
+echo "(Done)"
+
+echo -n "Rejecting keys we can't use..."
+body=$("$script_dir/rotate_key.py" "$old_seckey" "$locker_id" "$new_seckey")
+pk=$(echo "$body" | jq -r '.pk')
+
+for bad in \
+  '{"pk": "abc"}' \
+  "{\"pk\": \"$(printf '0%.0s' {1..64})\"}" \
+  "{\"pk\": \"$pk\", \"signature\": \"abc\"}"; do
+  result=$(rotate "$locker_id" "$bad")
+  if [ "$result" != "400" ]; then
+    echo "Error: expected 400 for $bad, got $result."
+    exit 1
+  fi
+done
+
+# signed by the key of another locker
+result=$(rotate "$locker_id" "$("$script_dir/rotate_key.py" "${old_seckey%1}2" "$locker_id" "$new_seckey")")
+if [ "$result" != "400" ]; then
+  echo "Error: expected 400 for a signature by another key, got $result."
+  exit 1
+fi
+
+# the key of locker A2
+result=$(rotate "$locker_id" '{"pk": "7aaa7852ba48c949c6e7a98263999c60ab0f2dde7031eaa080245b8bb250e285"}')
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409 for another locker's key, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rotating the key..."
+result=$(rotate "$locker_id" "$body")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+event=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/lockers/$locker_id/events?limit=1" | jq -r '.data[0] | "\(.event) \(.details.pk) \(.details.signed_by_old_key)"')
+if [ "$event" != "key_rotated $pk true" ]; then
+  echo "Error: expected a key_rotated event, got $event."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing what was authorized for the old controller..."
+reason=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "{\"locker_id\": $locker_id, \"token\": \"$token\"}" \
+  "$root_api_url/verify_token" | jq -r '.data.reason')
+if [ "$reason" != "revoked" ]; then
+  echo "Error: expected the old token to be revoked, got $reason."
+  exit 1
+fi
+
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$old_seckey" "$locker_id")" \
+  "$root_api_url/update_locker_open")
+if [ "$status" != "400" ]; then
+  echo "Error: expected the old key's report to be rejected, got $status."
+  exit 1
+fi
+
+# the old key can't come back either
+result=$(rotate "$locker_id" '{"pk": "347d79020cf8914031ed69aae2dd7f6e6ce7e036d2976e50c3e3c412165df746"}')
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409 for the retired key, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting reports from the new controller..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$new_seckey" "$locker_id")" \
+  "$root_api_url/update_locker_open"
+
+echo "(Done)"
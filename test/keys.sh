@@ -0,0 +1,53 @@
+#!/bin/bash
+# This script checks the server keyring: /keys lists every key with its status, and what we sign
+# says which key signed it. It needs a server running with the mock lightning backend, the test
+# lockers and the test keyring.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml SERVER_KEYRING=test/keyring.toml cargo run --features mock-ln & ./keys.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running keyring tests..."
+
+echo -n "Listing the keys..."
+keys=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/keys" | jq -r '[.data[] | "\(.kid):\(.status)"] | join(",")')
+
+if [ "$keys" != "old:retired,current:active,next:pending" ]; then
+  echo "Error: expected old to be retired, current active and next pending, got $keys."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Signing with the active key..."
+authorization=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/1")
+kid=$(echo "$authorization" | jq -r '.data.kid')
+token=$(echo "$authorization" | jq -r '.data.token')
+
+if [ "$kid" != "current" ]; then
+  echo "Error: expected the current key, got $kid."
+  exit 1
+fi
+
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "{\"locker_id\": 1, \"token\": \"$token\"}" \
+  "$root_api_url/verify_token" | jq -r '.data.reason // "valid"')
+
+if [ "$result" != "valid" ]; then
+  echo "Error: expected the token to be valid, got $result."
+  exit 1
+fi
+
+echo "(Done)"
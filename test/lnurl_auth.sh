@@ -0,0 +1,141 @@
+#!/bin/bash
+# This script checks customers can log in with their wallet through LNURL-auth, and then find the
+# lockers they used and what they paid, while customers who don't log in use lockers as before.
+# It needs a fresh server running with the mock lightning backend, the test lockers and a public
+# URL.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml PUBLIC_URL=http://127.0.0.1:8080 cargo run --features mock-ln & ./lnurl_auth.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+linking_seckey="2000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running LNURL-auth tests..."
+
+# prints the status code of a $3 to $1 with the bearer token $2, a GET unless $3 says otherwise
+status() {
+  curl -X "${3:-GET}" \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: Bearer $2" \
+    "$1"
+}
+
+echo -n "Asking for a challenge..."
+challenge=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/auth/lnurl")
+
+token=$(echo "$challenge" | jq -r '.data.token')
+callback=$(echo "$challenge" | jq -r '.data.callback')
+lnurl=$(echo "$challenge" | jq -r '.data.lnurl')
+
+if [[ "$lnurl" != LNURL1* ]]; then
+  echo "Error: expected an LNURL, got $lnurl."
+  exit 1
+fi
+
+result=$(status "$root_api_url/me" "$token")
+if [ "$result" != "401" ]; then
+  echo "Error: expected 401 before the wallet signs in, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Signing in with the wallet..."
+login=$("$script_dir/wallet.py" "$linking_seckey" "$callback")
+linking_key=${login##*&key=}
+
+# the signature with another wallet's key
+other_key=$("$script_dir/wallet.py" "${linking_seckey%1}2" "$callback")
+answer=$(curl -X GET --silent "${login%&key=*}&key=${other_key##*&key=}" | jq -r '.status')
+if [ "$answer" != "ERROR" ]; then
+  echo "Error: expected a signature by another key to be refused, got $answer."
+  exit 1
+fi
+
+answer=$(curl -X GET --silent "$login" | jq -r '.status')
+if [ "$answer" != "OK" ]; then
+  echo "Error: expected the wallet to sign in, got $answer."
+  exit 1
+fi
+
+answer=$(curl -X GET --silent "$login" | jq -r '.status')
+if [ "$answer" != "ERROR" ]; then
+  echo "Error: expected the challenge to only be signed once, got $answer."
+  exit 1
+fi
+
+me=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $token" \
+  "$root_api_url/me" | jq -r '.data.linking_key')
+if [ "$me" != "$linking_key" ]; then
+  echo "Error: expected to be logged in as $linking_key, got $me."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting tokens that don't log anyone in..."
+for url in "$root_api_url/me" "$root_api_url/me/sessions" "$root_api_url/use_locker/2"; do
+  method=GET
+  if [[ "$url" == */use_locker/* ]]; then
+    method=POST
+  fi
+
+  result=$(status "$url" "not-a-token" "$method")
+  if [ "$result" != "401" ]; then
+    echo "Error: expected 401 from $url, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Using a locker logged in, and another anonymously..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "Authorization: Bearer $token" \
+  "$root_api_url/use_locker/1"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+curl -X GET --silent --fail --output /dev/null "$root_api_url/payments/$payment_hash/receipt"
+
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/2"
+
+echo "(Done)"
+
+echo -n "Listing what the customer did..."
+sessions=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $token" \
+  "$root_api_url/me/sessions" | jq -r '"\(.data.total) \(.data.sessions[0].locker_id) \(.data.sessions[0].payment_hash)"')
+if [ "$sessions" != "1 1 $payment_hash" ]; then
+  echo "Error: expected only the session for locker 1, got $sessions."
+  exit 1
+fi
+
+payments=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $token" \
+  "$root_api_url/me/payments" | jq -r '"\(.data.total) \(.data.payments[0].payment_hash)"')
+if [ "$payments" != "1 $payment_hash" ]; then
+  echo "Error: expected only the payment for locker 1, got $payments."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+"""Plays the part of a locker: prints the JSON body of an `/update_locker_open` report, signed
+with the locker's secret key.
+
+Usage: ./locker.py <secret key hex> <locker id> [timestamp] [expires_at] [action] [authorized_at]
+
+For lockers using HMAC, pass `hmac:<shared secret hex>` as the key, and the report is tagged with
+it rather than signed.
+
+The authorization the locker was opened with expires 15 minutes after the timestamp unless
+`expires_at` says otherwise. Passing `legacy` as `expires_at` reports the way firmware from before
+authorizations expired does, leaving it and the action out. The locker is opened to `store`
+something unless `action` is `retrieve`. `authorized_at` is the timestamp of the authorization the
+locker was opened with, the `issued_at` of the receipt when retrieving, and is left out unless
+given.
+
+The signing follows the BIP340 reference implementation, so we don't need any dependencies.
+"""
+
+import hashlib
+import hmac
+import json
+import struct
+import sys
+import time
+
+P = 0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F
+N = 0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141
+G = (
+    0x79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798,
+    0x483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8,
+)
+
+
+def point_add(p1, p2):
+    if p1 is None:
+        return p2
+    if p2 is None:
+        return p1
+    if p1[0] == p2[0] and p1[1] != p2[1]:
+        return None
+    if p1 == p2:
+        lam = (3 * p1[0] * p1[0] * pow(2 * p1[1], P - 2, P)) % P
+    else:
+        lam = ((p2[1] - p1[1]) * pow(p2[0] - p1[0], P - 2, P)) % P
+    x = (lam * lam - p1[0] - p2[0]) % P
+    return (x, (lam * (p1[0] - x) - p1[1]) % P)
+
+
+def point_mul(p, n):
+    r = None
+    for i in range(256):
+        if (n >> i) & 1:
+            r = point_add(r, p)
+        p = point_add(p, p)
+    return r
+
+
+def tagged_hash(tag, msg):
+    tag_hash = hashlib.sha256(tag.encode()).digest()
+    return hashlib.sha256(tag_hash + tag_hash + msg).digest()
+
+
+def sign(msg, seckey, aux_rand=bytes(32)):
+    d0 = int.from_bytes(seckey, "big")
+    pk = point_mul(G, d0)
+    d = d0 if pk[1] % 2 == 0 else N - d0
+    t = (d ^ int.from_bytes(tagged_hash("BIP0340/aux", aux_rand), "big")).to_bytes(32, "big")
+    pk_bytes = pk[0].to_bytes(32, "big")
+    k0 = int.from_bytes(tagged_hash("BIP0340/nonce", t + pk_bytes + msg), "big") % N
+    r = point_mul(G, k0)
+    k = k0 if r[1] % 2 == 0 else N - k0
+    r_bytes = r[0].to_bytes(32, "big")
+    e = int.from_bytes(tagged_hash("BIP0340/challenge", r_bytes + pk_bytes + msg), "big") % N
+    return r_bytes + ((k + e * d) % N).to_bytes(32, "big")
+
+
+ACTIONS = {"store": 1, "retrieve": 2}
+
+
+def report_digest(locker_id, action, timestamp, expires_at):
+    """What the locker signs: the `locker/open-report` tagged hash of its id, what it was opened
+    for and the timestamps."""
+    message = struct.pack(">qBQQ", locker_id, ACTIONS[action], timestamp, expires_at)
+    return tagged_hash("locker/open-report", message)
+
+
+def hmac_tag(secret, digest):
+    """What lockers using HMAC send instead of a signature."""
+    return hmac.new(secret, digest, hashlib.sha256).digest()
+
+
+def legacy_digest():
+    """What firmware from before authorizations expired signs.
+
+    That firmware takes the midstate of a sha256 engine fed less than a block of data, which is
+    always the sha256 initial state, whatever the locker id and timestamp are.
+    """
+    return bytes.fromhex("6a09e667bb67ae853c6ef372a54ff53a510e527f9b05688c1f83d9ab5be0cd19")
+
+
+def main():
+    if len(sys.argv) not in (3, 4, 5, 6, 7):
+        sys.exit(__doc__)
+
+    use_hmac = sys.argv[1].startswith("hmac:")
+    seckey = bytes.fromhex(sys.argv[1].removeprefix("hmac:"))
+    locker_id = int(sys.argv[2])
+    timestamp = int(sys.argv[3]) if len(sys.argv) >= 4 else int(time.time())
+    if len(sys.argv) < 5:
+        expires_at = timestamp + 15 * 60
+    elif sys.argv[4] == "legacy":
+        expires_at = None
+    else:
+        expires_at = int(sys.argv[4])
+    action = sys.argv[5] if len(sys.argv) >= 6 else "store"
+    authorized_at = int(sys.argv[6]) if len(sys.argv) == 7 else None
+
+    if use_hmac:
+        if expires_at is None:
+            sys.exit("lockers using HMAC always say when their authorization expires")
+        signature = hmac_tag(seckey, report_digest(locker_id, action, timestamp, expires_at))
+    elif expires_at is None:
+        signature = sign(legacy_digest(), seckey)
+    else:
+        signature = sign(report_digest(locker_id, action, timestamp, expires_at), seckey)
+
+    report = {"locker_id": locker_id, "signature": signature.hex(), "timestamp": timestamp}
+    if expires_at is not None:
+        report["expires_at"] = expires_at
+        report["action"] = action
+    if authorized_at is not None:
+        report["authorized_at"] = authorized_at
+    print(json.dumps(report))
+
+
+if __name__ == "__main__":
+    main()
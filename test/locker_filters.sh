@@ -0,0 +1,77 @@
+#!/bin/bash
+# This script checks /lockers can be filtered by state, size, location and site, that the filters
+# combine with each other and with pagination, and that unknown filter values are rejected. It needs
+# a fresh server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./locker_filters.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running locker filter tests..."
+
+# prints the total and the ids of the lockers listed with the query $1
+page() {
+  curl -X GET \
+    --silent \
+    --fail \
+    "$root_api_url/lockers?$1" | jq -r '"\(.total) \([.data[].id] | join(","))"'
+}
+
+# checks the page for the query $1 is $2
+expect_page() {
+  result=$(page "$1")
+  if [ "$result" != "$2" ]; then
+    echo "Error: expected $2 for ?$1, got $result."
+    exit 1
+  fi
+}
+
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+
+echo -n "Filtering by state..."
+expect_page "state=available" "2 2,3"
+expect_page "state=in_use" "1 1"
+echo "(Done)"
+
+echo -n "Filtering by size..."
+expect_page "size=large" "1 2"
+expect_page "size=small" "1 1"
+echo "(Done)"
+
+echo -n "Filtering by location..."
+expect_page "location=Entrance%20B" "1 2"
+expect_page "location=entrance%20b" "1 2"
+expect_page "location=MAIN%20ENTRANCE" "2 1,3"
+expect_page "location=entrance" "0 "
+echo "(Done)"
+
+echo -n "Combining filters..."
+expect_page "state=available&size=medium" "1 3"
+expect_page "state=in_use&size=large" "0 "
+expect_page "state=available&size=large&site_id=1" "0 "
+expect_page "size=large&location=entrance%20b&state=available" "1 2"
+expect_page "size=large&location=main%20entrance&state=available" "0 "
+expect_page "size=medium&location=main%20entrance&state=in_use" "0 "
+echo "(Done)"
+
+echo -n "Paging through filtered lockers..."
+expect_page "state=available&limit=1" "2 2"
+expect_page "state=available&limit=1&offset=1" "2 3"
+expect_page "state=available&limit=1&after_id=2" "2 3"
+expect_page "location=main%20entrance&limit=1" "2 1"
+expect_page "location=main%20entrance&limit=1&offset=1" "2 3"
+expect_page "location=main%20entrance&state=available&limit=1&after_id=1" "1 3"
+echo "(Done)"
+
+echo -n "Rejecting unknown filter values..."
+for query in "state=broken" "state=provisioning" "size=huge" "size=LARGE&location=entrance%20b"; do
+  result=$(curl -X GET --silent --output /dev/null --write-out "%{http_code}" "$root_api_url/lockers?$query")
+  if [ "$result" != "400" ]; then
+    echo "Error: expected 400 for ?$query, got $result."
+    exit 1
+  fi
+done
+echo "(Done)"
@@ -0,0 +1,101 @@
+#!/bin/bash
+# This script checks /admin/lockers/{id}/history lists the sessions of a locker, newest first, with
+# what was paid for them and how they ended, filtered by date and a page at a time. It needs a fresh
+# server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./locker_history.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+started=$(date +%s)
+
+echo "Running locker history tests..."
+
+# prints the total and the outcome of each session in the history of locker 1 with the query $1
+history() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/lockers/1/history?$1" |
+    jq -r '"\(.data.total) \([.data.sessions[] | "\(.session_id):\(.outcome)"] | join(","))"'
+}
+
+# checks the history for the query $1 is $2
+expect_history() {
+  result=$(history "$1")
+  if [ "$result" != "$2" ]; then
+    echo "Error: expected $2 for ?$1, got $result."
+    exit 1
+  fi
+}
+
+echo -n "Using and paying for locker 1..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+curl -X GET --silent --fail --output /dev/null "$root_api_url/payments/$payment_hash/receipt"
+
+# the locker never reports being opened, so free it up for the next customer
+curl -X POST --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/consistency_check?fix=true"
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+
+echo "(Done)"
+
+echo -n "Listing the sessions of locker 1..."
+expect_history "" "2 2:in_progress,1:paid"
+
+sessions=$(curl -X GET --silent --fail -H "$auth" "$root_api_url/admin/lockers/1/history")
+if ! echo "$sessions" | jq -e --arg hash "$payment_hash" '.data.sessions[1] | .payment_hash == $hash and .amount_paid_sat != null and .ended_at >= .started_at and .duration_seconds == .ended_at - .started_at' >/dev/null; then
+  echo "Error: unexpected paid session in $sessions."
+  exit 1
+fi
+
+if ! echo "$sessions" | jq -e '.data.sessions[0] | .ended_at == null and .amount_paid_sat == null' >/dev/null; then
+  echo "Error: unexpected open session in $sessions."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Paging through the history..."
+expect_history "limit=1" "2 2:in_progress"
+expect_history "limit=1&offset=1" "2 1:paid"
+expect_history "offset=2" "2 "
+echo "(Done)"
+
+echo -n "Filtering the history by date..."
+expect_history "from=$started" "2 2:in_progress,1:paid"
+expect_history "from=$((started + 3600))" "0 "
+expect_history "to=$started" "0 "
+echo "(Done)"
+
+echo -n "Listing the history of a locker nobody used..."
+result=$(curl -X GET --silent --fail -H "$auth" "$root_api_url/admin/lockers/2/history" | jq -r '.data.total')
+if [ "$result" != "0" ]; then
+  echo "Error: expected no sessions, got $result."
+  exit 1
+fi
+echo "(Done)"
+
+echo -n "Rejecting unknown lockers and missing tokens..."
+for request in "$ADMIN_TOKEN:1000:404" "wrong:1:401"; do
+  IFS=: read -r token locker_id expected <<<"$request"
+  result=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: Bearer $token" \
+    "$root_api_url/admin/lockers/$locker_id/history")
+  if [ "$result" != "$expected" ]; then
+    echo "Error: expected $expected for locker $locker_id, got $result."
+    exit 1
+  fi
+done
+echo "(Done)"
@@ -0,0 +1,46 @@
+#!/bin/bash
+# This script checks that the public routes taking a locker id reject malformed ones with a JSON
+# error naming the field, and agree that lockers that don't exist are missing. It needs a server
+# running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./locker_ids.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running locker id tests..."
+
+for route in GET:lockers POST:use_locker POST:pay_for_usage; do
+  method=${route%%:*}
+  route=${route#*:}
+  echo -n "Checking /$route..."
+
+  for locker_id in abc -5 0 1.5 99999999999999999999; do
+    response=$(curl -X "$method" \
+      --silent \
+      --write-out "\n%{http_code}" \
+      "$root_api_url/$route/$locker_id")
+
+    status=$(echo "$response" | tail -n 1)
+    error=$(echo "$response" | head -n 1 | jq -r '.error.message')
+    if [ "$status" != "400" ] || [ "$error" != "locker_id: expected a positive integer" ]; then
+      echo "Error: expected /$route/$locker_id to be rejected, got $status $error."
+      exit 1
+    fi
+  done
+
+  status=$(curl -X "$method" \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url/$route/999999")
+
+  if [ "$status" != "404" ]; then
+    echo "Error: expected /$route/999999 to be missing, got $status."
+    exit 1
+  fi
+
+  echo "(Done)"
+done
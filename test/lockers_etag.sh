@@ -0,0 +1,116 @@
+#!/bin/bash
+# This script checks /lockers and /lockers/{id} are tagged with an ETag, that clients sending it
+# back get an empty 304, and that claiming, changing or releasing a locker changes it. It needs a
+# fresh server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./lockers_etag.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running locker ETag tests..."
+
+# prints the ETag of $1
+etag() {
+  curl -X GET \
+    --silent \
+    --fail \
+    --dump-header - \
+    --output /dev/null \
+    "$root_api_url$1" | grep -i "^etag:" | cut -d' ' -f2 | tr -d '\r'
+}
+
+# prints the status and the size of the body of $1 when sent with If-None-Match: $2
+revalidate() {
+  curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code} %{size_download}" \
+    --header "If-None-Match: $2" \
+    "$root_api_url$1"
+}
+
+# checks $1 is still tagged $2
+expect_unchanged() {
+  result=$(revalidate "$1" "$2")
+  if [ "$result" != "304 0" ]; then
+    echo "Error: expected an empty 304 for $1, got $result."
+    exit 1
+  fi
+}
+
+# checks $1 isn't tagged $2 anymore
+expect_changed() {
+  result=$(revalidate "$1" "$2")
+  if [ "${result%% *}" != "200" ]; then
+    echo "Error: expected $1 to have changed, got $result."
+    exit 1
+  fi
+}
+
+echo -n "Revalidating an unchanged listing..."
+tag=$(etag "/lockers")
+if [ -z "$tag" ]; then
+  echo "Error: expected /lockers to have an ETag."
+  exit 1
+fi
+
+expect_unchanged "/lockers" "$tag"
+expect_unchanged "/lockers?state=available&limit=1" "$tag"
+expect_unchanged "/lockers/1" "$tag"
+expect_changed "/lockers" "\"something-else\""
+
+echo "(Done)"
+
+echo -n "Claiming a locker..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+expect_changed "/lockers" "$tag"
+expect_changed "/lockers/2" "$tag"
+
+tag=$(etag "/lockers/1")
+state=$(curl -X GET --silent --fail "$root_api_url/lockers/1" | jq -r '.data.state')
+if [ "$state" != "in_use" ]; then
+  echo "Error: expected locker 1 to be in use, got $state."
+  exit 1
+fi
+expect_unchanged "/lockers/1" "$tag"
+
+echo "(Done)"
+
+echo -n "Changing a locker as an admin..."
+curl -X PATCH \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "$auth" \
+  -H "content-type: application/json" \
+  -d '{"name": "Renamed"}' \
+  "$root_api_url/admin/lockers/2"
+
+expect_changed "/lockers/2" "$tag"
+tag=$(etag "/lockers")
+
+echo "(Done)"
+
+echo -n "Reading something that doesn't change lockers..."
+curl -X GET --silent --fail --output /dev/null "$root_api_url/health"
+curl -X GET --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/1/events"
+expect_unchanged "/lockers" "$tag"
+
+echo "(Done)"
+
+echo -n "Releasing a locker..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "$auth" \
+  -H "content-type: application/json" \
+  -d '{"reason": "testing ETags"}' \
+  "$root_api_url/admin/lockers/1/force_release"
+expect_changed "/lockers" "$tag"
+
+echo "(Done)"
@@ -0,0 +1,49 @@
+#!/bin/bash
+# This script checks /lockers can be fetched a page at a time, by offset or after a locker id, in a
+# stable order and with the total number of lockers, and that pages past the end are empty rather
+# than an error. It needs a server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./lockers_pages.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running locker pagination tests..."
+
+# prints the total and the ids of the lockers listed with the query $1
+page() {
+  curl -X GET \
+    --silent \
+    --fail \
+    "$root_api_url/lockers?$1" | jq -r '"\(.total) \([.data[].id] | join(","))"'
+}
+
+# checks the page for the query $1 is $2
+expect_page() {
+  result=$(page "$1")
+  if [ "$result" != "$2" ]; then
+    echo "Error: expected $2 for ?$1, got $result."
+    exit 1
+  fi
+}
+
+echo -n "Listing every locker by default..."
+expect_page "" "3 1,2,3"
+echo "(Done)"
+
+echo -n "Paging by offset..."
+expect_page "limit=2" "3 1,2"
+expect_page "limit=2&offset=2" "3 3"
+echo "(Done)"
+
+echo -n "Paging after a locker id..."
+expect_page "limit=2&after_id=2" "3 3"
+expect_page "after_id=1&offset=1" "3 3"
+echo "(Done)"
+
+echo -n "Returning empty pages past the end..."
+expect_page "offset=1000" "3 "
+expect_page "after_id=1000" "3 "
+echo "(Done)"
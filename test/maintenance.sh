@@ -0,0 +1,97 @@
+#!/bin/bash
+# This script checks staff can put lockers under maintenance, where they're still listed but
+# can't be claimed, and back, and that a locker someone is using can't be put under maintenance.
+# It needs a fresh server running with the test lockers and an admin token.
+
+# Usage: LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run &
+#        ADMIN_TOKEN=<token> ./maintenance.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running maintenance tests..."
+
+# sends a $1 request to /admin/lockers/$2/maintenance, printing the response body followed by the
+# status code
+maintenance() {
+  curl -X "$1" \
+    --silent \
+    --write-out " %{http_code}" \
+    -H "$auth" \
+    "$root_api_url/admin/lockers/$2/maintenance"
+}
+
+# checks a $1 request for locker $2 gets the status code $3, and a response the jq filter $4 turns
+# into $5
+expect_maintenance() {
+  response=$(maintenance "$1" "$2")
+  result="$(echo "${response% *}" | jq -r "$4") ${response##* }"
+  if [ "$result" != "$5 $3" ]; then
+    echo "Error: expected $5 $3 for $1 on locker $2, got $result."
+    exit 1
+  fi
+}
+
+echo -n "Putting a locker under maintenance..."
+expect_maintenance POST 3 200 '.data.state' "maintenance"
+# asking again changes nothing
+expect_maintenance POST 3 200 '.data.state' "maintenance"
+
+event=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers/3/events" | jq -r '.data[0] | "\(.event) \(.actor)"')
+if [ "$event" != "maintenance_started admin" ]; then
+  echo "Error: expected a maintenance_started event, got $event."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Listing lockers under maintenance..."
+listed=$(curl -X GET --silent --fail "$root_api_url/lockers" | jq -r '.data[] | select(.id == 3) | .state')
+if [ "$listed" != "maintenance" ]; then
+  echo "Error: expected locker 3 to be listed under maintenance, got $listed."
+  exit 1
+fi
+
+filtered=$(curl -X GET --silent --fail "$root_api_url/lockers?state=maintenance" | jq -r '[.data[].id] | join(",")')
+if [ "$filtered" != "3" ]; then
+  echo "Error: expected only locker 3 to be under maintenance, got $filtered."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to claim a locker under maintenance..."
+response=$(curl -X POST --silent --write-out " %{http_code}" "$root_api_url/use_locker/3")
+result="$(echo "${response% *}" | jq -r '.error.message') ${response##* }"
+if [ "$result" != "Locker is under maintenance 409" ]; then
+  echo "Error: expected the locker to be under maintenance, got $response."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to put a locker in use under maintenance..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+expect_maintenance POST 1 409 '.data.state' "in_use"
+echo "(Done)"
+
+echo -n "Making a locker under maintenance available again..."
+expect_maintenance DELETE 3 200 '.data.state' "available"
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/3"
+echo "(Done)"
+
+echo -n "Putting a locker that doesn't exist under maintenance..."
+status=$(curl -X POST --silent --output /dev/null --write-out "%{http_code}" -H "$auth" "$root_api_url/admin/lockers/1000/maintenance")
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,125 @@
+#!/bin/bash
+# This script checks /lockers/{id}/meter tells what a locker in use costs so far without invoicing
+# it, and that sessions keep the rate they started with when the price changes. It needs a fresh
+# server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./meter.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running meter tests..."
+
+# prints the status code of the meter of locker $1
+meter_status() {
+  curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url/lockers/$1/meter"
+}
+
+# prints the meter of locker $1
+meter() {
+  curl -X GET --silent --fail "$root_api_url/lockers/$1/meter" | jq -c '.data'
+}
+
+# sets the price override of locker $1 to $2
+set_price() {
+  curl -X PUT \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "$auth" \
+    -H "Content-Type: application/json" \
+    -d "{\"price_per_minute_msat\": $2}" \
+    "$root_api_url/admin/lockers/$1/price"
+}
+
+# prints how many payments were ever created
+payments() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/payments" | jq -r '.data.total'
+}
+
+echo -n "Refusing the meter of a locker nobody uses..."
+status=$(meter_status 1)
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Metering a locker in use..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+sleep 2
+
+result=$(meter 1)
+elapsed=$(echo "$result" | jq -r '.elapsed_seconds')
+if [ "$elapsed" -lt 2 ]; then
+  echo "Error: expected at least 2 seconds, got $result."
+  exit 1
+fi
+
+# the default of one satoshi a second
+summary=$(echo "$result" | jq -r '"\(.price_per_minute_msat) \(.amount_sat) \([.breakdown[].amount_sat] | add)"')
+if [ "$summary" != "60000 $elapsed $elapsed" ]; then
+  echo "Error: expected $elapsed sat at 60000 msat a minute, adding up, got $result."
+  exit 1
+fi
+
+count=$(payments)
+if [ "$count" != "0" ]; then
+  echo "Error: expected no payments, got $count."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the rate the session started with..."
+set_price 1 120000
+
+rate=$(meter 1 | jq -r '.price_per_minute_msat')
+if [ "$rate" != "60000" ]; then
+  echo "Error: expected the session to keep 60000 msat a minute, got $rate."
+  exit 1
+fi
+
+billed=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/end_usage/1" | jq -r '"\(.data.lease_time) \(.data.amount)"')
+if [ "${billed% *}" != "${billed#* }" ]; then
+  echo "Error: expected to be billed a satoshi a second, got $billed."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing the meter once the usage ended..."
+status=$(meter_status 1)
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Charging new sessions the new price..."
+set_price 2 120000
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/2"
+
+rate=$(meter 2 | jq -r '.price_per_minute_msat')
+if [ "$rate" != "120000" ]; then
+  echo "Error: expected 120000 msat a minute, got $rate."
+  exit 1
+fi
+
+echo "(Done)"
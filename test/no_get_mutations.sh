@@ -0,0 +1,83 @@
+#!/bin/bash
+# This script checks that GET requests, like those of link prefetchers and crawlers, can't claim a
+# locker or create an invoice anymore, and are pointed at POST instead. It needs a fresh server
+# running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./no_get_mutations.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+headers=$(mktemp)
+trap 'rm -f "$headers"' EXIT
+
+echo "Running GET mutation tests..."
+
+# prints the state of locker 1
+locker_state() {
+  curl -X GET --silent --fail "$root_api_url/lockers/1" | jq -r '.data.state'
+}
+
+# prints how many payments were ever created
+payments() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    "$root_api_url/admin/payments" | jq -r '.data.total'
+}
+
+# sends a GET to the path $1, printing the status code and the Allow header
+get() {
+  status=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --dump-header "$headers" \
+    --write-out "%{http_code}" \
+    "$root_api_url$1")
+  echo "$status $(grep -i '^allow:' "$headers" | cut -d' ' -f2 | tr -d '\r')"
+}
+
+echo -n "Refusing to claim a locker on GET..."
+result=$(get /use_locker/1)
+if [ "$result" != "405 POST" ]; then
+  echo "Error: expected 405 allowing POST, got $result."
+  exit 1
+fi
+
+state=$(locker_state)
+if [ "$state" != "available" ]; then
+  echo "Error: expected locker 1 to stay available, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to create an invoice on GET..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+
+result=$(get /pay_for_usage/1)
+if [ "$result" != "405 POST" ]; then
+  echo "Error: expected 405 allowing POST, got $result."
+  exit 1
+fi
+
+count=$(payments)
+if [ "$count" != "0" ]; then
+  echo "Error: expected no payments, got $count."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Creating an invoice on POST..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/pay_for_usage/1"
+
+count=$(payments)
+if [ "$count" != "1" ]; then
+  echo "Error: expected a payment, got $count."
+  exit 1
+fi
+
+echo "(Done)"
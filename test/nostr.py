@@ -0,0 +1,48 @@
+#!/usr/bin/env python3
+"""Plays the part of a nostr client, like the kiosk app: prints the value of a NIP-98
+`Authorization` header for a request, signed with the user's secret key.
+
+Usage: ./nostr.py <secret key hex> <method> <url> [created_at]
+
+The event is created now unless `created_at` says otherwise.
+"""
+
+import base64
+import hashlib
+import json
+import sys
+import time
+
+from locker import G, point_mul, sign
+
+
+def main():
+    if len(sys.argv) not in (4, 5):
+        sys.exit(__doc__)
+
+    seckey = bytes.fromhex(sys.argv[1])
+    method = sys.argv[2]
+    url = sys.argv[3]
+    created_at = int(sys.argv[4]) if len(sys.argv) == 5 else int(time.time())
+
+    pubkey = point_mul(G, int.from_bytes(seckey, "big"))[0].to_bytes(32, "big").hex()
+    tags = [["u", url], ["method", method]]
+    serialized = json.dumps(
+        [0, pubkey, created_at, 27235, tags, ""], separators=(",", ":"), ensure_ascii=False
+    )
+    event_id = hashlib.sha256(serialized.encode()).digest()
+
+    event = {
+        "id": event_id.hex(),
+        "pubkey": pubkey,
+        "created_at": created_at,
+        "kind": 27235,
+        "tags": tags,
+        "content": "",
+        "sig": sign(event_id, seckey).hex(),
+    }
+    print("Nostr " + base64.b64encode(json.dumps(event).encode()).decode())
+
+
+if __name__ == "__main__":
+    main()
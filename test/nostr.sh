@@ -0,0 +1,87 @@
+#!/bin/bash
+# This script checks clients can say which nostr user they claim and pay for a locker for with a
+# NIP-98 header, that headers which don't check out are rejected, that another user can't take
+# over the session, and that leaving the header out keeps working as before. It needs a fresh
+# server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./nostr.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+seckey="3000000000000000000000000000000000000000000000000000000000000001"
+pubkey="a6469c5b80419de916498141a68fcf4085d89b02dad3966df1f8915c356902b4"
+other_seckey="3000000000000000000000000000000000000000000000000000000000000002"
+
+echo "Running NIP-98 tests..."
+
+# prints the status code of a POST to the path $1 with the Authorization header $2
+status() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: $2" \
+    "$root_api_url$1"
+}
+
+echo -n "Rejecting headers that don't check out..."
+for authorization in \
+  "Nostr abc" \
+  "$("$script_dir/nostr.py" "$seckey" POST "$root_api_url/use_locker/2")" \
+  "$("$script_dir/nostr.py" "$seckey" GET "$root_api_url/use_locker/3")" \
+  "$("$script_dir/nostr.py" "$seckey" POST "$root_api_url/use_locker/3" $(($(date +%s) - 120)))"; do
+  result=$(status "/use_locker/3" "$authorization")
+  if [ "$result" != "401" ]; then
+    echo "Error: expected 401 for $authorization, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Claiming and paying for a locker as a nostr user..."
+result=$(status "/use_locker/1" "$("$script_dir/nostr.py" "$seckey" POST "$root_api_url/use_locker/1")")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+result=$(status "/pay_for_usage/1" "$("$script_dir/nostr.py" "$seckey" POST "$root_api_url/pay_for_usage/1")")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to let another nostr user take the session over..."
+result=$(status "/pay_for_usage/1" "$("$script_dir/nostr.py" "$other_seckey" POST "$root_api_url/pay_for_usage/1")")
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Claiming a locker anonymously..."
+# the rejected headers didn't claim it either
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/3"
+
+echo "(Done)"
+
+echo -n "Recording who used each session..."
+sessions=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/export/sessions.csv" | tr -d '\r' | awk -F, 'NR > 1 { print $2 ":" $NF }' | paste -sd ' ')
+
+if [ "$sessions" != "1:$pubkey 3:" ]; then
+  echo "Error: expected only the session of locker 1 to have a nostr user, got $sessions."
+  exit 1
+fi
+
+echo "(Done)"
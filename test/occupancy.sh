@@ -0,0 +1,86 @@
+#!/bin/bash
+# This script checks the occupancy statistics under /admin/stats/occupancy, in particular that
+# sessions are clipped to the period we ask about. It needs a server running with an admin token.
+
+# Usage: ADMIN_TOKEN=<token> cargo run & ADMIN_TOKEN=<token> ./occupancy.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running occupancy tests..."
+
+echo -n "Using an available locker..."
+locker_id=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers" | jq -r '[.data[] | select(.state == "available")][0].id')
+
+if [ "$locker_id" == "null" ]; then
+  echo "No available locker found."
+  exit 1
+fi
+
+start_time=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/$locker_id" | jq -r '.data.start_time')
+
+echo "(Done)"
+
+# occupancy of our locker between two timestamps, as "<seconds> <sessions>"
+occupancy() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/stats/occupancy?from=$1&to=$2" \
+    | jq -r ".data.lockers[] | select(.locker_id == $locker_id) | \"\(.occupied_seconds) \(.sessions)\""
+}
+
+sleep 3
+
+echo -n "Counting the open session up to now..."
+result=$(occupancy $((start_time - 60)) $((start_time + 3600)))
+seconds=${result% *}
+if [ "${result#* }" != "1" ] || [ "$seconds" -lt 3 ] || [ "$seconds" -gt 5 ]; then
+  echo "Error: expected about 3 seconds in 1 session, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Clipping the session to the period..."
+result=$(occupancy $((start_time + 1)) $((start_time + 2)))
+if [ "$result" != "1 1" ]; then
+  echo "Error: expected 1 second in 1 session, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Leaving out the session before it started..."
+result=$(occupancy $((start_time - 60)) "$start_time")
+if [ "$result" != "0 0" ]; then
+  echo "Error: expected nothing, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Asking for an empty period..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/stats/occupancy?from=$start_time&to=$start_time")
+
+if [ "$status" != "422" ]; then
+  echo "Error: expected 422, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,120 @@
+#!/bin/bash
+# This script checks that authorizations say what the locker is opened for, storing after claiming
+# it and retrieving after paying, that lockers report opens in that order, and that retrievals
+# not saying which receipt they used redeem the last one issued. It needs a fresh server running
+# with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./open_actions.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 1, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running open action tests..."
+
+# reports locker 1 being opened to $1 at $2, printing the status code
+report() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d "$("$script_dir/locker.py" "$locker_seckey" 1 "$2" "$(($2 + 900))" "$1")" \
+    "$root_api_url/update_locker_open"
+}
+
+locker_state() {
+  curl -X GET \
+    --silent \
+    --fail \
+    "$root_api_url/lockers" | jq -r '.data[] | select(.id == 1) | .state'
+}
+
+now=$(date +%s)
+
+echo -n "Authorizing storing after claiming..."
+action=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/1" | jq -r '"\(.data.action) \(.data.signed_receipt.receipt.action)"')
+if [ "$action" != "store store" ]; then
+  echo "Error: expected store, got $action."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a retrieval before anything was stored..."
+status=$(report retrieve "$now")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the locker in use after storing..."
+status=$(report store "$((now + 1))")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+state=$(locker_state)
+if [ "$state" != "in_use" ]; then
+  echo "Error: expected the locker to stay in use, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Authorizing retrieving after paying..."
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+action=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '"\(.data.action) \(.data.signed_receipt.receipt.action)"')
+if [ "$action" != "retrieve retrieve" ]; then
+  echo "Error: expected retrieve, got $action."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Freeing the locker after retrieving..."
+# the report doesn't say which receipt was used, so it's taken to be the one just issued
+redeemed=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 1 "$((now + 2))" "$((now + 902))" retrieve)" \
+  "$root_api_url/update_locker_open" | jq -r '.data.redeemed')
+if [ "$redeemed" != "$payment_hash" ]; then
+  echo "Error: expected the receipt of $payment_hash to be redeemed, got $redeemed."
+  exit 1
+fi
+
+state=$(locker_state)
+if [ "$state" != "available" ]; then
+  echo "Error: expected the locker to be available, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a second retrieval..."
+status=$(report retrieve "$((now + 3))")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,122 @@
+#!/bin/bash
+# This script checks that reports of a locker being opened can't be replayed: once a report is
+# accepted, neither it nor any report from before it is accepted again, while reports that were
+# turned down don't count. It needs a server running with the mock lightning backend and the test
+# lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./open_replay.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# the secret key of locker 2, see lockers.toml
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000002"
+
+echo "Running open replay tests..."
+
+claim() {
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    "$root_api_url/use_locker/2"
+}
+
+# sends the given report, printing the status code
+report() {
+  curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d "$1" \
+    "$root_api_url/update_locker_open"
+}
+
+now=$(date +%s)
+report=$("$script_dir/locker.py" "$locker_seckey" 2 "$now" "$((now + 900))" retrieve)
+
+echo -n "Accepting a report..."
+claim
+status=$(report "$("$script_dir/locker.py" "$locker_seckey" 2 "$((now - 2))")")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200 storing, got $status."
+  exit 1
+fi
+
+status=$(report "$report")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200 retrieving, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting the same report again..."
+claim
+status=$(report "$report")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+state=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers" | jq -r '.data[] | select(.id == 2) | .state')
+if [ "$state" != "in_use" ]; then
+  echo "Error: expected locker 2 to stay in use, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting an older report..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$((now - 1))")" \
+  "$root_api_url/update_locker_open")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting a newer report..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" 2 "$((now + 1))")" \
+  "$root_api_url/update_locker_open")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Not counting a report that was turned down..."
+# retrieving with a receipt we never issued
+status=$(report "$("$script_dir/locker.py" "$locker_seckey" 2 "$((now + 5))" "$((now + 900))" retrieve "$now")")
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+# so its timestamp can still be reported
+status=$(report "$("$script_dir/locker.py" "$locker_seckey" 2 "$((now + 5))")")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
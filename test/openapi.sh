@@ -0,0 +1,46 @@
+#!/bin/bash
+# This script checks GET /openapi.json describes the API, public and admin routes alike, and can be
+# cached like the discovery routes. It needs a server running with the mock lightning backend.
+
+# Usage: LN_BACKEND=mock cargo run --features mock-ln &
+#        ./openapi.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running OpenAPI tests..."
+
+echo -n "Checking the API is described..."
+response=$(curl -X GET --silent --fail --include "$root_api_url/openapi.json")
+spec=$(echo "$response" | sed '1,/^\r$/d')
+
+result=$(echo "$spec" | jq -r '"\(.openapi | startswith("3.")) \(.paths | has("/use_locker/{locker_id}")) \(.paths | has("/admin/lockers/{locker_id}/maintenance"))"')
+if [ "$result" != "true true true" ]; then
+  echo "Error: expected an OpenAPI 3 description of every route, got $result."
+  exit 1
+fi
+
+statuses=$(echo "$spec" | jq -r '.paths["/use_locker/{locker_id}"].post.responses | keys | join(",")')
+if [ "$statuses" != "200,400,401,404,406,409,429,500,503" ]; then
+  echo "Error: expected every status /use_locker answers with, got $statuses."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Checking the description can be cached..."
+etag=$(echo "$response" | grep -i '^etag:' | cut -d ' ' -f 2 | tr -d '\r')
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "If-None-Match: $etag" \
+  "$root_api_url/openapi.json")
+if [ "$status" != "304" ]; then
+  echo "Error: expected 304, got $status."
+  exit 1
+fi
+
+echo "(Done)"
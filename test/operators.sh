@@ -0,0 +1,173 @@
+#!/bin/bash
+# This script checks that tokens scoped to an operator only see and manage that operator's
+# lockers and payments, answering 404 for everyone else's, and that payments record the operator
+# of their locker. It needs a fresh server running with the mock lightning backend, the test
+# lockers, an admin token and two more called "acme" and "globex", scoped to the first and the
+# second operator.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> ADMIN_TOKENS="acme:$(echo -n <acme token> | sha256sum | cut -d ' ' -f 1):owner:1,globex:$(echo -n <globex token> | sha256sum | cut -d ' ' -f 1):owner:2" cargo run --features mock-ln & ADMIN_TOKEN=<token> ACME_TOKEN=<acme token> GLOBEX_TOKEN=<globex token> ./operators.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+pk="a6469c5b80419de916498141a68fcf4085d89b02dad3966df1f8915c356902b4"
+
+echo "Running operator tests..."
+
+# prints the status code of $1 to $2 with the token $3, passing the rest of the arguments to curl
+status() {
+  curl -X "$1" \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "Authorization: Bearer $3" \
+    "${@:4}" \
+    "$root_api_url$2"
+}
+
+# prints the data of a GET to $1 with the token $2, filtered by the jq expression $3
+get() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "Authorization: Bearer $2" \
+    "$root_api_url$1" | jq -c "$3"
+}
+
+echo -n "Adding two operators..."
+for name in acme globex; do
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    -H "Content-Type: application/json" \
+    -d "{\"name\": \"$name\"}" \
+    "$root_api_url/admin/operators"
+done
+
+operators=$(get /admin/operators "$ADMIN_TOKEN" '[.data[] | "\(.id):\(.name)"]')
+if [ "$operators" != '["1:acme","2:globex"]' ]; then
+  echo "Error: expected acme and globex, got $operators."
+  exit 1
+fi
+
+result=$(status POST /admin/operators "$ADMIN_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"name": "acme"}')
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409 for a taken name, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping operators out of what's about the whole server..."
+for route in /admin/operators /admin/metrics /admin/backup; do
+  result=$(status GET "$route" "$ACME_TOKEN")
+  if [ "$result" != "403" ]; then
+    echo "Error: expected 403 for $route, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Commissioning a locker for acme..."
+result=$(status POST /admin/lockers "$ACME_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"pk\": \"$pk\", \"operator_id\": 2}")
+if [ "$result" != "403" ]; then
+  echo "Error: expected 403 for another operator's locker, got $result."
+  exit 1
+fi
+
+locker_id=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ACME_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"pk\": \"$pk\", \"name\": \"C1\"}" \
+  "$root_api_url/admin/lockers" | jq -r '.data.id')
+
+lockers=$(get /admin/lockers "$ACME_TOKEN" '[.data[] | "\(.id):\(.operator_id)"]')
+if [ "$lockers" != "[\"$locker_id:1\"]" ]; then
+  echo "Error: expected only locker $locker_id, got $lockers."
+  exit 1
+fi
+
+lockers=$(get /admin/lockers "$GLOBEX_TOKEN" '.data | length')
+if [ "$lockers" != "0" ]; then
+  echo "Error: expected globex to have no lockers, got $lockers."
+  exit 1
+fi
+
+# super-admins see every operator's lockers, and those of none
+lockers=$(get /admin/lockers "$ADMIN_TOKEN" '.data | length')
+if [ "$lockers" -lt 2 ]; then
+  echo "Error: expected every locker, got $lockers."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Hiding acme's locker from globex..."
+for route in "GET /admin/lockers/$locker_id/events" "GET /admin/lockers/$locker_id/history" \
+  "POST /admin/lockers/$locker_id/decommission" "GET /admin/lockers/1/events"; do
+  result=$(status ${route% *} "${route#* }" "$GLOBEX_TOKEN")
+  if [ "$result" != "404" ]; then
+    echo "Error: expected 404 for $route, got $result."
+    exit 1
+  fi
+done
+
+result=$(status GET "/admin/lockers/$locker_id/events" "$ACME_TOKEN")
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200 for acme, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Recording the operator of payments..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/$locker_id"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/$locker_id" | jq -r '.data.invoice.payment_hash')
+
+payments=$(get /admin/payments "$ACME_TOKEN" '[.data.payments[] | "\(.payment_hash):\(.operator_id)"]')
+if [ "$payments" != "[\"$payment_hash:1\"]" ]; then
+  echo "Error: expected acme's payment, got $payments."
+  exit 1
+fi
+
+payments=$(get /admin/payments "$GLOBEX_TOKEN" '.data.total')
+if [ "$payments" != "0" ]; then
+  echo "Error: expected globex to have no payments, got $payments."
+  exit 1
+fi
+
+result=$(status POST "/admin/payments/$payment_hash/cancel" "$GLOBEX_TOKEN")
+if [ "$result" != "404" ]; then
+  echo "Error: expected 404 for acme's payment, got $result."
+  exit 1
+fi
+
+occupancy=$(get /admin/stats/occupancy "$GLOBEX_TOKEN" '.data.lockers | length')
+if [ "$occupancy" != "0" ]; then
+  echo "Error: expected globex's stats to have no lockers, got $occupancy."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping each operator's calls in their own audit log..."
+tokens=$(get /admin/audit "$GLOBEX_TOKEN" '[.data[].token_id] | unique')
+if [ "$tokens" != '["globex"]' ]; then
+  echo "Error: expected only globex's calls, got $tokens."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,106 @@
+#!/bin/bash
+# This script checks `/payments/{payment_hash}/events` streams a pending payment until it settles,
+# ending with the receipt `/payments/{payment_hash}/receipt` hands out, and that a settled payment only gets its
+# last event. It needs a fresh server running with the mock lightning backend, which settles
+# invoices right away, and the test lockers, since it plays the part of locker A1.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./payment_events.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# locker A1 from test/lockers.toml, the first one synced into a fresh database
+locker_id=1
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running payment events tests..."
+
+# prints the events streamed for $1, as `<event> <data>` lines, once the server ends the stream
+events() {
+  curl -X GET \
+    --silent \
+    --fail \
+    --no-buffer \
+    --max-time 30 \
+    "$root_api_url/payments/$1/events" |
+    awk '/^event:/ { name = $2 } /^data:/ { sub(/^data: ?/, ""); print name, $0 }'
+}
+
+echo -n "Paying for locker $locker_id..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/$locker_id"
+
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" "$locker_id" "$(date +%s)")" \
+  "$root_api_url/update_locker_open"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/$locker_id" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Following the payment until it settles..."
+stream=$(events "$payment_hash")
+
+names=$(echo "$stream" | cut -d ' ' -f 1 | paste -sd ',')
+if [ "$names" != "pending,paid" ]; then
+  echo "Error: expected pending then paid, got $stream."
+  exit 1
+fi
+
+pending=$(echo "$stream" | head -n 1 | cut -d ' ' -f 2-)
+if [ "$(echo "$pending" | jq -r '"\(.payment_hash) \(.locker_id)"')" != "$payment_hash $locker_id" ]; then
+  echo "Error: expected the pending event to describe the payment, got $pending."
+  exit 1
+fi
+
+streamed=$(echo "$stream" | tail -n 1 | cut -d ' ' -f 2- | jq -r '.signature')
+polled=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '.data.signature')
+if [ "$streamed" == "null" ] || [ "$streamed" != "$polled" ]; then
+  echo "Error: expected the receipt $polled, got $streamed."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Only ending the stream of a settled payment..."
+stream=$(events "$payment_hash")
+if [ "$(echo "$stream" | cut -d ' ' -f 1 | paste -sd ',')" != "paid" ]; then
+  echo "Error: expected only paid, got $stream."
+  exit 1
+fi
+
+if [ "$(echo "$stream" | cut -d ' ' -f 2- | jq -r '.signature')" != "$polled" ]; then
+  echo "Error: expected the same receipt again, got $stream."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing payments we don't know..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$(printf '0%.0s' $(seq 1 64))/events")
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,86 @@
+#!/bin/bash
+# This script checks that clients can tell whether a payment arrived without getting its receipt.
+# It needs a fresh server running with the mock lightning backend, the test lockers and an admin
+# token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./payment_status.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running payment status tests..."
+
+echo -n "Using and paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Checking the payment arrived..."
+# the mock backend considers every invoice paid
+payment=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash" | jq -c '.data')
+
+summary=$(echo "$payment" | jq -r '"\(.payment_hash) \(.status) \(.locker_id) \(.created_at | type) \(.settled_at | type)"')
+if [ "$summary" != "$payment_hash paid 1 number number" ]; then
+  echo "Error: expected a settled payment for locker 1, got $payment."
+  exit 1
+fi
+
+# checking it didn't issue the receipt
+history=$(curl -X GET \
+  --silent \
+  --fail \
+  --header "$auth" \
+  "$root_api_url/admin/lockers/1/events" | jq -r '[.data[].event] | index("receipt_issued")')
+if [ "$history" != "null" ]; then
+  echo "Error: expected no receipt to be issued yet."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Checking the payment once its receipt is issued..."
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt"
+
+status=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash" | jq -r '.data.status')
+if [ "$status" != "redeemed" ]; then
+  echo "Error: expected the payment to be redeemed, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Checking an unknown payment..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/0000000000000000000000000000000000000000000000000000000000000000")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,77 @@
+#!/bin/bash
+# This script checks the list of payments awaiting settlement under /admin/payments/pending. It
+# needs a fresh server running with the mock lightning backend, the test lockers and an admin
+# token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./pending.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running pending payment tests..."
+
+echo -n "Asking to pay for locker 1..."
+session_id=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/1" | jq -r '.data.session_id')
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Listing the pending payment with its locker and session..."
+payment=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments/pending" \
+  | jq -r ".data[] | select(.payment_hash == \"$payment_hash\") | \"\(.locker_name) \(.locker_state) \(.session_id) \(.age_seconds < 60)\"")
+
+if [ "$payment" != "A1 in_use $session_id true" ]; then
+  echo "Error: expected \"A1 in_use $session_id true\", got \"$payment\"."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Leaving out payments that aren't stale yet..."
+count=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments/pending?stale_minutes=10" | jq -r '.data | length')
+
+if [ "$count" != "0" ]; then
+  echo "Error: expected no stale payments, got $count."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Leaving out payments once they're settled..."
+# the mock backend considers every invoice paid
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt"
+
+count=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/payments/pending" | jq -r '.data | length')
+
+if [ "$count" != "0" ]; then
+  echo "Error: expected no pending payments, got $count."
+  exit 1
+fi
+
+echo "(Done)"
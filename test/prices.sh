@@ -0,0 +1,100 @@
+#!/bin/bash
+# This script checks per-locker prices: setting and clearing them under /admin, showing them in
+# /lockers and charging them over the configured price. It needs a fresh server running with the
+# mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./prices.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running price tests..."
+
+# the price override of locker 2, as listed in /lockers
+listed_price() {
+  curl -X GET \
+    --silent \
+    --fail \
+    "$root_api_url/lockers" | jq -r '.data[] | select(.id == 2) | .price_per_minute_msat'
+}
+
+# sets the price override of locker 2 to $1, which may be null
+set_price() {
+  curl -X PUT \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "$auth" \
+    -H "Content-Type: application/json" \
+    -d "{\"price_per_minute_msat\": $1}" \
+    "$root_api_url/admin/lockers/2/price"
+}
+
+echo -n "Setting a price for locker 2..."
+if [ "$(listed_price)" != "null" ]; then
+  echo "Error: locker 2 already has a price."
+  exit 1
+fi
+
+# twice the default of one satoshi a second
+set_price 120000
+
+if [ "$(listed_price)" != "120000" ]; then
+  echo "Error: expected 120000, got $(listed_price)."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Charging the locker's price..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/2"
+
+sleep 2
+
+payment=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/2")
+lease_time=$(echo "$payment" | jq -r '.data.lease_time')
+amount=$(echo "$payment" | jq -r '.data.amount')
+
+if [ "$amount" != "$((lease_time * 2))" ]; then
+  echo "Error: expected $((lease_time * 2)) sat for $lease_time seconds, got $amount."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Clearing the price..."
+set_price null
+
+if [ "$(listed_price)" != "null" ]; then
+  echo "Error: expected no price, got $(listed_price)."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Setting the price of a locker that doesn't exist..."
+status=$(curl -X PUT \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"price_per_minute_msat": 1000}' \
+  "$root_api_url/admin/lockers/999999/price")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,138 @@
+#!/bin/bash
+# This script checks that new lockers can register themselves with a public key signed by the
+# provisioning key, and can't be used until an admin approves them. It needs a fresh server
+# running with the test provisioning key, see below.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml PROVISIONING_PUBKEY=e9a4f0b9434ad0f8f87735aba91b852346558c7c8aed6bcd50f3ef5986c347bc ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./provision.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# test keys only, their secret keys are public: the provisioning key, whose public key is
+# e9a4f0b9434ad0f8f87735aba91b852346558c7c8aed6bcd50f3ef5986c347bc, and the new locker's
+provisioning_seckey="1000000000000000000000000000000000000000000000000000000000000003"
+locker_pk="276a82181313c0e6076802c2d4472615afce89363bc1f29483511348bdd28235"
+# another key nobody vouched for
+stranger_seckey="1000000000000000000000000000000000000000000000000000000000000005"
+
+echo "Running provisioning tests..."
+
+# registers $1 with the signature $2, printing the response body and then the status code
+register() {
+  curl -X POST \
+    --silent \
+    --write-out "\n%{http_code}" \
+    -H "content-type: application/json" \
+    -d "{\"pk\": \"$1\", \"signature\": \"$2\", \"name\": \"B1\", \"size\": \"small\"}" \
+    "$root_api_url/provision/register"
+}
+
+echo -n "Rejecting a key signed by someone else..."
+signature=$("$script_dir/provisioner.py" "$stranger_seckey" "$locker_pk")
+result=$(register "$locker_pk" "$signature" | tail -n 1)
+if [ "$result" != "400" ]; then
+  echo "Error: expected 400, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a malformed key..."
+result=$(register "${locker_pk:2}" "$signature" | head -n 1 | jq -r '.error.message')
+if [ "$result" != "pk: expected 64 hex characters" ]; then
+  echo "Error: expected the key to be rejected, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Registering a key signed by the provisioning key..."
+signature=$("$script_dir/provisioner.py" "$provisioning_seckey" "$locker_pk")
+response=$(register "$locker_pk" "$signature")
+result=$(echo "$response" | tail -n 1)
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+locker_id=$(echo "$response" | head -n 1 | jq -r '.data.locker_id')
+state=$(echo "$response" | head -n 1 | jq -r '.data.state')
+if [ "$state" != "provisioning" ]; then
+  echo "Error: expected the locker to be provisioning, got $state."
+  exit 1
+fi
+
+device_token=$(echo "$response" | head -n 1 | jq -r '.data.device_token')
+if [ "${#device_token}" != "64" ]; then
+  echo "Error: expected a device token, got $device_token."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting the same key again..."
+result=$(register "$locker_pk" "$signature" | tail -n 1)
+if [ "$result" != "409" ]; then
+  echo "Error: expected 409, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping it from customers until it's approved..."
+listed=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers" | jq -r ".data[] | select(.id == $locker_id) | .id")
+if [ -n "$listed" ]; then
+  echo "Error: expected locker $locker_id not to be listed."
+  exit 1
+fi
+
+result=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/use_locker/$locker_id")
+if [ "$result" == "200" ]; then
+  echo "Error: expected locker $locker_id not to be usable."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Approving it..."
+state=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/lockers/$locker_id/approve" | jq -r '.data.state')
+if [ "$state" != "available" ]; then
+  echo "Error: expected the locker to be available, got $state."
+  exit 1
+fi
+
+result=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/lockers/$locker_id/approve")
+if [ "$result" != "409" ]; then
+  echo "Error: expected approving it twice to give 409, got $result."
+  exit 1
+fi
+
+listed=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers" | jq -r ".data[] | select(.id == $locker_id) | .id")
+if [ "$listed" != "$locker_id" ]; then
+  echo "Error: expected locker $locker_id to be listed."
+  exit 1
+fi
+
+echo "(Done)"
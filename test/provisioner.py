@@ -0,0 +1,23 @@
+#!/usr/bin/env python3
+"""Plays the part of an installer: prints the signature of the provisioning key over a new
+locker's public key, which the locker sends to `/provision/register`.
+
+Usage: ./provisioner.py <provisioning secret key hex> <locker x-only public key hex>
+"""
+
+import sys
+
+from locker import sign, tagged_hash
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+
+    seckey = bytes.fromhex(sys.argv[1])
+    pk = bytes.fromhex(sys.argv[2])
+    print(sign(tagged_hash("locker/provision", pk), seckey).hex())
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,125 @@
+#!/bin/bash
+# This script checks that new lockers can register themselves with a one-time code from staff
+# instead of a signature by the provisioning key, that each code registers a single locker before
+# it expires, and that staff can list and revoke the codes they handed out. It needs a fresh server
+# running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./provisioning_codes.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+# the public keys of new lockers, made up for the test
+locker_pk="716a41eb526a2b4a3871bfcd45098ced860218ec8addab0400f0884cca65effd"
+other_pk="1535bd197200bd399faeae15d2325d4af2ecd9b6c13e0acd608c614bb14be5c1"
+late_pk="41146ff828a81f0cdb75da2a1d878e63d02a690a0eb476a6684f757cf1313a25"
+
+echo "Running provisioning code tests..."
+
+# makes a code with the JSON body $1, printing the response body
+create() {
+  curl -X POST \
+    --silent \
+    --fail \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    -H "content-type: application/json" \
+    -d "$1" \
+    "$root_api_url/admin/provisioning_codes"
+}
+
+# registers $1 with the code $2, printing the response body and then the status code
+register() {
+  curl -X POST \
+    --silent \
+    --write-out "\n%{http_code}" \
+    -H "content-type: application/json" \
+    -d "{\"pk\": \"$1\", \"code\": \"$2\"}" \
+    "$root_api_url/provision/register"
+}
+
+# prints the ids of the outstanding codes
+outstanding() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "Authorization: Bearer $ADMIN_TOKEN" \
+    "$root_api_url/admin/provisioning_codes" | jq -r '[.data[].id] | join(",")'
+}
+
+echo -n "Registering a locker with a code..."
+code=$(create '{"label": "front desk"}' | jq -r '.data.code')
+
+# the code as typed on a keypad
+response=$(register "$locker_pk" "$(echo "${code/-/}" | tr '[:upper:]' '[:lower:]')")
+result=$(echo "$response" | tail -n 1)
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+state=$(echo "$response" | head -n 1 | jq -r '.data.state')
+if [ "$state" != "provisioning" ]; then
+  echo "Error: expected the locker to be provisioning, got $state."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a code that was already used..."
+response=$(register "$other_pk" "$code")
+result=$(echo "$response" | tail -n 1)
+error=$(echo "$response" | head -n 1 | jq -r '.error.message')
+if [ "$result" != "409" ] || [ "$error" != "Provisioning code already used" ]; then
+  echo "Error: expected 409, got $result: $error."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting an expired code..."
+code=$(create '{"ttl_seconds": 1}' | jq -r '.data.code')
+sleep 2
+
+response=$(register "$late_pk" "$code")
+result=$(echo "$response" | tail -n 1)
+error=$(echo "$response" | head -n 1 | jq -r '.error.message')
+if [ "$result" != "410" ] || [ "$error" != "Provisioning code expired" ]; then
+  echo "Error: expected 410, got $result: $error."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Listing and revoking outstanding codes..."
+created=$(create '{}')
+code=$(echo "$created" | jq -r '.data.code')
+id=$(echo "$created" | jq -r '.data.id')
+
+ids=$(outstanding)
+if [ "$ids" != "$id" ]; then
+  echo "Error: expected only code $id to be outstanding, got $ids."
+  exit 1
+fi
+
+curl -X DELETE \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/provisioning_codes/$id"
+
+ids=$(outstanding)
+if [ -n "$ids" ]; then
+  echo "Error: expected no outstanding codes, got $ids."
+  exit 1
+fi
+
+result=$(register "$other_pk" "$code" | tail -n 1)
+if [ "$result" != "400" ]; then
+  echo "Error: expected 400 registering with a revoked code, got $result."
+  exit 1
+fi
+
+echo "(Done)"
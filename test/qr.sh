@@ -0,0 +1,132 @@
+#!/bin/bash
+# This script checks that the invoice for a payment can be fetched as a QR code until it's settled.
+# It needs a fresh server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./qr.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running QR code tests..."
+
+echo -n "Using and paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Fetching the invoice as a PNG..."
+headers=$(mktemp)
+magic=$(curl -X GET \
+  --silent \
+  --fail \
+  --dump-header "$headers" \
+  "$root_api_url/payments/$payment_hash/qr.png" | head -c 4 | tail -c 3)
+
+if [ "$magic" != "PNG" ]; then
+  echo "Error: expected a PNG, got $magic."
+  exit 1
+fi
+
+if ! grep -qi "^content-type: image/png" "$headers"; then
+  echo "Error: expected an image/png Content-Type, got $(cat "$headers")."
+  exit 1
+fi
+
+if ! grep -qi "^cache-control: public, max-age=[0-9]*, immutable" "$headers"; then
+  echo "Error: expected the image to be cached, got $(cat "$headers")."
+  exit 1
+fi
+
+rm "$headers"
+
+for accept in "image/png" "image/*" "image/svg+xml"; do
+  status=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    --header "Accept: $accept" \
+    "$root_api_url/payments/$payment_hash/qr.png")
+
+  expected=200
+  if [ "$accept" = "image/svg+xml" ]; then
+    expected=406
+  fi
+  if [ "$status" != "$expected" ]; then
+    echo "Error: expected $expected for Accept: $accept, got $status."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Fetching the invoice as an SVG..."
+width=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/qr.svg?size=400" | grep -o 'width="[0-9]*"' | head -1 | tr -dc '0-9')
+
+if [ "$width" -gt 400 ] || [ "$width" -lt 200 ]; then
+  echo "Error: expected the image to fit in 400 pixels, got $width."
+  exit 1
+fi
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/qr.svg?size=10")
+
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400 for a size out of range, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Fetching the QR code once the invoice is settled..."
+# the mock backend considers every invoice paid
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt"
+
+for format in png svg; do
+  status=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url/payments/$payment_hash/qr.$format")
+
+  if [ "$status" != "410" ]; then
+    echo "Error: expected 410 for the $format, got $status."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Fetching the QR code of an unknown payment..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/0000000000000000000000000000000000000000000000000000000000000000/qr.png")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
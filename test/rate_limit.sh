@@ -0,0 +1,75 @@
+#!/bin/bash
+# This script checks that each client is rate limited, with a tighter limit for the expensive
+# routes than for the cheap ones. It needs a fresh server running with the default limits and
+# without TRUST_FORWARDED_FOR set.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./rate_limit.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+# the default EXPENSIVE_RATE_LIMIT
+expensive_limit=20
+
+echo "Running rate limit tests..."
+
+# prints the status code of GET $1, passing the rest of the arguments to curl
+status() {
+  curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "${@:2}" \
+    "$root_api_url$1"
+}
+
+echo -n "Letting a client make $expensive_limit expensive calls..."
+for i in $(seq 1 "$expensive_limit"); do
+  result=$(status /payments/unknown/invoice)
+  if [ "$result" == "429" ]; then
+    echo "Error: call $i was rate limited."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Rate limiting the next one..."
+headers=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --dump-header - \
+  "$root_api_url/payments/unknown/invoice")
+
+result=$(echo "$headers" | head -n 1 | cut -d ' ' -f 2)
+if [ "$result" != "429" ]; then
+  echo "Error: expected 429, got $result."
+  exit 1
+fi
+
+if ! echo "$headers" | grep -qi "^retry-after: [0-9]"; then
+  echo "Error: expected a Retry-After header."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Ignoring X-Forwarded-For from clients..."
+result=$(status /payments/unknown/invoice -H "X-Forwarded-For: 192.0.2.1")
+if [ "$result" != "429" ]; then
+  echo "Error: expected 429, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the cheap routes available..."
+result=$(status /lockers)
+if [ "$result" != "200" ]; then
+  echo "Error: expected 200, got $result."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,93 @@
+#!/bin/bash
+# This script checks receipts are handed out under `/payments/{payment_hash}/receipt`, and, while
+# the legacy path is enabled, the same under `/payment_receipt/{payment_hash}`, marked deprecated.
+# It needs a fresh server running with the mock lightning backend and the test lockers. Pass the
+# same LEGACY_RECEIPT_PATH as the server to check the old path is gone once it's turned off.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./receipt_path.sh
+#        LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml LEGACY_RECEIPT_PATH=false cargo run --features mock-ln &
+#        LEGACY_RECEIPT_PATH=false ./receipt_path.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+headers=$(mktemp)
+trap 'rm -f "$headers"' EXIT
+
+echo "Running receipt path tests..."
+
+echo -n "Paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Getting the receipt from its path..."
+signature=$(curl -X GET \
+  --silent \
+  --fail \
+  --dump-header "$headers" \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '.data.signature')
+
+if [ -z "$signature" ] || [ "$signature" = "null" ]; then
+  echo "Error: expected a receipt, got none."
+  exit 1
+fi
+
+if grep -qi '^deprecation:' "$headers"; then
+  echo "Error: expected the path not to be deprecated, got $(cat "$headers")."
+  exit 1
+fi
+
+echo "(Done)"
+
+if [ "${LEGACY_RECEIPT_PATH:-true}" = "false" ]; then
+  echo -n "Refusing the old path..."
+  status=$(curl -X GET \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$root_api_url/payment_receipt/$payment_hash")
+
+  if [ "$status" != "404" ]; then
+    echo "Error: expected 404, got $status."
+    exit 1
+  fi
+
+  echo "(Done)"
+  exit 0
+fi
+
+echo -n "Getting the same receipt from the old path..."
+legacy_signature=$(curl -X GET \
+  --silent \
+  --fail \
+  --dump-header "$headers" \
+  "$root_api_url/payment_receipt/$payment_hash" | jq -r '.data.signature')
+
+if [ "$legacy_signature" != "$signature" ]; then
+  echo "Error: expected the receipt $signature, got $legacy_signature."
+  exit 1
+fi
+
+deprecation=$(grep -i '^deprecation:' "$headers" | cut -d' ' -f2 | tr -d '\r')
+if [ "$deprecation" != "true" ]; then
+  echo "Error: expected the old path to be deprecated, got $(cat "$headers")."
+  exit 1
+fi
+
+if ! grep -qi "^link: </v1/payments/$payment_hash/receipt>; rel=\"successor-version\"" "$headers"; then
+  echo "Error: expected a link to the new path, got $(cat "$headers")."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,70 @@
+#!/bin/bash
+# This script checks that polls for the receipt of each payment are limited, however many clients
+# they come from. It needs a fresh server running with the mock lightning backend, the test
+# lockers, a receipt poll limit of 5 and an expensive route limit high enough not to get in the
+# way.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml RECEIPT_POLL_RATE_LIMIT=5 EXPENSIVE_RATE_LIMIT=1000 cargo run --features mock-ln & ./receipt_poll.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running receipt poll tests..."
+
+echo -n "Paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Polling for the receipt up to the limit..."
+for _ in $(seq 1 5); do
+  curl -X GET \
+    --silent \
+    --fail \
+    --output /dev/null \
+    "$root_api_url/payments/$payment_hash/receipt"
+done
+
+echo "(Done)"
+
+echo -n "Limiting further polls..."
+headers=$(curl -X GET \
+  --silent \
+  --dump-header - \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+if ! echo "$headers" | head -n 1 | grep -q " 429"; then
+  echo "Error: expected to be rate limited, got $headers."
+  exit 1
+fi
+
+if ! echo "$headers" | grep -qi "^retry-after: "; then
+  echo "Error: expected a Retry-After header, got $headers."
+  exit 1
+fi
+
+# other payments have their own limit
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$(printf '0%.0s' $(seq 1 64))/receipt")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected an unknown payment to be missing, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,121 @@
+#!/bin/bash
+# This script checks that paying once only ever opens a locker once: asking for the receipt again
+# hands out the same one, and once the locker reported being opened with it, we refuse to hand it
+# out again. It needs a fresh server running with the mock lightning backend and the test
+# lockers, since it plays the part of locker A1.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./receipt_reuse.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+
+# locker A1 from test/lockers.toml, the first one synced into a fresh database
+locker_id=1
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running receipt reuse tests..."
+
+echo -n "Using locker $locker_id..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/$locker_id"
+
+stored_at=$(date +%s)
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" "$locker_id" "$stored_at")" \
+  "$root_api_url/update_locker_open"
+
+echo "(Done)"
+
+echo -n "Paying for locker $locker_id..."
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/$locker_id" | jq -r '.data.invoice.payment_hash')
+
+echo "(Done)"
+
+echo -n "Getting the receipt..."
+receipt=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt")
+first_signature=$(echo "$receipt" | jq -r '"\(.data.signature) \(.data.token) \(.data.signed_receipt.signature)"')
+issued_at=$(echo "$receipt" | jq -r '.data.issued_at')
+expires_at=$(echo "$receipt" | jq -r '.data.expires_at')
+
+if [[ "$first_signature" == *null* ]]; then
+  echo "Error: no receipt was issued."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Getting the receipt again before opening..."
+second_signature=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '"\(.data.signature) \(.data.token) \(.data.signed_receipt.signature)"')
+
+if [ "$second_signature" != "$first_signature" ]; then
+  echo "Error: expected the same receipt, got $second_signature."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting an open with an authorization we never issued..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" "$locker_id" "$((stored_at + 1))" \
+    "$expires_at" retrieve "$((issued_at - 1))")" \
+  "$root_api_url/update_locker_open")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Reporting the locker opened with the receipt..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" "$locker_id" "$((stored_at + 2))" \
+    "$expires_at" retrieve "$issued_at")" \
+  "$root_api_url/update_locker_open"
+
+echo "(Done)"
+
+echo -n "Getting the receipt after opening..."
+response=$(curl -X GET \
+  --silent \
+  --write-out "\n%{http_code}" \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+if [ "$(echo "$response" | tail -n 1)" != "410" ]; then
+  echo "Error: a spent receipt was handed out again, got $response."
+  exit 1
+fi
+
+if [ "$(echo "$response" | head -n 1 | jq -r '.error.message')" != "Payment already redeemed" ]; then
+  echo "Error: expected the payment to be redeemed, got $response."
+  exit 1
+fi
+
+echo "(Done)"
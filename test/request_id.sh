@@ -0,0 +1,88 @@
+#!/bin/bash
+# This script checks every response carries an X-Request-Id, that errors carry it in the envelope
+# too, and that ids from a proxy we trust round-trip. It needs a server running with the mock
+# lightning backend, the test lockers and TRUST_FORWARDED_FOR=true.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml TRUST_FORWARDED_FOR=true cargo run --features mock-ln & ./request_id.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+uuid='^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$'
+
+echo "Running request id tests..."
+
+# prints the X-Request-Id of a request to $1, with the extra curl arguments after it
+request_id() {
+  route=$1
+  shift
+  curl --silent --output /dev/null --dump-header - "$@" "$root_api_url$route" |
+    grep -i "^x-request-id:" | cut -d' ' -f2 | tr -d '\r'
+}
+
+echo -n "Giving each request an id..."
+first=$(request_id /lockers)
+second=$(request_id /lockers)
+for id in "$first" "$second"; do
+  if ! echo "$id" | grep -Eq "$uuid"; then
+    echo "Error: expected a UUID, got '$id'."
+    exit 1
+  fi
+done
+
+if [ "$first" = "$second" ]; then
+  echo "Error: expected each request to get its own id, got $first twice."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Putting the id in errors..."
+headers=$(mktemp)
+body=$(curl --silent --dump-header "$headers" "$root_api_url/lockers/999999")
+id=$(grep -i "^x-request-id:" "$headers" | cut -d' ' -f2 | tr -d '\r')
+rm "$headers"
+
+in_body=$(echo "$body" | jq -r '.error.request_id')
+if [ -z "$id" ] || [ "$in_body" != "$id" ]; then
+  echo "Error: expected the error to carry the request id $id, got $in_body."
+  exit 1
+fi
+
+# rejections axum answers on its own too
+in_body=$(curl --silent "$root_api_url/nowhere" | jq -r '.error.request_id')
+if ! echo "$in_body" | grep -Eq "$uuid"; then
+  echo "Error: expected the rejection to carry a request id, got $in_body."
+  exit 1
+fi
+
+# successes don't have an error to put it in
+in_body=$(curl --silent "$root_api_url/lockers/1" | jq -r '.error')
+if [ "$in_body" != "null" ]; then
+  echo "Error: expected no error, got $in_body."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Keeping the id a proxy we trust sent..."
+id=$(request_id /lockers -H "X-Request-Id: proxy-1234")
+if [ "$id" != "proxy-1234" ]; then
+  echo "Error: expected the id to round-trip, got $id."
+  exit 1
+fi
+
+in_body=$(curl --silent -H "X-Request-Id: proxy-5678" "$root_api_url/lockers/999999" | jq -r '.error.request_id')
+if [ "$in_body" != "proxy-5678" ]; then
+  echo "Error: expected the error to carry the proxy's id, got $in_body."
+  exit 1
+fi
+
+id=$(request_id /lockers -H "X-Request-Id: not valid")
+if ! echo "$id" | grep -Eq "$uuid"; then
+  echo "Error: expected a malformed id to be replaced, got '$id'."
+  exit 1
+fi
+
+echo "(Done)"
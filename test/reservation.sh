@@ -0,0 +1,84 @@
+#!/bin/bash
+# This script checks a reserved locker can only be claimed with its reservation token, and is
+# available again once the reservation expires. It needs a fresh server running with the mock
+# lightning backend, the test lockers and short reservations.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml RESERVATION_SECONDS=2 cargo run --features mock-ln & ./reservation.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running reservation tests..."
+
+# checks a POST request to $1, with the extra curl arguments after it, is answered with $2
+expect_status() {
+  route=$1
+  expected=$2
+  shift 2
+  result=$(curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    "$@" \
+    "$root_api_url$route")
+  if [ "$result" != "$expected" ]; then
+    echo "Error: expected $route to answer $expected, got $result."
+    exit 1
+  fi
+}
+
+# prints the state of locker $1
+locker_state() {
+  curl -X GET --silent --fail "$root_api_url/lockers/$1" | jq -r '.data.state'
+}
+
+echo -n "Reserving locker 1..."
+reservation=$(curl -X POST --silent --fail "$root_api_url/reserve/1")
+token=$(echo "$reservation" | jq -r '.data.reservation_token')
+if [ "$token" == "null" ] || [ "$(echo "$reservation" | jq -r '.data.expires_at | type')" != "number" ]; then
+  echo "Error: expected a token and an expiry, got $reservation."
+  exit 1
+fi
+
+if [ "$(locker_state 1)" != "reserved" ]; then
+  echo "Error: expected locker 1 to be reserved, got $(locker_state 1)."
+  exit 1
+fi
+
+# only one reservation at a time
+expect_status /reserve/1 409
+echo "(Done)"
+
+echo -n "Refusing to claim it for anyone else..."
+expect_status /use_locker/1 409
+expect_status /use_locker/1 409 -H "X-Reservation-Token: $(printf 'f%.0s' $(seq 1 64))"
+echo "(Done)"
+
+echo -n "Claiming it with the token..."
+expect_status /use_locker/1 200 -H "X-Reservation-Token: $token"
+if [ "$(locker_state 1)" != "in_use" ]; then
+  echo "Error: expected locker 1 to be in use, got $(locker_state 1)."
+  exit 1
+fi
+
+# lockers that aren't available can't be reserved
+expect_status /reserve/1 409
+expect_status /reserve/999999 404
+echo "(Done)"
+
+echo -n "Letting reservations expire..."
+expect_status /reserve/2 200
+for _ in $(seq 100); do
+  [ "$(locker_state 2)" == "available" ] && break
+  sleep 0.1
+done
+
+if [ "$(locker_state 2)" != "available" ]; then
+  echo "Error: expected locker 2 to be available again, got $(locker_state 2)."
+  exit 1
+fi
+
+expect_status /use_locker/2 200
+echo "(Done)"
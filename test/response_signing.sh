@@ -0,0 +1,66 @@
+#!/bin/bash
+# This script checks responses are signed by the server key when asked to, that the signature
+# covers the body, so changing a single byte of it is caught, and that responses nobody asked to
+# sign aren't. It needs a server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./response_signing.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+script_dir=$(dirname "$0")
+headers=$(mktemp)
+body=$(mktemp)
+trap 'rm -f "$headers" "$body"' EXIT
+
+echo "Running response signing tests..."
+
+# prints the value of the response header $1
+header() {
+  grep -i "^$1:" "$headers" | cut -d' ' -f2 | tr -d '\r'
+}
+
+pubkey=$(curl -X GET --silent --fail "$root_api_url/pubkey" | jq -r '.data.pubkey')
+
+echo -n "Signing responses when asked to..."
+curl -X GET \
+  --silent \
+  --fail \
+  --dump-header "$headers" \
+  --output "$body" \
+  -H "X-Sign-Response: 1" \
+  "$root_api_url/lockers/1"
+
+if ! "$script_dir/verify_response.py" "$pubkey" "$(header x-server-timestamp)" "$(header x-server-signature)" <"$body"; then
+  echo "Error: expected the response to be signed by $pubkey."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Catching a tampered body..."
+# flips the lowest bit of the first byte
+first=$(head -c 1 "$body" | od -An -tu1 | tr -d ' ')
+tampered=$( (printf "\\$(printf '%03o' $((first ^ 1)))"; tail -c +2 "$body") | base64 -w0)
+
+if echo "$tampered" | base64 -d | "$script_dir/verify_response.py" "$pubkey" "$(header x-server-timestamp)" "$(header x-server-signature)" 2>/dev/null; then
+  echo "Error: expected the tampered body to fail verification."
+  exit 1
+fi
+
+if "$script_dir/verify_response.py" "$pubkey" "$(($(header x-server-timestamp) + 1))" "$(header x-server-signature)" <"$body" 2>/dev/null; then
+  echo "Error: expected another timestamp to fail verification."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Leaving other responses alone..."
+curl -X GET --silent --fail --dump-header "$headers" --output /dev/null "$root_api_url/lockers/1"
+if [ -n "$(header x-server-signature)" ]; then
+  echo "Error: expected the response not to be signed."
+  exit 1
+fi
+
+echo "(Done)"
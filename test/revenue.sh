@@ -0,0 +1,130 @@
+#!/bin/bash
+# This script checks the revenue statistics under /admin/stats/revenue. It needs a fresh server
+# running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./revenue.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+script_dir=$(dirname "$0")
+seckey="3000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running revenue statistics tests..."
+
+echo -n "Using and paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+# settles the payment, the mock backend considers every invoice paid
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipt"
+
+echo "(Done)"
+
+for granularity in day week month; do
+  echo -n "Getting the revenue by $granularity..."
+  stats=$(curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/stats/revenue?granularity=$granularity")
+
+  if [ "$(echo "$stats" | jq '.data | length')" != "1" ] \
+    || [ "$(echo "$stats" | jq '.data[0].payments')" != "1" ] \
+    || [ "$(echo "$stats" | jq '.data[0].sessions')" != "1" ] \
+    || [ "$(echo "$stats" | jq '.data[0].lockers[0].locker_id')" != "1" ]; then
+    echo "Error: unexpected statistics $stats."
+    exit 1
+  fi
+
+  echo "(Done)"
+done
+
+echo -n "Getting the revenue for a range without payments..."
+stats=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/stats/revenue?from=$(($(date +%s) + 3600))")
+
+if [ "$(echo "$stats" | jq '.data | length')" != "0" ]; then
+  echo "Error: expected no periods, got $stats."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Getting the revenue by an unknown granularity..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/stats/revenue?granularity=year")
+
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Paying for lockers 2 and 3 with one invoice..."
+for locker_id in 2 3; do
+  curl -X POST \
+    --silent \
+    --fail \
+    --output /dev/null \
+    -H "Authorization: $("$script_dir/nostr.py" "$seckey" POST "$root_api_url/use_locker/$locker_id")" \
+    "$root_api_url/use_locker/$locker_id"
+done
+sleep 1
+
+batch=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "Authorization: $("$script_dir/nostr.py" "$seckey" POST "$root_api_url/pay_for_usage_batch")" \
+  -H "Content-Type: application/json" \
+  -d '{"locker_ids": [2, 3]}' \
+  "$root_api_url/pay_for_usage_batch" | jq -c '.data')
+payment_hash=$(echo "$batch" | jq -r '.invoice.payment_hash')
+
+curl -X GET \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/payments/$payment_hash/receipts"
+
+echo "(Done)"
+
+echo -n "Crediting each locker its share of the batch payment..."
+stats=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/stats/revenue")
+
+expected=$(echo "$batch" | jq -c '[.lockers[] | {locker_id, total_sat: .amount, payments: 1, sessions: 1}]')
+lockers=$(echo "$stats" \
+  | jq -c '[.data[0].lockers[] | select(.locker_id != 1) | {locker_id, total_sat, payments, sessions}]')
+if [ "$lockers" != "$expected" ]; then
+  echo "Error: expected $expected, got $lockers."
+  exit 1
+fi
+
+echo "(Done)"
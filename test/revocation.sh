@@ -0,0 +1,219 @@
+#!/bin/bash
+# This script checks that staff can revoke a receipt under /admin: it's no longer handed out,
+# /verify_token and /verify_receipt refuse it, and it shows up under /revocations. It needs a fresh
+# server running with the mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./revocation.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running revocation tests..."
+
+echo -n "Paying for locker 1..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+# the mock backend considers every invoice paid
+receipt=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+token=$(echo "$receipt" | jq -r '.data.token')
+signed_receipt=$(echo "$receipt" | jq -c '.data.signed_receipt')
+
+echo "(Done)"
+
+echo -n "Accepting the receipt before it's revoked..."
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "$signed_receipt" \
+  "$root_api_url/verify_receipt" | jq -r '.data | "\(.reason // "valid") \(.receipt.locker_id) \(.expired) \(.revoked) \(.consumed)"')
+
+if [ "$result" != "valid 1 false false false" ]; then
+  echo "Error: expected the receipt to be valid, got $result."
+  exit 1
+fi
+
+# a receipt changed after it was signed doesn't pass
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "$(echo "$signed_receipt" | jq -c '.receipt.locker_id = 2')" \
+  "$root_api_url/verify_receipt" | jq -r '.data.reason // "valid"')
+
+if [ "$result" != "bad_signature" ]; then
+  echo "Error: expected bad_signature, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting malformed receipts..."
+expect_invalid() {
+  local result
+  result=$(curl -X POST \
+    --silent \
+    -H "content-type: application/json" \
+    -d "$(echo "$signed_receipt" | jq -c "$1")" \
+    --write-out " %{http_code}" \
+    "$root_api_url/verify_receipt" | jq -rR 'capture("(?<body>.*) (?<status>[0-9]+)$") | "\(.status) \(.body | fromjson | .error.message)"')
+
+  if [ "$result" != "400 $2" ]; then
+    echo "Error: expected 400 $2, got $result."
+    exit 1
+  fi
+}
+
+expect_invalid '.signature = "zz"' "signature: expected 128 hex characters"
+expect_invalid '.receipt.payment_hash = "abc"' "payment_hash: expected 64 hex characters"
+expect_invalid '.receipt.payment_hash |= ascii_upcase' "payment_hash: expected lowercase hex"
+expect_invalid 'del(.receipt.kid)' "receipt: missing field \`kid\`"
+
+echo "(Done)"
+
+echo -n "Rejecting a malformed payment hash..."
+result=$(curl -X POST \
+  --silent \
+  -H "$auth" \
+  "$root_api_url/admin/receipts/not-a-payment/revoke" | jq -r '.error.message')
+
+if [ "$result" != "payment_hash: expected 64 hex characters" ]; then
+  echo "Error: expected the payment hash to be rejected, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Revoking the receipt..."
+revocation=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "$auth" \
+  -H "content-type: application/json" \
+  -d '{"reason": "forged webhook"}' \
+  "$root_api_url/admin/receipts/$payment_hash/revoke")
+
+if [ "$(echo "$revocation" | jq -r '.data.reason')" != "forged webhook" ]; then
+  echo "Error: expected the revocation to keep its reason, got $revocation."
+  exit 1
+fi
+
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/receipts/$payment_hash/revoke")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected revoking twice to conflict, got $status."
+  exit 1
+fi
+
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/receipts/$(printf '0%.0s' $(seq 1 64))/revoke")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected an unknown receipt to be missing, got $status."
+  exit 1
+fi
+
+event=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/lockers/1/events" | jq -r '.data[0].event')
+
+if [ "$event" != "receipt_revoked" ]; then
+  echo "Error: expected a receipt_revoked event, got $event."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing the revoked receipt..."
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+if [ "$status" != "410" ]; then
+  echo "Error: expected the revoked receipt to be gone, got $status."
+  exit 1
+fi
+
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "$signed_receipt" \
+  "$root_api_url/verify_receipt" | jq -r '.data | "\(.reason // "valid") \(.revoked)"')
+
+if [ "$result" != "revoked true" ]; then
+  echo "Error: expected the receipt to be revoked, got $result."
+  exit 1
+fi
+
+result=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "content-type: application/json" \
+  -d "{\"locker_id\": 1, \"token\": \"$token\"}" \
+  "$root_api_url/verify_token" | jq -r '.data.reason // "valid"')
+
+if [ "$result" != "revoked" ]; then
+  echo "Error: expected the token to be revoked, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Listing the revocation..."
+feed=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/revocations?since=0&locker_id=1")
+
+if [ "$(echo "$feed" | jq -r '.data.revocations[0].payment_hash')" != "$payment_hash" ]; then
+  echo "Error: expected the revocation to be listed, got $feed."
+  exit 1
+fi
+
+if [ "$(echo "$feed" | jq -r '.data.revocations[0].reason')" != "null" ]; then
+  echo "Error: the feed shouldn't tell why a receipt was revoked, got $feed."
+  exit 1
+fi
+
+since=$(($(echo "$feed" | jq -r '.data.server_time') + 1))
+count=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/revocations?since=$since" | jq -r '.data.revocations | length')
+
+if [ "$count" != "0" ]; then
+  echo "Error: expected nothing revoked since $since, got $count."
+  exit 1
+fi
+
+echo "(Done)"
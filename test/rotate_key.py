@@ -0,0 +1,29 @@
+#!/usr/bin/env python3
+"""Plays the part of a locker getting a new controller: prints the JSON body of a
+`/admin/lockers/{id}/rotate_key` call handing over to the new controller's key, signed with the
+old one.
+
+Usage: ./rotate_key.py <old secret key hex> <locker id> <new secret key hex>
+"""
+
+import json
+import struct
+import sys
+
+from locker import G, point_mul, sign, tagged_hash
+
+
+def main():
+    if len(sys.argv) != 4:
+        sys.exit(__doc__)
+
+    old_seckey = bytes.fromhex(sys.argv[1])
+    locker_id = int(sys.argv[2])
+    pk = point_mul(G, int(sys.argv[3], 16))[0].to_bytes(32, "big")
+
+    signature = sign(tagged_hash("locker/rotate-key", struct.pack(">q", locker_id) + pk), old_seckey)
+    print(json.dumps({"pk": pk.hex(), "signature": signature.hex()}))
+
+
+if __name__ == "__main__":
+    main()
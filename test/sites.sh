@@ -0,0 +1,182 @@
+#!/bin/bash
+# This script checks sites: managing them under /admin/sites, filtering lockers by site, charging
+# each site's price and embedding the site in receipts. It needs a fresh server running with the
+# mock lightning backend, the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./sites.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+
+echo "Running site tests..."
+
+echo -n "Creating a site..."
+site_id=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"name": "Downtown", "address": "1 Main St", "timezone": "America/Los_Angeles", "price_per_minute_msat": 600000}' \
+  "$root_api_url/admin/sites" | jq -r '.data.id')
+
+if [ "$site_id" == "null" ]; then
+  echo "Error: the site wasn't created."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a site without a name..."
+status=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"address": "2 Main St"}' \
+  "$root_api_url/admin/sites")
+
+if [ "$status" != "400" ]; then
+  echo "Error: expected 400, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Moving locker 1 to the site..."
+curl -X PATCH \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d "{\"site_id\": $site_id}" \
+  "$root_api_url/admin/lockers/1"
+
+lockers=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/lockers?site_id=$site_id" | jq -r '[.data[] | "\(.id) \(.site.name)"] | join(",")')
+
+if [ "$lockers" != "1 Downtown" ]; then
+  echo "Error: expected only locker 1 at Downtown, got $lockers."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Moving a locker to a site that doesn't exist..."
+status=$(curl -X PATCH \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"site_id": 999999}' \
+  "$root_api_url/admin/lockers/2")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Paying the site's price..."
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  "$root_api_url/use_locker/1"
+
+sleep 2
+
+payment=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1")
+lease_time=$(echo "$payment" | jq -r '.data.lease_time')
+amount=$(echo "$payment" | jq -r '.data.amount')
+payment_hash=$(echo "$payment" | jq -r '.data.invoice.payment_hash')
+
+# 600000 msat a minute is 10 sat a second
+if [ "$amount" != "$((lease_time * 10))" ]; then
+  echo "Error: expected $((lease_time * 10)) sat for $lease_time seconds, got $amount."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Embedding the site in the receipt..."
+receipt_site_id=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '.data.site_id')
+
+if [ "$receipt_site_id" != "$site_id" ]; then
+  echo "Error: expected site $site_id in the receipt, got $receipt_site_id."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing to delete a site that still has lockers..."
+status=$(curl -X DELETE \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/sites/$site_id")
+
+if [ "$status" != "409" ]; then
+  echo "Error: expected 409, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Updating and deleting an empty site..."
+empty_site_id=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"name": "Airport"}' \
+  "$root_api_url/admin/sites" | jq -r '.data.id')
+
+timezone=$(curl -X PATCH \
+  --silent \
+  --fail \
+  -H "$auth" \
+  -H "Content-Type: application/json" \
+  -d '{"timezone": "Europe/Lisbon"}' \
+  "$root_api_url/admin/sites/$empty_site_id" | jq -r '"\(.data.name) \(.data.timezone)"')
+
+if [ "$timezone" != "Airport Europe/Lisbon" ]; then
+  echo "Error: expected Airport in Europe/Lisbon, got $timezone."
+  exit 1
+fi
+
+curl -X DELETE \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "$auth" \
+  "$root_api_url/admin/sites/$empty_site_id"
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "$auth" \
+  "$root_api_url/admin/sites/$empty_site_id")
+
+if [ "$status" != "404" ]; then
+  echo "Error: expected 404, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,53 @@
+#!/bin/bash
+# This script checks the server can serve HTTPS itself, redirecting plain HTTP to it. It needs a
+# server running with the mock lightning backend and a self-signed certificate for localhost,
+# redirecting plain HTTP on port 8081.
+
+# Usage: openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days 1 \
+#          -subj /CN=localhost -keyout tls.key -out tls.pem
+#        LN_BACKEND=mock TLS_CERT_FILE=tls.pem TLS_KEY_FILE=tls.key \
+#          HTTP_REDIRECT_ADDRESS=127.0.0.1:8081 cargo run --features mock-ln &
+#        ./tls.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="https://127.0.0.1:8080/v1"
+redirect_url="http://127.0.0.1:8081/v1"
+
+echo "Running TLS tests..."
+
+echo -n "Answering over HTTPS..."
+status=$(curl -X GET \
+  --silent \
+  --insecure \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/health")
+if [ "$status" != "200" ]; then
+  echo "Error: expected 200, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Refusing plain HTTP on the HTTPS port..."
+if curl -X GET --silent --output /dev/null "${root_api_url/https/http}/health"; then
+  echo "Error: expected plain HTTP to fail."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Redirecting plain HTTP to the same URL over HTTPS..."
+result=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code} %{redirect_url}" \
+  "$redirect_url/use_locker/1?from=kiosk")
+if [ "$result" != "308 https://127.0.0.1:8080/v1/use_locker/1?from=kiosk" ]; then
+  echo "Error: expected a permanent redirect, got $result."
+  exit 1
+fi
+
+echo "(Done)"
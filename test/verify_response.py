@@ -0,0 +1,56 @@
+#!/usr/bin/env python3
+"""Plays the part of a kiosk checking a signed response: reads the body from stdin and exits with
+an error unless the `X-Server-Signature` given is the server's signature over it and the
+`X-Server-Timestamp` given.
+
+Usage: ./verify_response.py <server public key hex> <timestamp> <signature hex> < body
+
+The verification follows the BIP340 reference implementation, so we don't need any dependencies.
+"""
+
+import hashlib
+import sys
+
+from locker import G, N, P, point_add, point_mul, tagged_hash
+
+
+def lift_x(x):
+    if x >= P:
+        return None
+    y_sq = (pow(x, 3, P) + 7) % P
+    y = pow(y_sq, (P + 1) // 4, P)
+    if pow(y, 2, P) != y_sq:
+        return None
+    return (x, y if y & 1 == 0 else P - y)
+
+
+def verify(pubkey, msg, sig):
+    """Whether `sig` is a valid BIP340 signature by the x-only `pubkey` over `msg`."""
+    point = lift_x(int.from_bytes(pubkey, "big"))
+    r = int.from_bytes(sig[:32], "big")
+    s = int.from_bytes(sig[32:], "big")
+    if point is None or r >= P or s >= N:
+        return False
+    e = int.from_bytes(tagged_hash("BIP0340/challenge", sig[:32] + pubkey + msg), "big") % N
+    R = point_add(point_mul(G, s), point_mul(point, N - e))
+    return R is not None and R[1] % 2 == 0 and R[0] == r
+
+
+def response_digest(timestamp, body):
+    """What the server signs: the `locker/response` tagged hash of the timestamp, as 8 big-endian
+    bytes, and the SHA-256 of the body."""
+    return tagged_hash("locker/response", timestamp.to_bytes(8, "big") + hashlib.sha256(body).digest())
+
+
+def main():
+    if len(sys.argv) != 4:
+        sys.exit(__doc__)
+
+    pubkey = bytes.fromhex(sys.argv[1])
+    digest = response_digest(int(sys.argv[2]), sys.stdin.buffer.read())
+    if not verify(pubkey, digest, bytes.fromhex(sys.argv[3])):
+        sys.exit("invalid signature")
+
+
+if __name__ == "__main__":
+    main()
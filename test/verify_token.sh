@@ -0,0 +1,139 @@
+#!/bin/bash
+# This script checks /verify_token, which lockers call to check the tokens customers present. It
+# needs a fresh server running with the mock lightning backend and the test lockers.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml cargo run --features mock-ln & ./verify_token.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running token verification tests..."
+
+# asks the server whether token $2 opens locker $1, to do $3 if given, printing the reason it
+# doesn't or "valid" followed by what it allows
+verify() {
+  local action=null
+  if [ -n "${3:-}" ]; then
+    action="\"$3\""
+  fi
+
+  curl -X POST \
+    --silent \
+    --fail \
+    -H "content-type: application/json" \
+    -d "{\"locker_id\": $1, \"token\": \"$2\", \"action\": $action}" \
+    "$root_api_url/verify_token" | jq -r '.data.reason // "valid \(.data.action)"'
+}
+
+echo -n "Claiming locker 1..."
+token=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/use_locker/1" | jq -r '.data.token')
+
+echo "(Done)"
+
+echo -n "Rejecting the token for another locker..."
+result=$(verify 2 "$token")
+if [ "$result" != "wrong_locker" ]; then
+  echo "Error: expected wrong_locker, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting a tampered token..."
+result=$(verify 1 "not.a.token")
+if [ "$result" != "malformed" ]; then
+  echo "Error: expected malformed, got $result."
+  exit 1
+fi
+
+# same header and signature, but claims we didn't sign
+result=$(verify 1 "${token%%.*}.eyJsb2NrZXJfaWQiOjF9.${token##*.}")
+if [ "$result" != "bad_signature" ]; then
+  echo "Error: expected bad_signature, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rejecting the token for taking something out..."
+result=$(verify 1 "$token" retrieve)
+if [ "$result" != "wrong_action" ]; then
+  echo "Error: expected wrong_action, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Accepting the token once..."
+result=$(verify 1 "$token" store)
+if [ "$result" != "valid store" ]; then
+  echo "Error: expected the token to be valid, got $result."
+  exit 1
+fi
+
+result=$(verify 1 "$token")
+if [ "$result" != "used" ]; then
+  echo "Error: expected used, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Using up the receipt along with its token..."
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+
+# the mock backend considers every invoice paid
+token=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/payments/$payment_hash/receipt" | jq -r '.data.token')
+
+# old firmware doesn't say what it's about to do, and is told
+result=$(verify 1 "$token")
+if [ "$result" != "valid retrieve" ]; then
+  echo "Error: expected the token to be valid, got $result."
+  exit 1
+fi
+
+status=$(curl -X GET \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  "$root_api_url/payments/$payment_hash/receipt")
+
+if [ "$status" != "410" ]; then
+  echo "Error: expected the receipt to be used up, got $status."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Rate limiting each locker..."
+for _ in $(seq 1 100); do
+  status=$(curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "content-type: application/json" \
+    -d '{"locker_id": 2, "token": "not a token"}' \
+    "$root_api_url/verify_token")
+
+  if [ "$status" == "429" ]; then
+    break
+  fi
+done
+
+if [ "$status" != "429" ]; then
+  echo "Error: expected to be rate limited, got $status."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,26 @@
+#!/bin/bash
+# This script checks GET /version says what's deployed, without needing any credentials. It
+# needs a server running with the mock lightning backend, built from a git checkout.
+
+# Usage: LN_BACKEND=mock cargo run --features mock-ln &
+#        ./version.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+
+echo "Running version tests..."
+
+echo -n "Checking what's deployed..."
+result=$(curl -X GET \
+  --silent \
+  --fail \
+  "$root_api_url/version" | jq -r '.data | "\(.version) \(.git_commit | length) \(.built_at > 0) \(.ln_backend) \(.api_version)"')
+version=$(grep -m 1 '^version' "$(dirname "$0")/../Cargo.toml" | cut -d '"' -f 2)
+if [ "$result" != "$version 40 true mock 1" ]; then
+  echo "Error: expected \"$version 40 true mock 1\", got \"$result\"."
+  exit 1
+fi
+
+echo "(Done)"
@@ -0,0 +1,50 @@
+#!/usr/bin/env python3
+"""Plays the part of a lightning wallet logging in with LNURL-auth: prints the callback URL with
+the wallet's signature of its `k1` and its linking key added, ready to be called.
+
+Usage: ./wallet.py <linking secret key hex> <callback url>
+
+The nonce is derived from the key and `k1` rather than random, which is fine for a test wallet but
+nothing else.
+"""
+
+import hashlib
+import sys
+from urllib.parse import parse_qs, urlparse
+
+from locker import G, N, point_mul
+
+
+def der_integer(value):
+    encoded = value.to_bytes(33, "big").lstrip(b"\x00")
+    # keep the integer positive
+    if encoded[0] & 0x80:
+        encoded = b"\x00" + encoded
+    return b"\x02" + bytes([len(encoded)]) + encoded
+
+
+def sign_ecdsa(msg, seckey):
+    d = int.from_bytes(seckey, "big")
+    k = int.from_bytes(hashlib.sha256(seckey + msg).digest(), "big") % N
+    r = point_mul(G, k)[0] % N
+    s = pow(k, N - 2, N) * (int.from_bytes(msg, "big") + r * d) % N
+    s = min(s, N - s)
+    body = der_integer(r) + der_integer(s)
+    return b"\x30" + bytes([len(body)]) + body
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+
+    seckey = bytes.fromhex(sys.argv[1])
+    callback = sys.argv[2]
+    k1 = bytes.fromhex(parse_qs(urlparse(callback).query)["k1"][0])
+
+    x, y = point_mul(G, int.from_bytes(seckey, "big"))
+    key = bytes([2 + y % 2]) + x.to_bytes(32, "big")
+    print(f"{callback}&sig={sign_ecdsa(k1, seckey).hex()}&key={key.hex()}")
+
+
+if __name__ == "__main__":
+    main()
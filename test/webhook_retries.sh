@@ -0,0 +1,190 @@
+#!/bin/bash
+# This script checks webhook deliveries the receiver doesn't take are tried again, and given up on
+# after too many attempts, and that subscriptions only get the events they asked for. It needs a
+# fresh server running with the mock lightning backend, the test lockers, an admin token and fast
+# retries, and listens for the webhooks on port 8098.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> WEBHOOK_MAX_ATTEMPTS=2 WEBHOOK_RETRY_SECONDS=1 cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./webhook_retries.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+script_dir=$(dirname "$0")
+receiver_port=8098
+# nothing listens there, so every delivery fails
+unreachable_url="http://127.0.0.1:9/hook"
+received=$(mktemp)
+trap 'rm -f "$received"; kill "$receiver" 2>/dev/null || true' EXIT
+
+# locker A1 from test/lockers.toml, the first one synced into a fresh database
+locker_id=1
+locker_seckey="1000000000000000000000000000000000000000000000000000000000000001"
+
+echo "Running webhook retry tests..."
+
+# subscribes $1 to the events in the JSON array $2, or every event if it's empty, printing its id
+subscribe() {
+  if [ -n "$2" ]; then
+    body="{\"url\": \"$1\", \"events\": $2}"
+  else
+    body="{\"url\": \"$1\"}"
+  fi
+
+  curl -X POST \
+    --silent \
+    --fail \
+    -H "$auth" \
+    -H "Content-Type: application/json" \
+    -d "$body" \
+    "$root_api_url/admin/webhooks" | jq -r '.data.id'
+}
+
+# prints `<event> <status> <attempts>` for each delivery to subscription $1, oldest first
+deliveries() {
+  curl -X GET \
+    --silent \
+    --fail \
+    -H "$auth" \
+    "$root_api_url/admin/webhooks/$1/deliveries" |
+    jq -r '.data | reverse | .[] | "\(.event) \(.status) \(.attempts)"' | paste -sd ','
+}
+
+# waits for the deliveries to subscription $1 to be $2
+wait_for_deliveries() {
+  for _ in $(seq 100); do
+    [ "$(deliveries "$1")" == "$2" ] && return
+    sleep 0.1
+  done
+
+  echo "Error: expected the deliveries $2, got $(deliveries "$1")."
+  exit 1
+}
+
+echo -n "Rejecting unknown events..."
+for events in '[]' '["locker.exploded"]'; do
+  result=$(curl -X POST \
+    --silent \
+    --output /dev/null \
+    --write-out "%{http_code}" \
+    -H "$auth" \
+    -H "Content-Type: application/json" \
+    -d "{\"url\": \"$unreachable_url\", \"events\": $events}" \
+    "$root_api_url/admin/webhooks")
+  if [ "$result" != "400" ]; then
+    echo "Error: expected 400 for $events, got $result."
+    exit 1
+  fi
+done
+
+echo "(Done)"
+
+echo -n "Subscribing..."
+flaky=$(subscribe "http://127.0.0.1:$receiver_port/hook" "")
+unreachable=$(subscribe "$unreachable_url" "")
+filtered=$(subscribe "$unreachable_url" '["locker.opened"]')
+
+listed=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/webhooks" | jq -r ".data[] | select(.id == $filtered) | .events | join(\",\")")
+if [ "$listed" != "locker.opened" ]; then
+  echo "Error: expected the subscription to only get locker.opened, got $listed."
+  exit 1
+fi
+
+echo "(Done)"
+
+# answers the first request with a 500 and the others with a 200, writing the event id of each
+# request it gets to $received
+python3 - "$receiver_port" "$received" <<'PYTHON' &
+import http.server, json, sys
+
+class Handler(http.server.BaseHTTPRequestHandler):
+    requests = 0
+
+    def do_POST(self):
+        body = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        with open(sys.argv[2], "a") as f:
+            f.write(f"{body['event']} {body['id']}\n")
+        Handler.requests += 1
+        self.send_response(500 if Handler.requests == 1 else 200)
+        self.end_headers()
+
+    def log_message(self, *args):
+        pass
+
+http.server.HTTPServer(("127.0.0.1", int(sys.argv[1])), Handler).serve_forever()
+PYTHON
+receiver=$!
+sleep 0.5
+
+echo -n "Opening and paying for locker $locker_id..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/$locker_id"
+curl -X POST \
+  --silent \
+  --fail \
+  --output /dev/null \
+  -H "content-type: application/json" \
+  -d "$("$script_dir/locker.py" "$locker_seckey" "$locker_id" "$(date +%s)")" \
+  "$root_api_url/update_locker_open"
+
+# waiting for the first delivery, so the receiver fails the one for the locker opening
+for _ in $(seq 50); do
+  [ -s "$received" ] && break
+  sleep 0.1
+done
+
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/$locker_id" | jq -r '.data.invoice.payment_hash')
+curl -X GET --silent --fail --output /dev/null "$root_api_url/payments/$payment_hash/receipt"
+
+echo "(Done)"
+
+echo -n "Trying failed deliveries again..."
+wait_for_deliveries "$flaky" "locker.opened delivered 2,payment.settled delivered 1"
+
+# the retry is the same event
+opened=$(grep '^locker.opened' "$received" | cut -d ' ' -f 2 | sort -u | wc -l)
+if [ "$(grep -c '^locker.opened' "$received")" != "2" ] || [ "$opened" != "1" ]; then
+  echo "Error: expected the same event twice, got $(cat "$received")."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Giving up on deliveries..."
+wait_for_deliveries "$unreachable" "locker.opened dead 2,payment.settled dead 2"
+
+listed=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/webhooks" |
+  jq -r ".data[] | select(.id == $unreachable) | \"\(.pending_deliveries) \(.dead_deliveries)\"")
+if [ "$listed" != "0 2" ]; then
+  echo "Error: expected two dead deliveries in the listing, got $listed."
+  exit 1
+fi
+
+last_error=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "$auth" \
+  "$root_api_url/admin/webhooks/$unreachable/deliveries?status=dead&limit=1" |
+  jq -r '.data[0] | "\(.last_status) \(.last_error != null)"')
+if [ "$last_error" != "null true" ]; then
+  echo "Error: expected why the delivery failed, got $last_error."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Only sending the events asked for..."
+wait_for_deliveries "$filtered" "locker.opened dead 2"
+echo "(Done)"
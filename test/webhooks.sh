@@ -0,0 +1,121 @@
+#!/bin/bash
+# This script checks that a payment settling is sent to the webhook subscriptions, signed with the
+# secret each one was given. It needs a fresh server running with the mock lightning backend, the
+# test lockers and an admin token, and listens for the webhook on port 8099.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln & ADMIN_TOKEN=<token> ./webhooks.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+receiver_port=8099
+delivery=$(mktemp)
+trap 'rm -f "$delivery"' EXIT
+
+echo "Running webhook tests..."
+
+echo -n "Rejecting subscriptions that aren't http..."
+result=$(curl -X POST \
+  --silent \
+  --output /dev/null \
+  --write-out "%{http_code}" \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d '{"url": "ftp://example.com"}' \
+  "$root_api_url/admin/webhooks")
+if [ "$result" != "400" ]; then
+  echo "Error: expected 400, got $result."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Subscribing..."
+secret=$(curl -X POST \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  -H "Content-Type: application/json" \
+  -d "{\"url\": \"http://127.0.0.1:$receiver_port/hook\"}" \
+  "$root_api_url/admin/webhooks" | jq -r '.data.secret')
+
+listed=$(curl -X GET \
+  --silent \
+  --fail \
+  -H "Authorization: Bearer $ADMIN_TOKEN" \
+  "$root_api_url/admin/webhooks" | jq -r '[.data[] | "\(.url) \(.secret)"] | join(",")')
+if [ "$listed" != "http://127.0.0.1:$receiver_port/hook null" ]; then
+  echo "Error: expected the subscription without its secret, got $listed."
+  exit 1
+fi
+
+echo "(Done)"
+
+# writes the headers and body of the first request it gets to $delivery
+python3 - "$receiver_port" "$delivery" <<'EOF' &
+import http.server, json, sys
+
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers["Content-Length"])).decode()
+        with open(sys.argv[2], "w") as f:
+            json.dump({
+                "timestamp": self.headers["X-Locker-Timestamp"],
+                "signature": self.headers["X-Locker-Signature"],
+                "body": body,
+            }, f)
+        self.send_response(200)
+        self.end_headers()
+
+    def log_message(self, *args):
+        pass
+
+http.server.HTTPServer(("127.0.0.1", int(sys.argv[1])), Handler).handle_request()
+EOF
+receiver=$!
+sleep 0.5
+
+echo -n "Paying for locker 1..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+payment_hash=$(curl -X POST \
+  --silent \
+  --fail \
+  "$root_api_url/pay_for_usage/1" | jq -r '.data.invoice.payment_hash')
+curl -X GET --silent --fail --output /dev/null "$root_api_url/payments/$payment_hash/receipt"
+
+echo "(Done)"
+
+echo -n "Receiving the signed webhook..."
+for _ in $(seq 50); do
+  [ -s "$delivery" ] && break
+  sleep 0.1
+done
+kill "$receiver" 2>/dev/null || true
+
+if [ ! -s "$delivery" ]; then
+  echo "Error: no webhook was delivered."
+  exit 1
+fi
+
+event=$(jq -r '.body | fromjson | "\(.event) \(.data.payment_hash) \(.data.locker_id)"' "$delivery")
+if [ "$event" != "payment.settled $payment_hash 1" ]; then
+  echo "Error: expected the payment to settle, got $event."
+  exit 1
+fi
+
+expected=$(python3 - "$secret" "$delivery" <<'EOF'
+import hashlib, hmac, json, sys
+
+delivery = json.load(open(sys.argv[2]))
+message = f"{delivery['timestamp']}.{delivery['body']}".encode()
+print("v1=" + hmac.new(bytes.fromhex(sys.argv[1]), message, hashlib.sha256).hexdigest())
+EOF
+)
+signature=$(jq -r '.signature' "$delivery")
+if [ "$signature" != "$expected" ]; then
+  echo "Error: expected the signature $expected, got $signature."
+  exit 1
+fi
+
+echo "(Done)"
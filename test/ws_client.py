@@ -0,0 +1,80 @@
+#!/usr/bin/env python3
+"""Plays the part of a kiosk following the lockers' state: connects to a WebSocket and prints each
+message it gets, one per line, until it got as many as asked for. Exits with an error if the
+server closes the connection first, or nothing comes for the given number of seconds.
+
+Usage: ./ws_client.py <url> <messages> [seconds]
+
+It only speaks as much of RFC 6455 as reading a server's messages takes, so we don't need any
+dependencies.
+"""
+
+import base64
+import os
+import socket
+import sys
+from urllib.parse import urlparse
+
+CLOSE = 0x8
+
+
+def connect(url, timeout):
+    """Opens the WebSocket at `url`, returning a file to read its frames from."""
+    parsed = urlparse(url)
+    sock = socket.create_connection((parsed.hostname, parsed.port or 80), timeout=timeout)
+    key = base64.b64encode(os.urandom(16)).decode()
+    sock.sendall(
+        (
+            f"GET {parsed.path} HTTP/1.1\r\n"
+            f"Host: {parsed.netloc}\r\n"
+            "Upgrade: websocket\r\n"
+            "Connection: Upgrade\r\n"
+            f"Sec-WebSocket-Key: {key}\r\n"
+            "Sec-WebSocket-Version: 13\r\n"
+            "\r\n"
+        ).encode()
+    )
+
+    reader = sock.makefile("rb")
+    status = reader.readline().decode().strip()
+    if " 101 " not in f"{status} ":
+        sys.exit(f"expected to switch protocols, got {status}")
+    while reader.readline() not in (b"\r\n", b""):
+        pass
+    return reader
+
+
+def read_frame(reader):
+    """Reads a frame, returning its opcode and payload. Servers don't mask their frames."""
+    header = reader.read(2)
+    if len(header) < 2:
+        return CLOSE, b""
+
+    opcode = header[0] & 0x0F
+    length = header[1] & 0x7F
+    if length == 126:
+        length = int.from_bytes(reader.read(2), "big")
+    elif length == 127:
+        length = int.from_bytes(reader.read(8), "big")
+    return opcode, reader.read(length)
+
+
+def main():
+    if len(sys.argv) not in (3, 4):
+        sys.exit(__doc__)
+
+    url, messages = sys.argv[1], int(sys.argv[2])
+    timeout = float(sys.argv[3]) if len(sys.argv) == 4 else 10
+    reader = connect(url, timeout)
+    for _ in range(messages):
+        try:
+            opcode, payload = read_frame(reader)
+        except socket.timeout:
+            sys.exit("timed out waiting for a message")
+        if opcode == CLOSE:
+            sys.exit("the server closed the connection")
+        print(payload.decode(), flush=True)
+
+
+if __name__ == "__main__":
+    main()
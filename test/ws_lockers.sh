@@ -0,0 +1,64 @@
+#!/bin/bash
+# This script checks `/ws/lockers` sends a snapshot of the lockers on connecting, then each change
+# of their state, whoever made it. It needs a fresh server running with the mock lightning backend,
+# the test lockers and an admin token.
+
+# Usage: LN_BACKEND=mock LOCKERS_CONFIG=test/lockers.toml ADMIN_TOKEN=<token> cargo run --features mock-ln &
+#        ADMIN_TOKEN=<token> ./ws_lockers.sh
+
+set -euo pipefail
+set -o posix
+
+root_api_url="http://127.0.0.1:8080/v1"
+auth="Authorization: Bearer $ADMIN_TOKEN"
+script_dir=$(dirname "$0")
+messages=$(mktemp)
+trap 'rm -f "$messages"' EXIT
+
+echo "Running locker feed tests..."
+
+echo -n "Getting a snapshot on connecting..."
+# the snapshot, then the four changes below
+"$script_dir/ws_client.py" "ws://127.0.0.1:8080/v1/ws/lockers" 5 >"$messages" &
+client=$!
+
+for _ in $(seq 1 50); do
+  [ -s "$messages" ] && break
+  sleep 0.1
+done
+
+snapshot=$(head -n 1 "$messages" | jq -r '"\(.type) \(.lockers[] | select(.locker_id == 1) | .state)"')
+if [ "$snapshot" != "snapshot available" ]; then
+  echo "Error: expected a snapshot with locker 1 available, got $(cat "$messages")."
+  exit 1
+fi
+
+echo "(Done)"
+
+echo -n "Following changes..."
+curl -X POST --silent --fail --output /dev/null "$root_api_url/use_locker/1"
+curl -X POST --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/3/maintenance"
+curl -X DELETE --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/3/maintenance"
+# asking again changes nothing, so nothing is sent
+curl -X DELETE --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/3/maintenance"
+curl -X POST --silent --fail --output /dev/null -H "$auth" "$root_api_url/admin/lockers/2/decommission"
+
+if ! wait "$client"; then
+  echo "Error: expected four changes, got $(cat "$messages")."
+  exit 1
+fi
+
+changes=$(tail -n +2 "$messages" | jq -r '"\(.type) \(.locker_id) \(.old_state) \(.new_state)"' | paste -sd ',')
+expected="state_changed 1 available in_use,state_changed 3 available maintenance"
+expected="$expected,state_changed 3 maintenance available,state_changed 2 available decommissioned"
+if [ "$changes" != "$expected" ]; then
+  echo "Error: expected $expected, got $changes."
+  exit 1
+fi
+
+if [ "$(tail -n 1 "$messages" | jq -r '.ts | type')" != "number" ]; then
+  echo "Error: expected changes to say when they happened, got $(tail -n 1 "$messages")."
+  exit 1
+fi
+
+echo "(Done)"